@@ -6,7 +6,7 @@ use solana_sdk::instruction::{AccountMeta, Instruction};
 use solana_sdk::message::Message;
 use solana_sdk::program_error::ProgramError;
 use solana_sdk::pubkey::Pubkey;
-use solana_sdk::signature::Signer;
+use solana_sdk::signature::{Signature, Signer};
 use solana_sdk::signer::keypair::Keypair;
 use solana_sdk::transaction::Transaction;
 use solana_transaction_status::option_serializer::OptionSerializer;
@@ -98,40 +98,28 @@ fn call_chsum_chunked(
     keypair: &Keypair,
     data: Vec<u8>,
 ) -> Result {
-    // Send chunks
-    eprintln!("Writing chunks into the data account…");
-    let (chunks, account, bump) =
-        solana_write_account::instruction::WriteIter::new(
-            &WRITE_ACCOUNT_PROGRAM_ID,
-            keypair.pubkey(),
-            SEED,
-            data,
-        )?;
-    for instruction in chunks {
-        send_and_confirm_instruction(
-            client,
-            keypair,
-            instruction,
-        )?;
-        eprintln!();
-    }
+    use solana_write_account::instruction::{
+        call_with_account_data, UploadConfig,
+    };
 
-    // Call chsum
-    eprintln!("Calling chsum program…");
-    let accounts = vec![AccountMeta::new(account, false)];
-    call_chsum(client, keypair, accounts, Vec::new())?;
-
-    // Free the account
-    eprintln!();
-    eprintln!("Freeing instruction data account…");
-    let instruction = solana_write_account::instruction::free(
-        WRITE_ACCOUNT_PROGRAM_ID,
-        keypair.pubkey(),
-        Some(account),
+    eprintln!("Calling chsum via data account…");
+    let instruction = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: Vec::new(),
+        data: Vec::new(),
+    };
+    let signatures = call_with_account_data(
+        client,
+        &WRITE_ACCOUNT_PROGRAM_ID,
+        keypair,
         SEED,
-        bump,
+        instruction,
+        data,
+        &UploadConfig::DEFAULT,
     )?;
-    send_and_confirm_instruction(client, keypair, instruction)
+    eprintln!("Wrote {} chunks", signatures.writes.len());
+    eprintln!("Freed data account in {}", signatures.free);
+    print_logs(client, &signatures.call)
 }
 
 
@@ -173,10 +161,16 @@ fn send_and_confirm_instruction(
     tx.sign(&[&keypair], blockhash);
 
     let sig = client.send_and_confirm_transaction(&tx)?;
+    print_logs(client, &sig)
+}
+
+
+/// Fetches a transaction and prints its log messages.
+fn print_logs(client: &RpcClient, sig: &Signature) -> Result {
     eprintln!("Signature: {sig}");
 
     let encoding = UiTransactionEncoding::Binary;
-    let resp = client.get_transaction(&sig, encoding)?;
+    let resp = client.get_transaction(sig, encoding)?;
     let (slot, tx) = (resp.slot, resp.transaction);
     eprintln!("Executed in slot: {slot}");
 
@@ -216,3 +210,14 @@ impl From<solana_client::client_error::ClientError> for Error {
         Self::Box(Box::new(err))
     }
 }
+
+#[cfg(feature = "use-write-account")]
+impl From<solana_write_account::instruction::UploadError>
+    for Error
+{
+    fn from(
+        err: solana_write_account::instruction::UploadError,
+    ) -> Self {
+        Self::Box(Box::new(err))
+    }
+}
@@ -0,0 +1,433 @@
+// solana-write-account — helper program and library for handling Solana
+//                        transaction size limit
+// © 2025 by Michał Nazarewicz <mina86@mina86.com>
+//
+// This program is free software; you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation; either version 2 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program; if not, see <https://www.gnu.org/licenses/>.
+
+//! Command line tool for managing Write accounts.
+//!
+//! ```text
+//! usage: write-account-cli [<options>] write <file>
+//!        write-account-cli [<options>] free
+//!        write-account-cli [<options>] inspect <address>
+//!        write-account-cli [<options>] list [<seed>...]
+//! options:
+//!     --url <url>          RPC URL of the cluster (default: localhost)
+//!     --keypair <path>     payer’s keypair (default: ~/.config/solana/id.json)
+//!     --program <address>  address of the write-account program
+//!     --seed <seed>        seed of the Write account (default: empty)
+//!     --dry-run            print base64-encoded transactions of write and
+//!                          free commands, one per line, instead of sending
+//! ```
+
+use std::process::ExitCode;
+use std::str::FromStr;
+
+use solana_client::rpc_client::RpcClient;
+use solana_keypair::Keypair;
+use solana_pubkey::Pubkey;
+use solana_signer::Signer;
+use solana_transaction::Transaction;
+use solana_write_account::header::{V2Header, ZSTD_FLAG};
+use solana_write_account::instruction;
+
+type Result<T = (), E = Box<dyn std::error::Error>> =
+    core::result::Result<T, E>;
+
+const USAGE: &str = "usage: write-account-cli [--url <url>] [--keypair \
+                     <path>] --program <address> [--seed <seed>] [--dry-run] \
+                     (write <file> | free | inspect <address> | list \
+                     [<seed>...])";
+
+fn main() -> ExitCode {
+    match Args::parse(std::env::args().skip(1)).and_then(|args| args.run()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+
+/// Parsed command line arguments.
+#[derive(Debug, PartialEq)]
+struct Args {
+    url: String,
+    keypair: Option<String>,
+    program: Pubkey,
+    seed: String,
+    dry_run: bool,
+    command: Command,
+}
+
+/// Subcommand to execute.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Write(String),
+    Free,
+    Inspect(Pubkey),
+    List(Vec<String>),
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut url = String::from("http://127.0.0.1:8899");
+        let mut keypair = None;
+        let mut program = None;
+        let mut seed = String::new();
+        let mut dry_run = false;
+        let mut positional = Vec::new();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(USAGE);
+            match arg.as_str() {
+                "--url" => url = value()?,
+                "--keypair" => keypair = Some(value()?),
+                "--program" => program = Some(Pubkey::from_str(&value()?)?),
+                "--seed" => seed = value()?,
+                "--dry-run" => dry_run = true,
+                "--help" | "-h" => return Err(USAGE.into()),
+                _ => positional.push(arg),
+            }
+        }
+        let command = match positional
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            ["write", file] => Command::Write(file.to_string()),
+            ["free"] => Command::Free,
+            ["inspect", address] => {
+                Command::Inspect(Pubkey::from_str(address)?)
+            }
+            ["list", seeds @ ..] => Command::List(
+                seeds.iter().map(|seed| seed.to_string()).collect(),
+            ),
+            _ => return Err(USAGE.into()),
+        };
+        let program = program.ok_or(USAGE)?;
+        Ok(Self { url, keypair, program, seed, dry_run, command })
+    }
+
+    fn run(self) -> Result {
+        let rpc = RpcClient::new(self.url.clone());
+        match &self.command {
+            Command::Write(file) => self.write(&rpc, file),
+            Command::Free => self.free(&rpc),
+            Command::Inspect(address) => inspect(&rpc, address),
+            Command::List(seeds) => self.list(&rpc, seeds),
+        }
+    }
+
+    /// Uploads content of the file into the Write account.
+    fn write(&self, rpc: &RpcClient, file: &str) -> Result {
+        let data = std::fs::read(file)?;
+        let keypair = self.read_keypair()?;
+        let config = &instruction::UploadConfig::DEFAULT;
+        if self.dry_run {
+            let seed = self.seed.as_bytes();
+            let program = &self.program;
+            let txs = instruction::dry_run(
+                rpc, program, &keypair, seed, data, None, config,
+            )?;
+            txs.iter().for_each(|tx| println!("{tx}"));
+            return Ok(());
+        }
+        let (account, bump) = instruction::upload_with_progress(
+            rpc,
+            &self.program,
+            &keypair,
+            self.seed.as_bytes(),
+            data,
+            config,
+            |chunk| {
+                eprint!("\rWritten chunk {}/{}", chunk.index + 1, chunk.total)
+            },
+        )?;
+        eprintln!();
+        println!("{account} (bump {bump})");
+        Ok(())
+    }
+
+    /// Frees the Write account.
+    fn free(&self, rpc: &RpcClient) -> Result {
+        let keypair = self.read_keypair()?;
+        let payer = keypair.pubkey();
+        let seed = self.seed.as_bytes();
+        let (account, bump) =
+            instruction::find_write_account(&self.program, &payer, seed)?;
+        let instruction =
+            instruction::free(self.program, payer, Some(account), seed, bump)?;
+        if self.dry_run {
+            let blockhash = rpc.get_latest_blockhash()?;
+            let batches = [Vec::from([instruction])];
+            let txs =
+                instruction::encode_transactions(&keypair, &batches, blockhash);
+            txs.iter().for_each(|tx| println!("{tx}"));
+            return Ok(());
+        }
+        let tx = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&payer),
+            &[&keypair],
+            rpc.get_latest_blockhash()?,
+        );
+        let signature = rpc.send_and_confirm_transaction(&tx)?;
+        println!("Freed {account} in {signature}");
+        Ok(())
+    }
+
+    /// Lists payer’s Write accounts created with given seeds.
+    ///
+    /// If no seeds are given, uses the `--seed` option.
+    fn list(&self, rpc: &RpcClient, seeds: &[String]) -> Result {
+        let payer = self.read_keypair()?.pubkey();
+        let seeds = match seeds {
+            [] => core::slice::from_ref(&self.seed),
+            seeds => seeds,
+        };
+        let seeds = seeds.iter().map(String::as_bytes);
+        let accounts = instruction::list_write_accounts(
+            rpc,
+            &self.program,
+            &payer,
+            seeds,
+        )?;
+        for account in accounts {
+            println!(
+                "{} seed \"{}\" bump {} {} bytes {} lamports",
+                account.address,
+                account.seed.escape_ascii(),
+                account.bump,
+                account.len,
+                account.lamports
+            );
+        }
+        Ok(())
+    }
+
+    fn read_keypair(&self) -> Result<Keypair> {
+        let path = match &self.keypair {
+            Some(path) => std::path::PathBuf::from(path),
+            None => {
+                let home = std::env::var_os("HOME").ok_or("HOME not set")?;
+                std::path::Path::new(&home).join(".config/solana/id.json")
+            }
+        };
+        solana_keypair::read_keypair_file(path)
+    }
+}
+
+
+/// Prints information about a Write account.
+fn inspect(rpc: &RpcClient, address: &Pubkey) -> Result {
+    let account = rpc.get_account(address)?;
+    println!("Address:  {address}");
+    println!("Owner:    {}", account.owner);
+    println!("Lamports: {}", account.lamports);
+    println!("Size:     {}", account.data.len());
+    describe_header(&account.data).iter().for_each(|line| println!("{line}"));
+    Ok(())
+}
+
+/// Describes header of the Write account data.
+///
+/// Returns lines to print with every field of the header.  Returns no lines
+/// if the data is too short to hold a header.
+fn describe_header(data: &[u8]) -> Vec<String> {
+    const FLAGS: [(u8, &str); 7] = [
+        (V2Header::FLAG_ZSTD, "zstd"),
+        (V2Header::FLAG_OFFSET, "offset"),
+        (V2Header::FLAG_CHAINED, "chained"),
+        (V2Header::FLAG_CRC32, "crc32"),
+        (V2Header::FLAG_TAG, "tag"),
+        (V2Header::FLAG_CONSUMED, "consumed"),
+        (V2Header::FLAG_SEQUENCE, "sequence"),
+    ];
+    let field = |value: Option<u32>| match value {
+        Some(value) => value.to_string(),
+        None => String::from("none"),
+    };
+
+    let Some(word) = data.first_chunk::<4>() else { return Vec::new() };
+    let prefix = u32::from_le_bytes(*word);
+    // Version byte of the versioned header overlaps with the most significant
+    // byte of the legacy length prefix which is zero save for the zstd flag.
+    match (prefix & !ZSTD_FLAG).to_le_bytes()[3] {
+        0 => Vec::from([
+            String::from("Header:   legacy"),
+            format!("Payload:  {} bytes", prefix & !ZSTD_FLAG),
+            format!("Zstd:     {}", prefix & ZSTD_FLAG != 0),
+        ]),
+        V2Header::VERSION => {
+            let Some((header, header_len)) = V2Header::decode(data) else {
+                return Vec::from([String::from(
+                    "Header:   invalid version 2",
+                )]);
+            };
+            let names = FLAGS
+                .iter()
+                .filter(|(flag, _)| header.flags & flag != 0)
+                .map(|(_, name)| *name)
+                .collect::<Vec<_>>();
+            let offset = match header.offset {
+                Some(offset) => offset.to_string(),
+                None => String::from("follows header"),
+            };
+            let crc32 = match header.crc32 {
+                Some(crc32) => format!("{crc32:#010x}"),
+                None => String::from("none"),
+            };
+            Vec::from([
+                format!("Header:   version 2, {header_len} bytes"),
+                format!(
+                    "Flags:    {:#04x} [{}]",
+                    header.flags,
+                    names.join(", ")
+                ),
+                format!("Payload:  {} bytes", header.len),
+                format!("Offset:   {offset}"),
+                format!("CRC-32:   {crc32}"),
+                format!("Tag:      {}", field(header.tag)),
+                format!("Sequence: {}", field(header.sequence)),
+            ])
+        }
+        version => Vec::from([format!("Header:   unknown version {version}")]),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    const PROGRAM: &str = "11111111111111111111111111111112";
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        let args = args.iter().map(|arg| arg.to_string());
+        Args::parse(args).map_err(|err| err.to_string())
+    }
+
+    fn args(command: Command) -> Args {
+        Args {
+            url: String::from("http://127.0.0.1:8899"),
+            keypair: None,
+            program: Pubkey::from_str(PROGRAM).unwrap(),
+            seed: String::new(),
+            dry_run: false,
+            command,
+        }
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        let address = Pubkey::new_from_array([1; 32]);
+        let inspect = address.to_string();
+        for (cmd, want) in [
+            (&["write", "file"][..], Command::Write("file".into())),
+            (&["free"], Command::Free),
+            (&["inspect", &inspect], Command::Inspect(address)),
+            (&["list"], Command::List(Vec::new())),
+            (&["list", "a", "b"], Command::List(vec!["a".into(), "b".into()])),
+        ] {
+            let argv = [&["--program", PROGRAM][..], cmd].concat();
+            assert_eq!(Ok(args(want)), parse(&argv));
+        }
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let argv = [
+            "free",
+            "--url",
+            "http://example.com",
+            "--keypair",
+            "id.json",
+            "--seed",
+            "foo",
+            "--dry-run",
+            "--program",
+            PROGRAM,
+        ];
+        let want = Args {
+            url: "http://example.com".into(),
+            keypair: Some("id.json".into()),
+            seed: "foo".into(),
+            dry_run: true,
+            ..args(Command::Free)
+        };
+        assert_eq!(Ok(want), parse(&argv));
+    }
+
+    #[test]
+    fn test_parse_usage_errors() {
+        let usage = Err(String::from(USAGE));
+        for argv in [
+            &[][..],
+            &["--program", PROGRAM],
+            &["free"],
+            &["--program", PROGRAM, "free", "extra"],
+            &["--program", PROGRAM, "write"],
+            &["--program", PROGRAM, "inspect"],
+            &["--program", PROGRAM, "bogus"],
+            &["--program", PROGRAM, "free", "--seed"],
+            &["--program", PROGRAM, "--help", "free"],
+            &["-h"],
+        ] {
+            assert_eq!(usage, parse(argv), "{argv:?}");
+        }
+        assert!(parse(&["--program", "bogus", "free"]).is_err());
+        assert!(parse(&["--program", PROGRAM, "inspect", "bogus"]).is_err());
+    }
+
+    #[test]
+    fn test_describe_header() {
+        assert_eq!(Vec::<String>::new(), describe_header(b"\x2a\0\0"));
+        assert_eq!(
+            ["Header:   legacy", "Payload:  42 bytes", "Zstd:     true"][..],
+            describe_header(b"\x2a\0\0\x80"),
+        );
+        let header = V2Header {
+            flags: V2Header::FLAG_CHAINED | V2Header::FLAG_CONSUMED,
+            len: 42,
+            offset: Some(100),
+            crc32: Some(0xdead_beef),
+            tag: Some(7),
+            sequence: None,
+        };
+        assert_eq!(
+            [
+                "Header:   version 2, 20 bytes",
+                "Flags:    0x3e [offset, chained, crc32, tag, consumed]",
+                "Payload:  42 bytes",
+                "Offset:   100",
+                "CRC-32:   0xdeadbeef",
+                "Tag:      7",
+                "Sequence: none",
+            ][..],
+            describe_header(&header.to_vec()),
+        );
+        assert_eq!(
+            ["Header:   invalid version 2"][..],
+            describe_header(&[0, 4, 0, 2]),
+        );
+        assert_eq!(
+            ["Header:   unknown version 3"][..],
+            describe_header(&[0, 8, 0, 3]),
+        );
+    }
+}
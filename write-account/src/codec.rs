@@ -0,0 +1,466 @@
+//! Codecs extracting instruction data from data of an account.
+//!
+//! The codecs don’t depend on any SDK and are shared by the
+//! [`mod@crate::entrypoint`] module and the pinocchio entrypoint (see
+//! [`mod@crate::pinocchio`]).
+
+use alloc::vec::Vec;
+
+use crate::header::V2Header;
+
+mod error;
+
+pub use error::WriteAccountError;
+
+pub use crate::header::ZSTD_FLAG;
+
+
+/// Transformation between data stored in an account and instruction data.
+///
+/// When instruction data is read from an account, the data of the account is
+/// passed through a codec which extracts the actual payload.  [`LengthPrefixed`]
+/// is the default codec which handles the format written by
+/// [`WriteIter`](`crate::instruction::WriteIter`).  Programs may implement their
+/// own codecs to perform other transformations such as decompression,
+/// decryption or stripping of envelopes.
+///
+/// Codec is chosen with [`deserialize_with`] and [`deserialize_into_with`]
+/// functions or with `codec` argument of the [`crate::entrypoint!`] and
+/// [`crate::entrypoint_no_alloc!`] macros (or their pinocchio counterparts).
+/// The trait doesn’t depend on any SDK so the same codecs work with both
+/// `solana-program` and pinocchio entrypoints.
+///
+/// [`deserialize_with`]: crate::entrypoint::deserialize_with
+/// [`deserialize_into_with`]: crate::entrypoint::deserialize_into_with
+pub trait PayloadCodec {
+    /// Decodes instruction data from the data of an account.
+    ///
+    /// If the codec needs to allocate a new buffer for the instruction data
+    /// (e.g. when decompressing the payload), it should leak it.  Solana’s
+    /// default bump allocator never frees memory anyway.
+    fn decode(data: &[u8]) -> Result<&[u8], WriteAccountError>;
+
+    /// Returns version of the header of the account data.
+    ///
+    /// Used to describe where the instruction data came from (see
+    /// [`IxDataSource`]).  The default implementation returns `None`.
+    ///
+    /// [`IxDataSource`]: crate::entrypoint::IxDataSource
+    fn header_version(data: &[u8]) -> Option<u8> {
+        let _ = data;
+        None
+    }
+
+    /// Returns flags of the versioned header of the account data.
+    ///
+    /// Used to check whether the payload has been consumed (see
+    /// [`Config::single_use`]).  Returns `None` if the data doesn’t start with
+    /// a [`V2Header`].  The default implementation returns `None`.
+    ///
+    /// [`Config::single_use`]: crate::entrypoint::Config::single_use
+    fn header_flags(data: &[u8]) -> Option<u8> {
+        let _ = data;
+        None
+    }
+
+    /// Decodes instruction data which may continue in other accounts.
+    ///
+    /// `accounts` are the remaining accounts passed to the instruction which
+    /// may hold continuation of the payload.  The default implementation
+    /// ignores the accounts and calls [`Self::decode`].
+    fn decode_chained<'a, A: Continuations + ?Sized>(
+        data: &'a [u8],
+        accounts: &A,
+    ) -> Result<&'a [u8], A::Error> {
+        let _ = accounts;
+        Ok(Self::decode(data)?)
+    }
+}
+
+/// Accounts which may hold continuation of a chained payload.
+///
+/// Abstracts over account types of different SDKs so that
+/// [`PayloadCodec::decode_chained`] doesn’t depend on any of them.
+/// Implementations are responsible for verifying that the continuation
+/// account may be trusted, e.g. the entrypoint checks them against
+/// [`Config::expected_writer`] and [`Config::require_readonly`] just like the
+/// account holding the start of the payload.
+///
+/// [`Config::expected_writer`]: crate::entrypoint::Config::expected_writer
+/// [`Config::require_readonly`]: crate::entrypoint::Config::require_readonly
+pub trait Continuations {
+    /// Error returned when the account cannot be read.
+    type Error: From<WriteAccountError>;
+
+    /// Returns number of the accounts.
+    ///
+    /// Bounds length of the chain so that looping chains are rejected.
+    fn count(&self) -> usize;
+
+    /// Looks up account with given address and passes its data to `f`.
+    ///
+    /// Returns [`WriteAccountError::MissingContinuationAccount`] error if
+    /// there’s no such account.
+    fn with_data<R>(
+        &self,
+        address: &[u8; 32],
+        f: impl FnOnce(&[u8]) -> Result<R, WriteAccountError>,
+    ) -> Result<R, Self::Error>;
+}
+
+/// No continuation accounts.  Used by [`LengthPrefixed::decode`].
+struct NoContinuations;
+
+impl Continuations for NoContinuations {
+    type Error = WriteAccountError;
+
+    fn count(&self) -> usize { 0 }
+
+    fn with_data<R>(
+        &self,
+        _address: &[u8; 32],
+        _f: impl FnOnce(&[u8]) -> Result<R, WriteAccountError>,
+    ) -> Result<R, WriteAccountError> {
+        Err(WriteAccountError::MissingContinuationAccount)
+    }
+}
+
+/// The default codec interpreting account data as length-prefixed payload.
+///
+/// The data of the account is interpreted as length-prefixed sequence of bytes
+/// with length being an unsigned 32-bit integer using little endian encoding.
+/// Any bytes following the payload are ignored.  If [`ZSTD_FLAG`] is set in the
+/// length prefix, the payload is decompressed.
+///
+/// Alternatively, the data may start with a versioned header (see
+/// [`crate::header`]) rather than the bare length prefix.  If the header has
+/// [`V2Header::FLAG_CHAINED`] set, the payload is stitched together from the
+/// chain of continuation accounts which must be passed to the instruction.
+/// The stitched payload is copied into a heap buffer so its length is limited
+/// by the program’s heap size (at most 256 KiB).  If the chain is longer
+/// than the number of accounts (e.g. because it loops) or an account is
+/// missing, [`WriteAccountError::MissingContinuationAccount`] error is
+/// returned.  Continuation accounts are looked up and checked by
+/// [`Continuations`] implementation passed to [`PayloadCodec::decode_chained`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LengthPrefixed;
+
+impl PayloadCodec for LengthPrefixed {
+    fn decode(data: &[u8]) -> Result<&[u8], WriteAccountError> {
+        Self::decode_chained(data, &NoContinuations)
+    }
+
+    /// Returns `1` for the legacy length prefix and version number stored in
+    /// the header otherwise.
+    fn header_version(data: &[u8]) -> Option<u8> {
+        match data.get(3)? & !0x80 {
+            0 => Some(1),
+            version => Some(version),
+        }
+    }
+
+    fn header_flags(data: &[u8]) -> Option<u8> {
+        match *data.first_chunk::<4>()? {
+            [flags, _, _, V2Header::VERSION] => Some(flags),
+            _ => None,
+        }
+    }
+
+    fn decode_chained<'a, A: Continuations + ?Sized>(
+        data: &'a [u8],
+        accounts: &A,
+    ) -> Result<&'a [u8], A::Error> {
+        decode_prefixed(split_prefixed(data)?, accounts)
+    }
+}
+
+/// Codec verifying SHA-256 hash of the length-prefixed payload.
+///
+/// The data of the account starts with a 32-byte SHA-256 hash of the payload
+/// followed by length-prefixed payload as handled by [`LengthPrefixed`].  The
+/// hash is calculated over the payload as stored in the account (i.e. before
+/// decompression if [`ZSTD_FLAG`] is set).  If the hash doesn’t match, the
+/// data is rejected with [`WriteAccountError::HashMismatch`] error.  This detects
+/// tampered or partially written accounts before the program processes them.
+///
+/// Such data is written by
+/// [`WriteIter::new_sha256`](`crate::instruction::WriteIter::new_sha256`).
+/// Hashing costs compute units proportional to the payload length.
+#[cfg(feature = "sha256")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Sha256Checked;
+
+#[cfg(feature = "sha256")]
+impl PayloadCodec for Sha256Checked {
+    fn decode(data: &[u8]) -> Result<&[u8], WriteAccountError> {
+        let (hash, data) = data
+            .split_at_checked(solana_hash::HASH_BYTES)
+            .ok_or(WriteAccountError::TooShort)?;
+        let Prefixed { payload, compressed, next, .. } = split_prefixed(data)?;
+        if solana_sha256_hasher::hash(payload).as_ref() != hash {
+            Err(WriteAccountError::HashMismatch)
+        } else if next.is_some() {
+            Err(WriteAccountError::MissingContinuationAccount)
+        } else if compressed {
+            decompress(payload)
+        } else {
+            Ok(payload)
+        }
+    }
+
+    fn header_version(data: &[u8]) -> Option<u8> {
+        LengthPrefixed::header_version(data.get(solana_hash::HASH_BYTES..)?)
+    }
+
+    fn header_flags(data: &[u8]) -> Option<u8> {
+        LengthPrefixed::header_flags(data.get(solana_hash::HASH_BYTES..)?)
+    }
+}
+
+/// Codec reading data of a BPF upgradeable loader buffer account.
+///
+/// The account data starts with the loader’s buffer metadata (the
+/// `UpgradeableLoaderState::Buffer` tag and the buffer authority) which is
+/// followed by the payload.  The payload spans the rest of the account and
+/// isn’t length-prefixed.  This allows feeding the program with data written
+/// with `solana program write-buffer` and other existing buffer tooling.
+///
+/// If the account isn’t a buffer, the data is rejected with
+/// [`WriteAccountError::UnsupportedHeader`] error.  Note that the codec doesn’t
+/// verify owner of the account.  Use [`Config::expected_writer`] set to the
+/// upgradeable loader’s id to make sure the data comes from a genuine buffer.
+///
+/// [`Config::expected_writer`]: crate::entrypoint::Config::expected_writer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LoaderBuffer;
+
+impl LoaderBuffer {
+    /// Tag of the `UpgradeableLoaderState::Buffer` variant.
+    const TAG: u32 = 1;
+
+    /// Length of the buffer metadata preceding the payload.
+    pub const METADATA_LEN: usize = 37;
+}
+
+impl PayloadCodec for LoaderBuffer {
+    fn decode(data: &[u8]) -> Result<&[u8], WriteAccountError> {
+        let (metadata, payload) = data
+            .split_at_checked(Self::METADATA_LEN)
+            .ok_or(WriteAccountError::TooShort)?;
+        if metadata[..4] != Self::TAG.to_le_bytes() {
+            return Err(WriteAccountError::UnsupportedHeader);
+        }
+        Ok(payload)
+    }
+}
+
+/// Codec interpreting account data as the instruction data as is.
+///
+/// Matches data written with
+/// [`WriteIter::new_raw`](`crate::instruction::WriteIter::new_raw`) or by
+/// other tooling which doesn’t length-prefix the payload.  By default, the
+/// entire account data is the instruction data.  If `LEN` is given, only the
+/// first `LEN` bytes are and [`WriteAccountError::TooShort`] error is returned
+/// if the account is shorter.  For example, `codec = Raw<64>` argument of the
+/// entrypoint macros makes the program read 64-byte instructions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Raw<const LEN: usize = { usize::MAX }>;
+
+impl<const LEN: usize> PayloadCodec for Raw<LEN> {
+    fn decode(data: &[u8]) -> Result<&[u8], WriteAccountError> {
+        if LEN == usize::MAX {
+            Ok(data)
+        } else {
+            data.get(..LEN).ok_or(WriteAccountError::TooShort)
+        }
+    }
+}
+
+/// Payload split from account data by [`split_prefixed`].
+#[derive(Clone, Copy)]
+pub(crate) struct Prefixed<'a> {
+    /// The payload as stored in the account.
+    pub payload: &'a [u8],
+
+    /// Whether the payload is compressed.
+    pub compressed: bool,
+
+    /// Address of the continuation account if the payload is chained.
+    pub next: Option<[u8; 32]>,
+
+    /// Bytes following the payload (and the continuation address if any).
+    #[cfg_attr(not(feature = "lib"), allow(dead_code))]
+    pub trailing: &'a [u8],
+}
+
+/// Splits length-prefixed payload from account data.
+///
+/// Handles both the legacy length prefix and the versioned header (see
+/// [`crate::header`]).
+pub(crate) fn split_prefixed(
+    data: &[u8],
+) -> Result<Prefixed<'_>, WriteAccountError> {
+    let (word, rest) =
+        data.split_first_chunk().ok_or(WriteAccountError::TooShort)?;
+    let (len, flags, payload, crc32) = match word[3] & !0x80 {
+        0 => {
+            let len = u32::from_le_bytes(*word);
+            let flags =
+                if len & ZSTD_FLAG != 0 { V2Header::FLAG_ZSTD } else { 0 };
+            (len & !ZSTD_FLAG, flags, rest, None)
+        }
+        V2Header::VERSION => {
+            let [flags, header_len, reserved, _] = *word;
+            let header_len = usize::from(header_len);
+            if word[3] != V2Header::VERSION ||
+                reserved != 0 ||
+                flags & !V2Header::KNOWN_FLAGS != 0 ||
+                header_len < V2Header::LEN
+            {
+                return Err(WriteAccountError::UnsupportedHeader);
+            }
+            let header =
+                data.get(..header_len).ok_or(WriteAccountError::TooShort)?;
+            let read_u32 = |offset: usize| {
+                let bytes = header.get(offset..offset + 4)?;
+                Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+            };
+            // Optional fields follow in the order of their flags.
+            let mut field = V2Header::LEN;
+            let mut read_field = |flag: u8| {
+                if flags & flag == 0 {
+                    return Ok(None);
+                }
+                let value = read_u32(field)
+                    .ok_or(WriteAccountError::UnsupportedHeader)?;
+                field += 4;
+                Ok::<_, WriteAccountError>(Some(value))
+            };
+            let len = read_u32(4).unwrap();
+            let offset = match read_field(V2Header::FLAG_OFFSET)? {
+                None => header_len,
+                Some(offset) => offset
+                    .try_into()
+                    .map_err(|_| WriteAccountError::LengthOutOfBounds)?,
+            };
+            let crc32 = read_field(V2Header::FLAG_CRC32)?;
+            let payload = data
+                .get(offset..)
+                .ok_or(WriteAccountError::LengthOutOfBounds)?;
+            (len, flags, payload, crc32)
+        }
+        _ => return Err(WriteAccountError::UnsupportedHeader),
+    };
+    let (payload, rest) = usize::try_from(len)
+        .ok()
+        .and_then(|len| payload.split_at_checked(len))
+        .ok_or(WriteAccountError::LengthOutOfBounds)?;
+    if crc32.is_some_and(|crc32| crate::header::crc32(payload) != crc32) {
+        return Err(WriteAccountError::ChecksumMismatch);
+    }
+    let (next, trailing) = if flags & V2Header::FLAG_CHAINED != 0 {
+        let (key, rest) = rest
+            .split_first_chunk()
+            .ok_or(WriteAccountError::LengthOutOfBounds)?;
+        (Some(*key), rest)
+    } else {
+        (None, rest)
+    };
+    let compressed = flags & V2Header::FLAG_ZSTD != 0;
+    Ok(Prefixed { payload, compressed, next, trailing })
+}
+
+/// Decodes payload split from account data by [`split_prefixed`].
+///
+/// Follows the chain of continuation accounts and decompresses the payload as
+/// necessary.
+pub(crate) fn decode_prefixed<'a, A: Continuations + ?Sized>(
+    mut prefixed: Prefixed<'a>,
+    accounts: &A,
+) -> Result<&'a [u8], A::Error> {
+    if prefixed.next.is_some() {
+        prefixed.payload = stitch(prefixed, accounts)?;
+    }
+    if prefixed.compressed {
+        Ok(decompress(prefixed.payload)?)
+    } else {
+        Ok(prefixed.payload)
+    }
+}
+
+/// Concatenates chained payload with its continuations.
+///
+/// Continuation accounts are looked up in `accounts`.  Whether the payload is
+/// compressed is determined by the first account; continuations must not be
+/// flagged as compressed.  The buffer is leaked so that it lives as long as
+/// the rest of the input.
+fn stitch<A: Continuations + ?Sized>(
+    first: Prefixed,
+    accounts: &A,
+) -> Result<&'static [u8], A::Error> {
+    // Walk the chain twice to allocate the buffer upfront.  Growing it would
+    // leave discarded buffers behind since Solana’s default bump allocator
+    // never frees memory so heap usage would exceed length of the payload.
+    let mut len = first.payload.len();
+    for_each_continuation(first.next, accounts, |payload| {
+        len += payload.len()
+    })?;
+    let mut buf = Vec::with_capacity(len);
+    buf.extend_from_slice(first.payload);
+    for_each_continuation(first.next, accounts, |payload| {
+        buf.extend_from_slice(payload)
+    })?;
+    Ok(buf.leak())
+}
+
+/// Calls `f` with payloads of continuations of a chained payload in order.
+///
+/// `next` is the address of the first continuation account.
+fn for_each_continuation<A: Continuations + ?Sized>(
+    mut next: Option<[u8; 32]>,
+    accounts: &A,
+    mut f: impl FnMut(&[u8]),
+) -> Result<(), A::Error> {
+    let mut hops = 0;
+    while let Some(address) = next {
+        hops += 1;
+        if hops > accounts.count() {
+            return Err(WriteAccountError::MissingContinuationAccount.into());
+        }
+        next = accounts.with_data(&address, |data| {
+            let segment = split_prefixed(data)?;
+            if segment.compressed {
+                return Err(WriteAccountError::UnsupportedHeader);
+            }
+            f(segment.payload);
+            Ok(segment.next)
+        })?;
+    }
+    Ok(())
+}
+
+
+/// Decompresses zstd-compressed payload into a heap buffer.
+///
+/// The buffer is leaked so that it lives as long as the rest of the input.
+/// This has no real cost on-chain since Solana’s default bump allocator never
+/// frees memory anyway.
+#[cfg(feature = "zstd")]
+fn decompress(mut data: &[u8]) -> Result<&'static [u8], WriteAccountError> {
+    use ruzstd::decoding::{BlockDecodingStrategy, FrameDecoder};
+
+    let mut decoder = FrameDecoder::new();
+    let decoded = decoder.init(&mut data).and_then(|()| {
+        decoder.decode_blocks(&mut data, BlockDecodingStrategy::All)
+    });
+    match decoded {
+        Ok(true) => Ok(decoder.collect().unwrap_or_default().leak()),
+        _ => Err(WriteAccountError::DecompressionFailed),
+    }
+}
+
+/// Rejects compressed payload since `zstd` feature is disabled.
+#[cfg(not(feature = "zstd"))]
+fn decompress(_data: &[u8]) -> Result<&'static [u8], WriteAccountError> {
+    Err(WriteAccountError::CompressionUnsupported)
+}
@@ -0,0 +1,114 @@
+//! Errors reported when resolving instruction data.
+
+/// Error resolving instruction data of a call.
+///
+/// Each variant converts into a distinct `ProgramError::Custom` code so that
+/// failures can be diagnosed from transaction logs.  The codes start at
+/// [`Self::BASE`] to make collisions with the program’s own custom errors
+/// unlikely.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum WriteAccountError {
+    /// Instruction data is to be read from an account but no account was
+    /// passed to the instruction.
+    MissingDataAccount = Self::BASE,
+
+    /// Account data is too short to hold the header.
+    TooShort,
+
+    /// Length prefix of the payload exceeds the account data.
+    LengthOutOfBounds,
+
+    /// Payload is compressed but the crate was built without `zstd` feature.
+    CompressionUnsupported,
+
+    /// Compressed payload could not be decompressed.
+    DecompressionFailed,
+
+    /// Hash of the payload doesn’t match hash stored in the account.
+    HashMismatch,
+
+    /// Account holding the instruction data is a duplicate of another account
+    /// passed to the instruction.
+    DuplicateDataAccount,
+
+    /// Account holding the instruction data isn’t owned by the expected writer
+    /// program.
+    WrongOwner,
+
+    /// More accounts were passed to the instruction than the entrypoint
+    /// supports.
+    TooManyAccounts,
+
+    /// Payload could not be deserialised into the expected type.
+    InvalidPayload,
+
+    /// Account data uses unsupported header version or flags.
+    UnsupportedHeader,
+
+    /// Payload continues in an account which wasn’t passed to the instruction
+    /// or the chain of continuation accounts is too long.
+    MissingContinuationAccount,
+
+    /// Account holding the instruction data is writable while the program
+    /// requires it to be read-only.
+    WritableDataAccount,
+
+    /// CRC-32 of the payload doesn’t match checksum stored in the header.
+    ChecksumMismatch,
+
+    /// Current top-level instruction doesn’t call the expected program with
+    /// the account holding the instruction data or the instructions sysvar
+    /// wasn’t passed to the instruction.
+    UnexpectedCaller,
+
+    /// Payload has already been consumed, i.e. [`V2Header::FLAG_CONSUMED`] is
+    /// set in its header.
+    ///
+    /// [`V2Header::FLAG_CONSUMED`]: crate::header::V2Header::FLAG_CONSUMED
+    PayloadConsumed,
+
+    /// Sequence number of the payload doesn’t match the one given in the
+    /// inline instruction data.
+    SequenceMismatch,
+
+    /// Payload is longer than the program accepts.
+    PayloadTooLong,
+}
+
+impl WriteAccountError {
+    /// Custom error code of the first variant.
+    pub const BASE: u32 = 0x5741_0000;
+}
+
+#[cfg(feature = "lib")]
+impl From<WriteAccountError> for crate::sdk::program_error::ProgramError {
+    fn from(err: WriteAccountError) -> Self { Self::Custom(err as u32) }
+}
+
+impl core::fmt::Display for WriteAccountError {
+    fn fmt(&self, fmtr: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        fmtr.write_str(match self {
+            Self::MissingDataAccount => "missing instruction data account",
+            Self::TooShort => "account data too short",
+            Self::LengthOutOfBounds => "payload length out of bounds",
+            Self::CompressionUnsupported => "compressed payload not supported",
+            Self::DecompressionFailed => "failed decompressing payload",
+            Self::HashMismatch => "payload hash mismatch",
+            Self::DuplicateDataAccount => "duplicate instruction data account",
+            Self::WrongOwner => "instruction data account has wrong owner",
+            Self::TooManyAccounts => "too many accounts",
+            Self::InvalidPayload => "invalid payload",
+            Self::UnsupportedHeader => "unsupported account data header",
+            Self::MissingContinuationAccount => {
+                "missing payload continuation account"
+            }
+            Self::WritableDataAccount => "instruction data account is writable",
+            Self::ChecksumMismatch => "payload checksum mismatch",
+            Self::UnexpectedCaller => "unexpected top-level caller",
+            Self::PayloadConsumed => "payload already consumed",
+            Self::SequenceMismatch => "payload sequence number mismatch",
+            Self::PayloadTooLong => "payload too long",
+        })
+    }
+}
@@ -1,9 +1,224 @@
 //! Functions for the smart contract to allow parsing the serialised program
 //! arguments and read instruction data from an account.
 
-use solana_program::account_info::AccountInfo;
-use solana_program::program_error::ProgramError;
-use solana_program::pubkey::Pubkey;
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+
+#[cfg(feature = "sha256")]
+pub use crate::codec::Sha256Checked;
+use crate::codec::{decode_prefixed, split_prefixed};
+pub use crate::codec::{
+    Continuations, LengthPrefixed, LoaderBuffer, PayloadCodec, Raw,
+    WriteAccountError, ZSTD_FLAG,
+};
+use crate::header::V2Header;
+use crate::sdk::account_info::AccountInfo;
+use crate::sdk::program_error::ProgramError;
+use crate::sdk::pubkey::Pubkey;
+
+mod data_ref;
+mod lazy;
+
+pub use data_ref::{deserialize_ref, AccountDataRef};
+pub use lazy::{
+    deserialize_fast, InstructionContext, LazyAccount, MaybeAccount,
+};
+
+/// Configuration of how the instruction data is resolved.
+///
+/// Used with [`deserialize_with_config`], [`deserialize_into_with_config`]
+/// and [`crate::entrypoint_with_config!`] macro.  The configuration is meant
+/// to be a constant such that the compiler can eliminate unused code paths.
+///
+/// # Example
+///
+/// ```
+/// use solana_write_account::entrypoint::Config;
+///
+/// const CONFIG: Config = Config {
+///     mixed: true,
+///     max_accounts: 8,
+///     ..Config::DEFAULT
+/// };
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Whether to always read the payload from the last account using the
+    /// inline instruction data as a prefix.  See [`deserialize_mixed`].
+    pub mixed: bool,
+
+    /// Program which must own the account holding the instruction data.
+    ///
+    /// If the account is owned by a different program,
+    /// [`WriteAccountError::WrongOwner`] error is returned.
+    pub expected_writer: Option<Pubkey>,
+
+    /// Whether to keep the account holding the instruction data in the
+    /// accounts passed to the program.
+    ///
+    /// If set, the data of the account stays immutably borrowed for the rest
+    /// of the program’s execution.  See [`deserialize_keep`].
+    pub retain_account: bool,
+
+    /// Maximum number of accounts the program accepts.
+    ///
+    /// Used by [`crate::entrypoint_with_config!`].  If non-zero, the accounts
+    /// are stored in an array of that size on stack rather than in a heap
+    /// allocated vector.  See [`crate::entrypoint_no_alloc!`].
+    pub max_accounts: usize,
+
+    /// Byte which, if it starts the inline instruction data, makes the
+    /// entrypoint read instruction data from the last account.
+    ///
+    /// This is for callers which cannot send empty inline instruction data.
+    /// The inline instruction data is ignored (even in mixed mode).
+    pub marker_byte: Option<u8>,
+
+    /// Account which, if passed to the instruction, makes the entrypoint read
+    /// instruction data from the last account.
+    ///
+    /// Just like with [`Self::marker_byte`], the inline instruction data is
+    /// ignored.  The marker account is passed to the program together with
+    /// the other accounts.
+    pub marker_account: Option<Pubkey>,
+
+    /// Byte which marks inline instruction data selecting the account holding
+    /// the payload by index.
+    ///
+    /// If set and the inline instruction data is exactly two bytes
+    /// `[index_magic, idx]`, the payload is read from the account at index
+    /// `idx` rather than from the last account.  This is for programs whose
+    /// interface requires the account in a fixed slot.  The account is left in
+    /// place (regardless of [`Self::retain_account`]) and its data stays
+    /// immutably borrowed for the rest of the program’s execution.
+    pub index_magic: Option<u8>,
+
+    /// Byte which marks inline instruction data selecting the account holding
+    /// the payload by a tag.
+    ///
+    /// If set and the inline instruction data is exactly five bytes
+    /// `[tag_magic, tag…]` (with the tag being a little-endian `u32`), the
+    /// payload is read from the first account whose data starts with
+    /// a [`V2Header`] with [`V2Header::tag`] equal to the tag.  This allows
+    /// a transaction to carry several pre-staged alternative payloads and
+    /// select one at execution time.  Just like with [`Self::index_magic`],
+    /// the account is left in place.  If no account has the tag,
+    /// [`WriteAccountError::MissingDataAccount`] error is returned.
+    pub tag_magic: Option<u8>,
+
+    /// Whether to reject the account holding the instruction data if it’s
+    /// writable.
+    ///
+    /// If set and the account is passed as writable,
+    /// [`WriteAccountError::WritableDataAccount`] error is returned.  This
+    /// guarantees that the payload cannot change while the instruction
+    /// executes (e.g. through a cross-program invocation).
+    pub require_readonly: bool,
+
+    /// Program which must have invoked the transaction’s current top-level
+    /// instruction with the account holding the instruction data.
+    ///
+    /// If set, the instructions sysvar (see [`INSTRUCTIONS_SYSVAR_ID`]) must
+    /// be passed to the instruction and the current top-level instruction
+    /// recorded in it must call the given program and list the account holding the
+    /// instruction data among its accounts.  Otherwise,
+    /// [`WriteAccountError::UnexpectedCaller`] error is returned.  This
+    /// hardens relayed flows where the program is reached through
+    /// a cross-program invocation and can’t otherwise tell whether the account
+    /// was supplied by the expected caller.
+    pub expected_caller: Option<Pubkey>,
+
+    /// Whether to reject payloads which have already been consumed.
+    ///
+    /// If set, the account data must start with a [`V2Header`] with
+    /// [`V2Header::FLAG_CONSUMED`] unset.  Otherwise,
+    /// [`WriteAccountError::PayloadConsumed`] error is returned (or
+    /// [`WriteAccountError::UnsupportedHeader`] if the data doesn’t use the
+    /// versioned header).  The entrypoint doesn’t set the flag itself since
+    /// the account is owned by the write-account program.  Instead, the owner
+    /// of the buffer is expected to set it afterwards (e.g. in the same
+    /// transaction with
+    /// [`mark_consumed`](`crate::instruction::mark_consumed`)) which prevents
+    /// the same staged payload from being replayed into the program.
+    pub single_use: bool,
+
+    /// Byte which marks inline instruction data carrying the expected sequence
+    /// number of the payload.
+    ///
+    /// If set and the inline instruction data is exactly five bytes
+    /// `[sequence_magic, seq…]` (with the sequence number being
+    /// a little-endian `u32`), the payload is read from the last account whose
+    /// data must start with a [`V2Header`] with [`V2Header::sequence`] equal
+    /// to the sequence number.  Otherwise,
+    /// [`WriteAccountError::SequenceMismatch`] error is returned.  This lets
+    /// callers staging successive revisions of a payload in the same account
+    /// guarantee that the program executes the intended revision rather than
+    /// a stale one.  Just like with [`Self::marker_byte`], the inline
+    /// instruction data is ignored.
+    pub sequence_magic: Option<u8>,
+
+    /// Maximum length of instruction data read from an account.
+    ///
+    /// If set and the payload (after decoding, i.e. decompressing and
+    /// stitching continuation accounts) is longer,
+    /// [`WriteAccountError::PayloadTooLong`] error is returned before the
+    /// program gets to process it.  This prevents a compromised relayer from
+    /// forcing the program to process pathologically large inputs.
+    pub max_len: Option<usize>,
+
+    /// Whether to zero the account holding the payload once the instruction
+    /// has been processed.
+    ///
+    /// If set and the program returns successfully, the entrypoint overwrites
+    /// the entire data of the account the payload was read from with zeros so
+    /// that the payload doesn’t linger on chain after it’s been used.  Zeroed
+    /// account decodes as an empty payload.  The account must be owned by the
+    /// program and writable or the runtime will fail the transaction.  In
+    /// particular, this cannot be used with buffers owned by the write-account
+    /// program.
+    ///
+    /// Only the entrypoint macros (e.g. [`crate::entrypoint_with_config!`])
+    /// honour this option.  It has no effect when the payload is read from an
+    /// account which stays borrowed, i.e. with [`Self::retain_account`],
+    /// [`Self::index_magic`] or [`Self::tag_magic`].
+    pub zeroize: bool,
+
+    /// Whether to accept empty instructions with no data account.
+    ///
+    /// If set and the inline instruction data is empty, the program is called
+    /// with empty instruction data (rather than failing with
+    /// [`WriteAccountError::MissingDataAccount`] error) when no data account
+    /// is present.  The last account is considered a data account if it
+    /// exists and, if [`Self::expected_writer`] is set, is owned by the
+    /// expected writer.  This lets programs which treat empty instructions as
+    /// valid (e.g. as a no-op) keep doing so.
+    pub allow_empty: bool,
+}
+
+impl Config {
+    /// The default configuration matching behaviour of [`deserialize`].
+    pub const DEFAULT: Self = Self {
+        mixed: false,
+        expected_writer: None,
+        retain_account: false,
+        max_accounts: 0,
+        marker_byte: None,
+        marker_account: None,
+        index_magic: None,
+        tag_magic: None,
+        require_readonly: false,
+        expected_caller: None,
+        single_use: false,
+        sequence_magic: None,
+        max_len: None,
+        zeroize: false,
+        allow_empty: false,
+    };
+}
+
+impl Default for Config {
+    fn default() -> Self { Self::DEFAULT }
+}
 
 
 /// Deserialize the input arguments.
@@ -12,10 +227,9 @@ use solana_program::pubkey::Pubkey;
 /// handling of empty instruction data.
 ///
 /// If the instruction data is empty, the instruction data is read from the last
-/// account passed to the instruction.  The data of the account is interpreted
-/// as length-prefixed sequence of bytes with length being an unsigned 32-bit
-/// integer using little endian encoding.  The account used to read the account
-/// data is not returned with the rest of the accounts.
+/// account passed to the instruction.  The data of the account is decoded with
+/// the [`LengthPrefixed`] codec.  The account used to read the account data is
+/// not returned with the rest of the accounts.
 ///
 /// # Safety
 ///
@@ -24,19 +238,165 @@ use solana_program::pubkey::Pubkey;
 pub unsafe fn deserialize<'a>(
     input: *mut u8,
 ) -> Result<(&'a Pubkey, Vec<AccountInfo<'a>>, &'a [u8]), ProgramError> {
+    // SAFETY: Caller promises this is safe.
+    unsafe { deserialize_with::<LengthPrefixed>(input) }
+}
+
+/// Deserialize the input arguments using given codec.
+///
+/// Behaves like [`deserialize`] except that data of the account is decoded with
+/// codec `C` rather than [`LengthPrefixed`].
+///
+/// # Safety
+///
+/// Must be called with pointer to properly serialised instruction such as done
+/// by the Solana runtime.  See [`solana_program::entrypoint::deserialize`].
+pub unsafe fn deserialize_with<'a, C: PayloadCodec>(
+    input: *mut u8,
+) -> Result<(&'a Pubkey, Vec<AccountInfo<'a>>, &'a [u8]), ProgramError> {
+    // SAFETY: Caller promises this is safe.
+    unsafe { deserialize_with_config::<C>(input, &Config::DEFAULT) }
+}
+
+/// Deserialize the input arguments treating instruction data as a prefix.
+///
+/// Behaves like [`deserialize_with`] except that the payload is always read
+/// from the last account and the inline instruction data (which may be empty)
+/// is prepended to it.  This lets callers keep a small header (such as
+/// a discriminator) in the transaction while the bulk of the data is read from
+/// an account.  See [`WriteIter::new_mixed`](`crate::instruction::WriteIter::new_mixed`).
+///
+/// Note that if the inline instruction data is not empty, the concatenated data
+/// is stored in a newly allocated (and leaked) buffer.
+///
+/// # Safety
+///
+/// Must be called with pointer to properly serialised instruction such as done
+/// by the Solana runtime.  See [`solana_program::entrypoint::deserialize`].
+pub unsafe fn deserialize_mixed<'a, C: PayloadCodec>(
+    input: *mut u8,
+) -> Result<(&'a Pubkey, Vec<AccountInfo<'a>>, &'a [u8]), ProgramError> {
+    let config = Config { mixed: true, ..Config::DEFAULT };
+    // SAFETY: Caller promises this is safe.
+    unsafe { deserialize_with_config::<C>(input, &config) }
+}
+
+/// Deserialize the input arguments using given configuration.
+///
+/// Behaves like [`deserialize_with`] with behaviour adjusted according to
+/// `config`.  [`Config::max_accounts`] is ignored.
+///
+/// # Safety
+///
+/// Must be called with pointer to properly serialised instruction such as done
+/// by the Solana runtime.  See [`solana_program::entrypoint::deserialize`].
+#[inline(always)]
+pub unsafe fn deserialize_with_config<'a, C: PayloadCodec>(
+    input: *mut u8,
+    config: &Config,
+) -> Result<(&'a Pubkey, Vec<AccountInfo<'a>>, &'a [u8]), ProgramError> {
+    // SAFETY: Caller promises this is safe.
+    unsafe { deserialize_with_source::<C>(input, config) }
+        .map(|(program_id, accounts, data, _)| (program_id, accounts, data))
+}
+
+/// Deserialize the input arguments reporting where instruction data came from.
+///
+/// Behaves like [`deserialize_with_config`] except that it additionally returns
+/// [`IxDataSource`] describing the source of the instruction data.
+///
+/// # Safety
+///
+/// Must be called with pointer to properly serialised instruction such as done
+/// by the Solana runtime.  See [`solana_program::entrypoint::deserialize`].
+#[inline(always)]
+#[allow(clippy::type_complexity)]
+pub unsafe fn deserialize_with_source<'a, C: PayloadCodec>(
+    input: *mut u8,
+    config: &Config,
+) -> Result<
+    (&'a Pubkey, Vec<AccountInfo<'a>>, &'a [u8], IxDataSource<'a>),
+    ProgramError,
+> {
+    // SAFETY: Caller promises this is safe.
+    unsafe { deserialize_impl::<C>(input, config) }.map(
+        |(program_id, accounts, data, source, _)| {
+            (program_id, accounts, data, source)
+        },
+    )
+}
+
+/// Implementation of [`deserialize_with_source`].
+///
+/// Additionally returns the data of the account the payload was taken from if
+/// [`Config::zeroize`] is set.
+///
+/// # Safety
+///
+/// Must be called with pointer to properly serialised instruction such as done
+/// by the Solana runtime.  See [`solana_program::entrypoint::deserialize`].
+#[inline(always)]
+#[allow(clippy::type_complexity)]
+unsafe fn deserialize_impl<'a, C: PayloadCodec>(
+    input: *mut u8,
+    config: &Config,
+) -> Result<
+    (
+        &'a Pubkey,
+        Vec<AccountInfo<'a>>,
+        &'a [u8],
+        IxDataSource<'a>,
+        Option<*mut [u8]>,
+    ),
+    ProgramError,
+> {
     // SAFETY: Caller promises this is safe.
     let (program_id, mut accounts, mut instruction_data) =
-        unsafe { solana_program::entrypoint::deserialize(input) };
+        unsafe { crate::sdk::entrypoint::deserialize(input) };
 
-    // If instruction data is empty, the actual instruction data comes from the
-    // last account passed in the call.
-    if instruction_data.is_empty() {
+    if let Some(index) = account_index(config, instruction_data, &accounts)? {
         let ix_acc =
-            accounts.pop().ok_or(ProgramError::NotEnoughAccountKeys)?;
-        instruction_data = get_ix_data(ix_acc)?;
+            accounts.get(index).ok_or(WriteAccountError::MissingDataAccount)?;
+        check_account::<C>(ix_acc, &accounts, config, None)?;
+        let source = (ix_acc.key, header_version::<C>(ix_acc));
+        let instruction_data = borrow_ix_data::<C>(ix_acc, &accounts, config)?;
+        check_len(config, instruction_data)?;
+        let source = IxDataSource::new(Some(source), instruction_data);
+        return Ok((program_id, accounts, instruction_data, source, None));
+    }
+
+    let empty = is_empty_ix(config, instruction_data, &accounts);
+    let sequence = expected_sequence(config, instruction_data);
+    if sequence.is_some() || is_marked(config, instruction_data, &accounts) {
+        instruction_data = &[];
     }
 
-    Ok((program_id, accounts, instruction_data))
+    // If instruction data is empty (or we’re in mixed mode), the actual
+    // instruction data comes from the last account passed in the call.
+    let mut source = None;
+    let mut wipe = None;
+    if !empty && (config.mixed || instruction_data.is_empty()) {
+        let payload = if config.retain_account {
+            let ix_acc =
+                accounts.last().ok_or(WriteAccountError::MissingDataAccount)?;
+            check_account::<C>(ix_acc, &accounts, config, sequence)?;
+            source = Some((ix_acc.key, header_version::<C>(ix_acc)));
+            borrow_ix_data::<C>(ix_acc, &accounts, config)?
+        } else {
+            let ix_acc =
+                accounts.pop().ok_or(WriteAccountError::MissingDataAccount)?;
+            check_account::<C>(&ix_acc, &accounts, config, sequence)?;
+            source = Some((ix_acc.key, header_version::<C>(&ix_acc)));
+            let (payload, data) = take_ix_data::<C>(ix_acc, &accounts, config)?;
+            wipe = Some(data).filter(|_| config.zeroize);
+            payload
+        };
+        check_len(config, payload)?;
+        instruction_data = concat(instruction_data, payload);
+    }
+    let source = IxDataSource::new(source, instruction_data);
+
+    Ok((program_id, accounts, instruction_data, source, wipe))
 }
 
 /// Deserialize the input arguments.
@@ -46,7 +406,8 @@ pub unsafe fn deserialize<'a>(
 /// writing the account infos into an uninitialised slice rather than allocating
 /// a new vector.
 ///
-/// Panics if the input slice is not large enough.
+/// Returns [`WriteAccountError::TooManyAccounts`] error if the input slice is
+/// not large enough.
 ///
 /// # Safety
 ///
@@ -57,378 +418,3142 @@ pub unsafe fn deserialize_into<'a>(
     accounts: &mut [core::mem::MaybeUninit<AccountInfo<'a>>],
 ) -> Result<(&'a Pubkey, usize, &'a [u8]), ProgramError> {
     // SAFETY: Caller promises this is safe.
-    let (program_id, mut count, mut instruction_data) = unsafe {
-        solana_program::entrypoint::deserialize_into(input, accounts)
-    };
-
-    // If instruction data is empty, the actual instruction data comes from the
-    // last account passed in the call.
-    if instruction_data.is_empty() {
-        count =
-            count.checked_sub(1).ok_or(ProgramError::NotEnoughAccountKeys)?;
-        // SAFETY: `deserialize_into` initialised the element.
-        let ix_acc = unsafe { accounts[count].assume_init_read() };
-        instruction_data = get_ix_data(ix_acc)?;
-    }
-
-    Ok((program_id, count, instruction_data))
+    unsafe { deserialize_into_with::<LengthPrefixed>(input, accounts) }
 }
 
-
-/// Interprets data in the last account as instruction data.
-fn get_ix_data<'a>(account: AccountInfo<'a>) -> Result<&'a [u8], ProgramError> {
-    let data = std::rc::Rc::try_unwrap(account.data);
-    let data = data.ok().unwrap().into_inner();
-    if data.len() < 4 {
-        return Err(ProgramError::InvalidInstructionData);
+/// Deserialize the input arguments using given codec.
+///
+/// Behaves like [`deserialize_into`] except that data of the account is decoded
+/// with codec `C` rather than [`LengthPrefixed`].
+///
+/// # Safety
+///
+/// Must be called with pointer to properly serialised instruction such as done
+/// by the Solana runtime.  See [`solana_program::entrypoint::deserialize`].
+pub unsafe fn deserialize_into_with<'a, C: PayloadCodec>(
+    input: *mut u8,
+    accounts: &mut [core::mem::MaybeUninit<AccountInfo<'a>>],
+) -> Result<(&'a Pubkey, usize, &'a [u8]), ProgramError> {
+    // SAFETY: Caller promises this is safe.
+    unsafe {
+        deserialize_into_with_config::<C>(input, accounts, &Config::DEFAULT)
     }
-    let (len, data) = data.split_at(4);
-    let len = u32::from_le_bytes(len.try_into().unwrap());
-    let len =
-        usize::try_from(len).map_err(|_| ProgramError::ArithmeticOverflow)?;
-    data.get(..len).ok_or(ProgramError::InvalidInstructionData)
 }
 
-
-/// Declare the program entrypoint and set up global handlers.
+/// Deserialize the input arguments treating instruction data as a prefix.
 ///
-/// Analogous to [`solana_program::entrypoint!`] macro with additional handling
-/// of empty instruction data as described in [`deserialize`].
-#[macro_export]
-macro_rules! entrypoint {
-    ($process_instruction:ident) => {
-        /// Solana program entry point.
-        ///
-        /// # Safety
-        ///
-        /// Must be called with pointer to properly serialised instruction such
-        /// as done by the Solana runtime.
-        #[no_mangle]
-        pub unsafe extern "C" fn entrypoint(input: *mut u8) -> u64 {
-            // SAFETY: Caller guarantees it’s safe.
-            unsafe {
-                $crate::entrypoint::__private::entrypoint_impl(
-                    input,
-                    |pid, accs, data| $process_instruction(pid, &accs, data),
-                )
-            }
-        }
-        $crate::entrypoint::__private::custom_heap_default!();
-        $crate::entrypoint::__private::custom_panic_default!();
-    };
+/// Behaves like [`deserialize_into_with`] except that the payload is always
+/// read from the last account and the inline instruction data is prepended to
+/// it.  See [`deserialize_mixed`].
+///
+/// # Safety
+///
+/// Must be called with pointer to properly serialised instruction such as done
+/// by the Solana runtime.  See [`solana_program::entrypoint::deserialize`].
+pub unsafe fn deserialize_into_mixed<'a, C: PayloadCodec>(
+    input: *mut u8,
+    accounts: &mut [core::mem::MaybeUninit<AccountInfo<'a>>],
+) -> Result<(&'a Pubkey, usize, &'a [u8]), ProgramError> {
+    let config = Config { mixed: true, ..Config::DEFAULT };
+    // SAFETY: Caller promises this is safe.
+    unsafe { deserialize_into_with_config::<C>(input, accounts, &config) }
 }
 
-
-/// Declare the program entrypoint and set up global handlers.
+/// Deserialize the input arguments using given configuration.
 ///
-/// Analogous to [`solana_program::entrypoint_no_alloc`] macro with additional
-/// handling of empty instruction data as described in [`deserialize_into`].
-#[macro_export]
-macro_rules! entrypoint_no_alloc {
-    ($process_instruction:ident) => {
-        /// Solana program entry point.
-        ///
-        /// # Safety
-        ///
-        /// Must be called with pointer to properly serialised instruction such
-        /// as done by the Solana runtime.
-        #[no_mangle]
-        pub unsafe extern "C" fn entrypoint(input: *mut u8) -> u64 {
-            // SAFETY: Caller guarantees it’s safe.
-            unsafe {
-                $crate::entrypoint::__private::entrypoint_no_alloc_impl(
-                    input,
-                    |pid, accs, data| $process_instruction(pid, accs, data),
-                )
-            }
-        }
-        $crate::entrypoint::__private::custom_heap_default!();
-        $crate::entrypoint::__private::custom_panic_default!();
-    };
+/// Behaves like [`deserialize_into_with`] with behaviour adjusted according to
+/// `config`.  [`Config::max_accounts`] is ignored; the length of `accounts`
+/// limits the number of accounts instead.
+///
+/// # Safety
+///
+/// Must be called with pointer to properly serialised instruction such as done
+/// by the Solana runtime.  See [`solana_program::entrypoint::deserialize`].
+#[inline(always)]
+pub unsafe fn deserialize_into_with_config<'a, C: PayloadCodec>(
+    input: *mut u8,
+    accounts: &mut [core::mem::MaybeUninit<AccountInfo<'a>>],
+    config: &Config,
+) -> Result<(&'a Pubkey, usize, &'a [u8]), ProgramError> {
+    // SAFETY: Caller promises this is safe.
+    unsafe { deserialize_into_with_source::<C>(input, accounts, config) }
+        .map(|(program_id, count, data, _)| (program_id, count, data))
 }
 
+/// Deserialize the input arguments reporting where instruction data came from.
+///
+/// Behaves like [`deserialize_into_with_config`] except that it additionally
+/// returns [`IxDataSource`] describing the source of the instruction data.
+///
+/// # Safety
+///
+/// Must be called with pointer to properly serialised instruction such as done
+/// by the Solana runtime.  See [`solana_program::entrypoint::deserialize`].
+#[inline(always)]
+pub unsafe fn deserialize_into_with_source<'a, C: PayloadCodec>(
+    input: *mut u8,
+    accounts: &mut [core::mem::MaybeUninit<AccountInfo<'a>>],
+    config: &Config,
+) -> Result<(&'a Pubkey, usize, &'a [u8], IxDataSource<'a>), ProgramError> {
+    // SAFETY: Caller promises this is safe.
+    unsafe { deserialize_into_impl::<C>(input, accounts, config) }.map(
+        |(program_id, count, data, source, _)| {
+            (program_id, count, data, source)
+        },
+    )
+}
 
-#[doc(hidden)]
-pub mod __private {
-    use core::mem::MaybeUninit;
+/// Implementation of [`deserialize_into_with_source`].
+///
+/// Additionally returns the data of the account the payload was taken from if
+/// [`Config::zeroize`] is set.
+///
+/// # Safety
+///
+/// Must be called with pointer to properly serialised instruction such as done
+/// by the Solana runtime.  See [`solana_program::entrypoint::deserialize`].
+#[inline(always)]
+#[allow(clippy::type_complexity)]
+unsafe fn deserialize_into_impl<'a, C: PayloadCodec>(
+    input: *mut u8,
+    accounts: &mut [core::mem::MaybeUninit<AccountInfo<'a>>],
+    config: &Config,
+) -> Result<
+    (&'a Pubkey, usize, &'a [u8], IxDataSource<'a>, Option<*mut [u8]>),
+    ProgramError,
+> {
+    // Check number of accounts here rather than letting `deserialize_into`
+    // panic.
+    // SAFETY: Caller promises this is safe.
+    if unsafe { *(input as *const u64) } > accounts.len() as u64 {
+        return Err(WriteAccountError::TooManyAccounts.into());
+    }
 
-    use solana_program::account_info::AccountInfo;
-    use solana_program::entrypoint::SUCCESS;
-    use solana_program::pubkey::Pubkey;
-    pub use solana_program::{custom_heap_default, custom_panic_default};
+    // SAFETY: Caller promises this is safe.
+    let (program_id, mut count, mut instruction_data) =
+        unsafe { crate::sdk::entrypoint::deserialize_into(input, accounts) };
 
-    type Result =
-        core::result::Result<(), solana_program::program_error::ProgramError>;
+    // SAFETY: `deserialize_into` initialised first `count` elements.
+    let initialised = unsafe { assume_init_slice(accounts, count) };
+    if let Some(index) = account_index(config, instruction_data, initialised)? {
+        let accounts = initialised;
+        let ix_acc =
+            accounts.get(index).ok_or(WriteAccountError::MissingDataAccount)?;
+        check_account::<C>(ix_acc, accounts, config, None)?;
+        let source = (ix_acc.key, header_version::<C>(ix_acc));
+        let instruction_data = borrow_ix_data::<C>(ix_acc, accounts, config)?;
+        check_len(config, instruction_data)?;
+        let source = IxDataSource::new(Some(source), instruction_data);
+        return Ok((program_id, count, instruction_data, source, None));
+    }
 
-    #[inline(always)]
-    pub unsafe fn entrypoint_impl(
-        input: *mut u8,
-        process: impl FnOnce(&Pubkey, Vec<AccountInfo>, &[u8]) -> Result,
-    ) -> u64 {
-        // SAFETY: Caller promises this is safe.
-        unsafe { super::deserialize(input) }
-            .and_then(|(pid, accs, data)| process(pid, accs, data))
-            .map_or_else(|error| error.into(), |()| SUCCESS)
+    let empty = is_empty_ix(config, instruction_data, initialised);
+    let sequence = expected_sequence(config, instruction_data);
+    if sequence.is_some() || is_marked(config, instruction_data, initialised) {
+        instruction_data = &[];
     }
 
-    #[inline(always)]
-    pub unsafe fn entrypoint_no_alloc_impl(
-        input: *mut u8,
-        process: impl FnOnce(&Pubkey, &[AccountInfo], &[u8]) -> Result,
-    ) -> u64 {
-        let mut accounts = [const { MaybeUninit::<AccountInfo>::uninit() }; 64];
-        // SAFETY: Caller promises this is safe.
-        let parsed = unsafe { super::deserialize_into(input, &mut accounts) };
-        let (program_id, num_accounts, instruction_data) = match parsed {
-            Ok(it) => it,
-            Err(error) => return error.into(),
+    // If instruction data is empty (or we’re in mixed mode), the actual
+    // instruction data comes from the last account passed in the call.
+    let mut source = None;
+    let mut wipe = None;
+    if !empty && (config.mixed || instruction_data.is_empty()) {
+        let last = count
+            .checked_sub(1)
+            .ok_or(WriteAccountError::MissingDataAccount)?;
+        let payload = if config.retain_account {
+            // SAFETY: `deserialize_into` initialised the element.
+            let ix_acc = unsafe { accounts[last].assume_init_ref() };
+            // SAFETY: `deserialize_into` initialised first `count` elements.
+            let accounts = unsafe { assume_init_slice(accounts, count) };
+            check_account::<C>(ix_acc, accounts, config, sequence)?;
+            source = Some((ix_acc.key, header_version::<C>(ix_acc)));
+            borrow_ix_data::<C>(ix_acc, accounts, config)?
+        } else {
+            count = last;
+            // SAFETY: `deserialize_into` initialised the element.
+            let ix_acc = unsafe { accounts[last].assume_init_read() };
+            // SAFETY: `deserialize_into` initialised first `count` elements.
+            let accounts = unsafe { assume_init_slice(accounts, count) };
+            check_account::<C>(&ix_acc, accounts, config, sequence)?;
+            source = Some((ix_acc.key, header_version::<C>(&ix_acc)));
+            let (payload, data) = take_ix_data::<C>(ix_acc, accounts, config)?;
+            wipe = Some(data).filter(|_| config.zeroize);
+            payload
         };
-        let accounts = &accounts[..num_accounts]
-            as *const [MaybeUninit<AccountInfo>]
-            as *const [AccountInfo];
-        // SAFETY: deserialize_into initialised the first num_accounts entries
-        // of the array and `MU<X>` has the same layout as `X`.
-        let accounts = unsafe { &*accounts };
+        check_len(config, payload)?;
+        instruction_data = concat(instruction_data, payload);
+    }
+    let source = IxDataSource::new(source, instruction_data);
 
-        // Make sure we have a new stack frame.  Solana stack frame sizes are
-        // limited so the accounts array would eat into user’s available stack
-        // space.
-        #[inline(never)]
-        fn inner(
-            program_id: &Pubkey,
-            accounts: &[AccountInfo],
-            data: &[u8],
-            process: impl FnOnce(&Pubkey, &[AccountInfo], &[u8]) -> Result,
-        ) -> Result {
-            process(program_id, accounts, data)
-        }
+    Ok((program_id, count, instruction_data, source, wipe))
+}
 
-        inner(program_id, accounts, instruction_data, process)
-            .map_or_else(|error| error.into(), |()| SUCCESS)
-    }
+
+/// Deserialize the input arguments from a byte slice.
+///
+/// Safe variant of [`deserialize_with_config`] meant for host-side testing of
+/// programs.  Verifies that `input` is aligned like the runtime aligns it and
+/// holds well-formed serialised program arguments.  Returns
+/// [`ProgramError::InvalidArgument`] error otherwise.
+///
+/// Note that just like the runtime’s deserialiser, this modifies padding bytes
+/// of the input.
+pub fn deserialize_slice<'a, C: PayloadCodec>(
+    input: &'a mut [u8],
+    config: &Config,
+) -> Result<(&'a Pubkey, Vec<AccountInfo<'a>>, &'a [u8]), ProgramError> {
+    lazy::validate_input(input).ok_or(ProgramError::InvalidArgument)?;
+    // SAFETY: We’ve just verified the input is properly serialised.
+    unsafe { deserialize_with_config::<C>(input.as_mut_ptr(), config) }
 }
 
-#[cfg(test)]
-mod tests {
-    use pretty_assertions::assert_eq;
-    use solana_program::entrypoint::{
-        BPF_ALIGN_OF_U128, MAX_PERMITTED_DATA_INCREASE, NON_DUP_MARKER,
-    };
+/// Deserialize the input arguments from a byte slice into given slice.
+///
+/// Safe variant of [`deserialize_into_with_config`].  See
+/// [`deserialize_slice`].
+pub fn deserialize_into_slice<'a, C: PayloadCodec>(
+    input: &'a mut [u8],
+    accounts: &mut [core::mem::MaybeUninit<AccountInfo<'a>>],
+    config: &Config,
+) -> Result<(&'a Pubkey, usize, &'a [u8]), ProgramError> {
+    lazy::validate_input(input).ok_or(ProgramError::InvalidArgument)?;
+    // SAFETY: We’ve just verified the input is properly serialised.
+    unsafe {
+        deserialize_into_with_config::<C>(input.as_mut_ptr(), accounts, config)
+    }
+}
 
-    use super::*;
 
-    #[test]
-    fn test_get_ix_data() {
-        let key = Pubkey::new_unique();
+/// Description of where the instruction data came from.
+///
+/// Returned by [`deserialize_with_source`] and [`deserialize_into_with_source`]
+/// and passed to the function processing the instruction if `with_source`
+/// argument is given to the entrypoint macros.  This allows programs to log,
+/// meter or branch on how the data arrived.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IxDataSource<'a> {
+    /// Address of the account the payload was read from or `None` if the
+    /// instruction data was passed inline.
+    pub account: Option<&'a Pubkey>,
 
-        fn account_info<'a>(
-            key: &'a Pubkey,
-            lamports: &'a mut u64,
-            data: &'a mut [u8],
-        ) -> AccountInfo<'a> {
-            AccountInfo::new(key, false, false, lamports, data, key, false, 0)
-        }
+    /// Length of the instruction data.
+    pub len: usize,
 
-        let check = |want, data: &[u8]| {
-            let mut lamports = 0u64;
-            let mut data = data.to_vec();
-            let acc = account_info(&key, &mut lamports, &mut data);
-            assert_eq!(want, super::get_ix_data(acc));
-        };
+    /// Version of the account data header as reported by
+    /// [`PayloadCodec::header_version`] or `None` if the instruction data was
+    /// passed inline.
+    pub version: Option<u8>,
+}
 
-        check(Err(ProgramError::InvalidInstructionData), &[][..]);
-        check(Ok(&[][..]), &[0, 0, 0, 0, 1, 2, 3, 4][..]);
-        check(Ok(&[1][..]), &[1, 0, 0, 0, 1, 2, 3, 4][..]);
-        check(Err(ProgramError::InvalidInstructionData), &[1, 0, 0, 0][..]);
-    }
-
-    #[derive(Debug)]
-    struct TestAccount {
-        key: Pubkey,
-        owner: Pubkey,
-        is_signer: bool,
-        is_writable: bool,
-        executable: bool,
-        rent_epoch: u64,
-        lamports: u64,
-        data: Vec<u8>,
-    }
-
-    impl TestAccount {
-        fn new(data: impl Into<Vec<u8>>) -> Self {
-            use std::sync::atomic::{AtomicU64, Ordering};
-            static CNT: AtomicU64 = AtomicU64::new(1);
-
-            let bits = CNT.fetch_add(1, Ordering::SeqCst);
-            Self {
-                key: Pubkey::new_unique(),
-                lamports: CNT.fetch_add(1, Ordering::SeqCst),
-                data: data.into(),
-                owner: Pubkey::new_unique(),
-                rent_epoch: CNT.fetch_add(1, Ordering::SeqCst),
-                is_signer: bits & 1 != 0,
-                is_writable: bits & 2 != 0,
-                executable: bits & 4 != 0,
-            }
+impl<'a> IxDataSource<'a> {
+    /// Constructs the description and logs it if `log` feature is enabled.
+    fn new(account: Option<(&'a Pubkey, Option<u8>)>, data: &[u8]) -> Self {
+        log_source(account.map(|(key, _)| key), data);
+        Self {
+            account: account.map(|(key, _)| key),
+            len: data.len(),
+            version: account.and_then(|(_, version)| version),
         }
     }
+}
 
-    impl PartialEq<AccountInfo<'_>> for TestAccount {
-        fn eq(&self, rhs: &AccountInfo<'_>) -> bool {
-            &self.key == rhs.key &&
-                self.lamports == rhs.lamports() &&
-                self.data.as_slice() == &rhs.try_borrow_data().unwrap()[..] &&
-                &self.owner == rhs.owner &&
-                self.rent_epoch == rhs.rent_epoch &&
-                self.is_signer == rhs.is_signer &&
-                self.is_writable == rhs.is_writable &&
-                self.executable == rhs.executable
-        }
-    }
+/// Instruction data returned by [`deserialize_keep`] and
+/// [`deserialize_into_keep`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeptIxData<'a> {
+    /// The instruction data.
+    pub data: &'a [u8],
 
-    fn serialise_input(
-        accounts: &[TestAccount],
-        instruction_data: &[u8],
-    ) -> (Pubkey, Vec<u8>, usize) {
-        let program_id = Pubkey::new_unique();
-        let mut vec = Vec::<u8>::new();
-        vec.extend_from_slice(&(accounts.len() as u64).to_le_bytes());
-
-        fn align(addr: usize) -> usize {
-            match addr % BPF_ALIGN_OF_U128 {
-                0 => 0,
-                n => BPF_ALIGN_OF_U128 - n,
+    /// Index of the account the instruction data was read from or `None` if
+    /// the data was passed inline.
+    pub account: Option<usize>,
+}
+
+/// Deserialize the input arguments keeping the account with instruction data.
+///
+/// Behaves like [`deserialize_with`] except that the account holding the
+/// instruction data is not removed from the returned accounts.  Instead, its
+/// index is returned alongside the data (see [`KeptIxData`]).  This allows the
+/// program to inspect or close the account itself.
+///
+/// The data of the account stays immutably borrowed for the rest of the
+/// program’s execution so that the instruction data cannot be modified while
+/// it’s being used.  In particular, this means that the program cannot
+/// resize the account (but can still transfer its lamports away).
+///
+/// # Safety
+///
+/// Must be called with pointer to properly serialised instruction such as done
+/// by the Solana runtime.  See [`solana_program::entrypoint::deserialize`].
+pub unsafe fn deserialize_keep<'a, C: PayloadCodec>(
+    input: *mut u8,
+) -> Result<(&'a Pubkey, Vec<AccountInfo<'a>>, KeptIxData<'a>), ProgramError> {
+    // SAFETY: Caller promises this is safe.
+    let (program_id, accounts, data) =
+        unsafe { crate::sdk::entrypoint::deserialize(input) };
+    let mut ix_data = KeptIxData { data, account: None };
+    if data.is_empty() {
+        let (ix_acc, _) = accounts
+            .split_last()
+            .ok_or(WriteAccountError::MissingDataAccount)?;
+        ix_data.data =
+            borrow_ix_data::<C>(ix_acc, &accounts, &Config::DEFAULT)?;
+        ix_data.account = Some(accounts.len() - 1);
+    }
+    log_source(ix_data.account.map(|idx| accounts[idx].key), ix_data.data);
+    Ok((program_id, accounts, ix_data))
+}
+
+/// Deserialize the input arguments keeping the account with instruction data.
+///
+/// Behaves like [`deserialize_into_with`] except that the account holding the
+/// instruction data is not removed from the returned accounts.  See
+/// [`deserialize_keep`].
+///
+/// # Safety
+///
+/// Must be called with pointer to properly serialised instruction such as done
+/// by the Solana runtime.  See [`solana_program::entrypoint::deserialize`].
+pub unsafe fn deserialize_into_keep<'a, C: PayloadCodec>(
+    input: *mut u8,
+    accounts: &mut [core::mem::MaybeUninit<AccountInfo<'a>>],
+) -> Result<(&'a Pubkey, usize, KeptIxData<'a>), ProgramError> {
+    // SAFETY: Caller promises this is safe.
+    if unsafe { *(input as *const u64) } > accounts.len() as u64 {
+        return Err(WriteAccountError::TooManyAccounts.into());
+    }
+
+    // SAFETY: Caller promises this is safe.
+    let (program_id, count, data) =
+        unsafe { crate::sdk::entrypoint::deserialize_into(input, accounts) };
+    let mut ix_data = KeptIxData { data, account: None };
+    if data.is_empty() {
+        let last = count
+            .checked_sub(1)
+            .ok_or(WriteAccountError::MissingDataAccount)?;
+        // SAFETY: `deserialize_into` initialised the element.
+        let ix_acc = unsafe { accounts[last].assume_init_ref() };
+        // SAFETY: `deserialize_into` initialised first `count` elements.
+        let accounts = unsafe { assume_init_slice(accounts, count) };
+        ix_data.data = borrow_ix_data::<C>(ix_acc, accounts, &Config::DEFAULT)?;
+        ix_data.account = Some(last);
+        log_source(Some(ix_acc.key), ix_data.data);
+    } else {
+        log_source(None, data);
+    }
+    Ok((program_id, count, ix_data))
+}
+
+
+/// Instruction data returned by [`deserialize_trailing`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrailingIxData<'a> {
+    /// The instruction data.
+    pub data: &'a [u8],
+
+    /// Bytes following the length-prefixed payload in the account.
+    ///
+    /// If the payload is chained (see [`V2Header::FLAG_CHAINED`]), these are
+    /// the bytes following the continuation address in the first account.
+    /// Empty if the instruction data was passed inline.
+    pub trailing: &'a [u8],
+}
+
+/// Deserialize the input arguments together with bytes trailing the payload.
+///
+/// Behaves like [`deserialize`] except that it also returns bytes which follow
+/// the length-prefixed payload in the account (see [`TrailingIxData`]).  This
+/// allows packing auxiliary information (e.g. signatures or routing hints) into
+/// the same account as the instruction data.
+///
+/// # Safety
+///
+/// Must be called with pointer to properly serialised instruction such as done
+/// by the Solana runtime.  See [`solana_program::entrypoint::deserialize`].
+pub unsafe fn deserialize_trailing<'a>(
+    input: *mut u8,
+) -> Result<(&'a Pubkey, Vec<AccountInfo<'a>>, TrailingIxData<'a>), ProgramError>
+{
+    // SAFETY: Caller promises this is safe.
+    let (program_id, mut accounts, data) =
+        unsafe { crate::sdk::entrypoint::deserialize(input) };
+    let mut ix_data = TrailingIxData { data, trailing: &[] };
+    if data.is_empty() {
+        let ix_acc =
+            accounts.pop().ok_or(WriteAccountError::MissingDataAccount)?;
+        let key = ix_acc.key;
+        let data = alloc::rc::Rc::try_unwrap(ix_acc.data)
+            .map_err(|_| WriteAccountError::DuplicateDataAccount)?
+            .into_inner();
+        let prefixed = split_prefixed(data)?;
+        ix_data.trailing = prefixed.trailing;
+        let checked = CheckedAccounts::new(&accounts, &Config::DEFAULT);
+        ix_data.data = decode_prefixed(prefixed, &checked)?;
+        log_source(Some(key), ix_data.data);
+    }
+    Ok((program_id, accounts, ix_data))
+}
+
+
+/// Extracts account-sourced instruction data from within an instruction handler.
+///
+/// Performs the same handling of empty instruction data as [`deserialize`] but
+/// does so on already deserialised accounts and instruction data.  This is
+/// meant for programs which cannot replace their entrypoint (e.g. because it’s
+/// generated by a framework).
+///
+/// If `data` is not empty, returns `accounts` and `data` unchanged.  Otherwise,
+/// reads instruction data from the last account (decoding it with the
+/// [`LengthPrefixed`] codec) and returns the remaining accounts together with
+/// a copy of the instruction data.  The data needs to be copied since the
+/// account’s data may be modified while the handler runs.
+pub fn split_ix_account<'a, 'info>(
+    accounts: &'a [AccountInfo<'info>],
+    data: &'a [u8],
+) -> Result<(&'a [AccountInfo<'info>], Cow<'a, [u8]>), ProgramError> {
+    split_ix_account_with::<LengthPrefixed>(accounts, data)
+}
+
+/// Extracts account-sourced instruction data using given codec.
+///
+/// Behaves like [`split_ix_account`] except that data of the account is
+/// decoded with codec `C` rather than [`LengthPrefixed`].
+pub fn split_ix_account_with<'a, 'info, C: PayloadCodec>(
+    accounts: &'a [AccountInfo<'info>],
+    data: &'a [u8],
+) -> Result<(&'a [AccountInfo<'info>], Cow<'a, [u8]>), ProgramError> {
+    if !data.is_empty() {
+        return Ok((accounts, Cow::Borrowed(data)));
+    }
+    let (ix_acc, accounts) =
+        accounts.split_last().ok_or(WriteAccountError::MissingDataAccount)?;
+    let data = ix_acc.try_borrow_data()?;
+    let checked = CheckedAccounts::new(accounts, &Config::DEFAULT);
+    let data = C::decode_chained(&data, &checked)?.to_vec();
+    Ok((accounts, Cow::Owned(data)))
+}
+
+
+/// Reads instruction data from given account.
+///
+/// Decodes data of the account with the [`LengthPrefixed`] codec.  This is
+/// meant for programs which do their own account routing and want to read
+/// instruction data from an arbitrary account rather than the last one.
+///
+/// Just like with [`deserialize_keep`], the data of the account stays
+/// immutably borrowed for the rest of the program’s execution so that the
+/// returned slice cannot be invalidated.  Chained payloads (see
+/// [`V2Header::FLAG_CHAINED`]) are not supported.
+pub fn read_ix_data<'a>(
+    account: &AccountInfo<'a>,
+) -> Result<&'a [u8], ProgramError> {
+    read_ix_data_with::<LengthPrefixed>(account)
+}
+
+/// Reads instruction data from given account using given codec.
+///
+/// Behaves like [`read_ix_data`] except that data of the account is decoded
+/// with codec `C` rather than [`LengthPrefixed`].
+pub fn read_ix_data_with<'a, C: PayloadCodec>(
+    account: &AccountInfo<'a>,
+) -> Result<&'a [u8], ProgramError> {
+    borrow_ix_data::<C>(account, &[], &Config::DEFAULT)
+}
+
+
+/// Interprets data in the last account as instruction data.
+///
+/// `accounts` are the remaining accounts which may hold continuation of the
+/// payload.  Returns the payload together with pointer to the entire account
+/// data.  The pointer may be used to modify the data once the returned payload
+/// is no longer used.
+fn take_ix_data<'a, C: PayloadCodec>(
+    account: AccountInfo<'a>,
+    accounts: &[AccountInfo],
+    config: &Config,
+) -> Result<(&'a [u8], *mut [u8]), ProgramError> {
+    let data = alloc::rc::Rc::try_unwrap(account.data)
+        .map_err(|_| WriteAccountError::DuplicateDataAccount)?;
+    let data: *mut [u8] = data.into_inner();
+    // SAFETY: The account data lives for 'a and we’ve just taken the only
+    // reference to it.
+    let checked = CheckedAccounts::new(accounts, config);
+    let payload = C::decode_chained(unsafe { &*data }, &checked)?;
+    Ok((payload, data))
+}
+
+/// Returns first `count` elements of `accounts` as initialised slice.
+///
+/// # Safety
+///
+/// The first `count` elements of `accounts` must be initialised.
+unsafe fn assume_init_slice<'b, 'a>(
+    accounts: &'b [core::mem::MaybeUninit<AccountInfo<'a>>],
+    count: usize,
+) -> &'b [AccountInfo<'a>] {
+    let accounts = &accounts[..count];
+    // SAFETY: Caller promises elements are initialised and MaybeUninit<T> has
+    // the same layout as T.
+    unsafe {
+        core::slice::from_raw_parts(accounts.as_ptr().cast(), accounts.len())
+    }
+}
+
+/// Returns index of the account to read instruction data from.
+///
+/// Returns `Some(idx)` if inline instruction data is exactly
+/// `[Config::index_magic, idx]` or if it’s `[Config::tag_magic, tag…]` and
+/// `idx` is index of the first account whose header carries the tag.  Returns
+/// [`WriteAccountError::MissingDataAccount`] error if no account has the tag.
+fn account_index(
+    config: &Config,
+    data: &[u8],
+    accounts: &[AccountInfo],
+) -> Result<Option<usize>, ProgramError> {
+    if let (Some(magic), &[byte, index]) = (config.index_magic, data) {
+        if byte == magic {
+            return Ok(Some(index.into()));
+        }
+    }
+    if let (Some(magic), &[byte, ref tag @ ..]) = (config.tag_magic, data) {
+        if let (true, Ok(tag)) = (byte == magic, <[u8; 4]>::try_from(tag)) {
+            let tag = u32::from_le_bytes(tag);
+            return accounts
+                .iter()
+                .position(|account| {
+                    account.try_borrow_data().is_ok_and(|data| {
+                        header_field(&data, V2Header::FLAG_TAG) == Some(tag)
+                    })
+                })
+                .map(Some)
+                .ok_or(WriteAccountError::MissingDataAccount.into());
+        }
+    }
+    Ok(None)
+}
+
+/// Returns optional field of the versioned header of account data.
+///
+/// `flag` is the flag indicating presence of the field (e.g.
+/// [`V2Header::FLAG_TAG`]).  Returns `None` if the data doesn’t start with
+/// a versioned header or the field isn’t present.  Doesn’t validate the rest
+/// of the header.
+fn header_field(data: &[u8], flag: u8) -> Option<u32> {
+    const FIELDS: u8 = V2Header::FLAG_OFFSET |
+        V2Header::FLAG_CRC32 |
+        V2Header::FLAG_TAG |
+        V2Header::FLAG_SEQUENCE;
+    let (&[flags, header_len, _, version], _) = data.split_first_chunk()?;
+    if version != V2Header::VERSION || flags & flag == 0 {
+        return None;
+    }
+    let preceding = flags & FIELDS & (flag - 1);
+    let offset = V2Header::LEN + 4 * preceding.count_ones() as usize;
+    let field = data.get(..usize::from(header_len))?.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(field.try_into().unwrap()))
+}
+
+/// Returns version of the header of account’s data as reported by codec `C`.
+fn header_version<C: PayloadCodec>(account: &AccountInfo) -> Option<u8> {
+    C::header_version(&account.try_borrow_data().ok()?)
+}
+
+/// Checks whether inline instruction data should be ignored.
+///
+/// Returns `true` if the inline data starts with [`Config::marker_byte`] or if
+/// [`Config::marker_account`] is among the `accounts`.
+fn is_marked(config: &Config, data: &[u8], accounts: &[AccountInfo]) -> bool {
+    let byte =
+        config.marker_byte.is_some_and(|byte| data.first() == Some(&byte));
+    byte || config
+        .marker_account
+        .is_some_and(|marker| accounts.iter().any(|acc| *acc.key == marker))
+}
+
+/// Checks whether the instruction is empty and carries no data account.
+///
+/// Returns `true` if [`Config::allow_empty`] is set, inline data is empty and
+/// the last account isn’t a data account (see [`Config::allow_empty`]).
+fn is_empty_ix(config: &Config, data: &[u8], accounts: &[AccountInfo]) -> bool {
+    config.allow_empty &&
+        data.is_empty() &&
+        accounts.last().is_none_or(|acc| {
+            config.expected_writer.is_some_and(|owner| *acc.owner != owner)
+        })
+}
+
+/// Checks length of instruction data read from an account.
+///
+/// Returns [`WriteAccountError::PayloadTooLong`] error if the data is longer
+/// than [`Config::max_len`].
+fn check_len(config: &Config, data: &[u8]) -> Result<(), ProgramError> {
+    match config.max_len {
+        Some(max) if data.len() > max => {
+            Err(WriteAccountError::PayloadTooLong.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns sequence number the payload is expected to have.
+///
+/// Returns `Some(seq)` if inline instruction data is exactly
+/// `[Config::sequence_magic, seq…]`.
+fn expected_sequence(config: &Config, data: &[u8]) -> Option<u32> {
+    let (&byte, seq) = data.split_first()?;
+    let seq = <[u8; 4]>::try_from(seq).ok()?;
+    (config.sequence_magic == Some(byte)).then(|| u32::from_le_bytes(seq))
+}
+
+/// Checks the account holding instruction data against the configuration.
+///
+/// Verifies that the account is owned by the expected writer program (if any)
+/// and, if required, that it’s read-only.  If `sequence` is given, verifies
+/// that the payload has that sequence number.
+fn check_account<C: PayloadCodec>(
+    account: &AccountInfo,
+    accounts: &[AccountInfo],
+    config: &Config,
+    sequence: Option<u32>,
+) -> Result<(), ProgramError> {
+    check_owner(account, config)?;
+    if let Some(caller) = config.expected_caller {
+        let sysvar = accounts
+            .iter()
+            .find(|acc| *acc.key == INSTRUCTIONS_SYSVAR_ID)
+            .ok_or(WriteAccountError::UnexpectedCaller)?;
+        let data = sysvar.try_borrow_data()?;
+        if is_called_by(&data, &caller, account.key) != Some(true) {
+            return Err(WriteAccountError::UnexpectedCaller.into());
+        }
+    }
+    if config.single_use {
+        let flags = C::header_flags(&account.try_borrow_data()?)
+            .ok_or(WriteAccountError::UnsupportedHeader)?;
+        if flags & V2Header::FLAG_CONSUMED != 0 {
+            return Err(WriteAccountError::PayloadConsumed.into());
+        }
+    }
+    if let Some(sequence) = sequence {
+        let data = account.try_borrow_data()?;
+        if header_field(&data, V2Header::FLAG_SEQUENCE) != Some(sequence) {
+            return Err(WriteAccountError::SequenceMismatch.into());
+        }
+    }
+    Ok(())
+}
+
+/// Checks owner of an account holding (part of) the instruction data.
+///
+/// Verifies that the account is owned by [`Config::expected_writer`] (if any)
+/// and, if [`Config::require_readonly`] is set, that it’s read-only.
+fn check_owner(
+    account: &AccountInfo,
+    config: &Config,
+) -> Result<(), ProgramError> {
+    match config.expected_writer {
+        Some(writer) if *account.owner != writer => {
+            Err(WriteAccountError::WrongOwner.into())
+        }
+        _ if config.require_readonly && account.is_writable => {
+            Err(WriteAccountError::WritableDataAccount.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Continuation accounts checked with [`check_owner`].
+pub(crate) struct CheckedAccounts<'r, 'info> {
+    accounts: &'r [AccountInfo<'info>],
+    config: &'r Config,
+}
+
+impl<'r, 'info> CheckedAccounts<'r, 'info> {
+    pub(crate) fn new(
+        accounts: &'r [AccountInfo<'info>],
+        config: &'r Config,
+    ) -> Self {
+        Self { accounts, config }
+    }
+}
+
+impl Continuations for CheckedAccounts<'_, '_> {
+    type Error = ProgramError;
+
+    fn count(&self) -> usize { self.accounts.len() }
+
+    fn with_data<R>(
+        &self,
+        address: &[u8; 32],
+        f: impl FnOnce(&[u8]) -> Result<R, WriteAccountError>,
+    ) -> Result<R, ProgramError> {
+        let account = self
+            .accounts
+            .iter()
+            .find(|account| account.key.as_ref() == address)
+            .ok_or(WriteAccountError::MissingContinuationAccount)?;
+        check_owner(account, self.config)?;
+        Ok(f(&account.try_borrow_data()?)?)
+    }
+}
+
+/// Address of the instructions sysvar.
+///
+/// The sysvar must be passed to the instruction if
+/// [`Config::expected_caller`] is set.
+pub const INSTRUCTIONS_SYSVAR_ID: Pubkey = Pubkey::new_from_array([
+    6, 167, 213, 23, 24, 123, 209, 102, 53, 218, 212, 4, 85, 253, 194, 192,
+    193, 36, 198, 143, 33, 86, 117, 165, 219, 186, 203, 95, 8, 0, 0, 0,
+]);
+
+/// Checks whether the current top-level instruction calls `program` with
+/// `account`.
+///
+/// `data` is data of the instructions sysvar.  Returns `None` if the data is
+/// malformed.
+fn is_called_by(
+    data: &[u8],
+    program: &Pubkey,
+    account: &Pubkey,
+) -> Option<bool> {
+    fn split_u16(data: &[u8]) -> Option<(usize, &[u8])> {
+        let (num, rest) = data.split_first_chunk::<2>()?;
+        Some((u16::from_le_bytes(*num).into(), rest))
+    }
+
+    let (count, offsets) = split_u16(data)?;
+    let (_, current) = data.split_last_chunk::<2>()?;
+    let current = usize::from(u16::from_le_bytes(*current));
+    if current >= count {
+        return None;
+    }
+    let (offset, _) = split_u16(offsets.get(2 * current..)?)?;
+    let (num_accounts, mut rest) = split_u16(data.get(offset..)?)?;
+    let mut found = false;
+    for _ in 0..num_accounts {
+        let (meta, tail) = rest.split_first_chunk::<33>()?;
+        found |= &meta[1..] == account.as_ref();
+        rest = tail;
+    }
+    let (program_id, _) = rest.split_first_chunk::<32>()?;
+    Some(found && &program_id[..] == program.as_ref())
+}
+
+/// Decodes Borsh-serialised instruction data.
+///
+/// Accepts anything which dereferences to a byte slice, e.g. instruction data
+/// returned by [`deserialize`] or [`split_ix_account`].  Returns
+/// [`WriteAccountError::InvalidPayload`] error if the data does not decode as
+/// `T` or if there are trailing bytes.
+#[cfg(feature = "borsh")]
+pub fn parse<T: borsh::BorshDeserialize>(
+    data: impl AsRef<[u8]>,
+) -> Result<T, ProgramError> {
+    T::try_from_slice(data.as_ref())
+        .map_err(|_| WriteAccountError::InvalidPayload.into())
+}
+
+
+/// Reinterprets instruction data as a plain-old-data type.
+///
+/// This lets fixed-layout (e.g. `#[repr(C)]`) instructions be consumed without
+/// any deserialisation cost.  Returns [`WriteAccountError::InvalidPayload`]
+/// error if length of the data doesn’t match size of `T` or the data isn’t
+/// suitably aligned for `T`.
+///
+/// Note that payload following the legacy 4-byte length prefix is aligned to
+/// four bytes only.  Types with greater alignment should be written with
+/// a [`V2Header`] which keeps the payload aligned to eight bytes.
+#[cfg(feature = "bytemuck")]
+pub fn parse_pod<T: bytemuck::Pod>(data: &[u8]) -> Result<&T, ProgramError> {
+    bytemuck::try_from_bytes(data)
+        .map_err(|_| WriteAccountError::InvalidPayload.into())
+}
+
+/// Splits Anchor-style 8-byte discriminator off the instruction data.
+///
+/// Returns the discriminator and the rest of the instruction data.  This lets
+/// dispatchers route the instruction without copying the payload.  Returns
+/// [`WriteAccountError::InvalidPayload`] error if the data is shorter than
+/// eight bytes.
+pub fn split_discriminator(
+    data: &[u8],
+) -> Result<(&[u8; 8], &[u8]), ProgramError> {
+    data.split_first_chunk().ok_or(WriteAccountError::InvalidPayload.into())
+}
+
+
+/// Interprets data in the account as instruction data without consuming it.
+///
+/// The account’s data is left immutably borrowed forever so that the returned
+/// slice cannot be invalidated by modifications of the account.
+fn borrow_ix_data<'a, C: PayloadCodec>(
+    account: &AccountInfo<'a>,
+    accounts: &[AccountInfo],
+    config: &Config,
+) -> Result<&'a [u8], ProgramError> {
+    let data = account.try_borrow_data()?;
+    // SAFETY: The data lives in the input buffer which is valid for 'a.  We
+    // never release the borrow so nothing can modify the data.
+    let slice: &'a [u8] =
+        unsafe { core::slice::from_raw_parts(data.as_ptr(), data.len()) };
+    core::mem::forget(data);
+    C::decode_chained(slice, &CheckedAccounts::new(accounts, config))
+}
+
+/// Logs where the instruction data came from if `log` feature is enabled.
+///
+/// `account` is address of the account the payload was read from or `None` if
+/// the instruction data was passed inline.
+#[inline(always)]
+fn log_source(account: Option<&Pubkey>, data: &[u8]) {
+    #[cfg(feature = "log")]
+    {
+        use alloc::format;
+
+        use crate::sdk::msg;
+
+        let len = data.len();
+        match account {
+            None => msg!("write-account: {} bytes of inline data", len),
+            Some(key) => msg!("write-account: {} bytes from {}", len, key),
+        }
+    }
+    #[cfg(not(feature = "log"))]
+    let _ = (account, data);
+}
+
+/// Concatenates inline instruction data with payload read from an account.
+///
+/// If `prefix` is empty, returns `payload` as is.  Otherwise, allocates a new
+/// buffer which is leaked so that it lives as long as the rest of the input.
+fn concat<'a>(prefix: &'a [u8], payload: &'a [u8]) -> &'a [u8] {
+    if prefix.is_empty() {
+        payload
+    } else {
+        [prefix, payload].concat().leak()
+    }
+}
+
+
+/// Declare the program entrypoint and set up global handlers.
+///
+/// Analogous to [`solana_program::entrypoint!`] macro with additional handling
+/// of empty instruction data as described in [`deserialize`].
+///
+/// The first argument is an expression (e.g. a path or a closure) evaluating
+/// to the function processing the instruction.  The function may return any
+/// error type which can be converted into [`ProgramError`].
+///
+/// The macro accepts the following optional arguments (in any order) after the
+/// function processing the instruction:
+/// * `codec = Type` specifies codec used to decode data of the account.  By
+///   default [`LengthPrefixed`] is used.
+/// * `mixed` makes the entrypoint always read instruction data from the last
+///   account with the inline instruction data used as a prefix as described
+///   in [`deserialize_mixed`].
+/// * `borsh` makes the entrypoint decode the instruction data with [`parse`]
+///   and pass the decoded value rather than `&[u8]` to the function processing
+///   the instruction.  Requires `borsh` feature.
+/// * `with_source` makes the entrypoint pass [`IxDataSource`] describing where
+///   the instruction data came from as the fourth argument of the function
+///   processing the instruction.  Cannot be combined with `borsh`.
+/// * `discriminator` makes the entrypoint split the 8-byte Anchor-style
+///   discriminator off the instruction data (see [`split_discriminator`]) and
+///   pass it as `&[u8; 8]` followed by the rest of the data to the function
+///   processing the instruction.  Cannot be combined with `borsh` or
+///   `with_source`.
+/// * `expected_writer = ID` makes the entrypoint fail with
+///   [`WriteAccountError::WrongOwner`] error if the account the instruction
+///   data is read from is not owned by program `ID` (typically the
+///   write-account program).  Without it, any account is accepted.
+/// * `max_len = N` makes the entrypoint fail with
+///   [`WriteAccountError::PayloadTooLong`] error if instruction data read from
+///   an account is longer than `N` bytes (see [`Config::max_len`]).
+/// * `allow_empty` makes the entrypoint call the function processing the
+///   instruction with empty instruction data if the inline instruction data is
+///   empty and no data account is present (see [`Config::allow_empty`]).
+/// * `allocator: Type = init` declares `init` as the global allocator instead
+///   of Solana’s default 32 KiB bump allocator.  This is useful for programs
+///   processing payloads which don’t fit in the default heap (e.g. a bump
+///   allocator spanning heap extended with `RequestHeapFrame` compute budget
+///   instruction).  The allocator is declared only when building for Solana.
+///
+/// For example: `entrypoint!(process_instruction, codec = MyCodec, mixed)`.
+#[macro_export]
+#[rustfmt::skip]
+macro_rules! entrypoint {
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*],
+     codec = $value:ty $(, $($rest:tt)*)?) => {
+        $crate::entrypoint!(
+            @parse $process_instruction, [$value], [$call],
+            [$($config)*], [$($heap)*],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*],
+     mixed $(, $($rest:tt)*)?) => {
+        $crate::entrypoint!(
+            @parse $process_instruction, [$codec], [$call],
+            [$($config)* mixed: true,], [$($heap)*],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*],
+     borsh $(, $($rest:tt)*)?) => {
+        $crate::entrypoint!(
+            @parse $process_instruction, [$codec], [call_borsh],
+            [$($config)*], [$($heap)*],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*],
+     with_source $(, $($rest:tt)*)?) => {
+        $crate::entrypoint!(
+            @parse $process_instruction, [$codec], [call_with_source],
+            [$($config)*], [$($heap)*],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*],
+     discriminator $(, $($rest:tt)*)?) => {
+        $crate::entrypoint!(
+            @parse $process_instruction, [$codec], [call_discriminated],
+            [$($config)*], [$($heap)*],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*],
+     expected_writer = $value:expr $(, $($rest:tt)*)?) => {
+        $crate::entrypoint!(
+            @parse $process_instruction, [$codec], [$call],
+            [$($config)* expected_writer: Some($value),], [$($heap)*],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*],
+     max_len = $value:expr $(, $($rest:tt)*)?) => {
+        $crate::entrypoint!(
+            @parse $process_instruction, [$codec], [$call],
+            [$($config)* max_len: Some($value),], [$($heap)*],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*],
+     allow_empty $(, $($rest:tt)*)?) => {
+        $crate::entrypoint!(
+            @parse $process_instruction, [$codec], [$call],
+            [$($config)* allow_empty: true,], [$($heap)*],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*],
+     allocator: $alloc:ty = $init:expr $(, $($rest:tt)*)?) => {
+        $crate::entrypoint!(
+            @parse $process_instruction, [$codec], [$call],
+            [$($config)*], [$alloc = $init],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*], ) => {
+        $crate::entrypoint_with_config!(
+            @emit $process_instruction, [$codec], [$call], [$($heap)*],
+            $crate::entrypoint::Config {
+                $($config)*
+                ..$crate::entrypoint::Config::DEFAULT
+            }
+        );
+    };
+    ($process_instruction:expr $(, $($args:tt)*)?) => {
+        $crate::entrypoint!(
+            @parse $process_instruction,
+            [$crate::entrypoint::LengthPrefixed], [call], [], [],
+            $($($args)*)?
+        );
+    };
+}
+
+
+/// Declare the program entrypoint and set up global handlers.
+///
+/// Analogous to [`solana_program::entrypoint_no_alloc`] macro with additional
+/// handling of empty instruction data as described in [`deserialize_into`].
+///
+/// Accepts the same optional arguments as [`crate::entrypoint!`] and
+/// additionally `max_accounts = N` which specifies the maximum number of
+/// accounts the program accepts.  The accounts are stored in an array on stack
+/// so smaller values reduce the stack usage.  By default 64 accounts are
+/// supported.  If the runtime passes more accounts, the entrypoint fails with
+/// [`WriteAccountError::TooManyAccounts`] error.
+///
+/// For example: `entrypoint_no_alloc!(process_instruction, max_accounts = 8)`.
+#[macro_export]
+#[rustfmt::skip]
+macro_rules! entrypoint_no_alloc {
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*], [$max:expr],
+     codec = $value:ty $(, $($rest:tt)*)?) => {
+        $crate::entrypoint_no_alloc!(
+            @parse $process_instruction, [$value], [$call],
+            [$($config)*], [$($heap)*], [$max],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*], [$max:expr],
+     mixed $(, $($rest:tt)*)?) => {
+        $crate::entrypoint_no_alloc!(
+            @parse $process_instruction, [$codec], [$call],
+            [$($config)* mixed: true,], [$($heap)*], [$max],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*], [$max:expr],
+     borsh $(, $($rest:tt)*)?) => {
+        $crate::entrypoint_no_alloc!(
+            @parse $process_instruction, [$codec], [call_borsh],
+            [$($config)*], [$($heap)*], [$max],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*], [$max:expr],
+     with_source $(, $($rest:tt)*)?) => {
+        $crate::entrypoint_no_alloc!(
+            @parse $process_instruction, [$codec], [call_with_source],
+            [$($config)*], [$($heap)*], [$max],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*], [$max:expr],
+     discriminator $(, $($rest:tt)*)?) => {
+        $crate::entrypoint_no_alloc!(
+            @parse $process_instruction, [$codec], [call_discriminated],
+            [$($config)*], [$($heap)*], [$max],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*], [$max:expr],
+     expected_writer = $value:expr $(, $($rest:tt)*)?) => {
+        $crate::entrypoint_no_alloc!(
+            @parse $process_instruction, [$codec], [$call],
+            [$($config)* expected_writer: Some($value),], [$($heap)*], [$max],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*], [$max:expr],
+     max_len = $value:expr $(, $($rest:tt)*)?) => {
+        $crate::entrypoint_no_alloc!(
+            @parse $process_instruction, [$codec], [$call],
+            [$($config)* max_len: Some($value),], [$($heap)*], [$max],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*], [$max:expr],
+     max_accounts = $value:expr $(, $($rest:tt)*)?) => {
+        $crate::entrypoint_no_alloc!(
+            @parse $process_instruction, [$codec], [$call],
+            [$($config)*], [$($heap)*], [$value],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*], [$max:expr],
+     allow_empty $(, $($rest:tt)*)?) => {
+        $crate::entrypoint_no_alloc!(
+            @parse $process_instruction, [$codec], [$call],
+            [$($config)* allow_empty: true,], [$($heap)*], [$max],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*], [$max:expr],
+     allocator: $alloc:ty = $init:expr $(, $($rest:tt)*)?) => {
+        $crate::entrypoint_no_alloc!(
+            @parse $process_instruction, [$codec], [$call],
+            [$($config)*], [$alloc = $init], [$max],
+            $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($config:tt)*], [$($heap:tt)*], [$max:expr], ) => {
+        $crate::entrypoint_with_config!(
+            @emit $process_instruction, [$codec], [$call], [$($heap)*],
+            $crate::entrypoint::Config {
+                $($config)*
+                max_accounts: $max,
+                ..$crate::entrypoint::Config::DEFAULT
+            }
+        );
+    };
+    ($process_instruction:expr $(, $($args:tt)*)?) => {
+        $crate::entrypoint_no_alloc!(
+            @parse $process_instruction,
+            [$crate::entrypoint::LengthPrefixed], [call], [], [], [64],
+            $($($args)*)?
+        );
+    };
+}
+
+
+/// Declare the program entrypoint configured with a [`Config`].
+///
+/// The first argument is the function processing the instruction (as in
+/// [`crate::entrypoint!`]) and the second is a constant expression evaluating
+/// to [`Config`].  If [`Config::max_accounts`] is zero, the accounts are
+/// stored in a heap-allocated vector as in [`crate::entrypoint!`].  Otherwise,
+/// they are stored on stack as in [`crate::entrypoint_no_alloc!`].
+///
+/// Optionally, `codec = Type`, `borsh`, `with_source`, `discriminator` and
+/// `allocator: Type = init` arguments (see [`crate::entrypoint!`]) may follow.
+///
+/// For example:
+///
+/// ```ignore
+/// const CONFIG: Config = Config {
+///     expected_writer: Some(WRITE_ACCOUNT_PROGRAM_ID),
+///     retain_account: true,
+///     ..Config::DEFAULT
+/// };
+///
+/// entrypoint_with_config!(process_instruction, CONFIG, codec = MyCodec);
+/// ```
+#[macro_export]
+#[rustfmt::skip]
+macro_rules! entrypoint_with_config {
+    (@parse $process_instruction:expr, $config:expr, [$codec:ty], [$call:ident],
+     [$($heap:tt)*], codec = $value:ty $(, $($rest:tt)*)?) => {
+        $crate::entrypoint_with_config!(
+            @parse $process_instruction, $config, [$value], [$call],
+            [$($heap)*], $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, $config:expr, [$codec:ty], [$call:ident],
+     [$($heap:tt)*], borsh $(, $($rest:tt)*)?) => {
+        $crate::entrypoint_with_config!(
+            @parse $process_instruction, $config, [$codec], [call_borsh],
+            [$($heap)*], $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, $config:expr, [$codec:ty], [$call:ident],
+     [$($heap:tt)*], with_source $(, $($rest:tt)*)?) => {
+        $crate::entrypoint_with_config!(
+            @parse $process_instruction, $config, [$codec], [call_with_source],
+            [$($heap)*], $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, $config:expr, [$codec:ty], [$call:ident],
+     [$($heap:tt)*], discriminator $(, $($rest:tt)*)?) => {
+        $crate::entrypoint_with_config!(
+            @parse $process_instruction, $config, [$codec],
+            [call_discriminated], [$($heap)*], $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, $config:expr, [$codec:ty], [$call:ident],
+     [$($heap:tt)*],
+     allocator: $alloc:ty = $init:expr $(, $($rest:tt)*)?) => {
+        $crate::entrypoint_with_config!(
+            @parse $process_instruction, $config, [$codec], [$call],
+            [$alloc = $init], $($($rest)*)?
+        );
+    };
+    (@parse $process_instruction:expr, $config:expr, [$codec:ty], [$call:ident],
+     [$($heap:tt)*], ) => {
+        $crate::entrypoint_with_config!(
+            @emit $process_instruction, [$codec], [$call], [$($heap)*], $config
+        );
+    };
+    (@heap) => {
+        $crate::entrypoint::__private::custom_heap_default!();
+    };
+    (@heap $alloc:ty = $init:expr) => {
+        #[cfg(target_os = "solana")]
+        #[global_allocator]
+        static __WRITE_ACCOUNT_ALLOCATOR: $alloc = $init;
+    };
+    (@emit $process_instruction:expr, [$codec:ty], [$call:ident],
+     [$($heap:tt)*], $config:expr) => {
+        /// Solana program entry point.
+        ///
+        /// # Safety
+        ///
+        /// Must be called with pointer to properly serialised instruction such
+        /// as done by the Solana runtime.
+        #[no_mangle]
+        pub unsafe extern "C" fn entrypoint(input: *mut u8) -> u64 {
+            // Unusual name so it doesn’t shadow a constant `$config` refers to.
+            const __WRITE_ACCOUNT_CONFIG: $crate::entrypoint::Config = $config;
+            // SAFETY: Caller guarantees it’s safe.
+            unsafe {
+                if __WRITE_ACCOUNT_CONFIG.max_accounts == 0 {
+                    $crate::entrypoint::__private::entrypoint_with_source_impl::<
+                        $codec,
+                    >(
+                        input,
+                        &__WRITE_ACCOUNT_CONFIG,
+                        |pid, accs, data, source| {
+                            $crate::entrypoint::__private::$call(
+                                pid, accs, data, source, $process_instruction,
+                            )
+                        },
+                    )
+                } else {
+                    $crate::entrypoint::__private::entrypoint_no_alloc_with_source_impl::<
+                        $codec,
+                        { __WRITE_ACCOUNT_CONFIG.max_accounts },
+                    >(
+                        input,
+                        &__WRITE_ACCOUNT_CONFIG,
+                        |pid, accs, data, source| {
+                            $crate::entrypoint::__private::$call(
+                                pid, accs, data, source, $process_instruction,
+                            )
+                        },
+                    )
+                }
+            }
+        }
+        $crate::entrypoint_with_config!(@heap $($heap)*);
+        $crate::entrypoint::__private::custom_panic_default!();
+    };
+    ($process_instruction:expr, $config:expr $(, $($args:tt)*)?) => {
+        $crate::entrypoint_with_config!(
+            @parse $process_instruction, $config,
+            [$crate::entrypoint::LengthPrefixed], [call], [],
+            $($($args)*)?
+        );
+    };
+}
+
+
+/// Declare the program entrypoint of an Anchor program.
+///
+/// Anchor’s `#[program]` attribute generates an `entry` function which
+/// dispatches the instruction based on the discriminator at the start of the
+/// instruction data.  This macro declares the program entrypoint which
+/// substitutes the instruction data (as described in [`deserialize`]) before
+/// calling that `entry` function.  Since the data read from the account is
+/// passed unmodified, Anchor discriminators and `Context` handling work as
+/// usual.
+///
+/// Anchor declares its own entrypoint unless the program is built with
+/// `no-entrypoint` feature.  The program therefore needs to enable that
+/// feature and invoke this macro in the crate root (where `entry` is
+/// defined) instead.
+///
+/// Accepts the same optional arguments as [`crate::entrypoint!`].  With the
+/// `mixed` argument, the client may keep the 8-byte discriminator inline (see
+/// [`WriteIter::new_mixed`](`crate::instruction::WriteIter::new_mixed`)).
+///
+/// For example:
+///
+/// ```ignore
+/// #[program]
+/// pub mod my_program {
+///     // ...
+/// }
+///
+/// solana_write_account::anchor_entrypoint!();
+/// ```
+#[macro_export]
+#[rustfmt::skip]
+macro_rules! anchor_entrypoint {
+    () => {
+        $crate::entrypoint!(entry);
+    };
+    ($($args:tt)+) => {
+        $crate::entrypoint!(entry, $($args)+);
+    };
+}
+
+
+/// Declare the program entrypoint which parses accounts on demand.
+///
+/// Rather than parsing all accounts up front, the `$process_instruction`
+/// function is passed an [`InstructionContext`] through which it reads the
+/// accounts and instruction data when it needs them.  The instruction data is
+/// resolved as described in [`deserialize`] only when the program asks for
+/// it.  This saves compute units for programs which take many accounts.
+///
+/// Just like with [`crate::entrypoint!`], codec can be specified with `codec`
+/// argument.
+#[macro_export]
+#[rustfmt::skip]
+macro_rules! entrypoint_lazy {
+    ($process_instruction:expr) => {
+        $crate::entrypoint_lazy!(
+            $process_instruction,
+            codec = $crate::entrypoint::LengthPrefixed
+        );
+    };
+    ($process_instruction:expr, codec = $codec:ty) => {
+        /// Solana program entry point.
+        ///
+        /// # Safety
+        ///
+        /// Must be called with pointer to properly serialised instruction such
+        /// as done by the Solana runtime.
+        #[no_mangle]
+        pub unsafe extern "C" fn entrypoint(input: *mut u8) -> u64 {
+            // SAFETY: Caller guarantees it’s safe.
+            unsafe {
+                $crate::entrypoint::__private::entrypoint_lazy_impl::<$codec, _>(
+                    input,
+                    $process_instruction,
+                )
+            }
+        }
+        $crate::entrypoint::__private::custom_heap_default!();
+        $crate::entrypoint::__private::custom_panic_default!();
+    };
+}
+
+
+#[doc(hidden)]
+pub mod __private {
+    use core::mem::MaybeUninit;
+
+    use super::IxDataSource;
+    use crate::sdk::account_info::AccountInfo;
+    use crate::sdk::entrypoint::SUCCESS;
+    pub use crate::sdk::entrypoint::{
+        custom_heap_default, custom_panic_default,
+    };
+    use crate::sdk::program_error::ProgramError;
+    use crate::sdk::pubkey::Pubkey;
+
+    type Result = core::result::Result<(), ProgramError>;
+
+    #[inline(always)]
+    pub fn call<'a, E: Into<ProgramError>>(
+        program_id: &'a Pubkey,
+        accounts: &'a [AccountInfo<'a>],
+        data: &'a [u8],
+        _source: IxDataSource<'a>,
+        process: impl FnOnce(
+            &'a Pubkey,
+            &'a [AccountInfo<'a>],
+            &'a [u8],
+        ) -> core::result::Result<(), E>,
+    ) -> Result {
+        process(program_id, accounts, data).map_err(Into::into)
+    }
+
+    #[inline(always)]
+    pub fn call_with_source<'a, E: Into<ProgramError>>(
+        program_id: &'a Pubkey,
+        accounts: &'a [AccountInfo<'a>],
+        data: &'a [u8],
+        source: IxDataSource<'a>,
+        process: impl FnOnce(
+            &'a Pubkey,
+            &'a [AccountInfo<'a>],
+            &'a [u8],
+            IxDataSource<'a>,
+        ) -> core::result::Result<(), E>,
+    ) -> Result {
+        process(program_id, accounts, data, source).map_err(Into::into)
+    }
+
+    #[inline(always)]
+    pub fn call_discriminated<'a, E: Into<ProgramError>>(
+        program_id: &'a Pubkey,
+        accounts: &'a [AccountInfo<'a>],
+        data: &'a [u8],
+        _source: IxDataSource<'a>,
+        process: impl FnOnce(
+            &'a Pubkey,
+            &'a [AccountInfo<'a>],
+            &'a [u8; 8],
+            &'a [u8],
+        ) -> core::result::Result<(), E>,
+    ) -> Result {
+        let (discriminator, data) = super::split_discriminator(data)?;
+        process(program_id, accounts, discriminator, data).map_err(Into::into)
+    }
+
+    #[cfg(feature = "borsh")]
+    #[inline(always)]
+    pub fn call_borsh<'a, T: borsh::BorshDeserialize, E: Into<ProgramError>>(
+        program_id: &'a Pubkey,
+        accounts: &'a [AccountInfo<'a>],
+        data: &[u8],
+        _source: IxDataSource<'a>,
+        process: impl FnOnce(
+            &'a Pubkey,
+            &'a [AccountInfo<'a>],
+            T,
+        ) -> core::result::Result<(), E>,
+    ) -> Result {
+        process(program_id, accounts, super::parse(data)?).map_err(Into::into)
+    }
+
+    #[inline(always)]
+    pub unsafe fn entrypoint_impl<C: super::PayloadCodec>(
+        input: *mut u8,
+        config: &super::Config,
+        process: impl for<'a> FnOnce(
+            &'a Pubkey,
+            &'a [AccountInfo<'a>],
+            &'a [u8],
+        ) -> Result,
+    ) -> u64 {
+        // SAFETY: Caller promises this is safe.
+        unsafe {
+            entrypoint_with_source_impl::<C>(
+                input,
+                config,
+                |pid, accs, data, _| process(pid, accs, data),
+            )
+        }
+    }
+
+    #[inline(always)]
+    pub unsafe fn entrypoint_with_source_impl<C: super::PayloadCodec>(
+        input: *mut u8,
+        config: &super::Config,
+        process: impl for<'a> FnOnce(
+            &'a Pubkey,
+            &'a [AccountInfo<'a>],
+            &'a [u8],
+            IxDataSource<'a>,
+        ) -> Result,
+    ) -> u64 {
+        // SAFETY: Caller promises this is safe.
+        let parsed = unsafe { super::deserialize_impl::<C>(input, config) };
+        let (program_id, accounts, instruction_data, source, wipe) =
+            match parsed {
+                Ok(it) => it,
+                Err(error) => return error.into(),
+            };
+        match process(program_id, &accounts, instruction_data, source) {
+            // SAFETY: The instruction has been processed so nothing refers to
+            // the payload any longer.
+            Ok(()) => unsafe { wipe_data(wipe) },
+            Err(error) => error.into(),
+        }
+    }
+
+    #[inline(always)]
+    pub unsafe fn entrypoint_no_alloc_impl<
+        C: super::PayloadCodec,
+        const MAX_ACCOUNTS: usize,
+    >(
+        input: *mut u8,
+        config: &super::Config,
+        process: impl for<'a> FnOnce(
+            &'a Pubkey,
+            &'a [AccountInfo<'a>],
+            &'a [u8],
+        ) -> Result,
+    ) -> u64 {
+        // SAFETY: Caller promises this is safe.
+        unsafe {
+            entrypoint_no_alloc_with_source_impl::<C, MAX_ACCOUNTS>(
+                input,
+                config,
+                |pid, accs, data, _| process(pid, accs, data),
+            )
+        }
+    }
+
+    #[inline(always)]
+    pub unsafe fn entrypoint_no_alloc_with_source_impl<
+        C: super::PayloadCodec,
+        const MAX_ACCOUNTS: usize,
+    >(
+        input: *mut u8,
+        config: &super::Config,
+        process: impl for<'a> FnOnce(
+            &'a Pubkey,
+            &'a [AccountInfo<'a>],
+            &'a [u8],
+            IxDataSource<'a>,
+        ) -> Result,
+    ) -> u64 {
+        let mut accounts =
+            [const { MaybeUninit::<AccountInfo>::uninit() }; MAX_ACCOUNTS];
+        // SAFETY: Caller promises this is safe.
+        let parsed = unsafe {
+            super::deserialize_into_impl::<C>(input, &mut accounts, config)
+        };
+        let (program_id, num_accounts, instruction_data, source, wipe) =
+            match parsed {
+                Ok(it) => it,
+                Err(error) => return error.into(),
+            };
+        let accounts = &accounts[..num_accounts]
+            as *const [MaybeUninit<AccountInfo>]
+            as *const [AccountInfo];
+        // SAFETY: deserialize_into initialised the first num_accounts entries
+        // of the array and `MU<X>` has the same layout as `X`.
+        let accounts = unsafe { &*accounts };
+
+        // Make sure we have a new stack frame.  Solana stack frame sizes are
+        // limited so the accounts array would eat into user’s available stack
+        // space.
+        #[inline(never)]
+        fn inner<'a>(
+            program_id: &'a Pubkey,
+            accounts: &'a [AccountInfo<'a>],
+            data: &'a [u8],
+            source: IxDataSource<'a>,
+            process: impl FnOnce(
+                &'a Pubkey,
+                &'a [AccountInfo<'a>],
+                &'a [u8],
+                IxDataSource<'a>,
+            ) -> Result,
+        ) -> Result {
+            process(program_id, accounts, data, source)
+        }
+
+        match inner(program_id, accounts, instruction_data, source, process) {
+            // SAFETY: The instruction has been processed so nothing refers to
+            // the payload any longer.
+            Ok(()) => unsafe { wipe_data(wipe) },
+            Err(error) => error.into(),
+        }
+    }
+
+    /// Zeroes account data returned when [`super::Config::zeroize`] is set
+    /// and returns [`SUCCESS`].
+    ///
+    /// # Safety
+    ///
+    /// Nothing may refer to the data.
+    unsafe fn wipe_data(data: Option<*mut [u8]>) -> u64 {
+        if let Some(data) = data {
+            // SAFETY: Caller promises this is safe.
+            unsafe { (*data).fill(0) };
+        }
+        SUCCESS
+    }
+
+    #[inline(always)]
+    pub unsafe fn entrypoint_lazy_impl<
+        C: super::PayloadCodec,
+        E: Into<ProgramError>,
+    >(
+        input: *mut u8,
+        process: impl FnOnce(
+            super::InstructionContext<C>,
+        ) -> core::result::Result<(), E>,
+    ) -> u64 {
+        // SAFETY: Caller promises this is safe.
+        let context = unsafe { super::InstructionContext::new(input) };
+        process(context).map_or_else(|error| error.into().into(), |()| SUCCESS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::sdk::entrypoint::MAX_PERMITTED_DATA_INCREASE;
+    use crate::test_utils::{serialise_input, TestAccount};
+
+    #[test]
+    fn test_get_ix_data() {
+        let key = Pubkey::new_unique();
+
+        fn account_info<'a>(
+            key: &'a Pubkey,
+            lamports: &'a mut u64,
+            data: &'a mut [u8],
+        ) -> AccountInfo<'a> {
+            AccountInfo::new(key, false, false, lamports, data, key, false, 0)
+        }
+
+        let check = |want, data: &[u8]| {
+            let mut lamports = 0u64;
+            let mut data = data.to_vec();
+            let acc = account_info(&key, &mut lamports, &mut data);
+            assert_eq!(
+                want,
+                super::take_ix_data::<LengthPrefixed>(
+                    acc,
+                    &[],
+                    &Config::DEFAULT
+                )
+                .map(|(payload, _)| payload)
+            );
+        };
+
+        check(Err(WriteAccountError::TooShort.into()), &[][..]);
+        check(Ok(&[][..]), &[0, 0, 0, 0, 1, 2, 3, 4][..]);
+        check(Ok(&[1][..]), &[1, 0, 0, 0, 1, 2, 3, 4][..]);
+        check(
+            Err(WriteAccountError::LengthOutOfBounds.into()),
+            &[1, 0, 0, 0][..],
+        );
+    }
+
+    #[test]
+    fn test_read_ix_data() {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = b"\x04\x00\x00\x00data".to_vec();
+        let account = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &key,
+            false,
+            0,
+        );
+        assert_eq!(Ok(&b"data"[..]), super::read_ix_data(&account));
+        assert!(account.try_borrow_mut_data().is_err());
+        assert_eq!(Ok(&b"data"[..]), super::read_ix_data(&account));
+    }
+
+    #[test]
+    fn test_get_ix_data_v2() {
+        let check = |want: Result<&[u8], WriteAccountError>, data: &[u8]| {
+            assert_eq!(want, LengthPrefixed::decode(data));
+        };
+
+        let header = V2Header {
+            flags: 0,
+            len: 4,
+            offset: None,
+            crc32: None,
+            tag: None,
+            sequence: None,
+        }
+        .to_vec();
+        assert_eq!([0, 8, 0, 2, 4, 0, 0, 0], header[..]);
+        check(Ok(b"data"), &[&header[..], b"data!"].concat());
+        check(Ok(b"data"), &[
+            0, 10, 0, 2, 4, 0, 0, 0, 9, 9, b'd', b'a', b't', b'a',
+        ]);
+        check(
+            Err(WriteAccountError::LengthOutOfBounds),
+            &[&header[..], b"dat"].concat(),
+        );
+        check(Err(WriteAccountError::TooShort), &[0, 10, 0, 2, 0, 0, 0, 0]);
+        check(Err(WriteAccountError::UnsupportedHeader), &[
+            0x80, 8, 0, 2, 0, 0, 0, 0,
+        ]);
+        check(Err(WriteAccountError::UnsupportedHeader), &[
+            0, 8, 1, 2, 0, 0, 0, 0,
+        ]);
+        check(Err(WriteAccountError::UnsupportedHeader), &[
+            0, 6, 0, 2, 0, 0, 0, 0,
+        ]);
+        check(Err(WriteAccountError::UnsupportedHeader), &[
+            0, 8, 0, 3, 0, 0, 0, 0,
+        ]);
+        check(Err(WriteAccountError::UnsupportedHeader), &[
+            0, 8, 0, 0x82, 0, 0, 0, 0,
+        ]);
+
+        let header = V2Header {
+            flags: 0,
+            len: 4,
+            offset: Some(14),
+            crc32: None,
+            tag: None,
+            sequence: None,
+        }
+        .to_vec();
+        assert_eq!([2, 12, 0, 2, 4, 0, 0, 0, 14, 0, 0, 0], header[..]);
+        check(Ok(b"data"), &[&header[..], b"..data"].concat());
+        check(Err(WriteAccountError::UnsupportedHeader), &[
+            2, 8, 0, 2, 4, 0, 0, 0, 14, 0, 0, 0,
+        ]);
+        check(
+            Err(WriteAccountError::LengthOutOfBounds),
+            &[&header[..], b"..dat"].concat(),
+        );
+        check(Err(WriteAccountError::LengthOutOfBounds), &header);
+
+        let crc32 = Some(crate::header::crc32(b"data"));
+        let header = V2Header {
+            flags: 0,
+            len: 4,
+            offset: None,
+            crc32,
+            tag: None,
+            sequence: None,
+        }
+        .to_vec();
+        assert_eq!([8, 12, 0, 2, 4, 0, 0, 0], header[..8]);
+        check(Ok(b"data"), &[&header[..], b"data"].concat());
+        check(
+            Err(WriteAccountError::ChecksumMismatch),
+            &[&header[..], b"date"].concat(),
+        );
+        let header = V2Header {
+            flags: 0,
+            len: 4,
+            offset: Some(18),
+            crc32,
+            tag: None,
+            sequence: None,
+        }
+        .to_vec();
+        assert_eq!([10, 16, 0, 2, 4, 0, 0, 0, 18, 0, 0, 0], header[..12]);
+        check(Ok(b"data"), &[&header[..], b"..data"].concat());
+        check(Err(WriteAccountError::UnsupportedHeader), &[
+            8, 8, 0, 2, 0, 0, 0, 0,
+        ]);
+    }
+
+    #[test]
+    fn test_loader_buffer() {
+        let check = |want: Result<&[u8], WriteAccountError>, data: &[u8]| {
+            assert_eq!(want, LoaderBuffer::decode(data));
+        };
+
+        let mut data = [0; LoaderBuffer::METADATA_LEN + 4];
+        data[0] = 1;
+        data[4] = 1;
+        data[LoaderBuffer::METADATA_LEN..].copy_from_slice(b"data");
+        check(Ok(b"data"), &data);
+        check(Ok(b""), &data[..LoaderBuffer::METADATA_LEN]);
+        check(Err(WriteAccountError::TooShort), &data[..10]);
+        data[0] = 3;
+        check(Err(WriteAccountError::UnsupportedHeader), &data);
+    }
+
+    #[test]
+    fn test_raw() {
+        assert_eq!(Ok(&b"data"[..]), Raw::<{ usize::MAX }>::decode(b"data"));
+        assert_eq!(Ok(&b""[..]), Raw::<{ usize::MAX }>::decode(b""));
+        assert_eq!(Ok(&b"da"[..]), Raw::<2>::decode(b"data"));
+        assert_eq!(Err(WriteAccountError::TooShort), Raw::<5>::decode(b"data"));
+    }
+
+    #[test]
+    #[cfg(feature = "bytemuck")]
+    fn test_parse_pod() {
+        let data = [0x1234_5678u32, 0x9abc_def0];
+        let bytes: &[u8] = bytemuck::cast_slice(&data);
+        assert_eq!(Ok(&data), parse_pod::<[u32; 2]>(bytes));
+        assert_eq!(Ok(&data[1]), parse_pod::<u32>(&bytes[4..]));
+        let err = Err(WriteAccountError::InvalidPayload.into());
+        assert_eq!(err, parse_pod::<u32>(&bytes[..3]).map(|_| ()));
+        assert_eq!(err, parse_pod::<u16>(&bytes[1..3]).map(|_| ()));
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0, crate::header::crc32(b""));
+        assert_eq!(0xCBF4_3926, crate::header::crc32(b"123456789"));
+    }
+
+    #[test]
+    fn test_get_ix_data_chained() {
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let writer = Pubkey::new_unique();
+        let chained = |len: u32, data: &[u8], next: &Pubkey| {
+            let flags = V2Header::FLAG_CHAINED;
+            let header = V2Header {
+                flags,
+                len,
+                offset: None,
+                crc32: None,
+                tag: None,
+                sequence: None,
+            }
+            .to_vec();
+            [&header[..], data, next.as_ref()].concat()
+        };
+        let head = chained(3, b"foo", &first);
+        let mut first_data = chained(3, b"bar", &second);
+        let mut second_data = [
+            &V2Header {
+                flags: 0,
+                len: 3,
+                offset: None,
+                crc32: None,
+                tag: None,
+                sequence: None,
             }
+            .to_vec()[..],
+            b"baz",
+        ]
+        .concat();
+        let (mut lamports_a, mut lamports_b) = (0u64, 0u64);
+        let accounts = [
+            AccountInfo::new(
+                &first,
+                false,
+                false,
+                &mut lamports_a,
+                &mut first_data,
+                &writer,
+                false,
+                0,
+            ),
+            AccountInfo::new(
+                &second,
+                false,
+                false,
+                &mut lamports_b,
+                &mut second_data,
+                &second,
+                false,
+                0,
+            ),
+        ];
+
+        let check = |want: Result<&[u8], WriteAccountError>, got| {
+            assert_eq!(want.map_err(ProgramError::from), got);
+        };
+        let missing = Err(WriteAccountError::MissingContinuationAccount);
+        let decode = |head, accounts: &[AccountInfo]| {
+            let checked = CheckedAccounts::new(accounts, &Config::DEFAULT);
+            LengthPrefixed::decode_chained(head, &checked)
+        };
+
+        check(Ok(b"foobarbaz"), decode(&head, &accounts));
+        check(missing, LengthPrefixed::decode(&head).map_err(Into::into));
+        check(missing, decode(&head, &accounts[..1]));
+        check(
+            Err(WriteAccountError::LengthOutOfBounds),
+            decode(&head[..head.len() - 1], &[]),
+        );
+
+        // Continuation accounts are subject to the same owner checks as the
+        // first account.  The second continuation is owned by a foreign
+        // program.
+        let decode_with = |config: Config| {
+            let checked = CheckedAccounts::new(&accounts, &config);
+            LengthPrefixed::decode_chained(&head, &checked)
+        };
+        let wrong_owner = Err(WriteAccountError::WrongOwner);
+        let config =
+            Config { expected_writer: Some(writer), ..Config::DEFAULT };
+        check(wrong_owner, decode_with(config));
+        let config =
+            Config { expected_writer: Some(second), ..Config::DEFAULT };
+        check(wrong_owner, decode_with(config));
+        let config = Config { require_readonly: true, ..Config::DEFAULT };
+        check(Ok(b"foobarbaz"), decode_with(config));
+
+        // A loop is detected rather than followed forever.
+        let looped = chained(3, b"bar", &first);
+        accounts[0].try_borrow_mut_data().unwrap().copy_from_slice(&looped);
+        check(missing, decode(&head, &accounts));
+    }
+
+    #[test]
+    fn test_get_ix_data_compressed() {
+        let key = Pubkey::new_unique();
+        let payload = b"The quick brown fox jumps over the lazy dog";
+        let frame = zstd_frame(payload);
+
+        let mut data = (frame.len() as u32 | ZSTD_FLAG).to_le_bytes().to_vec();
+        data.extend_from_slice(&frame);
+        data.extend_from_slice(b"garbage");
+
+        let mut lamports = 0u64;
+        let acc = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &key,
+            false,
+            0,
+        );
+        if cfg!(feature = "zstd") {
+            assert_eq!(
+                Ok(&payload[..]),
+                super::take_ix_data::<LengthPrefixed>(
+                    acc,
+                    &[],
+                    &Config::DEFAULT
+                )
+                .map(|(payload, _)| payload)
+            );
+        } else {
+            assert_eq!(
+                Err(WriteAccountError::CompressionUnsupported.into()),
+                super::take_ix_data::<LengthPrefixed>(
+                    acc,
+                    &[],
+                    &Config::DEFAULT
+                )
+                .map(|(payload, _)| payload)
+            );
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    fn zstd_frame(data: &[u8]) -> Vec<u8> {
+        use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+        compress_to_vec(data, CompressionLevel::Fastest)
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    fn zstd_frame(data: &[u8]) -> Vec<u8> { data.to_vec() }
+
+    /// Frames produced by libzstd (which clients use to compress the data)
+    /// decode with the pure-Rust decoder used on chain.
+    #[test]
+    #[cfg(all(feature = "zstd", feature = "client-zstd"))]
+    fn test_decompress_libzstd() {
+        let compressed = |frame: &[u8]| {
+            let len = frame.len() as u32 | ZSTD_FLAG;
+            [&len.to_le_bytes()[..], frame].concat()
+        };
+        let payload = b"The quick brown fox jumps over the lazy dog".repeat(16);
+        for level in [1, 3, 19] {
+            let frame = zstd::stream::encode_all(&payload[..], level).unwrap();
+            let data = compressed(&frame);
+            assert_eq!(Ok(&payload[..]), LengthPrefixed::decode(&data));
+        }
+        let want = Err(WriteAccountError::DecompressionFailed);
+        assert_eq!(want, LengthPrefixed::decode(&compressed(b"garbage")));
+    }
+
+    #[test]
+    fn test_split_ix_account() {
+        let key = Pubkey::new_unique();
+        let mut lamports = [0u64; 2];
+        let [lamports_0, lamports_1] = &mut lamports;
+        let mut data_0 = b"raz".to_vec();
+        let mut data_1 = b"\x04\x00\x00\x00data".to_vec();
+        let accounts = [
+            AccountInfo::new(
+                &key,
+                false,
+                false,
+                lamports_0,
+                &mut data_0,
+                &key,
+                false,
+                0,
+            ),
+            AccountInfo::new(
+                &key,
+                false,
+                false,
+                lamports_1,
+                &mut data_1,
+                &key,
+                false,
+                0,
+            ),
+        ];
+
+        let (accs, data) = split_ix_account(&accounts, b"inline").unwrap();
+        assert_eq!((2, &b"inline"[..]), (accs.len(), &*data));
+        assert!(matches!(data, Cow::Borrowed(_)));
+
+        let (accs, data) = split_ix_account(&accounts, b"").unwrap();
+        assert_eq!((1, &b"data"[..]), (accs.len(), &*data));
+
+        assert_eq!(
+            Err(WriteAccountError::TooShort.into()),
+            split_ix_account(&accounts[..1], b"").map(|_| ())
+        );
+        assert_eq!(
+            Err(WriteAccountError::MissingDataAccount.into()),
+            split_ix_account(&[], b"").map(|_| ())
+        );
+    }
+
+    /// Returns error code the entrypoint returns for given error.
+    fn code(err: WriteAccountError) -> u64 { ProgramError::from(err).into() }
+
+    fn do_test_entrypoint(
+        accounts: &[TestAccount],
+        instruction_data: &[u8],
+        want: Result<(usize, &[u8]), u64>,
+    ) {
+        do_test_entrypoint_with::<LengthPrefixed>(
+            accounts,
+            instruction_data,
+            want,
+        )
+    }
+
+    fn do_test_entrypoint_with<C: PayloadCodec>(
+        accounts: &[TestAccount],
+        instruction_data: &[u8],
+        want: Result<(usize, &[u8]), u64>,
+    ) {
+        do_test_entrypoint_impl::<C, false>(accounts, instruction_data, want)
+    }
+
+    fn do_test_entrypoint_impl<C: PayloadCodec, const MIXED: bool>(
+        accounts: &[TestAccount],
+        instruction_data: &[u8],
+        want: Result<(usize, &[u8]), u64>,
+    ) {
+        let (program_id, mut data, offset) =
+            serialise_input(accounts, instruction_data);
+        let config = Config { mixed: MIXED, ..Config::DEFAULT };
+
+        let check =
+            |got_id: &Pubkey, got_accounts: &[AccountInfo], got_data: &[u8]| {
+                assert_eq!(&program_id, got_id);
+                assert_eq!(want, Ok((got_accounts.len(), got_data)));
+                for (acc, got) in accounts.iter().zip(got_accounts.iter()) {
+                    assert_eq!(acc, got);
+                }
+            };
+
+        let input = data.as_mut_ptr().wrapping_add(offset);
+        let want_result = want.err().unwrap_or(0);
+        assert_eq!(want_result, unsafe {
+            __private::entrypoint_impl::<C>(
+                input,
+                &config,
+                |id, accounts, data| {
+                    check(id, accounts, data);
+                    Ok(())
+                },
+            )
+        });
+        assert_eq!(want_result, unsafe {
+            __private::entrypoint_no_alloc_impl::<C, 64>(
+                input,
+                &config,
+                |id, accounts, data| {
+                    check(id, accounts, data);
+                    Ok(())
+                },
+            )
+        });
+        if MIXED {
+            return;
+        }
+        assert_eq!(want_result, unsafe {
+            __private::entrypoint_lazy_impl::<C, ProgramError>(
+                input,
+                |mut ctx| {
+                    let data = ctx.instruction_data()?;
+                    let mut got_accounts = Vec::new();
+                    while let Ok(account) = ctx.next_account() {
+                        match account {
+                            MaybeAccount::Account(account) => {
+                                got_accounts.push(account)
+                            }
+                            MaybeAccount::Duplicated(_) => unreachable!(),
+                        }
+                    }
+                    assert_eq!(0, ctx.remaining());
+                    check(ctx.program_id(), &got_accounts, data);
+                    Ok(())
+                },
+            )
+        });
+    }
+
+    #[test]
+    fn test_entrypoint_normal() {
+        let accounts = [TestAccount::new(b"raz")];
+        do_test_entrypoint(&accounts, b"data", Ok((1, b"data")));
+    }
+
+    #[test]
+    fn test_entrypoint_staged() {
+        let data = b"\x04\x00\x00\x00data";
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(data)];
+        do_test_entrypoint(&accounts[1..], b"", Ok((0, b"data")));
+        do_test_entrypoint(&accounts, b"", Ok((1, b"data")));
+    }
+
+    #[test]
+    fn test_entrypoint_long_staged() {
+        let data = b"\x04\x00\x00\x00datagarbage";
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(data)];
+        do_test_entrypoint(&accounts[1..], b"", Ok((0, b"data")));
+        do_test_entrypoint(&accounts, b"", Ok((1, b"data")));
+    }
+
+    #[test]
+    fn test_entrypoint_short_staged() {
+        let data = b"\x04\x00\x00\x00dat";
+        let want = Err(code(WriteAccountError::LengthOutOfBounds));
+        do_test_entrypoint(&[TestAccount::new(data)], b"", want);
+    }
+
+    #[test]
+    fn test_deserialize_keep() {
+        use core::mem::MaybeUninit;
+
+        let data = b"\x04\x00\x00\x00data";
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(data)];
+        let want = KeptIxData { data: b"data", account: Some(1) };
+
+        let (program_id, mut input, offset) = serialise_input(&accounts, b"");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let (got_id, got_accounts, got) =
+            unsafe { deserialize_keep::<LengthPrefixed>(input) }.unwrap();
+        assert_eq!((&program_id, 2, want), (got_id, got_accounts.len(), got));
+        assert_eq!(&accounts[1], &got_accounts[1]);
+        assert!(got_accounts[1].try_borrow_mut_data().is_err());
+
+        let (_, mut input, offset) = serialise_input(&accounts, b"");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let mut infos = [const { MaybeUninit::uninit() }; 2];
+        let (_, count, got) = unsafe {
+            deserialize_into_keep::<LengthPrefixed>(input, &mut infos)
+        }
+        .unwrap();
+        assert_eq!((2, want), (count, got));
+
+        let (_, mut input, offset) = serialise_input(&accounts, b"inline");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let (_, got_accounts, got) =
+            unsafe { deserialize_keep::<LengthPrefixed>(input) }.unwrap();
+        let want = KeptIxData { data: b"inline", account: None };
+        assert_eq!((2, want), (got_accounts.len(), got));
+    }
+
+    #[test]
+    fn test_entrypoint_anchor_signature() {
+        // Anchor’s generated `entry` ties lifetime of the accounts slice with
+        // the lifetime of the accounts.
+        fn entry<'info>(
+            _program_id: &Pubkey,
+            accounts: &'info [AccountInfo<'info>],
+            data: &[u8],
+        ) -> Result<(), ProgramError> {
+            assert_eq!((1, &b"data"[..]), (accounts.len(), data));
+            Ok(())
+        }
+
+        let data = b"\x04\x00\x00\x00data";
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(data)];
+        let (_, mut input, offset) = serialise_input(&accounts, b"");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        assert_eq!(0, unsafe {
+            __private::entrypoint_with_source_impl::<LengthPrefixed>(
+                input,
+                &Config::DEFAULT,
+                |pid, accs, data, src| {
+                    __private::call(pid, accs, data, src, entry)
+                },
+            )
+        });
+        assert_eq!(0, unsafe {
+            __private::entrypoint_no_alloc_with_source_impl::<LengthPrefixed, 4>(
+                input,
+                &Config::DEFAULT,
+                |pid, accs, data, src| {
+                    __private::call(pid, accs, data, src, entry)
+                },
+            )
+        });
+    }
+
+    #[test]
+    fn test_entrypoint_custom_error() {
+        struct Error;
+
+        impl From<Error> for ProgramError {
+            fn from(_: Error) -> Self { ProgramError::Custom(42) }
         }
 
-        for account in accounts {
-            vec.extend_from_slice(&[
-                NON_DUP_MARKER,
-                account.is_signer as u8,
-                account.is_writable as u8,
-                account.executable as u8,
-                0,
-                0,
-                0,
-                0,
-            ]);
-            vec.extend_from_slice(&account.key.as_ref());
-            vec.extend_from_slice(&account.owner.as_ref());
-            vec.extend_from_slice(&account.lamports.to_le_bytes());
-            vec.extend_from_slice(&(account.data.len() as u64).to_le_bytes());
-            vec.extend_from_slice(account.data.as_slice());
-            let align_offset = align(account.data.len());
-            let padding = MAX_PERMITTED_DATA_INCREASE + align_offset;
-            vec.resize(vec.len() + padding, 0);
-            vec.extend_from_slice(&account.rent_epoch.to_le_bytes());
-        }
-
-        vec.extend_from_slice(&(instruction_data.len() as u64).to_le_bytes());
-        vec.extend_from_slice(instruction_data);
-        vec.extend_from_slice(program_id.as_ref());
-        vec.reserve(BPF_ALIGN_OF_U128 - 1);
-
-        // Make sure the data is serialised.  We do it by inserting appropriate
-        // number of bytes at the start of the vector.
-        let pad = match vec.as_ptr().addr() % BPF_ALIGN_OF_U128 {
-            0 => 0,
-            n => {
-                vec.splice(0..0, core::iter::repeat_n(0, 8 - n));
-                8 - n
+        let accounts = [TestAccount::new(b"raz")];
+        let (_, mut input, offset) = serialise_input(&accounts, b"data");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let process = |_: &Pubkey, _: &[AccountInfo], _: &[u8]| Err(Error);
+        assert_eq!(42, unsafe {
+            __private::entrypoint_with_source_impl::<LengthPrefixed>(
+                input,
+                &Config::DEFAULT,
+                |pid, accs, data, src| {
+                    __private::call(pid, accs, data, src, process)
+                },
+            )
+        });
+        assert_eq!(42, unsafe {
+            __private::entrypoint_lazy_impl::<LengthPrefixed, _>(input, |_| {
+                Err(Error)
+            })
+        });
+    }
+
+    #[test]
+    fn test_entrypoint_expected_writer() {
+        let data = b"\x04\x00\x00\x00data";
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(data)];
+        let (_, mut input, offset) = serialise_input(&accounts, b"");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let invoke = |writer: &Pubkey| unsafe {
+            let config =
+                Config { expected_writer: Some(*writer), ..Config::DEFAULT };
+            let alloc = __private::entrypoint_impl::<LengthPrefixed>(
+                input,
+                &config,
+                |_, _, _| Ok(()),
+            );
+            let no_alloc = __private::entrypoint_no_alloc_impl::<
+                LengthPrefixed,
+                4,
+            >(input, &config, |_, _, _| Ok(()));
+            assert_eq!(alloc, no_alloc);
+            alloc
+        };
+        assert_eq!(0, invoke(&accounts[1].owner));
+        let want = code(WriteAccountError::WrongOwner);
+        assert_eq!(want, invoke(&Pubkey::new_unique()));
+    }
+
+    #[test]
+    fn test_entrypoint_allow_empty() {
+        let data = TestAccount::new(b"\x04\x00\x00\x00data");
+        let other = TestAccount::new(b"raz");
+        let invoke = |accounts: &[TestAccount], config: &Config| {
+            let (_, mut input, offset) = serialise_input(accounts, b"");
+            let input = input.as_mut_ptr().wrapping_add(offset);
+            let got = core::cell::RefCell::new(None);
+            let process = |_: &Pubkey, accs: &[AccountInfo], data: &[u8]| {
+                *got.borrow_mut() = Some((accs.len(), data.to_vec()));
+                Ok(())
+            };
+            let alloc = unsafe {
+                __private::entrypoint_impl::<LengthPrefixed>(
+                    input, config, process,
+                )
+            };
+            let no_alloc = unsafe {
+                __private::entrypoint_no_alloc_impl::<LengthPrefixed, 4>(
+                    input, config, process,
+                )
+            };
+            assert_eq!(alloc, no_alloc);
+            got.into_inner().ok_or(alloc)
+        };
+
+        let config = Config { allow_empty: true, ..Config::DEFAULT };
+        assert_eq!(Ok((0, Vec::new())), invoke(&[], &config));
+        assert_eq!(
+            Err(code(WriteAccountError::MissingDataAccount)),
+            invoke(&[], &Config::DEFAULT)
+        );
+        let got = invoke(core::slice::from_ref(&data), &config);
+        assert_eq!(Ok((0, b"data".to_vec())), got);
+
+        let config = Config {
+            allow_empty: true,
+            expected_writer: Some(data.owner),
+            ..Config::DEFAULT
+        };
+        let got = invoke(&[data.clone(), other.clone()], &config);
+        assert_eq!(Ok((2, Vec::new())), got);
+        let got = invoke(&[other, data], &config);
+        assert_eq!(Ok((1, b"data".to_vec())), got);
+    }
+
+    #[test]
+    fn test_entrypoint_marker() {
+        let marker = TestAccount::new(b"marker");
+        let data = TestAccount::new(b"\x04\x00\x00\x00data");
+        let check =
+            |accounts: &[&TestAccount], config: &Config, want: &[u8]| {
+                let accounts: Vec<_> =
+                    accounts.iter().map(|&acc| acc.clone()).collect();
+                let (_, mut input, offset) =
+                    serialise_input(&accounts, b"\xffinline");
+                let input = input.as_mut_ptr().wrapping_add(offset);
+                let process = |_: &Pubkey, _: &[AccountInfo], data: &[u8]| {
+                    assert_eq!(want, data);
+                    Ok(())
+                };
+                assert_eq!(0, unsafe {
+                    __private::entrypoint_impl::<LengthPrefixed>(
+                        input, config, process,
+                    )
+                });
+                assert_eq!(0, unsafe {
+                    __private::entrypoint_no_alloc_impl::<LengthPrefixed, 4>(
+                        input, config, process,
+                    )
+                });
+            };
+
+        let by_byte = Config { marker_byte: Some(0xff), ..Config::DEFAULT };
+        let by_account =
+            Config { marker_account: Some(marker.key), ..Config::DEFAULT };
+        check(&[&data], &Config::DEFAULT, b"\xffinline");
+        check(&[&data], &by_byte, b"data");
+        check(&[&data], &by_account, b"\xffinline");
+        check(&[&marker, &data], &by_account, b"data");
+        let by_other = Config { marker_byte: Some(0), ..by_account };
+        check(&[&data], &by_other, b"\xffinline");
+    }
+
+    #[test]
+    fn test_entrypoint_index() {
+        let data = b"\x04\x00\x00\x00data";
+        let accounts = [TestAccount::new(data), TestAccount::new(b"raz")];
+        let config = Config { index_magic: Some(0xff), ..Config::DEFAULT };
+        let invoke = |ix_data: &[u8], want: Result<&[u8], u64>| {
+            let (_, mut input, offset) = serialise_input(&accounts, ix_data);
+            let input = input.as_mut_ptr().wrapping_add(offset);
+            let process = |_: &Pubkey, accs: &[AccountInfo], data: &[u8]| {
+                assert_eq!((2, want.unwrap()), (accs.len(), data));
+                let borrowed = accs[0].try_borrow_mut_data().is_err();
+                assert_eq!(data == b"data", borrowed);
+                Ok(())
+            };
+            let want = want.err().unwrap_or(0);
+            assert_eq!(want, unsafe {
+                __private::entrypoint_impl::<LengthPrefixed>(
+                    input, &config, process,
+                )
+            });
+            assert_eq!(want, unsafe {
+                __private::entrypoint_no_alloc_impl::<LengthPrefixed, 4>(
+                    input, &config, process,
+                )
+            });
+        };
+        invoke(b"\xff\x00", Ok(b"data"));
+        invoke(b"\xff\x00!", Ok(b"\xff\x00!"));
+        invoke(b"\xfe\x00", Ok(b"\xfe\x00"));
+        invoke(b"\xff\x02", Err(code(WriteAccountError::MissingDataAccount)));
+        invoke(b"\xff\x01", Err(code(WriteAccountError::TooShort)));
+    }
+
+    #[test]
+    fn test_entrypoint_tag() {
+        let tagged = |tag: u32, payload: &[u8]| {
+            let len = payload.len() as u32;
+            let header = V2Header { len, tag: Some(tag), ..Default::default() };
+            [header.to_vec().as_slice(), payload].concat()
+        };
+        let accounts = [
+            TestAccount::new(b"raz"),
+            TestAccount::new(tagged(1, b"one")),
+            TestAccount::new(tagged(2, b"two")),
+        ];
+        let config = Config { tag_magic: Some(0xff), ..Config::DEFAULT };
+        let invoke = |ix_data: &[u8], want: Result<&[u8], u64>| {
+            let (_, mut input, offset) = serialise_input(&accounts, ix_data);
+            let input = input.as_mut_ptr().wrapping_add(offset);
+            let process = |_: &Pubkey, accs: &[AccountInfo], data: &[u8]| {
+                assert_eq!((3, want.unwrap()), (accs.len(), data));
+                Ok(())
+            };
+            let want = want.err().unwrap_or(0);
+            assert_eq!(want, unsafe {
+                __private::entrypoint_impl::<LengthPrefixed>(
+                    input, &config, process,
+                )
+            });
+            assert_eq!(want, unsafe {
+                __private::entrypoint_no_alloc_impl::<LengthPrefixed, 4>(
+                    input, &config, process,
+                )
+            });
+        };
+        invoke(b"\xff\x01\x00\x00\x00", Ok(b"one"));
+        invoke(b"\xff\x02\x00\x00\x00", Ok(b"two"));
+        invoke(b"\xff\x02\x00\x00", Ok(b"\xff\x02\x00\x00"));
+        invoke(
+            b"\xff\x03\x00\x00\x00",
+            Err(code(WriteAccountError::MissingDataAccount)),
+        );
+    }
+
+    #[test]
+    fn test_entrypoint_require_readonly() {
+        let data = b"\x04\x00\x00\x00data";
+        let config = Config { require_readonly: true, ..Config::DEFAULT };
+        let invoke = |is_writable: bool| {
+            let accounts =
+                [TestAccount { is_writable, ..TestAccount::new(data) }];
+            let (_, mut input, offset) = serialise_input(&accounts, b"");
+            let input = input.as_mut_ptr().wrapping_add(offset);
+            let alloc = unsafe {
+                __private::entrypoint_impl::<LengthPrefixed>(
+                    input,
+                    &config,
+                    |_, _, _| Ok(()),
+                )
+            };
+            let no_alloc = unsafe {
+                __private::entrypoint_no_alloc_impl::<LengthPrefixed, 4>(
+                    input,
+                    &config,
+                    |_, _, _| Ok(()),
+                )
+            };
+            assert_eq!(alloc, no_alloc);
+            alloc
+        };
+        assert_eq!(0, invoke(false));
+        assert_eq!(code(WriteAccountError::WritableDataAccount), invoke(true));
+    }
+
+    #[test]
+    fn test_entrypoint_expected_caller() {
+        let caller = Pubkey::new_unique();
+        let data_acc = TestAccount::new(b"\x04\x00\x00\x00data");
+        let sysvar = |program: &Pubkey, keys: &[&Pubkey]| {
+            let mut data = Vec::from([1, 0, 4, 0, keys.len() as u8, 0]);
+            for key in keys {
+                data.push(0);
+                data.extend_from_slice(key.as_ref());
+            }
+            data.extend_from_slice(program.as_ref());
+            data.extend_from_slice(&[0, 0, 0, 0]);
+            TestAccount {
+                key: INSTRUCTIONS_SYSVAR_ID,
+                ..TestAccount::new(data)
             }
         };
+        let config =
+            Config { expected_caller: Some(caller), ..Config::DEFAULT };
+        let invoke = |accounts: &[TestAccount]| {
+            let (_, mut input, offset) = serialise_input(accounts, b"");
+            let input = input.as_mut_ptr().wrapping_add(offset);
+            let alloc = unsafe {
+                __private::entrypoint_impl::<LengthPrefixed>(
+                    input,
+                    &config,
+                    |_, _, _| Ok(()),
+                )
+            };
+            let no_alloc = unsafe {
+                __private::entrypoint_no_alloc_impl::<LengthPrefixed, 4>(
+                    input,
+                    &config,
+                    |_, _, _| Ok(()),
+                )
+            };
+            assert_eq!(alloc, no_alloc);
+            alloc
+        };
+        let err = code(WriteAccountError::UnexpectedCaller);
+        let good = sysvar(&caller, &[&Pubkey::new_unique(), &data_acc.key]);
+        assert_eq!(0, invoke(&[good, data_acc.clone()]));
+        assert_eq!(err, invoke(core::slice::from_ref(&data_acc)));
+        let other = sysvar(&Pubkey::new_unique(), &[&data_acc.key]);
+        assert_eq!(err, invoke(&[other, data_acc.clone()]));
+        let missing = sysvar(&caller, &[&Pubkey::new_unique()]);
+        assert_eq!(err, invoke(&[missing, data_acc.clone()]));
+    }
+
+    #[test]
+    fn test_entrypoint_single_use() {
+        let config = Config { single_use: true, ..Config::DEFAULT };
+        let invoke = |data: Vec<u8>| {
+            let accounts = [TestAccount::new(data)];
+            let (_, mut input, offset) = serialise_input(&accounts, b"");
+            let input = input.as_mut_ptr().wrapping_add(offset);
+            let alloc = unsafe {
+                __private::entrypoint_impl::<LengthPrefixed>(
+                    input,
+                    &config,
+                    |_, _, _| Ok(()),
+                )
+            };
+            let no_alloc = unsafe {
+                __private::entrypoint_no_alloc_impl::<LengthPrefixed, 4>(
+                    input,
+                    &config,
+                    |_, _, _| Ok(()),
+                )
+            };
+            assert_eq!(alloc, no_alloc);
+            alloc
+        };
+        let v2 = |flags: u8| {
+            let header = V2Header { flags, len: 4, ..Default::default() };
+            [header.to_vec().as_slice(), b"data"].concat()
+        };
+        assert_eq!(0, invoke(v2(0)));
         assert_eq!(
-            0,
-            (vec.as_ptr().wrapping_add(pad)).addr() % BPF_ALIGN_OF_U128
+            code(WriteAccountError::PayloadConsumed),
+            invoke(v2(V2Header::FLAG_CONSUMED))
+        );
+        assert_eq!(
+            code(WriteAccountError::UnsupportedHeader),
+            invoke(b"\x04\x00\x00\x00data".to_vec())
         );
+    }
+
+    #[test]
+    fn test_entrypoint_sequence() {
+        let header =
+            V2Header { len: 4, sequence: Some(7), ..Default::default() };
+        let data = [header.to_vec().as_slice(), b"data"].concat();
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(data)];
+        let config = Config { sequence_magic: Some(0xff), ..Config::DEFAULT };
+        let invoke = |ix_data: &[u8], want: Result<&[u8], u64>| {
+            let (_, mut input, offset) = serialise_input(&accounts, ix_data);
+            let input = input.as_mut_ptr().wrapping_add(offset);
+            let process = |_: &Pubkey, _: &[AccountInfo], data: &[u8]| {
+                assert_eq!(want.unwrap(), data);
+                Ok(())
+            };
+            let want = want.err().unwrap_or(0);
+            assert_eq!(want, unsafe {
+                __private::entrypoint_impl::<LengthPrefixed>(
+                    input, &config, process,
+                )
+            });
+            assert_eq!(want, unsafe {
+                __private::entrypoint_no_alloc_impl::<LengthPrefixed, 4>(
+                    input, &config, process,
+                )
+            });
+        };
+        let err = code(WriteAccountError::SequenceMismatch);
+        invoke(b"", Ok(b"data"));
+        invoke(b"\xff\x07\x00\x00\x00", Ok(b"data"));
+        invoke(b"\xff\x06\x00\x00\x00", Err(err));
+        invoke(b"\xfe\x06\x00\x00\x00", Ok(b"\xfe\x06\x00\x00\x00"));
+    }
+
+    #[test]
+    fn test_deserialize_slice() {
+        let data = b"\x04\x00\x00\x00data";
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(data)];
+        let (program_id, mut input, offset) = serialise_input(&accounts, b"");
+        let len = input.len();
+
+        let config = Config::DEFAULT;
+        let input = &mut input[offset..];
+        let got = deserialize_slice::<LengthPrefixed>(input, &config).unwrap();
+        assert_eq!((&program_id, 1, &b"data"[..]), (got.0, got.1.len(), got.2));
+
+        let mut accs = [const { core::mem::MaybeUninit::uninit() }; 2];
+        let got =
+            deserialize_into_slice::<LengthPrefixed>(input, &mut accs, &config);
+        assert_eq!(Ok((&program_id, 1, &b"data"[..])), got);
+
+        let err = Err(ProgramError::InvalidArgument);
+        let truncated = &mut input[..len - offset - 1];
+        let got = deserialize_slice::<LengthPrefixed>(truncated, &config);
+        assert_eq!(err, got.map(|_| ()));
+        let misaligned = &mut input[1..];
+        let got = deserialize_slice::<LengthPrefixed>(misaligned, &config);
+        assert_eq!(err, got.map(|_| ()));
+    }
+
+    #[test]
+    fn test_entrypoint_max_len() {
+        let accounts = [TestAccount::new(b"\x04\x00\x00\x00data")];
+        let invoke = |ix_data: &[u8], max_len: usize| {
+            let config = Config { max_len: Some(max_len), ..Config::DEFAULT };
+            let (_, mut input, offset) = serialise_input(&accounts, ix_data);
+            let input = input.as_mut_ptr().wrapping_add(offset);
+            let alloc = unsafe {
+                __private::entrypoint_impl::<LengthPrefixed>(
+                    input,
+                    &config,
+                    |_, _, _| Ok(()),
+                )
+            };
+            let no_alloc = unsafe {
+                __private::entrypoint_no_alloc_impl::<LengthPrefixed, 4>(
+                    input,
+                    &config,
+                    |_, _, _| Ok(()),
+                )
+            };
+            assert_eq!(alloc, no_alloc);
+            alloc
+        };
+        assert_eq!(0, invoke(b"", 4));
+        assert_eq!(code(WriteAccountError::PayloadTooLong), invoke(b"", 3));
+        assert_eq!(0, invoke(b"inline", 3));
+    }
+
+    #[test]
+    fn test_entrypoint_zeroize() {
+        const DATA: &[u8] = b"\x04\x00\x00\x00data";
+        let accounts = [TestAccount::new(DATA)];
+        let config = Config { zeroize: true, ..Config::DEFAULT };
+        let has_data =
+            |input: &[u8]| input.windows(DATA.len()).any(|win| win == DATA);
+        let invoke = |no_alloc: bool, result: Result<(), ProgramError>| {
+            let (_, mut input, offset) = serialise_input(&accounts, b"");
+            let ptr = input.as_mut_ptr().wrapping_add(offset);
+            let process = |_: &Pubkey, _: &[AccountInfo], data: &[u8]| {
+                assert_eq!(b"data", data);
+                result
+            };
+            let ret = unsafe {
+                if no_alloc {
+                    __private::entrypoint_no_alloc_impl::<LengthPrefixed, 4>(
+                        ptr, &config, process,
+                    )
+                } else {
+                    __private::entrypoint_impl::<LengthPrefixed>(
+                        ptr, &config, process,
+                    )
+                }
+            };
+            (ret, has_data(&input))
+        };
+        for no_alloc in [false, true] {
+            assert_eq!((0, false), invoke(no_alloc, Ok(())));
+            let err = ProgramError::InvalidArgument;
+            let want = (u64::from(err.clone()), true);
+            assert_eq!(want, invoke(no_alloc, Err(err)));
+        }
+    }
+
+    #[test]
+    fn test_deserialize_trailing() {
+        let data = b"\x04\x00\x00\x00datatrailer";
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(data)];
+        let (_, mut input, offset) = serialise_input(&accounts, b"");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let (_, got_accounts, got) =
+            unsafe { deserialize_trailing(input) }.unwrap();
+        assert_eq!(1, got_accounts.len());
+        let want = TrailingIxData { data: b"data", trailing: b"trailer" };
+        assert_eq!(want, got);
+
+        let (_, mut input, offset) = serialise_input(&accounts, b"inline");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let (_, got_accounts, got) =
+            unsafe { deserialize_trailing(input) }.unwrap();
+        assert_eq!(2, got_accounts.len());
+        let want = TrailingIxData { data: b"inline", trailing: b"" };
+        assert_eq!(want, got);
+    }
+
+    #[test]
+    fn test_deserialize_ref() {
+        let data = b"\x04\x00\x00\x00data";
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(data)];
+        let (_, mut input, offset) = serialise_input(&accounts, b"");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let (_, got_accounts, mut got) =
+            unsafe { deserialize_ref::<LengthPrefixed>(input) }.unwrap();
+        assert_eq!(1, got_accounts.len());
+        assert_eq!(b"data", &*got);
+        assert_eq!(Some(&accounts[1].key), got.key());
+        got.data_mut().unwrap().unwrap()[4..].fill(0);
+        assert_eq!(&[0; 4], &*got);
+
+        // Account data cannot be borrowed mutably while the instruction data
+        // is dereferenced even through a clone of the account.
+        let account = got.account().unwrap().clone();
+        let data: &[u8] = &got;
+        assert!(account.try_borrow_mut_data().is_err());
+        assert_eq!(&[0; 4], data);
+        let mut data = account.try_borrow_mut_data();
+        assert!(data.is_err());
+        drop(data);
+        // Releasing the borrow with data_mut lets the clone borrow mutably
+        // but then the guard cannot reborrow the data until it’s released.
+        drop(got.data_mut());
+        data = account.try_borrow_mut_data();
+        assert!(data.is_ok());
+        assert!(got.data_mut().unwrap().is_err());
+        drop(data);
+        assert_eq!(&[0; 4], &*got);
+        let got = got.into_account().unwrap();
+        assert!(got.try_borrow_mut_data().is_ok());
+
+        let (_, mut input, offset) = serialise_input(&accounts, b"inline");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let (_, got_accounts, got) =
+            unsafe { deserialize_ref::<LengthPrefixed>(input) }.unwrap();
+        assert_eq!(2, got_accounts.len());
+        assert_eq!(b"inline", &*got);
+        assert!(got.account().is_none());
+    }
+
+    #[test]
+    fn test_entrypoint_discriminator() {
+        let data = b"\x0b\x00\x00\x00discrimdata";
+        let accounts = [TestAccount::new(data)];
+        let invoke = |ix_data: &[u8], want: Result<&[u8], u64>| {
+            let (_, mut input, offset) = serialise_input(&accounts, ix_data);
+            let input = input.as_mut_ptr().wrapping_add(offset);
+            let process =
+                |_: &Pubkey, _: &[AccountInfo], disc: &[u8; 8], data: &[u8]| {
+                    assert_eq!(want.unwrap(), [&disc[..], data].concat());
+                    Ok::<_, ProgramError>(())
+                };
+            let want = want.err().unwrap_or(0);
+            assert_eq!(want, unsafe {
+                __private::entrypoint_with_source_impl::<LengthPrefixed>(
+                    input,
+                    &Config::DEFAULT,
+                    |pid, accs, data, src| {
+                        __private::call_discriminated(
+                            pid, accs, data, src, process,
+                        )
+                    },
+                )
+            });
+        };
+        invoke(b"", Ok(b"discrimdata"));
+        invoke(b"12345678", Ok(b"12345678"));
+        invoke(b"1234567", Err(code(WriteAccountError::InvalidPayload)));
+    }
+
+    #[test]
+    fn test_entrypoint_with_source() {
+        let accounts = [TestAccount::new(b"raz")];
+        let check = |ix_data: &[u8], want: IxDataSource| {
+            let (_, mut input, offset) = serialise_input(&accounts, ix_data);
+            let input = input.as_mut_ptr().wrapping_add(offset);
+            let process = |_: &Pubkey,
+                           _: &[AccountInfo],
+                           _: &[u8],
+                           source: IxDataSource| {
+                assert_eq!(want, source);
+                Ok::<_, ProgramError>(())
+            };
+            assert_eq!(0, unsafe {
+                __private::entrypoint_with_source_impl::<LengthPrefixed>(
+                    input,
+                    &Config::DEFAULT,
+                    |pid, accs, data, src| {
+                        __private::call_with_source(
+                            pid, accs, data, src, process,
+                        )
+                    },
+                )
+            });
+            assert_eq!(0, unsafe {
+                __private::entrypoint_no_alloc_with_source_impl::<
+                    LengthPrefixed,
+                    4,
+                >(
+                    input,
+                    &Config::DEFAULT,
+                    |pid, accs, data, src| {
+                        __private::call_with_source(
+                            pid, accs, data, src, process,
+                        )
+                    },
+                )
+            });
+        };
+
+        check(b"inline", IxDataSource { account: None, len: 6, version: None });
+        let data = b"\x02\x0c\x00\x02\x04\x00\x00\x00\x0c\x00\x00\x00data";
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(&data[..])];
+        let (_, mut input, offset) = serialise_input(&accounts, b"");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let (_, _, got, source) = unsafe {
+            deserialize_with_source::<LengthPrefixed>(input, &Config::DEFAULT)
+        }
+        .unwrap();
+        assert_eq!(&b"data"[..], got);
+        let want = IxDataSource {
+            account: Some(&accounts[1].key),
+            len: 4,
+            version: Some(2),
+        };
+        assert_eq!(want, source);
+    }
+
+    #[test]
+    fn test_entrypoint_retain_account() {
+        let data = b"\x04\x00\x00\x00data";
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(data)];
+        let (_, mut input, offset) = serialise_input(&accounts, b"head:");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let config =
+            Config { mixed: true, retain_account: true, ..Config::DEFAULT };
+        let process = |_: &Pubkey, accs: &[AccountInfo], data: &[u8]| {
+            assert_eq!((2, &b"head:data"[..]), (accs.len(), data));
+            assert_eq!(&accounts[1], &accs[1]);
+            assert!(accs[1].try_borrow_mut_data().is_err());
+            Ok(())
+        };
+        assert_eq!(0, unsafe {
+            __private::entrypoint_impl::<LengthPrefixed>(
+                input, &config, process,
+            )
+        });
+        assert_eq!(0, unsafe {
+            __private::entrypoint_no_alloc_impl::<LengthPrefixed, 4>(
+                input, &config, process,
+            )
+        });
+    }
+
+    #[test]
+    fn test_entrypoint_too_many_accounts() {
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(b"dwa")];
+        let (_, mut data, offset) = serialise_input(&accounts, b"data");
+        let input = data.as_mut_ptr().wrapping_add(offset);
+        let invoke = |input| unsafe {
+            __private::entrypoint_no_alloc_impl::<LengthPrefixed, 1>(
+                input,
+                &Config::DEFAULT,
+                |_, _, _| Ok(()),
+            )
+        };
+        assert_eq!(code(WriteAccountError::TooManyAccounts), invoke(input));
+        let (_, mut data, offset) = serialise_input(&accounts[1..], b"data");
+        let input = data.as_mut_ptr().wrapping_add(offset);
+        assert_eq!(0, invoke(input));
+    }
 
-        (program_id, vec, pad)
+    #[test]
+    fn test_entrypoint_mixed() {
+        let test = do_test_entrypoint_impl::<LengthPrefixed, true>;
+        let data = b"\x04\x00\x00\x00data";
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(data)];
+        test(&accounts, b"", Ok((1, b"data")));
+        test(&accounts, b"head:", Ok((1, b"head:data")));
+        test(&accounts[1..], b"head:", Ok((0, b"head:data")));
+        test(&accounts[..1], b"head:", Err(code(WriteAccountError::TooShort)));
+        test(&[], b"head:", Err(code(WriteAccountError::MissingDataAccount)));
     }
 
-    /// Tests whether `serialise_input` is implemented correctly.  If this test
-    /// fails, other tests are likely to fail as well.
     #[test]
-    fn test_serialise() {
+    fn test_deserialize_fast() {
+        let data = b"\x04\x00\x00\x00data";
         let accounts = [
             TestAccount::new(b"raz"),
             TestAccount::new(b"dwa"),
-            TestAccount::new(b"trzy"),
-            TestAccount::new(b"cztery"),
+            TestAccount::new(data),
         ];
-        let (program, mut data, offset) =
-            serialise_input(&accounts[..], b"data");
+        let (program_id, mut input, offset) = serialise_input(&accounts, b"");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let (got_id, got_accounts, got_data) =
+            unsafe { deserialize_fast::<LengthPrefixed>(input) }.unwrap();
+        assert_eq!((&program_id, &b"data"[..]), (got_id, got_data));
+        assert_eq!(2, got_accounts.len());
+        for (want, got) in accounts.iter().zip(got_accounts.iter()) {
+            assert_eq!(
+                (&want.key, &want.owner, want.is_signer, want.is_writable),
+                (got.key(), got.owner(), got.is_signer(), got.is_writable())
+            );
+            let want = TestAccount {
+                executable: false,
+                rent_epoch: 0,
+                ..want.clone()
+            };
+            assert_eq!(&want, got.info());
+        }
+    }
 
-        // SAFETY: Data is correctly aligned and serialised.  (We assume).
-        let (got_program, got_accounts, got_data) = unsafe {
-            solana_program::entrypoint::deserialize(
-                (&mut data[offset..]).as_mut_ptr(),
-            )
-        };
+    #[test]
+    fn test_deserialize_fast_duplicate() {
+        /// Serialises the accounts inserting a duplicate of the first one
+        /// after it.
+        fn serialise_with_dup(accounts: &[TestAccount]) -> (Vec<u8>, usize) {
+            let (_, mut input, offset) = serialise_input(accounts, b"");
+            // Fixed fields, three bytes of data padded to eight and
+            // rent_epoch.
+            let second = offset +
+                size_of::<u64>() +
+                88 +
+                8 +
+                MAX_PERMITTED_DATA_INCREASE +
+                size_of::<u64>();
+            input.splice(second..second, [0; 8]);
+            input[offset] = accounts.len() as u8 + 1;
+            (input, offset)
+        }
 
-        assert_eq!(&program, got_program);
+        let data = b"\x04\x00\x00\x00data";
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(data)];
+        let (mut input, offset) = serialise_with_dup(&accounts);
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let (_, got_accounts, got_data) =
+            unsafe { deserialize_fast::<LengthPrefixed>(input) }.unwrap();
         assert_eq!(&b"data"[..], got_data);
-        assert_eq!(accounts.len(), got_accounts.len());
-        for (acc, got) in accounts.iter().zip(got_accounts.iter()) {
-            assert_eq!(acc, got);
+        assert_eq!(2, got_accounts.len());
+        assert_eq!(&accounts[0].key, got_accounts[1].key());
+        let (first, dup) = (got_accounts[0].info(), got_accounts[1].info());
+        assert!(alloc::rc::Rc::ptr_eq(&first.data, &dup.data));
+        assert!(alloc::rc::Rc::ptr_eq(&first.lamports, &dup.lamports));
+
+        // Duplicated account can’t hold the instruction data.
+        let (mut input, offset) = serialise_with_dup(&accounts[..1]);
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let got = unsafe { deserialize_fast::<LengthPrefixed>(input) };
+        let want = ProgramError::from(WriteAccountError::DuplicateDataAccount);
+        assert_eq!(Some(want), got.err());
+    }
+
+    /// Allocator counting allocations made by the current thread.
+    struct CountingAllocator;
+
+    std::thread_local! {
+        static ALLOCATIONS: core::cell::Cell<usize> =
+            const { core::cell::Cell::new(0) };
+    }
+
+    // SAFETY: Forwards to the system allocator.
+    unsafe impl core::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|cnt| cnt.set(cnt.get() + 1));
+            // SAFETY: Caller promises this is safe.
+            unsafe { std::alloc::System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
+            // SAFETY: Caller promises this is safe.
+            unsafe { std::alloc::System.dealloc(ptr, layout) }
         }
     }
 
-    fn do_test_entrypoint(
-        accounts: &[TestAccount],
-        instruction_data: &[u8],
-        want: Result<(usize, &[u8]), u64>,
-    ) {
-        let (program_id, mut data, offset) =
-            serialise_input(accounts, instruction_data);
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
 
-        let check =
-            |got_id: &Pubkey, got_accounts: &[AccountInfo], got_data: &[u8]| {
-                assert_eq!(&program_id, got_id);
-                assert_eq!(want, Ok((got_accounts.len(), got_data)));
-                for (acc, got) in accounts.iter().zip(got_accounts.iter()) {
-                    assert_eq!(acc, got);
-                }
-            };
+    /// Returns number of allocations `f` makes.
+    fn count_allocations<R>(f: impl FnOnce() -> R) -> (usize, R) {
+        let start = ALLOCATIONS.get();
+        let result = f();
+        (ALLOCATIONS.get() - start, result)
+    }
 
-        let input = data.as_mut_ptr().wrapping_add(offset);
-        let want_result = want.clone().err().unwrap_or(0);
-        assert_eq!(want_result, unsafe {
-            __private::entrypoint_impl(input, |id, accounts, data| {
-                Ok(check(id, accounts.as_slice(), data))
-            })
+    /// Number of allocations `deserialize` and `deserialize_fast` make
+    /// when given `count` accounts.
+    #[cfg(not(feature = "log"))]
+    fn deserialize_allocations(count: usize) -> (usize, usize) {
+        let mut accounts = Vec::new();
+        accounts.resize_with(count, || TestAccount::new(b"raz"));
+        let (_, mut input, offset) = serialise_input(&accounts, b"data");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let (full, _) = count_allocations(|| {
+            unsafe { deserialize_with::<LengthPrefixed>(input) }.unwrap()
         });
-        assert_eq!(want_result, unsafe {
-            __private::entrypoint_no_alloc_impl(input, |id, accounts, data| {
-                Ok(check(id, accounts, data))
-            })
+        let (fast, _) = count_allocations(|| {
+            unsafe { deserialize_fast::<LengthPrefixed>(input) }.unwrap()
         });
+        (full, fast)
     }
 
+    /// `deserialize_fast` allocates only the vector of accounts while
+    /// `deserialize` allocates two reference-counted cells for every account.
+    ///
+    /// Skipped with `log` feature since capturing of the logged messages
+    /// allocates unpredictably.
     #[test]
-    fn test_entrypoint_normal() {
-        let accounts = [TestAccount::new(b"raz")];
-        do_test_entrypoint(&accounts, b"data", Ok((1, b"data")));
+    #[cfg(not(feature = "log"))]
+    fn test_deserialize_fast_allocations() {
+        let (full_1, fast_1) = deserialize_allocations(1);
+        let (full_9, fast_9) = deserialize_allocations(9);
+        assert_eq!(2, full_1 - fast_1);
+        assert_eq!(2 * 9, full_9 - fast_9);
+        assert_eq!(fast_1, fast_9);
     }
 
+    /// Account info of [`LazyAccount`] is created on demand.
     #[test]
-    fn test_entrypoint_staged() {
+    fn test_lazy_account_info() {
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(b"")];
+        let (_, mut input, offset) = serialise_input(&accounts, b"data");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let (_, got_accounts, _) =
+            unsafe { deserialize_fast::<LengthPrefixed>(input) }.unwrap();
+        let (lazy, _) = count_allocations(|| got_accounts[0].info().lamports());
+        assert_eq!(2, lazy);
+        let (lazy, _) = count_allocations(|| got_accounts[0].info().lamports());
+        assert_eq!(0, lazy);
+    }
+
+    #[test]
+    fn test_entrypoint_lazy() {
         let data = b"\x04\x00\x00\x00data";
-        let accounts = [TestAccount::new(b"raz"), TestAccount::new(data)];
-        do_test_entrypoint(&accounts[1..], b"", Ok((0, b"data")));
-        do_test_entrypoint(&accounts, b"", Ok((1, b"data")));
+        let accounts = [
+            TestAccount::new(b"raz"),
+            TestAccount::new(b"dwa"),
+            TestAccount::new(data),
+        ];
+        let (program_id, mut input, offset) = serialise_input(&accounts, b"");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+
+        // Accounts first; the account with data is never returned.
+        let mut ctx =
+            unsafe { InstructionContext::<LengthPrefixed>::new(input) };
+        for (idx, acc) in accounts[..2].iter().enumerate() {
+            assert_eq!(2 - idx as u64, ctx.remaining());
+            match ctx.next_account() {
+                Ok(MaybeAccount::Account(got)) => assert_eq!(acc, &got),
+                _ => panic!("expected an account"),
+            }
+        }
+        assert!(ctx.next_account().is_err());
+        assert_eq!(Ok(&b"data"[..]), ctx.instruction_data());
+        assert_eq!(&program_id, ctx.program_id());
+
+        // Data first.
+        let mut ctx =
+            unsafe { InstructionContext::<LengthPrefixed>::new(input) };
+        assert_eq!(&program_id, ctx.program_id());
+        assert_eq!(Ok(&b"data"[..]), ctx.instruction_data());
+        assert_eq!(2, ctx.remaining());
+        match ctx.next_account() {
+            Ok(MaybeAccount::Account(got)) => assert_eq!(&accounts[0], &got),
+            _ => panic!("expected an account"),
+        }
+        assert_eq!(1, ctx.remaining());
     }
 
+    #[cfg(feature = "pinocchio")]
     #[test]
-    fn test_entrypoint_long_staged() {
-        let data = b"\x04\x00\x00\x00datagarbage";
+    fn test_pinocchio_deserialize() {
+        use core::mem::MaybeUninit;
+
+        let data = b"\x04\x00\x00\x00data";
         let accounts = [TestAccount::new(b"raz"), TestAccount::new(data)];
-        do_test_entrypoint(&accounts[1..], b"", Ok((0, b"data")));
-        do_test_entrypoint(&accounts, b"", Ok((1, b"data")));
+        let (program_id, mut input, offset) = serialise_input(&accounts, b"");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+
+        let mut views = [const { MaybeUninit::uninit() }; 4];
+        let (got_id, count, got_data) = unsafe {
+            crate::pinocchio::deserialize::<LengthPrefixed, 4>(
+                input, &mut views,
+            )
+        }
+        .unwrap();
+        assert_eq!(program_id.as_ref(), got_id.as_ref());
+        assert_eq!(1, count);
+        assert_eq!(&b"data"[..], got_data);
+        let view = unsafe { views[0].assume_init_ref() };
+        assert_eq!(accounts[0].key.as_ref(), view.address().as_ref());
+        let view = unsafe { views[1].assume_init_ref() };
+        assert!(view.is_borrowed());
+
+        // With room for fewer accounts, the account holding the instruction
+        // data wouldn’t be parsed.
+        let (_, mut input, offset) = serialise_input(&accounts, b"");
+        let input = input.as_mut_ptr().wrapping_add(offset);
+        let mut views = [const { MaybeUninit::uninit() }; 1];
+        let got = unsafe {
+            crate::pinocchio::deserialize::<LengthPrefixed, 1>(
+                input, &mut views,
+            )
+        };
+        let want = ::pinocchio::error::ProgramError::Custom(
+            WriteAccountError::TooManyAccounts as u32,
+        );
+        assert_eq!(Some(want), got.err());
     }
 
     #[test]
-    fn test_entrypoint_short_staged() {
-        let data = b"\x04\x00\x00\x00dat";
-        do_test_entrypoint(&[TestAccount::new(data)], b"", Err(12884901888));
+    #[cfg(feature = "borsh")]
+    fn test_entrypoint_borsh() {
+        #[derive(
+            borsh::BorshSerialize, borsh::BorshDeserialize, Debug, PartialEq,
+        )]
+        struct Instruction {
+            id: u32,
+            name: alloc::string::String,
+        }
+
+        let want = Instruction { id: 42, name: "foo".into() };
+        let data = borsh::to_vec(&want).unwrap();
+        assert_eq!(Ok(&want), parse::<Instruction>(&data).as_ref());
+        assert_eq!(
+            Err(WriteAccountError::InvalidPayload.into()),
+            parse::<Instruction>(&data[1..])
+        );
+
+        let mut account = (data.len() as u32).to_le_bytes().to_vec();
+        account.extend_from_slice(&data);
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(account)];
+        for (accounts, data) in
+            [(&accounts[..], &[][..]), (&accounts[..1], &data)]
+        {
+            let (_, mut input, offset) = serialise_input(accounts, data);
+            let input = input.as_mut_ptr().wrapping_add(offset);
+            let process =
+                |_: &Pubkey, accs: &[AccountInfo], ix: Instruction| {
+                    assert_eq!((1, &want), (accs.len(), &ix));
+                    Ok::<_, ProgramError>(())
+                };
+            assert_eq!(0, unsafe {
+                __private::entrypoint_with_source_impl::<LengthPrefixed>(
+                    input,
+                    &Config::DEFAULT,
+                    |pid, accs, data, src| {
+                        __private::call_borsh(pid, accs, data, src, process)
+                    },
+                )
+            });
+            assert_eq!(0, unsafe {
+                __private::entrypoint_no_alloc_with_source_impl::<
+                    LengthPrefixed,
+                    4,
+                >(
+                    input,
+                    &Config::DEFAULT,
+                    |pid, accs, data, src| {
+                        __private::call_borsh(pid, accs, data, src, process)
+                    },
+                )
+            });
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "sha256")]
+    fn test_entrypoint_sha256() {
+        let mut data = crate::sdk::hash::hash(b"data").to_bytes().to_vec();
+        data.extend_from_slice(b"\x04\x00\x00\x00data");
+        let mut tampered = data.clone();
+        tampered[36] = b'D';
+
+        let accounts = [
+            TestAccount::new(b"raz"),
+            TestAccount::new(data),
+            TestAccount::new(tampered),
+        ];
+        let want = Ok((1, &b"data"[..]));
+        do_test_entrypoint_with::<Sha256Checked>(&accounts[..2], b"", want);
+        let want = Err(code(WriteAccountError::HashMismatch));
+        do_test_entrypoint_with::<Sha256Checked>(&accounts[1..], b"", want);
+        let want = Err(code(WriteAccountError::TooShort));
+        do_test_entrypoint_with::<Sha256Checked>(&accounts[..1], b"", want);
+    }
+
+    #[test]
+    fn test_entrypoint_custom_codec() {
+        /// Codec which strips a one-byte envelope tag.
+        struct Envelope;
+
+        impl PayloadCodec for Envelope {
+            fn decode(data: &[u8]) -> Result<&[u8], WriteAccountError> {
+                match data.split_first() {
+                    Some((b'E', data)) => Ok(data),
+                    _ => Err(WriteAccountError::InvalidPayload),
+                }
+            }
+        }
+
+        let accounts = [TestAccount::new(b"raz"), TestAccount::new(b"Edata")];
+        do_test_entrypoint_with::<Envelope>(&accounts, b"", Ok((1, b"data")));
+        do_test_entrypoint_with::<Envelope>(
+            &accounts[..1],
+            b"",
+            Err(code(WriteAccountError::InvalidPayload)),
+        );
+        do_test_entrypoint_with::<Envelope>(
+            &accounts[..1],
+            b"inline",
+            Ok((1, b"inline")),
+        );
     }
 }
@@ -0,0 +1,205 @@
+//! Instruction data which keeps the account it was read from.
+
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::{OnceCell, Ref, RefMut};
+use core::ops::Range;
+
+use super::{
+    log_source, CheckedAccounts, Config, PayloadCodec, WriteAccountError,
+};
+use crate::sdk::account_info::AccountInfo;
+use crate::sdk::program_error::ProgramError;
+use crate::sdk::pubkey::Pubkey;
+
+
+/// Instruction data together with the account it was read from.
+///
+/// Dereferences to the instruction data.  Unlike slices returned by
+/// [`super::deserialize`] and similar functions, the data of the account isn’t
+/// borrowed for the rest of the program’s execution.  Instead, the guard owns
+/// the [`AccountInfo`] and gives mutable access to its data through
+/// [`Self::data_mut`].  This allows the program to modify the account (e.g.
+/// wipe the payload once the instruction has been processed) in the same
+/// instruction.
+///
+/// While the instruction data is dereferenced, the guard holds a shared borrow
+/// of the account data.  Borrowing the data mutably through the account (see
+/// [`Self::account`]) fails until [`Self::data_mut`] is called which releases
+/// the borrow.  It’s reacquired when the guard is dereferenced again.
+///
+/// If the payload is stored in the account as is, modifications of the
+/// account data are reflected in the instruction data.  If it was decoded into
+/// a separate buffer (e.g. because it was compressed), the instruction data
+/// doesn’t change.
+pub struct AccountDataRef<'a> {
+    /// Shared borrow of the account data held while the instruction data is
+    /// dereferenced.
+    ///
+    /// Declared before `account` so that it’s dropped before the account.
+    data: OnceCell<Ref<'a, [u8]>>,
+
+    /// Account the instruction data was read from or `None` if it was passed
+    /// inline.
+    account: Option<AccountInfo<'a>>,
+
+    /// Range of the payload within the account data or `None` if the payload
+    /// doesn’t live in the account data.
+    range: Option<Range<usize>>,
+
+    /// The payload if it doesn’t live in the account data.
+    detached: &'a [u8],
+}
+
+impl<'a> AccountDataRef<'a> {
+    /// Reads instruction data from given account using given codec.
+    ///
+    /// `accounts` are the remaining accounts which may hold continuation of
+    /// the payload.  Returns [`WriteAccountError::DuplicateDataAccount`] error
+    /// if the account is a duplicate of another account passed to the
+    /// instruction.
+    pub fn new<C: PayloadCodec>(
+        account: AccountInfo<'a>,
+        accounts: &[AccountInfo],
+    ) -> Result<Self, ProgramError> {
+        if Rc::strong_count(&account.data) != 1 {
+            return Err(WriteAccountError::DuplicateDataAccount.into());
+        }
+        let data = account.try_borrow_data()?;
+        let checked = CheckedAccounts::new(accounts, &Config::DEFAULT);
+        let payload = C::decode_chained(&data, &checked)?;
+        let offset =
+            (payload.as_ptr() as usize).wrapping_sub(data.as_ptr() as usize);
+        let (range, detached) =
+            if offset <= data.len() && payload.len() <= data.len() - offset {
+                (Some(offset..offset + payload.len()), &[][..])
+            } else {
+                // SAFETY: Codecs return either a slice of the account data or
+                // a buffer which is never freed (see PayloadCodec::decode).
+                let payload = unsafe {
+                    core::slice::from_raw_parts(payload.as_ptr(), payload.len())
+                };
+                (None, payload)
+            };
+        drop(data);
+        Ok(Self {
+            data: OnceCell::new(),
+            account: Some(account),
+            range,
+            detached,
+        })
+    }
+
+    /// Returns guard over instruction data passed inline.
+    fn inline(data: &'a [u8]) -> Self {
+        Self {
+            data: OnceCell::new(),
+            account: None,
+            range: None,
+            detached: data,
+        }
+    }
+
+    /// Returns the account the instruction data was read from or `None` if
+    /// it was passed inline.
+    ///
+    /// While the instruction data is dereferenced, the account data cannot be
+    /// borrowed mutably through the account; use [`Self::data_mut`] instead.
+    pub fn account(&self) -> Option<&AccountInfo<'a>> { self.account.as_ref() }
+
+    /// Mutably borrows data of the account the instruction data was read from.
+    ///
+    /// Releases the shared borrow held by the guard.  Returns `None` if the
+    /// instruction data was passed inline and
+    /// [`ProgramError::AccountBorrowFailed`] error if the data is already
+    /// borrowed (e.g. through a clone of the account).  If the account is
+    /// shrunk so that it no longer holds the payload, dereferencing the guard
+    /// afterwards panics.
+    pub fn data_mut(
+        &mut self,
+    ) -> Option<Result<RefMut<'_, &'a mut [u8]>, ProgramError>> {
+        self.data.take();
+        let account = self.account.as_ref()?;
+        Some(
+            account
+                .data
+                .try_borrow_mut()
+                .map_err(|_| ProgramError::AccountBorrowFailed),
+        )
+    }
+
+    /// Returns address of the account the instruction data was read from or
+    /// `None` if it was passed inline.
+    pub fn key(&self) -> Option<&'a Pubkey> {
+        self.account.as_ref().map(|account| account.key)
+    }
+
+    /// Consumes the guard and returns the account the instruction data was
+    /// read from.
+    pub fn into_account(self) -> Option<AccountInfo<'a>> {
+        let Self { data, account, .. } = self;
+        drop(data);
+        account
+    }
+}
+
+impl core::ops::Deref for AccountDataRef<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        let (Some(account), Some(range)) = (&self.account, &self.range) else {
+            return self.detached;
+        };
+        let data = self.data.get_or_init(|| {
+            // SAFETY: The cell lives in an allocation kept alive by the
+            // `account` which outlives `self.data`.  The borrow is released
+            // before the account is dropped or moved out of the guard.
+            let cell: &'_ core::cell::RefCell<&'_ mut [u8]> =
+                unsafe { &*Rc::as_ptr(&account.data) };
+            Ref::map(
+                cell.try_borrow().expect("account data mutably borrowed"),
+                |data| &**data,
+            )
+        });
+        &data[range.clone()]
+    }
+}
+
+impl core::fmt::Debug for AccountDataRef<'_> {
+    fn fmt(&self, fmtr: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        fmtr.debug_struct("AccountDataRef")
+            .field("key", &self.key())
+            .field("data", &&**self)
+            .finish()
+    }
+}
+
+
+/// Deserialize the input arguments returning guard over the instruction data.
+///
+/// Behaves like [`super::deserialize_with`] except that the instruction data
+/// is returned as [`AccountDataRef`] which keeps the account holding it.  This
+/// allows the program to modify the account once it’s done with the
+/// instruction data.
+///
+/// # Safety
+///
+/// Must be called with pointer to properly serialised instruction such as done
+/// by the Solana runtime.  See [`solana_program::entrypoint::deserialize`].
+pub unsafe fn deserialize_ref<'a, C: PayloadCodec>(
+    input: *mut u8,
+) -> Result<(&'a Pubkey, Vec<AccountInfo<'a>>, AccountDataRef<'a>), ProgramError>
+{
+    // SAFETY: Caller promises this is safe.
+    let (program_id, mut accounts, data) =
+        unsafe { crate::sdk::entrypoint::deserialize(input) };
+    let data = if data.is_empty() {
+        let ix_acc =
+            accounts.pop().ok_or(WriteAccountError::MissingDataAccount)?;
+        AccountDataRef::new::<C>(ix_acc, &accounts)?
+    } else {
+        AccountDataRef::inline(data)
+    };
+    log_source(data.key(), &data);
+    Ok((program_id, accounts, data))
+}
@@ -0,0 +1,484 @@
+//! Lazy parsing of the serialised program arguments.
+
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::{OnceCell, RefCell};
+use core::marker::PhantomData;
+use core::mem::size_of;
+
+use super::{Continuations, LengthPrefixed, PayloadCodec, WriteAccountError};
+use crate::sdk::account_info::AccountInfo;
+use crate::sdk::entrypoint::{
+    BPF_ALIGN_OF_U128, MAX_PERMITTED_DATA_INCREASE, NON_DUP_MARKER,
+};
+use crate::sdk::program_error::ProgramError;
+use crate::sdk::pubkey::Pubkey;
+
+
+/// Account returned by [`InstructionContext::next_account`].
+pub enum MaybeAccount<'a> {
+    /// An account which appears for the first time in the instruction.
+    Account(AccountInfo<'a>),
+
+    /// A duplicate of an earlier account with given index.
+    Duplicated(u8),
+}
+
+/// Serialised program arguments parsed on demand.
+///
+/// Rather than parsing all accounts up front, accounts are parsed one at
+/// a time as the program asks for them with [`Self::next_account`].  Since
+/// instruction data is serialised after all the accounts, locating it requires
+/// skipping over accounts which haven’t been parsed yet.  This is done only
+/// when the program asks for the instruction data (or the program id) and is
+/// considerably cheaper than constructing [`AccountInfo`] objects.
+///
+/// Just like with [`super::deserialize`], if the instruction data is empty, the
+/// instruction data is read from the last account passed to the instruction
+/// and decoded with codec `C`.  That account is never returned by
+/// [`Self::next_account`].
+pub struct InstructionContext<'a, C = LengthPrefixed> {
+    input: *mut u8,
+
+    /// Offset of the next account in the input.
+    offset: usize,
+
+    /// Index of the next account.
+    index: u64,
+
+    /// Number of accounts in the input including the one holding instruction
+    /// data (if any).
+    total: u64,
+
+    /// Location of the instruction data once it’s been found.
+    tail: Option<Tail>,
+
+    /// Instruction data once it’s been decoded.
+    data: Option<&'a [u8]>,
+
+    phantom: PhantomData<(&'a mut [u8], C)>,
+}
+
+/// Location of the instruction data in the input.
+#[derive(Clone, Copy)]
+struct Tail {
+    /// Offset of the last account if inline instruction data is empty.
+    data_account: Option<usize>,
+
+    /// Offset of the inline instruction data length.
+    data: usize,
+}
+
+impl<'a, C: PayloadCodec> InstructionContext<'a, C> {
+    /// Creates a new context for parsing serialised program arguments.
+    ///
+    /// # Safety
+    ///
+    /// Must be called with pointer to properly serialised instruction such as
+    /// done by the Solana runtime.  See
+    /// [`solana_program::entrypoint::deserialize`].
+    pub unsafe fn new(input: *mut u8) -> Self {
+        // SAFETY: Caller promises input is properly serialised.
+        let total = unsafe { read_u64(input, 0) };
+        Self {
+            input,
+            offset: size_of::<u64>(),
+            index: 0,
+            total,
+            tail: None,
+            data: None,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns number of accounts which haven’t been read yet.
+    ///
+    /// The account holding instruction data (if any) is not counted.  Note
+    /// that this requires locating the instruction data.
+    pub fn remaining(&mut self) -> u64 {
+        let tail = self.tail();
+        self.total - self.index - u64::from(tail.data_account.is_some())
+    }
+
+    /// Parses and returns the next account.
+    ///
+    /// Returns `NotEnoughAccountKeys` error if there are no more accounts.
+    pub fn next_account(&mut self) -> Result<MaybeAccount<'a>, ProgramError> {
+        if self.index >= self.total ||
+            (self.index + 1 == self.total &&
+                self.tail().data_account.is_some())
+        {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+        // SAFETY: We’ve checked there are still accounts in the input.
+        let (account, offset) =
+            unsafe { parse_account::<true>(self.input, self.offset) };
+        self.offset = offset;
+        self.index += 1;
+        Ok(account)
+    }
+
+    /// Returns the instruction data.
+    ///
+    /// If the inline instruction data is empty, reads the data from the last
+    /// account and decodes it using codec `C`.  Returns
+    /// [`WriteAccountError::MissingDataAccount`] if there’s no account to read
+    /// the data from.
+    pub fn instruction_data(&mut self) -> Result<&'a [u8], ProgramError> {
+        if let Some(data) = self.data {
+            return Ok(data);
+        }
+        let tail = self.tail();
+        // SAFETY: `tail` located the data in the input.
+        let data = unsafe { inline_data(self.input, tail.data) };
+        let (data, source) = match tail.data_account {
+            _ if !data.is_empty() => (data, None),
+            // SAFETY: `tail` located the account in the input.
+            Some(offset) => unsafe {
+                let data = C::decode(account_data(self.input, offset)?)?;
+                let key = &*(self.input.add(offset + 8) as *const Pubkey);
+                (data, Some(key))
+            },
+            None => return Err(WriteAccountError::MissingDataAccount.into()),
+        };
+        super::log_source(source, data);
+        self.data = Some(data);
+        Ok(data)
+    }
+
+    /// Returns id of the program being executed.
+    ///
+    /// Note that this requires locating the instruction data.
+    pub fn program_id(&mut self) -> &'a Pubkey {
+        let tail = self.tail();
+        // SAFETY: `tail` located the data in the input and program id follows
+        // it.
+        unsafe {
+            let len = read_u64(self.input, tail.data) as usize;
+            let offset = tail.data + size_of::<u64>() + len;
+            &*(self.input.add(offset) as *const Pubkey)
+        }
+    }
+
+    /// Locates the instruction data skipping over remaining accounts.
+    fn tail(&mut self) -> Tail {
+        if let Some(tail) = self.tail {
+            return tail;
+        }
+        let mut offset = self.offset;
+        let mut last = None;
+        for _ in self.index..self.total {
+            last = Some(offset);
+            // SAFETY: There are still accounts in the input.
+            offset = unsafe { skip_account(self.input, offset) };
+        }
+        // SAFETY: Instruction data follows the accounts.
+        let empty = unsafe { read_u64(self.input, offset) } == 0;
+        let tail = Tail { data_account: last.filter(|_| empty), data: offset };
+        self.tail = Some(tail);
+        tail
+    }
+}
+
+
+/// Account returned by [`deserialize_fast`].
+///
+/// A view of an account in the serialised input which creates the
+/// [`AccountInfo`] (together with the reference-counted cells for lamports and
+/// data it requires) only once [`Self::info`] is called.  Key, owner and flags
+/// of the account are read directly from the input so accounts which the
+/// program only inspects (e.g. to check a signer) cost no allocations.
+pub struct LazyAccount<'a> {
+    input: *mut u8,
+
+    /// Offset of the account in the input.
+    offset: usize,
+
+    /// The account info once it’s been created.
+    info: OnceCell<AccountInfo<'a>>,
+}
+
+impl<'a> LazyAccount<'a> {
+    /// Returns address of the account.
+    pub fn key(&self) -> &'a Pubkey {
+        // SAFETY: `offset` points at a serialised account.
+        unsafe { &*(self.input.add(self.offset + 8) as *const Pubkey) }
+    }
+
+    /// Returns owner of the account.
+    pub fn owner(&self) -> &'a Pubkey {
+        // SAFETY: `offset` points at a serialised account.
+        unsafe { &*(self.input.add(self.offset + 40) as *const Pubkey) }
+    }
+
+    /// Returns whether the account signed the transaction.
+    pub fn is_signer(&self) -> bool {
+        // SAFETY: `offset` points at a serialised account.
+        unsafe { *self.input.add(self.offset + 1) != 0 }
+    }
+
+    /// Returns whether the account is writable.
+    pub fn is_writable(&self) -> bool {
+        // SAFETY: `offset` points at a serialised account.
+        unsafe { *self.input.add(self.offset + 2) != 0 }
+    }
+
+    /// Returns the account info creating it on first call.
+    ///
+    /// `executable` and `rent_epoch` fields of the info are always set to
+    /// `false` and `0` respectively.
+    pub fn info(&self) -> &AccountInfo<'a> {
+        self.info.get_or_init(|| {
+            // SAFETY: `offset` points at a serialised account which isn’t
+            // a duplicate.  Duplicates share info of the original account
+            // (see `deserialize_fast`) so the info is created only once for
+            // the account data.
+            match unsafe { parse_account::<false>(self.input, self.offset) } {
+                (MaybeAccount::Account(account), _) => account,
+                (MaybeAccount::Duplicated(_), _) => unreachable!(),
+            }
+        })
+    }
+
+    /// Converts the view into the account info.
+    pub fn into_info(self) -> AccountInfo<'a> {
+        self.info();
+        self.info.into_inner().unwrap()
+    }
+}
+
+impl Continuations for [LazyAccount<'_>] {
+    type Error = ProgramError;
+
+    fn count(&self) -> usize { self.len() }
+
+    fn with_data<R>(
+        &self,
+        address: &[u8; 32],
+        f: impl FnOnce(&[u8]) -> Result<R, WriteAccountError>,
+    ) -> Result<R, ProgramError> {
+        let account = self
+            .iter()
+            .find(|account| account.key().as_ref() == address)
+            .ok_or(WriteAccountError::MissingContinuationAccount)?;
+        Ok(f(&account.info().try_borrow_data()?)?)
+    }
+}
+
+
+/// Deserialize the input arguments skipping work programs rarely need.
+///
+/// Behaves like [`super::deserialize_with`] except that rather than
+/// [`AccountInfo`] objects it returns [`LazyAccount`] views which don’t
+/// allocate reference-counted cells for lamports and data until the program
+/// asks for the account info.  Furthermore, `executable` and `rent_epoch`
+/// fields of the accounts are not read.  They are always set to `false` and
+/// `0` respectively.  This trims the fixed compute cost the entrypoint adds on
+/// top of the runtime’s own parsing.
+///
+/// # Safety
+///
+/// Must be called with pointer to properly serialised instruction such as done
+/// by the Solana runtime.  See [`solana_program::entrypoint::deserialize`].
+pub unsafe fn deserialize_fast<'a, C: PayloadCodec>(
+    input: *mut u8,
+) -> Result<(&'a Pubkey, Vec<LazyAccount<'a>>, &'a [u8]), ProgramError> {
+    // SAFETY: Caller promises input is properly serialised.
+    let total = unsafe { read_u64(input, 0) } as usize;
+    let mut offset = size_of::<u64>();
+    let mut accounts = Vec::<LazyAccount>::with_capacity(total);
+    let mut duplicated = false;
+    for _ in 0..total {
+        // SAFETY: There are still accounts in the input.
+        let dup = unsafe { *input.add(offset) };
+        duplicated = dup != NON_DUP_MARKER;
+        accounts.push(if duplicated {
+            let account = &accounts[usize::from(dup)];
+            let info = OnceCell::from(account.info().clone());
+            LazyAccount { input, offset: account.offset, info }
+        } else {
+            LazyAccount { input, offset, info: OnceCell::new() }
+        });
+        // SAFETY: There are still accounts in the input.
+        offset = unsafe { skip_account(input, offset) };
+    }
+
+    // SAFETY: Instruction data and program id follow the accounts.
+    let (program_id, mut data) = unsafe {
+        let data = inline_data(input, offset);
+        let offset = offset + size_of::<u64>() + data.len();
+        (&*(input.add(offset) as *const Pubkey), data)
+    };
+    let mut source = None;
+    if data.is_empty() {
+        let account =
+            accounts.pop().ok_or(WriteAccountError::MissingDataAccount)?;
+        if duplicated {
+            return Err(WriteAccountError::DuplicateDataAccount.into());
+        }
+        // SAFETY: The account isn’t a duplicate and we’ve just taken the only
+        // view of it so nothing can modify the data.
+        let slice = unsafe { account_data(input, account.offset)? };
+        data = C::decode_chained(slice, accounts.as_slice())?;
+        source = Some(account.key());
+    }
+    super::log_source(source, data);
+    Ok((program_id, accounts, data))
+}
+
+
+/// Checks that `input` holds properly serialised program arguments.
+///
+/// Verifies that the input is aligned, all accounts, the instruction data and
+/// the program id fit in it and duplicate accounts refer to earlier accounts.
+/// Returns `None` if any of those checks fails.
+pub(super) fn validate_input(input: &[u8]) -> Option<()> {
+    let read = |offset: usize| {
+        let bytes = input.get(offset..)?.first_chunk::<8>()?;
+        usize::try_from(u64::from_ne_bytes(*bytes)).ok()
+    };
+
+    if input.as_ptr().align_offset(BPF_ALIGN_OF_U128) != 0 {
+        return None;
+    }
+    let total = read(0)?;
+    let mut offset = size_of::<u64>();
+    for index in 0..total {
+        let dup = *input.get(offset)?;
+        if dup != NON_DUP_MARKER {
+            if usize::from(dup) >= index {
+                return None;
+            }
+            offset += 8;
+            continue;
+        }
+        let len = read(offset + DATA_LEN_OFFSET)?;
+        offset = (offset + DATA_LEN_OFFSET + size_of::<u64>())
+            .checked_add(len)?
+            .checked_add(MAX_PERMITTED_DATA_INCREASE)?
+            .checked_next_multiple_of(BPF_ALIGN_OF_U128)?;
+        read(offset)?;
+        offset += size_of::<u64>();
+    }
+    let len = read(offset)?;
+    let end = (offset + size_of::<u64>())
+        .checked_add(len)?
+        .checked_add(size_of::<Pubkey>())?;
+    (end <= input.len()).then_some(())
+}
+
+/// Reads a `u64` at given offset of the input.
+///
+/// # Safety
+///
+/// `input + offset` must point at a `u64` of the serialised input.
+unsafe fn read_u64(input: *const u8, offset: usize) -> u64 {
+    // SAFETY: Caller promises this is safe.  The runtime aligns all integers.
+    unsafe { *(input.add(offset) as *const u64) }
+}
+
+/// Returns `offset` rounded up to alignment of `u128` on Solana.
+fn align(offset: usize) -> usize { offset.next_multiple_of(BPF_ALIGN_OF_U128) }
+
+/// Offset of the data length from the start of a non-duplicate account.
+const DATA_LEN_OFFSET: usize = 8 + 2 * size_of::<Pubkey>() + size_of::<u64>();
+
+/// Skips over account at given offset and returns offset of the next one.
+///
+/// # Safety
+///
+/// `input + offset` must point at a serialised account.
+unsafe fn skip_account(input: *const u8, offset: usize) -> usize {
+    // SAFETY: Caller promises this is safe.
+    unsafe {
+        if *input.add(offset) != NON_DUP_MARKER {
+            return offset + 8;
+        }
+        let len = read_u64(input, offset + DATA_LEN_OFFSET) as usize;
+        let offset = offset + DATA_LEN_OFFSET + size_of::<u64>() + len;
+        align(offset + MAX_PERMITTED_DATA_INCREASE) + size_of::<u64>()
+    }
+}
+
+/// Parses account at given offset and returns it with offset of the next one.
+///
+/// If `FULL` is `false`, `executable` and `rent_epoch` fields are not read and
+/// set to `false` and `0` respectively.
+///
+/// # Safety
+///
+/// `input + offset` must point at a serialised account.
+#[inline(always)]
+unsafe fn parse_account<'a, const FULL: bool>(
+    input: *mut u8,
+    offset: usize,
+) -> (MaybeAccount<'a>, usize) {
+    // SAFETY: Caller promises this is safe.
+    unsafe {
+        let dup = *input.add(offset);
+        if dup != NON_DUP_MARKER {
+            return (MaybeAccount::Duplicated(dup), offset + 8);
+        }
+        let is_signer = *input.add(offset + 1) != 0;
+        let is_writable = *input.add(offset + 2) != 0;
+        let executable = FULL && *input.add(offset + 3) != 0;
+        let key = &*(input.add(offset + 8) as *const Pubkey);
+        let owner = &*(input.add(offset + 40) as *const Pubkey);
+        let lamports = &mut *(input.add(offset + 72) as *mut u64);
+        let len = read_u64(input, offset + DATA_LEN_OFFSET) as usize;
+        // Store the original data length in the padding like the runtime’s
+        // deserialiser does.  It’s used to detect invalid reallocations.
+        *(input.add(offset + 4) as *mut u32) = len as u32;
+        let data_offset = offset + DATA_LEN_OFFSET + size_of::<u64>();
+        let data = core::slice::from_raw_parts_mut(input.add(data_offset), len);
+        let offset = align(data_offset + len + MAX_PERMITTED_DATA_INCREASE);
+        let rent_epoch = if FULL { read_u64(input, offset) } else { 0 };
+        let account = AccountInfo {
+            key,
+            is_signer,
+            is_writable,
+            lamports: Rc::new(RefCell::new(lamports)),
+            data: Rc::new(RefCell::new(data)),
+            owner,
+            executable,
+            rent_epoch,
+        };
+        (MaybeAccount::Account(account), offset + size_of::<u64>())
+    }
+}
+
+/// Returns data of account at given offset.
+///
+/// Returns `DuplicateDataAccount` error if the account is a duplicate.
+///
+/// # Safety
+///
+/// `input + offset` must point at a serialised account.
+unsafe fn account_data<'a>(
+    input: *const u8,
+    offset: usize,
+) -> Result<&'a [u8], ProgramError> {
+    // SAFETY: Caller promises this is safe.
+    unsafe {
+        if *input.add(offset) != NON_DUP_MARKER {
+            return Err(WriteAccountError::DuplicateDataAccount.into());
+        }
+        let len = read_u64(input, offset + DATA_LEN_OFFSET) as usize;
+        let data = input.add(offset + DATA_LEN_OFFSET + size_of::<u64>());
+        Ok(core::slice::from_raw_parts(data, len))
+    }
+}
+
+/// Returns inline instruction data whose length is at given offset.
+///
+/// # Safety
+///
+/// `input + offset` must point at a serialised instruction data.
+unsafe fn inline_data<'a>(input: *const u8, offset: usize) -> &'a [u8] {
+    // SAFETY: Caller promises this is safe.
+    unsafe {
+        let len = read_u64(input, offset) as usize;
+        let data = input.add(offset + size_of::<u64>());
+        core::slice::from_raw_parts(data, len)
+    }
+}
@@ -0,0 +1,317 @@
+//! Layout of the account data holding instruction data.
+//!
+//! Two layouts are supported.  The legacy layout is a 4-byte little-endian
+//! length prefix followed by the payload (see [`ZSTD_FLAG`] for the meaning of
+//! the most significant bit).  Since account size is limited to 10 MiB, the most
+//! significant byte of the legacy length prefix is always either `0x00` or
+//! `0x80`.
+//!
+//! The versioned layout uses that byte to store the version number.  Version
+//! 2 header is described by [`V2Header`].
+
+use alloc::vec::Vec;
+
+/// Flag in the legacy length prefix indicating that the payload is
+/// zstd-compressed.
+///
+/// Since account size is limited to 10 MiB, the most significant bits of the
+/// length prefix are never set for valid uncompressed payloads.  If the most
+/// significant bit is set, the remaining bits specify length of a zstd frame
+/// which is decompressed into a heap buffer before being handed to the
+/// program.
+///
+/// Decompression is supported only if the crate is built with `zstd` feature.
+/// Otherwise, compressed payloads are rejected with
+/// [`WriteAccountError::CompressionUnsupported`](`crate::codec::WriteAccountError::CompressionUnsupported`)
+/// error.
+pub const ZSTD_FLAG: u32 = 1 << 31;
+
+/// Version 2 header of the account data.
+///
+/// The header is encoded as follows:
+///
+/// | Offset | Size | Field                                  |
+/// |--------|------|----------------------------------------|
+/// | 0      | 1    | flags (see `FLAG_*` constants)         |
+/// | 1      | 1    | header length in bytes (at least 8)    |
+/// | 2      | 1    | reserved, must be zero                 |
+/// | 3      | 1    | version, i.e. [`V2Header::VERSION`]    |
+/// | 4      | 4    | payload length, little-endian          |
+/// | 8      | 4    | payload offset, little-endian (only if `FLAG_OFFSET`) |
+/// | 8/12   | 4    | CRC-32 of the payload, little-endian (only if `FLAG_CRC32`) |
+/// | 8–16   | 4    | tag, little-endian (only if `FLAG_TAG`) |
+/// | 8–20   | 4    | sequence number, little-endian (only if `FLAG_SEQUENCE`) |
+///
+/// The payload follows the header unless [`V2Header::FLAG_OFFSET`] is set in
+/// which case it starts at the given offset counted from the start of the
+/// header.  This lets buffers which keep their own bookkeeping (or are shared
+/// with other on-chain consumers) point at the payload without moving it.
+///
+/// Optional fields are present only if corresponding flag is set and appear in
+/// the order of the flags’ bits.  If [`V2Header::FLAG_CRC32`] is set, the
+/// header includes CRC-32 (see [`crc32`]) of the payload as stored in the
+/// account (i.e. before decompression).  It’s a cheap alternative to the
+/// SHA-256 hash which catches truncated or partially overwritten buffers.
+///
+/// If [`V2Header::FLAG_TAG`] is set, the header includes an arbitrary tag
+/// which lets the entrypoint pick one of several accounts passed to the
+/// instruction (see
+/// [`Config::tag_magic`](`crate::entrypoint::Config::tag_magic`)).
+///
+/// If [`V2Header::FLAG_SEQUENCE`] is set, the header includes a sequence
+/// number which callers staging successive revisions of a payload in the same
+/// account increment with each revision (see
+/// [`Config::sequence_magic`](`crate::entrypoint::Config::sequence_magic`)).
+///
+/// If [`V2Header::FLAG_CHAINED`] is set, the payload is followed by a 32-byte
+/// address of a continuation account whose data holds the rest of the payload
+/// (again prefixed by a header).  This allows uploading parts of the payload
+/// independently.  The continuation may itself be chained.  Note that the
+/// entrypoint joins the parts in a heap buffer so the payload is limited by
+/// the program’s heap size rather than the account size.
+///
+/// Parsers skip any bytes between the fields they know of and the end of the
+/// header which lets future extensions add fields to the header without
+/// breaking deployed programs.  Unknown flags on the other hand are rejected
+/// since they may change meaning of the payload.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct V2Header {
+    /// Flags describing the payload.
+    ///
+    /// [`V2Header::FLAG_OFFSET`] is ignored when encoding the header.  It’s set
+    /// depending on whether [`Self::offset`] is set.
+    pub flags: u8,
+
+    /// Length of the payload.
+    pub len: u32,
+
+    /// Offset of the payload counted from the start of the header.
+    ///
+    /// If `None`, the payload follows the header.
+    pub offset: Option<u32>,
+
+    /// CRC-32 checksum of the payload.
+    ///
+    /// [`V2Header::FLAG_CRC32`] is ignored when encoding the header.  It’s set
+    /// depending on whether this field is set.
+    pub crc32: Option<u32>,
+
+    /// Tag identifying the payload.
+    ///
+    /// [`V2Header::FLAG_TAG`] is ignored when encoding the header.  It’s set
+    /// depending on whether this field is set.
+    pub tag: Option<u32>,
+
+    /// Sequence number of the payload.
+    ///
+    /// [`V2Header::FLAG_SEQUENCE`] is ignored when encoding the header.  It’s
+    /// set depending on whether this field is set.
+    pub sequence: Option<u32>,
+}
+
+impl V2Header {
+    /// Version number stored in the header.
+    pub const VERSION: u8 = 2;
+
+    /// Length of the encoded header without any optional fields.
+    pub const LEN: usize = 8;
+
+    /// Length of the encoded header including the payload offset.
+    pub const LEN_WITH_OFFSET: usize = 12;
+
+    /// Length of the encoded header including all optional fields.
+    pub const MAX_LEN: usize = 24;
+
+    /// Flag indicating that the payload is a zstd frame.
+    pub const FLAG_ZSTD: u8 = 1;
+
+    /// Flag indicating that the header includes the payload offset.
+    pub const FLAG_OFFSET: u8 = 2;
+
+    /// Flag indicating that the payload is followed by address of
+    /// a continuation account.
+    pub const FLAG_CHAINED: u8 = 4;
+
+    /// Flag indicating that the header includes CRC-32 of the payload.
+    pub const FLAG_CRC32: u8 = 8;
+
+    /// Flag indicating that the header includes a tag.
+    pub const FLAG_TAG: u8 = 16;
+
+    /// Flag indicating that the payload has already been consumed.
+    ///
+    /// Doesn’t affect how the payload is interpreted.  It’s set by the owner
+    /// of the buffer once the payload has been used and checked by programs
+    /// which accept each payload only once (see
+    /// [`Config::single_use`](`crate::entrypoint::Config::single_use`)).
+    pub const FLAG_CONSUMED: u8 = 32;
+
+    /// Flag indicating that the header includes a sequence number.
+    pub const FLAG_SEQUENCE: u8 = 64;
+
+    /// All flags understood by this version of the crate.
+    pub const KNOWN_FLAGS: u8 = Self::FLAG_ZSTD |
+        Self::FLAG_OFFSET |
+        Self::FLAG_CHAINED |
+        Self::FLAG_CRC32 |
+        Self::FLAG_TAG |
+        Self::FLAG_CONSUMED |
+        Self::FLAG_SEQUENCE;
+
+    /// Returns length of the encoded header.
+    pub fn encoded_len(&self) -> usize {
+        Self::LEN +
+            4 * usize::from(self.offset.is_some()) +
+            4 * usize::from(self.crc32.is_some()) +
+            4 * usize::from(self.tag.is_some()) +
+            4 * usize::from(self.sequence.is_some())
+    }
+
+    /// Decodes the header from the start of account data.
+    ///
+    /// Returns the header and its encoded length which may be larger than
+    /// [`Self::encoded_len`] if the header includes fields this version of
+    /// the crate doesn’t know.  Returns `None` if the data doesn’t start with
+    /// a valid version 2 header, e.g. uses the legacy layout, has unknown flags
+    /// or is truncated.  Doesn’t validate the payload.
+    pub fn decode(data: &[u8]) -> Option<(Self, usize)> {
+        let (&[flags, header_len, reserved, version], _) =
+            data.split_first_chunk()?;
+        let header_len = usize::from(header_len);
+        if version != Self::VERSION ||
+            reserved != 0 ||
+            flags & !Self::KNOWN_FLAGS != 0 ||
+            header_len < Self::LEN
+        {
+            return None;
+        }
+        let mut words = data
+            .get(4..header_len)?
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()));
+        let len = words.next()?;
+        // Optional fields follow in the order of their flags.
+        let mut field = |flag: u8| {
+            if flags & flag == 0 {
+                Some(None)
+            } else {
+                words.next().map(Some)
+            }
+        };
+        let header = Self {
+            flags,
+            len,
+            offset: field(Self::FLAG_OFFSET)?,
+            crc32: field(Self::FLAG_CRC32)?,
+            tag: field(Self::FLAG_TAG)?,
+            sequence: field(Self::FLAG_SEQUENCE)?,
+        };
+        Some((header, header_len))
+    }
+
+    /// Encodes the header.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let header_len = self.encoded_len();
+        let fields = [
+            (Self::FLAG_OFFSET, self.offset),
+            (Self::FLAG_CRC32, self.crc32),
+            (Self::FLAG_TAG, self.tag),
+            (Self::FLAG_SEQUENCE, self.sequence),
+        ];
+        let mut flags = self.flags;
+        for (flag, value) in fields {
+            flags = if value.is_some() { flags | flag } else { flags & !flag };
+        }
+        let mut buf = Vec::with_capacity(header_len);
+        buf.extend_from_slice(&[flags, header_len as u8, 0, Self::VERSION]);
+        buf.extend_from_slice(&self.len.to_le_bytes());
+        for value in fields.into_iter().filter_map(|(_, value)| value) {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        buf
+    }
+}
+
+
+/// Calculates CRC-32 (as used by zlib, PNG etc.) of given data.
+///
+/// This is the checksum stored in [`V2Header::crc32`].
+pub fn crc32(data: &[u8]) -> u32 {
+    let crc = data.iter().fold(!0u32, |crc, &byte| {
+        CRC32_TABLE[usize::from(crc as u8 ^ byte)] ^ (crc >> 8)
+    });
+    !crc
+}
+
+/// Lookup table for [`crc32`] calculation.
+static CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        let mut crc = idx as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc =
+                if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[idx] = crc;
+        idx += 1;
+    }
+    table
+};
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let header = V2Header {
+            flags: V2Header::FLAG_ZSTD | V2Header::FLAG_CRC32,
+            len: 42,
+            offset: None,
+            crc32: Some(0xdead_beef),
+            tag: None,
+            sequence: None,
+        };
+        let mut data = header.to_vec();
+        assert_eq!(Some((header, 12)), V2Header::decode(&data));
+        data.extend_from_slice(b"payload");
+        assert_eq!(Some((header, 12)), V2Header::decode(&data));
+
+        let header = V2Header {
+            flags: V2Header::KNOWN_FLAGS,
+            len: 42,
+            offset: Some(100),
+            crc32: Some(1),
+            tag: Some(2),
+            sequence: Some(3),
+        };
+        let data = header.to_vec();
+        assert_eq!(V2Header::MAX_LEN, data.len());
+        assert_eq!(Some((header, 24)), V2Header::decode(&data));
+        assert_eq!(None, V2Header::decode(&data[..23]));
+
+        // Unknown trailing fields are skipped.
+        let mut data = header.to_vec();
+        data[1] = 28;
+        data.extend_from_slice(&[0; 4]);
+        assert_eq!(Some((header, 28)), V2Header::decode(&data));
+
+        // Legacy length prefix, unknown flags and missing fields.
+        assert_eq!(None, V2Header::decode(b"\x2a\0\0\x80"));
+        assert_eq!(None, V2Header::decode(&[0x80, 8, 0, 2, 0, 0, 0, 0]));
+        assert_eq!(None, V2Header::decode(&[8, 8, 0, 2, 0, 0, 0, 0]));
+        assert_eq!(None, V2Header::decode(&[0, 8, 1, 2, 0, 0, 0, 0]));
+        assert_eq!(None, V2Header::decode(&[0, 4, 0, 2, 0, 0, 0, 0]));
+        let data = vec![0, 8, 0, 2, 42, 0, 0, 0];
+        let header = V2Header { len: 42, ..V2Header::default() };
+        assert_eq!(Some((header, 8)), V2Header::decode(&data));
+    }
+}
@@ -1,13 +1,68 @@
 //! Functions and interface for client application to call smart contracts
 //! reading the instruction data from an account.
+//!
+//! [`WriteIter`] (or [`WriteBuilder`]) generates instructions filling the
+//! Write account with the instruction data.  [`CallViaAccount`] and
+//! [`Accounts`] build the instruction calling the target program with the
+//! Write account and [`free`] closes the account afterwards.
 
+use alloc::borrow::Cow;
+use alloc::vec;
+use alloc::vec::Vec;
 use core::num::NonZeroU16;
+use core::ops::Range;
+use core::time::Duration;
 
-use solana_program::instruction::{AccountMeta, Instruction};
-use solana_program::program_error::ProgramError;
-use solana_program::pubkey::Pubkey;
+use solana_hash::Hash;
+use solana_instruction::{AccountMeta, Instruction};
+use solana_message::{
+    v0, AddressLookupTableAccount, CompileError, Message, VersionedMessage,
+};
+use solana_pubkey::{Pubkey, PUBKEY_BYTES};
+use solana_rent::Rent;
+
+use crate::header::{V2Header, ZSTD_FLAG};
+
+mod builder;
+mod call;
+mod error;
+#[cfg(feature = "jito")]
+pub mod jito;
+mod shard;
+#[cfg(feature = "std")]
+mod stream;
+#[cfg(feature = "client-rpc")]
+mod upload;
+
+pub use builder::WriteBuilder;
+pub use call::{Accounts, CallViaAccount};
+pub use error::Error;
+pub use shard::{Shard, ShardedWrite, MAX_SHARDED_LEN, MAX_SHARDS};
+#[cfg(feature = "std")]
+pub use stream::StreamWriteIter;
+#[cfg(feature = "client-rpc")]
+pub use upload::{
+    call_with_account_data, dry_run, encode_transactions, fetch_data,
+    list_write_accounts, preflight_call, unique_seed, upload,
+    upload_iter_with_ephemeral_payer, upload_sharded, upload_with_config,
+    upload_with_ephemeral_payer, upload_with_extra_instructions,
+    upload_with_guard, upload_with_progress, upload_with_report,
+    CallSignatures, ChunkReport, Preflight, RateLimit, RetryPolicy,
+    UploadConfig, UploadError, UploadReport, WriteAccountInfo, WriteGuard,
+    UNIQUE_SEED_LEN,
+};
+#[cfg(feature = "client-async")]
+pub use upload::{
+    call_with_account_data_async, fetch_data_async, list_write_accounts_async,
+    preflight_call_async, unique_seed_async, upload_async,
+    upload_async_with_config, upload_async_with_extra_instructions,
+    upload_async_with_progress, upload_async_with_pubsub,
+    upload_async_with_report, upload_parallel_async,
+};
+#[cfg(feature = "client-tpu")]
+pub use upload::{upload_with_tpu, TpuClient};
 
-type Result<T = (), E = ProgramError> = core::result::Result<T, E>;
+type Result<T = (), E = Error> = core::result::Result<T, E>;
 
 /// Maximum chunk size sent to the write-account program.
 ///
@@ -26,9 +81,9 @@ pub const MAX_CHUNK_SIZE: NonZeroU16 = match NonZeroU16::new(988) {
 /// Maximum possible data length.
 ///
 /// This corresponds directly to the maximum Solana account size which is 10
-/// MiB, see [`solana_program::system_instruction::MAX_PERMITTED_DATA_LENGTH`]
+/// MiB, see [`solana_system_interface::MAX_PERMITTED_DATA_LENGTH`]
 const MAX_DATA_SIZE: u32 =
-    solana_program::system_instruction::MAX_PERMITTED_DATA_LENGTH as u32;
+    solana_system_interface::MAX_PERMITTED_DATA_LENGTH as u32;
 
 /// Iterator generating Solana instructions calling the write-account program
 /// filling given account with given data.
@@ -36,11 +91,58 @@ pub struct WriteIter<'a> {
     write_program: &'a Pubkey,
     payer: Pubkey,
     write_account: Pubkey,
-    seed: &'a [u8],
+    seed: Cow<'a, [u8]>,
     bump: u8,
-    data: Vec<u8>,
+    data: Data<'a>,
     position: usize,
     chunk_size: NonZeroU16,
+    max_chunk_size: usize,
+    unit_limit: Option<u32>,
+    unit_price: Option<u64>,
+    lookup_table: Option<AddressLookupTableAccount>,
+    existing: Vec<u8>,
+    index: usize,
+    total: Option<usize>,
+    keypair: bool,
+    fee_payer: Option<Pubkey>,
+    reserved: usize,
+    base_offset: usize,
+    system_program: Pubkey,
+    rent: Rent,
+}
+
+/// Write instruction along with information about the chunk it writes.
+///
+/// Returned by [`WriteIter::next_chunk`].  Useful for reporting progress of
+/// long uploads.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkInfo {
+    /// Index of the chunk counting from zero.
+    pub index: usize,
+
+    /// Total number of chunks the iterator generates.
+    pub total: usize,
+
+    /// Offset within the Write account the chunk is written at.
+    pub offset: usize,
+
+    /// Length of the chunk.
+    pub len: usize,
+
+    /// The Write instruction.
+    pub instruction: Instruction,
+}
+
+/// Restartable iterator over chunks written by a [`WriteIter`].
+///
+/// Returned by [`WriteIter::chunks`].  Cloning the iterator lets the chunks
+/// be generated again from the same point.
+#[derive(Clone)]
+pub struct Chunks<'b, 'a> {
+    iter: &'b WriteIter<'a>,
+    position: usize,
+    index: usize,
+    total: usize,
 }
 
 impl<'a> WriteIter<'a> {
@@ -57,14 +159,15 @@ impl<'a> WriteIter<'a> {
     /// format Borsh uses for array serialisation.  The length-prefixed data is
     /// what [`crate::entrypoint!`] macro expects.
     ///
-    /// Returns an `ArithmeticOverflow` error if the resulting data exceeds
+    /// Returns [`Error::DataTooLarge`] error if the resulting data exceeds
     /// maximum Solana account size (which is 10 MiB).  If the write account
     /// already exists and is larger than data’s length, the remaining bytes of
     /// the account will be untouched.  The length-prefix allows extracting the
     /// actual data length.
     ///
     /// Note that `seed` can be at most 31 bytes long which is one-less than
-    /// normally allowed for seeds.
+    /// normally allowed for seeds.  Longer seeds can be mapped to valid ones
+    /// with [`hash_seed`].
     ///
     /// On success, returns iterator which generates Write instructions calling
     /// `write_program` and the address and bump of the write account where the
@@ -92,16 +195,199 @@ impl<'a> WriteIter<'a> {
     /// }
     /// ```
     pub fn new(
+        write_program: &'a Pubkey,
+        payer: Pubkey,
+        seed: &'a [u8],
+        data: Vec<u8>,
+    ) -> Result<(Self, Pubkey, u8)> {
+        let data = Data::prefixed(Cow::Owned(data), false)?;
+        Self::new_impl(write_program, payer, seed.into(), data)
+    }
+
+    /// Constructs a new iterator generating Write instructions writing
+    /// length-prefixed borrowed data.
+    ///
+    /// Behaves like [`Self::new`] except that it borrows `data` rather than
+    /// taking ownership of it.  Neither constructor copies the data; this one
+    /// lets the caller keep using the payload (e.g. memory-mapped file)
+    /// without cloning it.
+    pub fn new_borrowed(
+        write_program: &'a Pubkey,
+        payer: Pubkey,
+        seed: &'a [u8],
+        data: &'a [u8],
+    ) -> Result<(Self, Pubkey, u8)> {
+        let data = Data::prefixed(Cow::Borrowed(data), false)?;
+        Self::new_impl(write_program, payer, seed.into(), data)
+    }
+
+    /// Constructs a new iterator generating Write instructions for a call with
+    /// mixed inline and account data.
+    ///
+    /// Target programs using the mixed mode (see `mixed` argument of the
+    /// [`crate::entrypoint!`] macro) always read the payload from the last
+    /// account and prepend inline instruction data to it.  This lets the caller
+    /// keep a small header (such as a discriminator) in the transaction.
+    ///
+    /// This splits `data` into first `inline_len` bytes, which are to be used
+    /// as inline instruction data of the call to the target program, and the
+    /// rest which is written into the account just like with [`Self::new`].
+    /// If `inline_len` exceeds length of the data, all of it is used as the
+    /// inline data and the account will hold an empty payload.
+    ///
+    /// On success, returns iterator which generates Write instructions, the
+    /// address and bump of the write account and the inline instruction data.
+    /// The write account must be passed as the last account of the call to
+    /// the target program.
+    pub fn new_mixed(
         write_program: &'a Pubkey,
         payer: Pubkey,
         seed: &'a [u8],
         mut data: Vec<u8>,
+        inline_len: usize,
+    ) -> Result<(Self, Pubkey, u8, Vec<u8>)> {
+        let payload = data.split_off(inline_len.min(data.len()));
+        let (iter, account, bump) =
+            Self::new(write_program, payer, seed, payload)?;
+        Ok((iter, account, bump, data))
+    }
+
+    /// Constructs a new iterator generating Write instructions writing
+    /// length-prefixed data preceded by its SHA-256 hash.
+    ///
+    /// Behaves like [`Self::new`] except that the data written into the account
+    /// starts with a 32-byte SHA-256 hash of `data`.  This is the format
+    /// expected by [`Sha256Checked`](`crate::codec::Sha256Checked`) codec
+    /// which lets the target program detect tampered or partially written
+    /// accounts.
+    pub fn new_sha256(
+        write_program: &'a Pubkey,
+        payer: Pubkey,
+        seed: &'a [u8],
+        data: Vec<u8>,
+    ) -> Result<(Self, Pubkey, u8)> {
+        Self::new_sha256_impl(write_program, payer, seed.into(), data, false)
+    }
+
+    fn new_sha256_impl(
+        write_program: &'a Pubkey,
+        payer: Pubkey,
+        seed: Cow<'a, [u8]>,
+        data: Vec<u8>,
+        compressed: bool,
+    ) -> Result<(Self, Pubkey, u8)> {
+        const HEADER_SIZE: usize = solana_hash::HASH_BYTES + 4;
+        let len = check_len(data.len(), HEADER_SIZE)?;
+        let len = if compressed { len | ZSTD_FLAG } else { len };
+        let hash = solana_sha256_hasher::hash(&data);
+        let header = [hash.to_bytes().as_slice(), &len.to_le_bytes()].concat();
+        let data = Data::new(header, Cow::Owned(data), Vec::new());
+        Self::new_impl(write_program, payer, seed, data)
+    }
+
+    /// Constructs a new iterator generating Write instructions writing data
+    /// with a versioned header.
+    ///
+    /// Behaves like [`Self::new`] except that the data is preceded by
+    /// a [`V2Header`] with given `flags` rather than a bare length prefix.
+    /// The caller is responsible for `data` matching the flags (e.g. being
+    /// a zstd frame if [`V2Header::FLAG_ZSTD`] is set).  The payload directly
+    /// follows the header so [`V2Header::FLAG_OFFSET`] is ignored.  If
+    /// [`V2Header::FLAG_CRC32`] is set, checksum of the data is calculated and
+    /// stored in the header.
+    pub fn new_v2(
+        write_program: &'a Pubkey,
+        payer: Pubkey,
+        seed: &'a [u8],
+        flags: u8,
+        data: Vec<u8>,
+    ) -> Result<(Self, Pubkey, u8)> {
+        let header = V2Header { flags, ..Default::default() };
+        Self::new_v2_impl(write_program, payer, seed.into(), header, data, None)
+    }
+
+    /// Constructs a new iterator generating Write instructions writing data
+    /// with a tagged versioned header.
+    ///
+    /// Behaves like [`Self::new_v2`] except that the header includes given
+    /// `tag`.  This allows several buffers to be passed to an instruction with
+    /// the program selecting one of them at execution time (see
+    /// [`Config::tag_magic`](`crate::entrypoint::Config::tag_magic`)).
+    pub fn new_v2_tagged(
+        write_program: &'a Pubkey,
+        payer: Pubkey,
+        seed: &'a [u8],
+        flags: u8,
+        data: Vec<u8>,
+        tag: u32,
+    ) -> Result<(Self, Pubkey, u8)> {
+        let header = V2Header { flags, tag: Some(tag), ..Default::default() };
+        Self::new_v2_impl(write_program, payer, seed.into(), header, data, None)
+    }
+
+    /// Constructs a new iterator generating Write instructions writing data
+    /// with a versioned header including a sequence number.
+    ///
+    /// Behaves like [`Self::new_v2`] except that the header includes given
+    /// `sequence` number.  This allows the program to verify that it executes
+    /// the intended revision of a payload staged in the account (see
+    /// [`Config::sequence_magic`](`crate::entrypoint::Config::sequence_magic`)).
+    pub fn new_v2_sequenced(
+        write_program: &'a Pubkey,
+        payer: Pubkey,
+        seed: &'a [u8],
+        flags: u8,
+        data: Vec<u8>,
+        sequence: u32,
+    ) -> Result<(Self, Pubkey, u8)> {
+        let sequence = Some(sequence);
+        let header = V2Header { flags, sequence, ..Default::default() };
+        Self::new_v2_impl(write_program, payer, seed.into(), header, data, None)
+    }
+
+    /// Constructs a new iterator generating Write instructions writing a chunk
+    /// of payload continued in another account.
+    ///
+    /// Behaves like [`Self::new_v2`] except that [`V2Header::FLAG_CHAINED`] is
+    /// set and address of the continuation account `next` is written after the
+    /// payload.  The continuation account (e.g. written with another
+    /// `WriteIter`) must be passed to the instruction alongside the account
+    /// written by this iterator.
+    pub fn new_v2_chained(
+        write_program: &'a Pubkey,
+        payer: Pubkey,
+        seed: &'a [u8],
+        flags: u8,
+        data: Vec<u8>,
+        next: &Pubkey,
+    ) -> Result<(Self, Pubkey, u8)> {
+        let flags = flags | V2Header::FLAG_CHAINED;
+        let header = V2Header { flags, ..Default::default() };
+        Self::new_v2_impl(
+            write_program,
+            payer,
+            seed.into(),
+            header,
+            data,
+            Some(next),
+        )
+    }
+
+    fn new_v2_impl(
+        write_program: &'a Pubkey,
+        payer: Pubkey,
+        seed: Cow<'a, [u8]>,
+        mut header: V2Header,
+        data: Vec<u8>,
+        next: Option<&Pubkey>,
     ) -> Result<(Self, Pubkey, u8)> {
-        let len = u32::try_from(data.len())
-            .ok()
-            .filter(|len| *len <= MAX_DATA_SIZE - 4)
-            .ok_or(ProgramError::ArithmeticOverflow)?;
-        data.splice(0..0, len.to_le_bytes());
+        header.crc32 = (header.flags & V2Header::FLAG_CRC32 != 0)
+            .then(|| crate::header::crc32(&data));
+        let overhead = header.encoded_len() +
+            if next.is_some() { PUBKEY_BYTES } else { 0 };
+        header.len = check_len(data.len(), overhead)?;
+        let next = next.map_or_else(Vec::new, |next| next.to_bytes().to_vec());
+        let data = Data::new(header.to_vec(), Cow::Owned(data), next);
         Self::new_impl(write_program, payer, seed, data)
     }
 
@@ -110,31 +396,159 @@ impl<'a> WriteIter<'a> {
     ///
     /// Just like [`WriteIter::new`] creates an iterator which generates Write
     /// instructions calling the write-account program.  The difference is that
-    /// it does not length-prefix the `data`.
+    /// it does not length-prefix the `data`.  Programs read such data with
+    /// [`Raw`](`crate::entrypoint::Raw`) codec.
     pub fn new_raw(
         write_program: &'a Pubkey,
         payer: Pubkey,
         seed: &'a [u8],
         data: Vec<u8>,
     ) -> Result<(Self, Pubkey, u8)> {
-        u32::try_from(data.len())
-            .ok()
-            .filter(|len| *len <= MAX_DATA_SIZE)
-            .ok_or(ProgramError::ArithmeticOverflow)?;
-        Self::new_impl(write_program, payer, seed, data)
+        check_len(data.len(), 0)?;
+        let data = Data::new(Vec::new(), Cow::Owned(data), Vec::new());
+        Self::new_impl(write_program, payer, seed.into(), data)
     }
 
-    fn new_impl(
+    /// Constructs a new iterator generating Write instructions writing raw
+    /// borrowed data.
+    ///
+    /// Behaves like [`Self::new_raw`] except that it borrows `data` rather than
+    /// taking ownership of it.
+    pub fn new_raw_borrowed(
         write_program: &'a Pubkey,
         payer: Pubkey,
         seed: &'a [u8],
-        data: Vec<u8>,
+        data: &'a [u8],
     ) -> Result<(Self, Pubkey, u8)> {
-        check_seed(seed)?;
-        let (write_account, bump) = Pubkey::find_program_address(
-            &[payer.as_ref(), seed],
+        check_len(data.len(), 0)?;
+        let data = Data::new(Vec::new(), Cow::Borrowed(data), Vec::new());
+        Self::new_impl(write_program, payer, seed.into(), data)
+    }
+
+    /// Constructs a new iterator generating Write instructions writing
+    /// borrowed data at given offset of the Write account.
+    ///
+    /// Unlike other constructors, which write the data from the start of the
+    /// account, this writes `data` as is (i.e. without any header) starting at
+    /// `offset`.  This lets callers patch a small region of an existing large
+    /// buffer (e.g. update a header field) without re-uploading everything.
+    /// Rest of the account is left untouched.  Since a single Write
+    /// instruction can grow the account by at most 10 KiB, the account is
+    /// expected to already extend (close) to `offset`.
+    ///
+    /// Offsets reported by [`ChunkInfo::offset`] and accepted by
+    /// [`Self::instruction_for_offset`] are offsets within the account.
+    ///
+    /// Returns [`Error::DataTooLarge`] error if `offset + data.len()` exceeds
+    /// maximum Solana account size.
+    pub fn with_offset(
+        write_program: &'a Pubkey,
+        payer: Pubkey,
+        seed: &'a [u8],
+        offset: usize,
+        data: &'a [u8],
+    ) -> Result<(Self, Pubkey, u8)> {
+        check_len(data.len(), offset)?;
+        let data = Data::new(Vec::new(), Cow::Borrowed(data), Vec::new());
+        let (mut iter, account, bump) =
+            Self::new_impl(write_program, payer, seed.into(), data)?;
+        iter.base_offset = offset;
+        Ok((iter, account, bump))
+    }
+
+    fn new_impl(
+        write_program: &'a Pubkey,
+        payer: Pubkey,
+        seed: Cow<'a, [u8]>,
+        data: Data<'a>,
+    ) -> Result<(Self, Pubkey, u8)> {
+        let (write_account, bump) =
+            find_write_account(write_program, &payer, &seed)?;
+        let iter = Self::with_account(
             write_program,
+            payer,
+            write_account,
+            seed,
+            bump,
+            data,
+            false,
         );
+        Ok((iter, write_account, bump))
+    }
+
+    /// Constructs a new iterator generating Write instructions writing
+    /// length-prefixed data into a keypair account.
+    ///
+    /// Some callers prefer throwaway keypair accounts to PDAs.  Such account
+    /// must be created before the data is written; the returned instruction
+    /// does that by calling the System program’s `CreateAccount` instruction
+    /// which allocates space for the entire data and assigns the account to
+    /// `write_program`.  The `CreateAccount` and all Write instructions must
+    /// be signed by `write_account` and `payer`.  Use [`free_keypair`] to close
+    /// the account.
+    ///
+    /// The returned instruction uses the System program’s well-known address
+    /// and default rent parameters.  If [`Self::system_program`] or
+    /// [`Self::rent`] are set afterwards, use [`Self::create_instruction`] to
+    /// get an instruction which takes them into account.
+    ///
+    /// Other than that behaves like [`Self::new`].  The `write_account` can be
+    /// passed to the target program just like a PDA Write account, e.g. with
+    /// [`CallViaAccount`].
+    pub fn new_keypair(
+        write_program: &'a Pubkey,
+        payer: Pubkey,
+        write_account: Pubkey,
+        data: Vec<u8>,
+    ) -> Result<(Self, Instruction)> {
+        let data = Data::prefixed(Cow::Owned(data), false)?;
+        let iter = Self::with_account(
+            write_program,
+            payer,
+            write_account,
+            Cow::Borrowed(&[]),
+            0,
+            data,
+            true,
+        );
+        let create = iter.create_keypair_instruction();
+        Ok((iter, create))
+    }
+
+    /// Returns the System program’s `CreateAccount` instruction creating the
+    /// keypair Write account.
+    ///
+    /// Returns `None` if the iterator doesn’t write into a keypair account,
+    /// i.e. wasn’t constructed with [`Self::new_keypair`].  Otherwise, the
+    /// instruction references the System program set with
+    /// [`Self::system_program`] and funds the account with rent calculated
+    /// using parameters set with [`Self::rent`].
+    pub fn create_instruction(&self) -> Option<Instruction> {
+        self.keypair.then(|| self.create_keypair_instruction())
+    }
+
+    fn create_keypair_instruction(&self) -> Instruction {
+        let len = self.base_offset + self.data.len();
+        let mut create = solana_system_interface::instruction::create_account(
+            &self.payer,
+            &self.write_account,
+            self.rent.minimum_balance(len),
+            len as u64,
+            self.write_program,
+        );
+        remap_system_program(&mut create, self.system_program);
+        create
+    }
+
+    fn with_account(
+        write_program: &'a Pubkey,
+        payer: Pubkey,
+        write_account: Pubkey,
+        seed: Cow<'a, [u8]>,
+        bump: u8,
+        data: Data<'a>,
+        keypair: bool,
+    ) -> Self {
         let mut iter = Self {
             write_program,
             payer,
@@ -144,9 +558,22 @@ impl<'a> WriteIter<'a> {
             data,
             position: 0,
             chunk_size: NonZeroU16::MAX,
+            max_chunk_size: usize::MAX,
+            unit_limit: None,
+            unit_price: None,
+            lookup_table: None,
+            existing: Vec::new(),
+            index: 0,
+            total: None,
+            keypair,
+            fee_payer: None,
+            reserved: 0,
+            base_offset: 0,
+            system_program: solana_sdk_ids::system_program::ID,
+            rent: Rent::default(),
         };
-        iter.chunk_size(usize::MAX);
-        Ok((iter, write_account, bump))
+        iter.update_chunk_size();
+        iter
     }
 
     /// Sets maximum chunk size.
@@ -158,54 +585,880 @@ impl<'a> WriteIter<'a> {
     /// executed together with the Write instructions.
     ///
     /// The `chunk_size` argument is clamped between 1 and [`MAX_CHUNK_SIZE`] -
-    /// seed length.
+    /// seed length - space taken by compute budget instructions (see
+    /// [`Self::compute_budget`]) - reserved space (see [`Self::reserve_bytes`])
+    /// adjusted by space saved by address lookup table (see
+    /// [`Self::lookup_table`]).
     pub fn chunk_size(&mut self, chunk_size: usize) {
-        let max = MAX_CHUNK_SIZE.get() - self.seed.len() as u16;
-        let chunk_size = chunk_size.min(usize::from(max)) as u16;
+        self.max_chunk_size = chunk_size;
+        self.update_chunk_size();
+    }
+
+    /// Reserves space in each Write transaction for additional instructions.
+    ///
+    /// Rather than calculating chunk size manually, callers which add their
+    /// own instructions (e.g. tips or memos) to the Write transactions may
+    /// declare how many bytes the instructions (including any accounts they
+    /// add to the transaction) take.  The chunk size is then derived from the
+    /// transaction size limit, the seed length, the configuration of the
+    /// iterator and the reservation.
+    pub fn reserve_bytes(&mut self, bytes: usize) {
+        self.reserved = bytes;
+        self.update_chunk_size();
+    }
+
+    /// Recalculates the chunk size after configuration changed.
+    fn update_chunk_size(&mut self) {
+        let overhead = self.seed.len() +
+            usize::from(self.compute_budget_overhead()) +
+            usize::from(self.lookup_table_overhead()) +
+            usize::from(self.keypair_overhead()) +
+            usize::from(self.fee_payer_overhead()) +
+            self.reserved;
+        let max =
+            usize::from(MAX_CHUNK_SIZE.get() + self.lookup_table_savings())
+                .saturating_sub(overhead);
+        let chunk_size = self.max_chunk_size.min(max) as u16;
         self.chunk_size =
             NonZeroU16::new(chunk_size).unwrap_or(NonZeroU16::MIN);
+        self.total = None;
     }
 
-    /// Consumes the iterator and returns Write account address and bump.
-    pub fn into_account(self) -> (Pubkey, u8) {
-        (self.write_account, self.bump)
+    /// Sets compute unit limit and price of the Write transactions.
+    ///
+    /// If set, the Write transactions are expected to start with
+    /// `SetComputeUnitLimit` and `SetComputeUnitPrice` compute budget
+    /// instructions as returned by [`Self::compute_budget_instructions`].  The
+    /// chunk size is reduced (if necessary) so that the transaction including
+    /// those instructions fits Solana’s transaction size limit.
+    pub fn compute_budget(
+        &mut self,
+        unit_limit: Option<u32>,
+        unit_price: Option<u64>,
+    ) {
+        self.unit_limit = unit_limit;
+        self.unit_price = unit_price;
+        self.update_chunk_size();
     }
-}
 
-impl core::iter::Iterator for WriteIter<'_> {
-    type Item = solana_program::instruction::Instruction;
+    /// Returns compute budget instructions to include in each Write
+    /// transaction before the Write instruction.
+    ///
+    /// The instructions are configured with [`Self::compute_budget`].  If
+    /// neither compute unit limit nor price is set, returns an empty vector.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// chunks.compute_budget(Some(20_000), Some(1_000));
+    /// let budget = chunks.compute_budget_instructions();
+    /// while let Some(instruction) = chunks.next() {
+    ///     let instructions = [&budget[..], &[instruction]].concat();
+    ///     // Send transaction with `instructions`.
+    /// }
+    /// ```
+    pub fn compute_budget_instructions(&self) -> Vec<Instruction> {
+        use solana_compute_budget_interface::ComputeBudgetInstruction;
 
-    fn next(&mut self) -> Option<Self::Item> {
+        let limit = self
+            .unit_limit
+            .map(ComputeBudgetInstruction::set_compute_unit_limit);
+        let price = self
+            .unit_price
+            .map(ComputeBudgetInstruction::set_compute_unit_price);
+        limit.into_iter().chain(price).collect()
+    }
+
+    /// Returns number of bytes compute budget instructions take in
+    /// a transaction.
+    fn compute_budget_overhead(&self) -> u16 {
+        // Each instruction takes program id index, accounts length, data
+        // length and the data.  Furthermore, if there are any compute budget
+        // instructions, the transaction needs to include address of the
+        // compute budget program.
+        let limit = self.unit_limit.map_or(0, |_| 3 + 5);
+        let price = self.unit_price.map_or(0, |_| 3 + 9);
+        let program = if limit + price > 0 { PUBKEY_BYTES as u16 } else { 0 };
+        limit + price + program
+    }
+
+    /// Sets address lookup table used to compile versioned Write messages.
+    ///
+    /// The table is used by [`Self::next_message_v0`].  If the table includes
+    /// the Write account and the system program, the messages are shorter
+    /// than legacy messages and the chunk size is increased accordingly (unless
+    /// explicitly limited with [`Self::chunk_size`]).  Otherwise, the chunk
+    /// size may need to be reduced to make room for the table reference.
+    pub fn lookup_table(&mut self, table: AddressLookupTableAccount) {
+        self.lookup_table = Some(table);
+        self.update_chunk_size();
+    }
+
+    /// Returns the next Write instruction compiled into a versioned message.
+    ///
+    /// The message starts with compute budget instructions (see
+    /// [`Self::compute_budget`]) and resolves accounts through the address
+    /// lookup table if one has been set (see [`Self::lookup_table`]).  The
+    /// Write account and system program may be resolved through the table.
+    ///
+    /// If no table has been set, a legacy message is returned instead.  It’s
+    /// two bytes shorter than an equivalent v0 message and the chunk size
+    /// doesn’t leave room for the version prefix and empty list of tables.
+    pub fn next_message_v0(
+        &mut self,
+        blockhash: Hash,
+    ) -> Option<VersionedMessage> {
+        let instruction = self.next()?;
+        let instructions =
+            [self.compute_budget_instructions(), vec![instruction]].concat();
+        let payer = self.fee_payer.as_ref().unwrap_or(&self.payer);
+        let Some(table) = self.lookup_table.as_ref() else {
+            let message = Message::new_with_blockhash(
+                &instructions,
+                Some(payer),
+                &blockhash,
+            );
+            return Some(VersionedMessage::Legacy(message));
+        };
+        // Compilation fails only if there are more than 256 accounts or table
+        // entries neither of which is possible here.
+        let tables = core::slice::from_ref(table);
+        Some(message_v0(payer, &instructions, tables, blockhash).unwrap())
+    }
+
+    /// Sets payer of the transaction fees.
+    ///
+    /// By default, the payer passed to the constructor both pays the
+    /// transaction fees and funds rent of the Write account.  This allows
+    /// a different account (e.g. relayer’s hot wallet) to pay the fees.  The
+    /// payer passed to the constructor still signs the Write instructions and
+    /// funds the rent since it’s the authority of the Write account.
+    ///
+    /// The Write transactions must be signed by both accounts and have
+    /// `fee_payer` set as their payer (as done by [`Self::next_message_v0`]).
+    /// The chunk size is reduced (if necessary) to make room for the
+    /// additional signature and account.
+    pub fn fee_payer(&mut self, fee_payer: Pubkey) {
+        self.fee_payer = Some(fee_payer);
+        self.update_chunk_size();
+    }
+
+    /// Sets address of the System program referenced by the Write, Free and
+    /// keypair account’s `CreateAccount` instructions.
+    ///
+    /// By default, the System program’s well-known address is used.  This
+    /// allows SVM-compatible chains and test harnesses which remap native
+    /// program ids to be targeted.  Note that the write-account program
+    /// itself invokes the System program when creating the Write account
+    /// thus it needs to be built for such environment as well.  See also
+    /// [`remap_system_program`].
+    pub fn system_program(&mut self, system_program: Pubkey) {
+        self.system_program = system_program;
+    }
+
+    /// Sets rent parameters used to calculate rent of the Write account.
+    ///
+    /// By default, Solana’s default rent parameters are assumed.  The
+    /// parameters affect only [`Self::estimate_cost`], [`Self::plan`] and
+    /// [`Self::create_instruction`]; the write-account program uses the Rent
+    /// sysvar of the cluster.
+    pub fn rent(&mut self, rent: Rent) { self.rent = rent; }
+
+    /// Returns number of bytes separate fee payer takes in a transaction.
+    fn fee_payer_overhead(&self) -> u16 {
+        // Fee payer needs a signature and an account address.
+        match self.fee_payer {
+            Some(fee_payer) if fee_payer != self.payer => {
+                64 + PUBKEY_BYTES as u16
+            }
+            _ => 0,
+        }
+    }
+
+    /// Returns number of signatures of the Write and Free transactions.
+    fn signatures(&self) -> u64 {
+        let fee_payer =
+            self.fee_payer.is_some_and(|fee_payer| fee_payer != self.payer);
+        1 + u64::from(self.keypair) + u64::from(fee_payer)
+    }
+
+    /// Returns number of bytes the signature of the keypair Write account
+    /// takes in a transaction.
+    fn keypair_overhead(&self) -> u16 {
+        if self.keypair {
+            64
+        } else {
+            0
+        }
+    }
+
+    /// Returns number of accounts which versioned messages resolve through
+    /// the address lookup table.
+    fn lookup_table_hits(&self) -> u16 {
+        let Some(table) = &self.lookup_table else { return 0 };
+        // Signers cannot be resolved through a lookup table.
+        let write = (!self.keypair).then_some(self.write_account);
+        let keys = write.into_iter().chain([self.system_program]);
+        keys.filter(|key| table.addresses.contains(key)).count() as u16
+    }
+
+    /// Returns number of bytes saved by resolving accounts through the
+    /// address lookup table.
+    fn lookup_table_savings(&self) -> u16 {
+        // Each account in the table takes a one-byte index rather than
+        // a 32-byte address.
+        self.lookup_table_hits() * (PUBKEY_BYTES as u16 - 1)
+    }
+
+    /// Returns number of bytes versioned message takes over legacy message.
+    fn lookup_table_overhead(&self) -> u16 {
+        // Versioned message has a version prefix and a vector of table lookups.
+        // Each table lookup consists of the table address and two vectors of
+        // indexes.
+        match (&self.lookup_table, self.lookup_table_hits()) {
+            (None, _) => 0,
+            (Some(_), 0) => 2,
+            (Some(_), _) => 2 + PUBKEY_BYTES as u16 + 2,
+        }
+    }
+
+    /// Skips ranges of data already present in the Write account.
+    ///
+    /// `existing` is the current content of the Write account (or empty if the
+    /// account doesn’t exist).  Once set, the iterator generates Write
+    /// instructions only for ranges where the data differs from `existing`.
+    /// This makes resuming interrupted uploads and uploading payloads similar
+    /// to the one already in the account (e.g. successive light client
+    /// headers) much cheaper.
+    ///
+    /// Note that the data the account ends up with is the same as without
+    /// skipping only if nothing else modifies the account in the meantime.
+    pub fn skip_unchanged(&mut self, existing: Vec<u8>) {
+        self.existing = existing;
+        self.total = None;
+    }
+
+    /// Returns the next Write instruction along with information about the
+    /// chunk it writes.
+    ///
+    /// Advances the iterator just like [`Iterator::next`].  The first call
+    /// needs to go through the remaining data to count the chunks.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// while let Some(chunk) = chunks.next_chunk() {
+    ///     println!("Writing chunk {}/{}", chunk.index + 1, chunk.total);
+    ///     // Send transaction with `chunk.instruction`.
+    /// }
+    /// ```
+    pub fn next_chunk(&mut self) -> Option<ChunkInfo> {
+        let total = self.total();
+        let range = self.next_range()?;
+        Some(ChunkInfo {
+            index: self.index,
+            total,
+            offset: self.base_offset + range.start,
+            len: range.len(),
+            instruction: self.advance(range),
+        })
+    }
+
+    /// Returns total number of chunks the iterator generates, including the
+    /// ones already generated.
+    fn total(&mut self) -> usize {
+        if let Some(total) = self.total {
+            return total;
+        }
+        let total = self.index + self.remaining();
+        self.total = Some(total);
+        total
+    }
+
+    /// Returns number of chunks the iterator is yet to generate.
+    fn remaining(&self) -> usize {
+        if let Some(total) = self.total {
+            return total - self.index;
+        }
+        self.count_from(self.position)
+    }
+
+    /// Returns number of chunks generated starting at given position.
+    fn count_from(&self, mut position: usize) -> usize {
+        if self.existing.is_empty() {
+            let len = self.data.len().saturating_sub(position);
+            return chunk_count(len, usize::from(self.chunk_size.get()));
+        }
+        let mut count = 0;
+        while let Some(range) = self.range_at(position) {
+            count += 1;
+            position = range.end;
+        }
+        count
+    }
+
+    /// Returns restartable iterator over all the chunks.
+    ///
+    /// Unlike iterating over `self`, the returned iterator borrows the
+    /// `WriteIter` and starts at the first chunk regardless of how far `self`
+    /// has advanced.  This lets the same prepared upload be attempted multiple
+    /// times or inspected without mutating or rebuilding the iterator.  The
+    /// chunks are the same as `self` generates provided its configuration
+    /// (e.g. chunk size) isn’t changed in the meantime.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// for attempt in 0..3 {
+    ///     for chunk in chunks.chunks() {
+    ///         // Send transaction with `chunk.instruction`.
+    ///     }
+    /// }
+    /// ```
+    pub fn chunks(&self) -> Chunks<'_, 'a> {
+        Chunks { iter: self, position: 0, index: 0, total: self.count_from(0) }
+    }
+
+    /// Estimates cost of writing the remaining chunks.
+    ///
+    /// Like [`estimate_cost`] but uses the iterator’s data and chunk size and
+    /// accounts for chunks skipped due to [`Self::skip_unchanged`].  The base
+    /// fee is charged for each signature of a transaction, i.e. of the payer,
+    /// the keypair Write account (see [`Self::new_keypair`]) and the separate
+    /// fee payer (see [`Self::fee_payer`]).  If compute unit price is set (see
+    /// [`Self::compute_budget`]), fees include the prioritisation fee.
+    pub fn estimate_cost(&self, lamports_per_signature: u64) -> CostEstimate {
+        let transactions = self.remaining();
+        let fee = self.write_fee(lamports_per_signature, self.unit_price);
+        CostEstimate {
+            rent: self.rent.minimum_balance(self.base_offset + self.data.len()),
+            transactions,
+            fees: fee.saturating_mul(transactions as u64),
+        }
+    }
+
+    /// Plans writing of the remaining chunks without sending anything.
+    ///
+    /// Like [`Self::estimate_cost`] but calculates fees at given compute unit
+    /// price (in micro-lamports; `None` uses price set with
+    /// [`Self::compute_budget`]), includes the Free transaction and roughly
+    /// estimates how long sending the transactions one by one takes.  Lets
+    /// callers decide whether to proceed or change parameters (e.g. chunk size
+    /// or compression) before uploading.
+    pub fn plan(
+        &self,
+        lamports_per_signature: u64,
+        unit_price: Option<u64>,
+    ) -> UploadPlan {
+        /// Typical time for a transaction to be confirmed.
+        const CONFIRMATION_TIME: Duration = Duration::from_millis(1200);
+
+        let chunks = self.remaining();
+        let transactions = chunks + 1;
+        let fee = self.write_fee(lamports_per_signature, unit_price);
+        let free_fee = lamports_per_signature.saturating_mul(self.signatures());
+        let fees = fee.saturating_mul(chunks as u64).saturating_add(free_fee);
+        UploadPlan {
+            chunks,
+            chunk_size: self.chunk_size.get().into(),
+            transactions,
+            rent: self.rent.minimum_balance(self.base_offset + self.data.len()),
+            fees,
+            duration: CONFIRMATION_TIME.saturating_mul(
+                u32::try_from(transactions).unwrap_or(u32::MAX),
+            ),
+        }
+    }
+
+    /// Returns fee (in lamports) of a single Write transaction.
+    fn write_fee(
+        &self,
+        lamports_per_signature: u64,
+        unit_price: Option<u64>,
+    ) -> u64 {
+        const DEFAULT_UNIT_LIMIT: u64 = 200_000;
+        let limit = self.unit_limit.map_or(DEFAULT_UNIT_LIMIT, u64::from);
+        let priority = unit_price.or(self.unit_price).map_or(0, |price| {
+            (u128::from(limit) * u128::from(price)).div_ceil(1_000_000) as u64
+        });
+        lamports_per_signature
+            .saturating_mul(self.signatures())
+            .saturating_add(priority)
+    }
+
+    /// Returns range of the data the next Write instruction is going to write.
+    fn next_range(&self) -> Option<Range<usize>> {
+        self.range_at(self.position)
+    }
+
+    /// Returns range of the data a Write instruction generated at given
+    /// position is going to write.
+    fn range_at(&self, position: usize) -> Option<Range<usize>> {
         let len = self.data.len();
-        let start = self.position;
+        let existing =
+            self.existing.get(self.base_offset..).unwrap_or_default();
+        let common = existing.len().min(len);
+        let start = (position..common)
+            .find(|&idx| self.data.get(idx) != existing[idx])
+            .unwrap_or(common.max(position));
         if start >= len {
             return None;
         }
-        let end = start.saturating_add(self.chunk_size.get().into()).min(len);
-        self.position = end;
-        let chunk = &self.data[start..end];
-
-        let data = [
-            /* discriminant: */ b"\0",
-            /* seed_len: */ &[self.seed.len() as u8][..],
-            /* seed: */ self.seed,
-            /* bump: */ &[self.bump],
-            /* offset: */
-            &u32::try_from(start).unwrap().to_le_bytes()[..],
-            /* data: */ chunk,
-        ]
-        .concat();
-
-        Some(solana_program::instruction::Instruction {
+        let mut end =
+            start.saturating_add(self.chunk_size.get().into()).min(len);
+        // Don’t rewrite tail of the chunk which is already in the account.
+        while end <= common && self.data.get(end - 1) == existing[end - 1] {
+            end -= 1;
+        }
+        Some(start..end)
+    }
+
+    /// Generates Write instruction for given range of data and advances the
+    /// iterator past it.
+    fn advance(&mut self, range: Range<usize>) -> Instruction {
+        self.position = range.end;
+        self.index += 1;
+        self.instruction_for(range)
+    }
+
+    /// Generates Write instruction for given range of data.
+    fn instruction_for(
+        &self,
+        Range { start, end }: Range<usize>,
+    ) -> Instruction {
+        let mut data = self.instruction_data(start, end - start);
+        self.data.extend_into(start..end, &mut data);
+        self.write_instruction(data)
+    }
+
+    /// Regenerates Write instruction for chunk with given index.
+    ///
+    /// Returns the same instruction as the `index`-th call to
+    /// [`Iterator::next`] (counting from zero) does provided the chunk size
+    /// hasn’t been changed in the meantime.  Doesn’t affect position of the
+    /// iterator.  This lets retry logic re-send a single chunk which failed to
+    /// land.  Returns `None` if there’s no chunk with such index.
+    pub fn instruction_at(&self, index: usize) -> Option<Instruction> {
+        let range = if self.existing.is_empty() {
+            let chunk_size = usize::from(self.chunk_size.get());
+            self.range_at(index.checked_mul(chunk_size)?)?
+        } else {
+            let mut range = self.range_at(0)?;
+            for _ in 0..index {
+                range = self.range_at(range.end)?;
+            }
+            range
+        };
+        Some(self.instruction_for(range))
+    }
+
+    /// Regenerates Write instruction writing chunk at given offset.
+    ///
+    /// The instruction writes up to chunk size bytes starting at `offset`
+    /// within the Write account (e.g. [`ChunkInfo::offset`] of a chunk which
+    /// failed to land).  Doesn’t affect position of the iterator.  Returns
+    /// `None` if `offset` is outside of the data.
+    pub fn instruction_for_offset(&self, offset: usize) -> Option<Instruction> {
+        let offset = offset.checked_sub(self.base_offset)?;
+        let len = self.data.len();
+        if offset >= len {
+            return None;
+        }
+        let end = offset.saturating_add(self.chunk_size.get().into()).min(len);
+        Some(self.instruction_for(offset..end))
+    }
+
+    /// Returns Write instruction data up to the chunk written at given offset.
+    ///
+    /// The chunk itself needs to be appended by the caller; `len` is used to
+    /// reserve space for it.
+    fn instruction_data(&self, offset: usize, len: usize) -> Vec<u8> {
+        let mut data = Vec::with_capacity(7 + self.seed.len() + len);
+        data.extend_from_slice(&[0, self.seed.len() as u8]);
+        data.extend_from_slice(&self.seed);
+        data.push(self.bump);
+        let offset = self.base_offset + offset;
+        data.extend_from_slice(&u32::try_from(offset).unwrap().to_le_bytes());
+        data
+    }
+
+    /// Returns Write instruction with given instruction data.
+    fn write_instruction(&self, data: Vec<u8>) -> Instruction {
+        Instruction {
             program_id: *self.write_program,
             accounts: vec![
                 AccountMeta::new(self.payer, true),
-                AccountMeta::new(self.write_account, false),
-                AccountMeta::new(solana_program::system_program::ID, false),
+                AccountMeta::new(self.write_account, self.keypair),
+                AccountMeta::new(self.system_program, false),
             ],
             data,
+        }
+    }
+
+    /// Returns instruction freeing the Write account.
+    ///
+    /// Equivalent to [`free`] or [`free_keypair`] depending on how the
+    /// iterator has been constructed.
+    pub fn free_instruction(&self) -> Instruction {
+        let mut instruction = if self.keypair {
+            free_keypair(*self.write_program, self.payer, self.write_account)
+        } else {
+            // Seed has been checked when the iterator was constructed.
+            let account = Some(self.write_account);
+            let seed = &self.seed;
+            free(*self.write_program, self.payer, account, seed, self.bump)
+                .unwrap()
+        };
+        remap_system_program(&mut instruction, self.system_program);
+        instruction
+    }
+
+    /// Returns all unsigned messages needed to call the target program.
+    ///
+    /// The messages are, in order: Write messages for the remaining chunks
+    /// (each preceded by compute budget instructions; see
+    /// [`Self::compute_budget`]), the message calling `call` with the Write
+    /// account appended to its accounts (see [`Accounts::append`]) and the
+    /// message freeing the Write account.  They must be executed in that
+    /// order, each confirmed before the next one is sent.
+    ///
+    /// This is meant for air-gapped or multisig signing workflows where keys
+    /// are not available to the caller.  The messages are paid for by the fee
+    /// payer (see [`Self::fee_payer`]) and use default hash as a placeholder
+    /// for the recent blockhash.  The signer must set `recent_blockhash` of
+    /// each message before signing it.
+    pub fn into_unsigned_messages(self, call: Instruction) -> Vec<Message> {
+        let payer = self.fee_payer.unwrap_or(self.payer);
+        self.into_instruction_batches(call)
+            .iter()
+            .map(|instructions| Message::new(instructions, Some(&payer)))
+            .collect()
+    }
+
+    /// Returns instructions needed to call the target program grouped into
+    /// transactions.
+    ///
+    /// Each element holds instructions of a single transaction; they’re the
+    /// same as in messages returned by [`Self::into_unsigned_messages`].  This
+    /// format suits multisig programs (such as Squads) which execute batches
+    /// of proposed transactions on behalf of a vault.
+    ///
+    /// In such setting the iterator must be constructed with the vault as the
+    /// payer (so that the vault is the authority of the Write account) and
+    /// each element submitted as a separate vault transaction, executed in
+    /// order.  Since executing a vault transaction takes more space than
+    /// sending the instructions directly, chunk size must be reduced
+    /// accordingly (see [`Self::chunk_size`]).
+    pub fn into_instruction_batches(
+        self,
+        mut call: Instruction,
+    ) -> Vec<Vec<Instruction>> {
+        let budget = self.compute_budget_instructions();
+        Accounts::append(&mut call, self.write_account);
+        let free = self.free_instruction();
+        self.map(|instruction| [&budget[..], &[instruction]].concat())
+            .chain([vec![call], vec![free]])
+            .collect()
+    }
+
+    /// Consumes the iterator and returns Write account address and bump.
+    pub fn into_account(self) -> (Pubkey, u8) {
+        (self.write_account, self.bump)
+    }
+}
+
+impl core::iter::Iterator for WriteIter<'_> {
+    type Item = solana_instruction::Instruction;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let range = self.next_range()?;
+        Some(self.advance(range))
+    }
+
+    /// Returns exact number of remaining Write instructions.
+    ///
+    /// If [`WriteIter::skip_unchanged`] is used, this needs to compare the
+    /// remaining data with existing account content.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.remaining();
+        (len, Some(len))
+    }
+}
+
+impl core::iter::ExactSizeIterator for WriteIter<'_> {}
+
+impl core::iter::Iterator for Chunks<'_, '_> {
+    type Item = ChunkInfo;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let range = self.iter.range_at(self.position)?;
+        let index = self.index;
+        self.position = range.end;
+        self.index += 1;
+        Some(ChunkInfo {
+            index,
+            total: self.total,
+            offset: self.iter.base_offset + range.start,
+            len: range.len(),
+            instruction: self.iter.instruction_for(range),
         })
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.total - self.index;
+        (len, Some(len))
+    }
+}
+
+impl core::iter::ExactSizeIterator for Chunks<'_, '_> {}
+
+/// Data written into the Write account.
+///
+/// The data is stored as a header, the payload and a trailer so that the
+/// payload doesn’t need to be moved (or copied if it’s borrowed) to make room
+/// for the header.
+struct Data<'a> {
+    head: Vec<u8>,
+    body: Cow<'a, [u8]>,
+    tail: Vec<u8>,
+}
+
+impl<'a> Data<'a> {
+    fn new(head: Vec<u8>, body: Cow<'a, [u8]>, tail: Vec<u8>) -> Self {
+        Self { head, body, tail }
+    }
+
+    /// Constructs length-prefixed data.
+    ///
+    /// If `compressed` is set, [`ZSTD_FLAG`] is set in the length prefix.
+    fn prefixed(body: Cow<'a, [u8]>, compressed: bool) -> Result<Self> {
+        let len = check_len(body.len(), 4)?;
+        let len = if compressed { len | ZSTD_FLAG } else { len };
+        Ok(Self::new(len.to_le_bytes().to_vec(), body, Vec::new()))
+    }
+
+    fn len(&self) -> usize {
+        self.head.len() + self.body.len() + self.tail.len()
+    }
+
+    /// Returns byte at given index.  Panics if index is out of bounds.
+    fn get(&self, idx: usize) -> u8 {
+        let (body, tail) = (self.head.len(), self.head.len() + self.body.len());
+        if idx < body {
+            self.head[idx]
+        } else if idx < tail {
+            self.body[idx - body]
+        } else {
+            self.tail[idx - tail]
+        }
+    }
+
+    /// Appends given range of the data to `out`.
+    fn extend_into(&self, range: Range<usize>, out: &mut Vec<u8>) {
+        let mut offset = 0;
+        for part in [&self.head[..], &self.body[..], &self.tail[..]] {
+            let start = range.start.clamp(offset, offset + part.len());
+            let end = range.end.clamp(offset, offset + part.len());
+            out.extend_from_slice(&part[start - offset..end - offset]);
+            offset += part.len();
+        }
+    }
+}
+
+/// Plan of writing data into the Write account.
+///
+/// Returned by [`WriteIter::plan`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UploadPlan {
+    /// Number of Write transactions, one per chunk.
+    pub chunks: usize,
+
+    /// Maximum number of bytes written by a single Write transaction.
+    pub chunk_size: usize,
+
+    /// Number of transactions including the Free transaction.
+    pub transactions: usize,
+
+    /// Lamports needed to make the Write account rent-exempt.
+    ///
+    /// The payer gets them back once the account is freed.
+    pub rent: u64,
+
+    /// Total fees (in lamports) of all the transactions.
+    pub fees: u64,
+
+    /// Rough estimate of time it takes to send and confirm the transactions
+    /// one by one.
+    pub duration: Duration,
+}
+
+/// Estimated cost of writing data into the Write account.
+///
+/// Returned by [`estimate_cost`] and [`WriteIter::estimate_cost`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CostEstimate {
+    /// Lamports needed to make the Write account rent-exempt.
+    ///
+    /// The payer gets them back once the account is freed.
+    pub rent: u64,
+
+    /// Number of Write transactions.
+    pub transactions: usize,
+
+    /// Total fees (in lamports) of the Write transactions.
+    pub fees: u64,
+}
+
+/// Estimates cost of writing data into the Write account.
+///
+/// `data_len` is the length of the data stored in the account (e.g. payload
+/// length plus four for the length prefix written by [`WriteIter::new`]),
+/// `chunk_size` is the number of bytes written in a single transaction (see
+/// [`WriteIter::chunk_size`]) and `lamports_per_signature` is the base fee of
+/// a transaction.  Each Write transaction has a single signature.  The Free
+/// transaction is not included.
+///
+/// The rent is calculated using default rent parameters which is what all
+/// public clusters use.
+pub fn estimate_cost(
+    data_len: usize,
+    chunk_size: NonZeroU16,
+    lamports_per_signature: u64,
+) -> CostEstimate {
+    let transactions = chunk_count(data_len, chunk_size.get() as usize);
+    CostEstimate {
+        rent: rent_exempt_balance(data_len),
+        transactions,
+        fees: lamports_per_signature.saturating_mul(transactions as u64),
+    }
+}
+
+/// Returns maximum chunk size of Write instructions using seed of given
+/// length.
+///
+/// `reserved_bytes` is the space reserved in each Write transaction for other
+/// instructions (see [`WriteIter::reserve_bytes`]).  This is the chunk size
+/// [`WriteIter`] uses for PDA Write account if compute budget, address lookup
+/// table or separate fee payer aren’t configured.  Returns zero if nothing
+/// fits in the transaction in which case [`WriteIter`] falls back to
+/// one-byte chunks.
+///
+/// Being `const` it can be used to size arrays or assert invariants at
+/// compile time.
+pub const fn max_chunk_size(seed_len: usize, reserved_bytes: usize) -> usize {
+    (MAX_CHUNK_SIZE.get() as usize)
+        .saturating_sub(seed_len)
+        .saturating_sub(reserved_bytes)
+}
+
+/// Returns number of Write instructions needed to write `data_len` bytes in
+/// chunks of `chunk_size` bytes.
+///
+/// `data_len` is the length of the data stored in the account as in
+/// [`estimate_cost`].  Panics (or fails to compile in const context) if
+/// `chunk_size` is zero.
+///
+/// # Example
+///
+/// ```
+/// # use solana_write_account::instruction::{chunk_count, max_chunk_size};
+/// const CHUNKS: usize = chunk_count(4 + 10_000, max_chunk_size(4, 0));
+/// assert_eq!(11, CHUNKS);
+/// ```
+pub const fn chunk_count(data_len: usize, chunk_size: usize) -> usize {
+    data_len.div_ceil(chunk_size)
+}
+
+/// Generates instruction topping up the Write account to stay rent-exempt.
+///
+/// `lamports` is the current balance of the Write account and `new_len` the
+/// length it’s going to have after the next Write.  If the balance is below
+/// the minimum rent-exempt balance for `new_len` bytes, returns System
+/// program’s transfer of the shortfall from `payer`.  Otherwise returns
+/// `None`.  The instruction can be included in the transaction before the
+/// Write instruction.
+///
+/// Note that the write-account program itself transfers missing lamports from
+/// the payer when a Write grows the account.  This helper is useful when the
+/// payer of the Write transaction isn’t supposed to fund the account (e.g.
+/// a relayer pre-funding accounts of users) or when the account has been
+/// underfunded by other means.
+pub fn rent_top_up(
+    payer: &Pubkey,
+    write_account: &Pubkey,
+    lamports: u64,
+    new_len: usize,
+) -> Option<Instruction> {
+    let shortfall = rent_exempt_balance(new_len).checked_sub(lamports)?;
+    (shortfall > 0).then(|| {
+        solana_system_interface::instruction::transfer(
+            payer,
+            write_account,
+            shortfall,
+        )
+    })
+}
+
+/// Replaces System program in the `instruction` with given address.
+///
+/// Functions generating instructions (such as [`free`] or [`rent_top_up`])
+/// reference the System program at its well-known address.  This rewrites the
+/// instruction’s program id and accounts so that the instruction can be used
+/// on SVM-compatible chains and in test harnesses which remap native program
+/// ids.  See also [`WriteIter::system_program`].
+pub fn remap_system_program(
+    instruction: &mut Instruction,
+    system_program: Pubkey,
+) {
+    let id = solana_sdk_ids::system_program::ID;
+    if instruction.program_id == id {
+        instruction.program_id = system_program;
+    }
+    for meta in instruction.accounts.iter_mut() {
+        if meta.pubkey == id {
+            meta.pubkey = system_program;
+        }
+    }
+}
+
+/// Returns minimum balance of a rent-exempt account with given data length.
+fn rent_exempt_balance(data_len: usize) -> u64 {
+    solana_rent::Rent::default().minimum_balance(data_len)
+}
+
+/// Compiles instructions into a versioned (v0) message.
+///
+/// Accounts present in given address lookup tables are resolved through them
+/// which makes the transaction shorter.  This is useful for the transaction
+/// calling the program which reads instruction data from the Write account
+/// (the Write account and target program’s accounts may be included in
+/// a table) as well as for the Free transaction.  Note that program ids cannot
+/// be resolved through a lookup table.
+pub fn message_v0(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+    blockhash: Hash,
+) -> Result<VersionedMessage, CompileError> {
+    v0::Message::try_compile(payer, instructions, lookup_tables, blockhash)
+        .map(VersionedMessage::V0)
+}
+
+/// Returns address and bump of the Write account.
+///
+/// The Write account is a PDA of the `write_program` with `[payer, seed]`
+/// seeds.  This is the same address [`WriteIter`] constructors return and
+/// lets code calling the target program (or freeing the account) find the
+/// account without constructing the iterator.
+///
+/// Returns [`Error::SeedTooLong`] error if `seed` is longer than 31 bytes.
+pub fn find_write_account(
+    write_program: &Pubkey,
+    payer: &Pubkey,
+    seed: &[u8],
+) -> Result<(Pubkey, u8)> {
+    check_seed(seed)?;
+    Ok(Pubkey::find_program_address(&[payer.as_ref(), seed], write_program))
 }
 
 /// Generates instruction data for Free operation.
@@ -215,7 +1468,10 @@ impl core::iter::Iterator for WriteIter<'_> {
 /// `seed`.
 ///
 /// If `write_account` is not given, it’s going to be generated from provided
-/// Write program id, Payer account, seed and bump.
+/// Write program id, Payer account, seed and bump.  Otherwise, it’s checked
+/// against the address generated from them and [`Error::DerivationMismatch`]
+/// error is returned if they don’t match (rather than the Write program
+/// failing the transaction).
 pub fn free(
     write_program_id: Pubkey,
     payer: Pubkey,
@@ -223,35 +1479,648 @@ pub fn free(
     seed: &[u8],
     bump: u8,
 ) -> Result<Instruction> {
-    let mut buf = [0; { solana_program::pubkey::MAX_SEED_LEN + 2 }];
-    buf[1] = check_seed(seed)?;
-    buf[2..seed.len() + 2].copy_from_slice(seed);
-    buf[seed.len() + 2] = bump;
-
-    let write_account = match write_account {
-        None => Pubkey::create_program_address(
-            &[payer.as_ref(), seed, &[bump]],
-            &write_program_id,
-        )?,
-        Some(acc) => acc,
-    };
+    instruction(write_program_id, payer, write_account, seed, bump, &[])
+}
+
+/// Generates instruction writing `chunk` at given `offset` of the Write
+/// account.
+///
+/// This is the Write instruction [`WriteIter`] generates for each chunk
+/// exposed for callers which schedule the writes themselves.  Data written
+/// this way isn’t prefixed with anything; it’s up to the caller to write the
+/// header (e.g. length prefix or [`V2Header`]) the target program expects.
+///
+/// The remaining arguments are as in [`free`].
+pub fn write(
+    write_program_id: Pubkey,
+    payer: Pubkey,
+    write_account: Option<Pubkey>,
+    seed: &[u8],
+    bump: u8,
+    offset: u32,
+    chunk: &[u8],
+) -> Result<Instruction> {
+    let tail = [&offset.to_le_bytes()[..], chunk].concat();
+    instruction(write_program_id, payer, write_account, seed, bump, &tail)
+}
+
+/// Generates instruction closing a keypair Write account.
+///
+/// Counterpart of [`free`] for accounts written by iterator constructed with
+/// [`WriteIter::new_keypair`].  The instruction must be signed by `payer` and
+/// `write_account`.  Lamports held by the account are transferred to `payer`.
+pub fn free_keypair(
+    write_program_id: Pubkey,
+    payer: Pubkey,
+    write_account: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: write_program_id,
+        accounts: vec![
+            AccountMeta::new(payer, true),
+            AccountMeta::new(write_account, true),
+            AccountMeta::new(solana_sdk_ids::system_program::ID, false),
+        ],
+        // Discriminant, seed length and bump; keypair accounts have empty seed
+        // and zero bump.
+        data: vec![0, 0, 0],
+    }
+}
+
+/// Generates Free instructions for multiple Write accounts grouped into
+/// transactions.
+///
+/// `accounts` lists seeds and bumps of Write accounts of given `payer`.  The
+/// instructions are packed into as few transactions paid for and signed by
+/// `payer` as fit in the transaction size limit.  Each element of the result
+/// holds instructions of a single transaction.  This lets operators clean up
+/// many stale Write accounts at once.
+///
+/// Fails under the same conditions as [`free`].
+pub fn free_batch(
+    write_program_id: Pubkey,
+    payer: Pubkey,
+    accounts: &[(&[u8], u8)],
+) -> Result<Vec<Vec<Instruction>>> {
+    use solana_packet::PACKET_DATA_SIZE;
+
+    // Signature count, signature, message header, account keys length,
+    // payer, Write program and System program keys, recent blockhash and
+    // instructions length.
+    const TX_OVERHEAD: usize = 1 + 64 + 3 + 1 + 3 * 32 + 32 + 1;
+    // Write account key, program id index, accounts length, three account
+    // indexes, data length, discriminant, seed length and bump.
+    const FREE_OVERHEAD: usize = 32 + 1 + 1 + 3 + 1 + 3;
+
+    let mut transactions = Vec::new();
+    let mut instructions = Vec::new();
+    let mut size = TX_OVERHEAD;
+    for &(seed, bump) in accounts {
+        let instruction = free(write_program_id, payer, None, seed, bump)?;
+        let len = FREE_OVERHEAD + seed.len();
+        if size + len > PACKET_DATA_SIZE {
+            transactions.push(core::mem::take(&mut instructions));
+            size = TX_OVERHEAD;
+        }
+        instructions.push(instruction);
+        size += len;
+    }
+    if !instructions.is_empty() {
+        transactions.push(instructions);
+    }
+    Ok(transactions)
+}
+
+/// Generates instruction marking payload in the Write account as consumed.
+///
+/// The instruction writes `flags` with [`V2Header::FLAG_CONSUMED`] set over
+/// the flags of the [`V2Header`] stored in the account.  `flags` must
+/// therefore be the flags the header was written with (as returned by
+/// [`V2Header::to_vec`]).  Including the instruction in the transaction after
+/// the instruction reading the payload prevents the payload from being
+/// replayed into programs which check the flag (see
+/// [`Config::single_use`](`crate::entrypoint::Config::single_use`)).
+///
+/// The remaining arguments are as in [`free`].
+pub fn mark_consumed(
+    write_program_id: Pubkey,
+    payer: Pubkey,
+    write_account: Option<Pubkey>,
+    seed: &[u8],
+    bump: u8,
+    flags: u8,
+) -> Result<Instruction> {
+    let data = [0, 0, 0, 0, flags | V2Header::FLAG_CONSUMED];
+    instruction(write_program_id, payer, write_account, seed, bump, &data)
+}
+
+/// Generates instruction calling the write-account program.
+///
+/// `tail` is the data following the seed and bump, i.e. offset and the data
+/// to write for the Write operation or empty for the Free operation.
+fn instruction(
+    write_program_id: Pubkey,
+    payer: Pubkey,
+    write_account: Option<Pubkey>,
+    seed: &[u8],
+    bump: u8,
+    tail: &[u8],
+) -> Result<Instruction> {
+    let data = [b"\0", &[check_seed(seed)?][..], seed, &[bump], tail].concat();
+
+    let address = Pubkey::create_program_address(
+        &[payer.as_ref(), seed, &[bump]],
+        &write_program_id,
+    )
+    .map_err(|_| Error::InvalidBump)?;
+    // Catch wrong account client-side rather than paying for a transaction
+    // which is going to fail.
+    if write_account.is_some_and(|acc| acc != address) {
+        return Err(Error::DerivationMismatch);
+    }
 
     Ok(Instruction {
         program_id: write_program_id,
         accounts: vec![
             AccountMeta::new(payer, true),
-            AccountMeta::new(write_account, false),
-            AccountMeta::new(solana_program::system_program::ID, false),
+            AccountMeta::new(address, false),
+            AccountMeta::new(solana_sdk_ids::system_program::ID, false),
         ],
-        data: buf[..seed.len() + 3].to_vec(),
+        data,
     })
 }
 
+/// Maximum length of the seed of the Write account.
+///
+/// One byte of the PDA seed is taken by the bump.
+const MAX_SEED_LEN: usize = solana_pubkey::MAX_SEED_LEN - 1;
+
+/// Hashes seeds longer than 31 bytes down to 31 bytes.
+///
+/// Seeds of the Write account can be at most 31 bytes long.  This lets
+/// callers use longer seeds (e.g. a namespace followed by a 32-byte hash) by
+/// deterministically mapping them to valid ones.  Seeds of up to 31 bytes are
+/// returned unchanged.  Longer seeds are replaced by the first 31 bytes of
+/// their SHA-256 hash.
+///
+/// The hashed seed has to be used consistently, i.e. passed to
+/// [`find_write_account`], [`free`] and other functions in place of the
+/// original seed.  [`WriteBuilder::hash_long_seed`] does that for the
+/// instructions it generates.
+pub fn hash_seed(seed: &[u8]) -> Cow<'_, [u8]> {
+    if seed.len() <= MAX_SEED_LEN {
+        Cow::Borrowed(seed)
+    } else {
+        let hash = solana_sha256_hasher::hash(seed).to_bytes();
+        Cow::Owned(hash[..MAX_SEED_LEN].to_vec())
+    }
+}
+
 /// Checks that seed is below the maximum length; returns length cast to `u8`.
 fn check_seed(seed: &[u8]) -> Result<u8> {
-    if seed.len() < solana_program::pubkey::MAX_SEED_LEN {
+    let max = MAX_SEED_LEN;
+    if seed.len() <= max {
         Ok(seed.len() as u8)
     } else {
-        Err(ProgramError::MaxSeedLengthExceeded)
+        Err(Error::SeedTooLong { len: seed.len(), max })
+    }
+}
+
+/// Checks that data of given length preceded by `overhead` bytes of header
+/// fits in an account; returns the length cast to `u32`.
+fn check_len(len: usize, overhead: usize) -> Result<u32> {
+    let max = (MAX_DATA_SIZE as usize).saturating_sub(overhead);
+    if len <= max {
+        Ok(len as u32)
+    } else {
+        Err(Error::DataTooLarge { len, max })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    const PROGRAM: Pubkey = Pubkey::new_from_array([1; 32]);
+
+    /// Returns 100 bytes of test data.
+    fn sample_data() -> Vec<u8> { (0..100).collect() }
+
+    /// Returns iterator writing raw `data` in 10-byte chunks.
+    fn raw_iter(data: Vec<u8>, existing: Vec<u8>) -> WriteIter<'static> {
+        let payer = Pubkey::new_from_array([2; 32]);
+        let (mut iter, ..) =
+            WriteIter::new_raw(&PROGRAM, payer, b"", data).unwrap();
+        iter.chunk_size(10);
+        iter.skip_unchanged(existing);
+        iter
+    }
+
+    /// Decodes offset and bytes written by a Write instruction with an empty
+    /// seed.
+    fn decode(instruction: &Instruction) -> (usize, Vec<u8>) {
+        let (head, bytes) = instruction.data.split_at(7);
+        assert_eq!([0, 0], head[..2]);
+        let offset = u32::from_le_bytes(head[3..].try_into().unwrap());
+        (offset as usize, bytes.to_vec())
+    }
+
+    /// Checks that [`WriteIter::chunks`] and [`WriteIter::instruction_at`]
+    /// agree with instructions the iterator generates and returns offsets and
+    /// bytes they write.
+    fn check_writes(iter: WriteIter) -> Vec<(usize, Vec<u8>)> {
+        let chunks = iter.chunks().collect::<Vec<_>>();
+        let at = (0..=chunks.len())
+            .map(|index| iter.instruction_at(index))
+            .collect::<Vec<_>>();
+        let instructions = iter.collect::<Vec<_>>();
+
+        assert_eq!(instructions.len(), chunks.len());
+        assert_eq!(None, at[chunks.len()]);
+        for (index, (instruction, chunk)) in
+            instructions.iter().zip(chunks.iter()).enumerate()
+        {
+            assert_eq!((index, chunks.len()), (chunk.index, chunk.total));
+            assert_eq!(instruction, &chunk.instruction);
+            assert_eq!(Some(instruction), at[index].as_ref());
+            let (offset, bytes) = decode(instruction);
+            assert_eq!((offset, bytes.len()), (chunk.offset, chunk.len));
+        }
+        instructions.iter().map(decode).collect()
+    }
+
+    #[test]
+    fn test_skip_unchanged_identical() {
+        let data = sample_data();
+        let iter = raw_iter(data.clone(), data);
+        assert_eq!(0, iter.len());
+        assert_eq!(Vec::<(usize, Vec<u8>)>::new(), check_writes(iter));
+    }
+
+    #[test]
+    fn test_skip_unchanged_partial() {
+        let data = sample_data();
+        let mut existing = data.clone();
+        existing[25] = 0xff;
+        existing[70..73].fill(0xff);
+        existing[79] = 0xff;
+        let iter = raw_iter(data.clone(), existing);
+        assert_eq!(2, iter.len());
+        // Unchanged tail of a chunk isn’t rewritten but unchanged bytes in the
+        // middle of a chunk are.
+        let want =
+            vec![(25, data[25..26].to_vec()), (70, data[70..80].to_vec())];
+        assert_eq!(want, check_writes(iter));
+    }
+
+    #[test]
+    fn test_skip_unchanged_shorter_existing() {
+        let data = sample_data();
+        let mut existing = data[..45].to_vec();
+        existing[3] = 0xff;
+        let iter = raw_iter(data.clone(), existing);
+        let mut want = vec![(3, data[3..4].to_vec())];
+        want.extend((45..100).step_by(10).map(|offset| {
+            (offset, data[offset..(offset + 10).min(100)].to_vec())
+        }));
+        assert_eq!(want, check_writes(iter));
+    }
+
+    #[test]
+    fn test_skip_unchanged_longer_existing() {
+        let data = sample_data();
+        let mut existing = data.clone();
+        existing.extend_from_slice(&[0xff; 50]);
+        let iter = raw_iter(data.clone(), existing.clone());
+        assert_eq!(Vec::<(usize, Vec<u8>)>::new(), check_writes(iter));
+
+        existing[99] = 0xff;
+        let iter = raw_iter(data.clone(), existing);
+        assert_eq!(vec![(99, data[99..].to_vec())], check_writes(iter));
+    }
+
+    #[test]
+    fn test_skip_unchanged_base_offset() {
+        let data = sample_data();
+        let mut existing = [vec![0xee; 8], data.clone()].concat();
+        existing[8 + 5] = 0xff;
+        existing[8 + 50] = 0xff;
+        let payer = Pubkey::new_from_array([2; 32]);
+        let (mut iter, ..) =
+            WriteIter::with_offset(&PROGRAM, payer, b"", 8, &data).unwrap();
+        iter.chunk_size(10);
+        iter.skip_unchanged(existing);
+        let want =
+            vec![(8 + 5, data[5..6].to_vec()), (8 + 50, data[50..51].to_vec())];
+        assert_eq!(want, check_writes(iter));
+    }
+
+    #[test]
+    fn test_instruction_at_without_existing() {
+        let data = sample_data();
+        let iter = raw_iter(data.clone(), Vec::new());
+        let want = (0..100)
+            .step_by(10)
+            .map(|offset| (offset, data[offset..offset + 10].to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(want, check_writes(iter));
+    }
+
+    #[test]
+    fn test_fees_per_signature() {
+        let payer = Pubkey::new_from_array([2; 32]);
+        let data = sample_data();
+        let (mut iter, ..) =
+            WriteIter::new_raw(&PROGRAM, payer, b"", data.clone()).unwrap();
+        iter.chunk_size(10);
+        assert_eq!(50_000, iter.estimate_cost(5000).fees);
+        assert_eq!(55_000, iter.plan(5000, None).fees);
+
+        // Fee payer same as the payer doesn’t add a signature.
+        iter.fee_payer(payer);
+        assert_eq!(50_000, iter.estimate_cost(5000).fees);
+
+        iter.fee_payer(Pubkey::new_from_array([3; 32]));
+        assert_eq!(100_000, iter.estimate_cost(5000).fees);
+        assert_eq!(110_000, iter.plan(5000, None).fees);
+
+        let account = Pubkey::new_from_array([4; 32]);
+        let (mut iter, _) =
+            WriteIter::new_keypair(&PROGRAM, payer, account, data).unwrap();
+        iter.chunk_size(10);
+        // Length prefix makes the data 104 bytes long.
+        assert_eq!(11, iter.len());
+        assert_eq!(110_000, iter.estimate_cost(5000).fees);
+        iter.fee_payer(Pubkey::new_from_array([3; 32]));
+        iter.compute_budget(Some(100_000), Some(10_000));
+        // Prioritisation fee of 1000 lamports is paid per Write transaction.
+        assert_eq!(11 * 16_000, iter.estimate_cost(5000).fees);
+        assert_eq!(11 * 16_000 + 15_000, iter.plan(5000, None).fees);
+    }
+
+    /// Returns iterator writing 2000 bytes into a PDA Write account with
+    /// a four-byte seed.
+    #[cfg(feature = "client-rpc")]
+    fn sized_iter() -> WriteIter<'static> {
+        let payer = Pubkey::new_from_array([2; 32]);
+        let data = vec![42; 2000];
+        WriteIter::new_raw(&PROGRAM, payer, b"seed", data).unwrap().0
+    }
+
+    /// Checks that a Write transaction with `extra` instructions fits in
+    /// a packet and that a chunk one byte longer wouldn’t.
+    ///
+    /// If `v0` is set and the iterator has a lookup table, the message is
+    /// compiled with [`message_v0`] using that table (as done by
+    /// [`WriteIter::next_message_v0`]).  Otherwise, a legacy message is used.
+    #[cfg(feature = "client-rpc")]
+    #[track_caller]
+    fn check_fits(iter: &WriteIter, extra: &[Instruction], v0: bool) {
+        use solana_packet::PACKET_DATA_SIZE;
+        use solana_signature::Signature;
+        use solana_transaction::versioned::VersionedTransaction;
+
+        let budget = iter.compute_budget_instructions();
+        let payer = iter.fee_payer.unwrap_or(iter.payer);
+        let size = |write: &Instruction| {
+            let write = core::slice::from_ref(write);
+            let instructions = [&budget[..], write, extra].concat();
+            let message = if let (true, Some(table)) = (v0, &iter.lookup_table)
+            {
+                let tables = core::slice::from_ref(table);
+                message_v0(&payer, &instructions, tables, Hash::default())
+                    .unwrap()
+            } else {
+                VersionedMessage::Legacy(Message::new(
+                    &instructions,
+                    Some(&payer),
+                ))
+            };
+            let signatures = message.header().num_required_signatures;
+            let signatures = vec![Signature::default(); signatures.into()];
+            let tx = VersionedTransaction { signatures, message };
+            bincode::serialize(&tx).unwrap().len()
+        };
+
+        let chunk_size = usize::from(iter.chunk_size.get());
+        let mut write = iter.instruction_at(0).unwrap();
+        assert_eq!(7 + iter.seed.len() + chunk_size, write.data.len());
+        let len = size(&write);
+        assert!(len <= PACKET_DATA_SIZE, "{len} > {PACKET_DATA_SIZE}");
+        write.data.push(0);
+        let len = size(&write);
+        assert!(len > PACKET_DATA_SIZE, "{len} <= {PACKET_DATA_SIZE}");
+    }
+
+    #[test]
+    #[cfg(feature = "client-rpc")]
+    fn test_chunk_size_default() {
+        let iter = sized_iter();
+        assert_eq!(max_chunk_size(4, 0), usize::from(iter.chunk_size.get()));
+        check_fits(&iter, &[], false);
+        check_fits(&iter, &[], true);
+
+        let mut iter = iter;
+        let message = iter.next_message_v0(Hash::default()).unwrap();
+        assert!(matches!(message, VersionedMessage::Legacy(_)));
+    }
+
+    #[test]
+    #[cfg(feature = "client-rpc")]
+    fn test_chunk_size_compute_budget() {
+        for (limit, price) in
+            [(Some(100_000), None), (None, Some(1000)), (Some(1), Some(1))]
+        {
+            let mut iter = sized_iter();
+            iter.compute_budget(limit, price);
+            check_fits(&iter, &[], false);
+            check_fits(&iter, &[], true);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "client-rpc")]
+    fn test_chunk_size_lookup_table() {
+        let other = Pubkey::new_from_array([3; 32]);
+        let write = sized_iter().write_account;
+        let system = solana_sdk_ids::system_program::ID;
+        for (addresses, hits) in [
+            (vec![], 0),
+            (vec![other], 0),
+            (vec![other, write], 1),
+            (vec![system], 1),
+            (vec![system, other, write], 2),
+        ] {
+            for budget in [false, true] {
+                let mut iter = sized_iter();
+                if budget {
+                    iter.compute_budget(Some(100_000), Some(1000));
+                }
+                let key = Pubkey::new_from_array([4; 32]);
+                let addresses = addresses.clone();
+                iter.lookup_table(AddressLookupTableAccount { key, addresses });
+                assert_eq!(hits, iter.lookup_table_hits());
+                check_fits(&iter, &[], true);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "client-rpc")]
+    fn test_chunk_size_fee_payer_and_keypair() {
+        let payer = Pubkey::new_from_array([2; 32]);
+        let keypair = Pubkey::new_from_array([6; 32]);
+        let new_iter = |is_keypair: bool| {
+            if is_keypair {
+                let data = vec![42; 2000];
+                WriteIter::new_keypair(&PROGRAM, payer, keypair, data)
+                    .unwrap()
+                    .0
+            } else {
+                sized_iter()
+            }
+        };
+
+        for is_keypair in [false, true] {
+            for fee_payer in [None, Some(payer), Some([5; 32].into())] {
+                for budget in [false, true] {
+                    let mut iter = new_iter(is_keypair);
+                    if let Some(fee_payer) = fee_payer {
+                        iter.fee_payer(fee_payer);
+                    }
+                    if budget {
+                        iter.compute_budget(Some(100_000), Some(1000));
+                    }
+                    check_fits(&iter, &[], false);
+
+                    // Signers can’t be resolved through the table so with
+                    // keypair Write account only the system program is.
+                    let addresses = vec![
+                        iter.write_account,
+                        solana_sdk_ids::system_program::ID,
+                    ];
+                    let key = Pubkey::new_from_array([4; 32]);
+                    iter.lookup_table(AddressLookupTableAccount {
+                        key,
+                        addresses,
+                    });
+                    let hits = if is_keypair { 1 } else { 2 };
+                    assert_eq!(hits, iter.lookup_table_hits());
+                    check_fits(&iter, &[], true);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "client-rpc")]
+    fn test_chunk_size_reserve_bytes() {
+        let payer = Pubkey::new_from_array([2; 32]);
+        let tip = Pubkey::new_from_array([7; 32]);
+        for len in [0, 10, 100] {
+            // New program id, its index, empty account list and data.
+            let memo = Instruction::new_with_bytes(tip, &vec![1; len], vec![]);
+            let memo_size = 32 + 1 + 1 + 1 + len;
+            // Additionally references the payer already in the transaction.
+            let mut signed = memo.clone();
+            signed.accounts.push(AccountMeta::new(payer, true));
+
+            for (extra, reserve) in [(memo, memo_size), (signed, memo_size + 1)]
+            {
+                let extra = [extra];
+                let mut iter = sized_iter();
+                iter.reserve_bytes(reserve);
+                check_fits(&iter, &extra, false);
+                check_fits(&iter, &extra, true);
+
+                iter.compute_budget(Some(100_000), Some(1000));
+                iter.fee_payer(Pubkey::new_from_array([5; 32]));
+                check_fits(&iter, &extra, false);
+
+                let addresses = vec![
+                    iter.write_account,
+                    solana_sdk_ids::system_program::ID,
+                ];
+                let key = Pubkey::new_from_array([4; 32]);
+                iter.lookup_table(AddressLookupTableAccount { key, addresses });
+                check_fits(&iter, &extra, true);
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_chunk_size() {
+        assert_eq!(988, max_chunk_size(0, 0));
+        assert_eq!(984, max_chunk_size(4, 0));
+        assert_eq!(978, max_chunk_size(0, 10));
+        assert_eq!(974, max_chunk_size(4, 10));
+        assert_eq!(1, max_chunk_size(31, 956));
+        assert_eq!(0, max_chunk_size(31, 957));
+        assert_eq!(0, max_chunk_size(usize::MAX, usize::MAX));
+    }
+
+    #[test]
+    fn test_chunk_count() {
+        for chunk_size in [1, 7, 988] {
+            assert_eq!(0, chunk_count(0, chunk_size));
+            assert_eq!(1, chunk_count(1, chunk_size));
+            assert_eq!(1, chunk_count(chunk_size, chunk_size));
+            assert_eq!(3, chunk_count(chunk_size * 3, chunk_size));
+            assert_eq!(4, chunk_count(chunk_size * 3 + 1, chunk_size));
+        }
+        assert_eq!(2, chunk_count(8, 7));
+        assert_eq!(usize::MAX, chunk_count(usize::MAX, 1));
+    }
+
+    #[test]
+    fn test_hash_seed() {
+        let seed = [7; 32];
+        // Seeds up to 31 bytes are borrowed unchanged.
+        for len in [0, 1, 31] {
+            let got = hash_seed(&seed[..len]);
+            assert!(matches!(got, Cow::Borrowed(_)), "len: {len}");
+            assert_eq!(&seed[..len], &got[..]);
+        }
+        // Longer seeds are replaced by truncated hash.
+        let got = hash_seed(&seed);
+        let hash = solana_sha256_hasher::hash(&seed).to_bytes();
+        assert_eq!(&hash[..31], &got[..]);
+        // The hashed seed is a valid seed and differs for different seeds.
+        let other = hash_seed(&[8; 32]);
+        assert_ne!(got, other);
+        find_write_account(&PROGRAM, &PROGRAM, &got).unwrap();
+        find_write_account(&PROGRAM, &PROGRAM, &seed[..31]).unwrap();
+        assert_eq!(
+            Err(Error::SeedTooLong { len: 32, max: 31 }),
+            find_write_account(&PROGRAM, &PROGRAM, &seed),
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "client-rpc")]
+    fn test_free_batch() {
+        use solana_packet::PACKET_DATA_SIZE;
+
+        let payer = Pubkey::new_from_array([2; 32]);
+        let seeds = (0..200)
+            .map(|idx: u8| vec![idx; usize::from(idx) * 7 % 32])
+            .collect::<Vec<_>>();
+        let accounts = seeds
+            .iter()
+            .map(|seed| {
+                let (_, bump) = find_write_account(&PROGRAM, &payer, seed)?;
+                Ok((seed.as_slice(), bump))
+            })
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let size = |instructions: &[Instruction]| {
+            let message = Message::new(instructions, Some(&payer));
+            let tx = solana_transaction::Transaction::new_unsigned(message);
+            bincode::serialize(&tx).unwrap().len()
+        };
+
+        let batches = free_batch(PROGRAM, payer, &accounts).unwrap();
+        assert!(batches.len() > 1);
+        assert_eq!(accounts.len(), batches.iter().map(Vec::len).sum::<usize>());
+        let mut end = 0;
+        for (idx, batch) in batches.iter().enumerate() {
+            let len = size(batch);
+            assert!(
+                len <= PACKET_DATA_SIZE,
+                "{idx}: {len} > {PACKET_DATA_SIZE}"
+            );
+            // First Free of the next batch wouldn’t fit in this one.
+            end += batch.len();
+            if let Some(&(seed, bump)) = accounts.get(end) {
+                let free = free(PROGRAM, payer, None, seed, bump).unwrap();
+                let len = size(&[&batch[..], &[free]].concat());
+                assert!(
+                    len > PACKET_DATA_SIZE,
+                    "{idx}: {len} <= {PACKET_DATA_SIZE}"
+                );
+            }
+        }
     }
 }
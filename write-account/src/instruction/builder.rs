@@ -0,0 +1,317 @@
+//! Fluent interface for configuring Write instructions.
+
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+
+use solana_instruction::Instruction;
+use solana_message::AddressLookupTableAccount;
+use solana_pubkey::Pubkey;
+use solana_rent::Rent;
+
+use super::{Data, Error, Result, WriteIter};
+use crate::header::V2Header;
+
+/// Builder collecting configuration of the Write instructions.
+///
+/// Rather than picking one of the [`WriteIter`] constructors and configuring
+/// the iterator afterwards, the builder gathers all the options and produces
+/// the iterator, address of the Write account and the Free instruction in one
+/// place.
+///
+/// # Example
+///
+/// ```ignore
+/// let (chunks, account, bump, free) =
+///     WriteBuilder::new(&write_account_program_id, authority.pubkey())
+///         .seed(b"seed")
+///         .v2(V2Header::FLAG_CRC32)
+///         .compute_budget(Some(20_000), Some(1_000))
+///         .build(instruction_data)?;
+/// ```
+pub struct WriteBuilder<'a> {
+    write_program: &'a Pubkey,
+    payer: Pubkey,
+    seed: &'a [u8],
+    hash_seed: bool,
+    format: Format,
+    chunk_size: Option<usize>,
+    reserved: usize,
+    unit_limit: Option<u32>,
+    unit_price: Option<u64>,
+    lookup_table: Option<AddressLookupTableAccount>,
+    existing: Option<Vec<u8>>,
+    fee_payer: Option<Pubkey>,
+    system_program: Option<Pubkey>,
+    rent: Option<Rent>,
+    #[cfg(feature = "client-zstd")]
+    zstd_level: Option<i32>,
+}
+
+/// Format of the data written into the account.
+enum Format {
+    /// Length-prefixed data; see [`WriteIter::new`].
+    Prefixed,
+    /// Raw data; see [`WriteIter::new_raw`].
+    Raw,
+    /// Data preceded by its hash; see [`WriteIter::new_sha256`].
+    Sha256,
+    /// Data with a versioned header and optional continuation account; see
+    /// [`WriteIter::new_v2`].
+    V2(V2Header, Option<Pubkey>),
+}
+
+impl<'a> WriteBuilder<'a> {
+    /// Creates a builder for Write instructions calling `write_program` paid
+    /// for by `payer`.
+    ///
+    /// By default, the seed is empty and the data is length-prefixed as with
+    /// [`WriteIter::new`].
+    pub fn new(write_program: &'a Pubkey, payer: Pubkey) -> Self {
+        Self {
+            write_program,
+            payer,
+            seed: &[],
+            hash_seed: false,
+            format: Format::Prefixed,
+            chunk_size: None,
+            reserved: 0,
+            unit_limit: None,
+            unit_price: None,
+            lookup_table: None,
+            existing: None,
+            fee_payer: None,
+            system_program: None,
+            rent: None,
+            #[cfg(feature = "client-zstd")]
+            zstd_level: None,
+        }
+    }
+
+    /// Sets seed used as part of the PDA of the Write account.
+    pub fn seed(mut self, seed: &'a [u8]) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Hashes the seed if it’s longer than 31 bytes; see [`super::hash_seed`].
+    ///
+    /// Without this option, [`Self::build`] fails if the seed is too long.
+    /// The hashed seed is what needs to be passed to functions such as
+    /// [`super::free`] when dealing with the Write account later.
+    pub fn hash_long_seed(mut self) -> Self {
+        self.hash_seed = true;
+        self
+    }
+
+    /// Writes the data without any prefix; see [`WriteIter::new_raw`].
+    pub fn raw(mut self) -> Self {
+        self.format = Format::Raw;
+        self
+    }
+
+    /// Precedes the data with its SHA-256 hash; see [`WriteIter::new_sha256`].
+    pub fn sha256(mut self) -> Self {
+        self.format = Format::Sha256;
+        self
+    }
+
+    /// Precedes the data with a versioned header with given `flags`; see
+    /// [`WriteIter::new_v2`].
+    pub fn v2(mut self, flags: u8) -> Self {
+        let flags = (self.header().flags & V2Header::FLAG_CHAINED) | flags;
+        self.header().flags = flags;
+        self
+    }
+
+    /// Includes `tag` in the versioned header; see
+    /// [`WriteIter::new_v2_tagged`].
+    ///
+    /// Implies versioned header (see [`Self::v2`]).
+    pub fn tag(mut self, tag: u32) -> Self {
+        self.header().tag = Some(tag);
+        self
+    }
+
+    /// Includes `sequence` number in the versioned header; see
+    /// [`WriteIter::new_v2_sequenced`].
+    ///
+    /// Implies versioned header (see [`Self::v2`]).
+    pub fn sequence(mut self, sequence: u32) -> Self {
+        self.header().sequence = Some(sequence);
+        self
+    }
+
+    /// Continues the payload in the `next` account; see
+    /// [`WriteIter::new_v2_chained`].
+    ///
+    /// Implies versioned header (see [`Self::v2`]).
+    pub fn chained(mut self, next: Pubkey) -> Self {
+        self.header().flags |= V2Header::FLAG_CHAINED;
+        if let Format::V2(_, ref mut chained) = self.format {
+            *chained = Some(next);
+        }
+        self
+    }
+
+    /// Sets maximum chunk size; see [`WriteIter::chunk_size`].
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// Reserves space in each Write transaction for additional instructions;
+    /// see [`WriteIter::reserve_bytes`].
+    pub fn reserve_bytes(mut self, bytes: usize) -> Self {
+        self.reserved = bytes;
+        self
+    }
+
+    /// Sets compute unit limit and price of the Write transactions; see
+    /// [`WriteIter::compute_budget`].
+    pub fn compute_budget(
+        mut self,
+        unit_limit: Option<u32>,
+        unit_price: Option<u64>,
+    ) -> Self {
+        self.unit_limit = unit_limit;
+        self.unit_price = unit_price;
+        self
+    }
+
+    /// Sets address lookup table used to compile versioned Write messages;
+    /// see [`WriteIter::lookup_table`].
+    pub fn lookup_table(mut self, table: AddressLookupTableAccount) -> Self {
+        self.lookup_table = Some(table);
+        self
+    }
+
+    /// Sets payer of the transaction fees; see [`WriteIter::fee_payer`].
+    pub fn fee_payer(mut self, fee_payer: Pubkey) -> Self {
+        self.fee_payer = Some(fee_payer);
+        self
+    }
+
+    /// Sets address of the System program; see
+    /// [`WriteIter::system_program`].
+    pub fn system_program(mut self, system_program: Pubkey) -> Self {
+        self.system_program = Some(system_program);
+        self
+    }
+
+    /// Sets rent parameters; see [`WriteIter::rent`].
+    pub fn rent(mut self, rent: Rent) -> Self {
+        self.rent = Some(rent);
+        self
+    }
+
+    /// Skips ranges of data already present in the Write account; see
+    /// [`WriteIter::skip_unchanged`].
+    pub fn skip_unchanged(mut self, existing: Vec<u8>) -> Self {
+        self.existing = Some(existing);
+        self
+    }
+
+    /// Compresses the data with zstd using given compression `level`.
+    ///
+    /// The compression is recorded in the length prefix or versioned header
+    /// (see [`V2Header::FLAG_ZSTD`]) so that programs built with `zstd`
+    /// feature decompress the payload transparently.  For compressible
+    /// payloads this reduces number of Write transactions and rent of the
+    /// Write account.  Level zero selects zstd’s default level.
+    ///
+    /// Raw data has no header to record the compression in thus
+    /// [`Self::build`] fails with [`Error::RawCompressed`] error if
+    /// compression is combined with [`Self::raw`].
+    #[cfg(feature = "client-zstd")]
+    pub fn zstd(mut self, level: i32) -> Self {
+        self.zstd_level = Some(level);
+        self
+    }
+
+    /// Builds the iterator generating Write instructions writing `data`.
+    ///
+    /// On success, returns the iterator, address and bump of the Write account
+    /// and the Free instruction which closes the account once it’s no longer
+    /// needed.  Fails under the same conditions as the [`WriteIter`]
+    /// constructors.
+    pub fn build(
+        self,
+        data: Vec<u8>,
+    ) -> Result<(WriteIter<'a>, Pubkey, u8, Instruction)> {
+        let (program, payer) = (self.write_program, self.payer);
+        let seed = if self.hash_seed {
+            super::hash_seed(self.seed)
+        } else {
+            Cow::Borrowed(self.seed)
+        };
+        #[cfg(feature = "client-zstd")]
+        let (data, compressed) = match self.zstd_level {
+            Some(level) => (compress(&data, level)?, true),
+            None => (data, false),
+        };
+        #[cfg(not(feature = "client-zstd"))]
+        let compressed = false;
+        let (mut iter, account, bump) = match self.format {
+            Format::Prefixed => {
+                let data = Data::prefixed(Cow::Owned(data), compressed)?;
+                WriteIter::new_impl(program, payer, seed, data)
+            }
+            Format::Raw if compressed => Err(Error::RawCompressed),
+            Format::Raw => {
+                super::check_len(data.len(), 0)?;
+                let data = Data::new(Vec::new(), Cow::Owned(data), Vec::new());
+                WriteIter::new_impl(program, payer, seed, data)
+            }
+            Format::Sha256 => WriteIter::new_sha256_impl(
+                program, payer, seed, data, compressed,
+            ),
+            Format::V2(mut header, next) => {
+                if compressed {
+                    header.flags |= V2Header::FLAG_ZSTD;
+                }
+                let next = next.as_ref();
+                WriteIter::new_v2_impl(program, payer, seed, header, data, next)
+            }
+        }?;
+        iter.compute_budget(self.unit_limit, self.unit_price);
+        if let Some(table) = self.lookup_table {
+            iter.lookup_table(table);
+        }
+        if let Some(fee_payer) = self.fee_payer {
+            iter.fee_payer(fee_payer);
+        }
+        if let Some(system_program) = self.system_program {
+            iter.system_program(system_program);
+        }
+        if let Some(rent) = self.rent {
+            iter.rent(rent);
+        }
+        iter.reserve_bytes(self.reserved);
+        if let Some(chunk_size) = self.chunk_size {
+            iter.chunk_size(chunk_size);
+        }
+        if let Some(existing) = self.existing {
+            iter.skip_unchanged(existing);
+        }
+        let free = iter.free_instruction();
+        Ok((iter, account, bump, free))
+    }
+
+    /// Returns versioned header switching to the versioned format if
+    /// necessary.
+    fn header(&mut self) -> &mut V2Header {
+        if !matches!(self.format, Format::V2(..)) {
+            self.format = Format::V2(V2Header::default(), None);
+        }
+        match self.format {
+            Format::V2(ref mut header, _) => header,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Compresses data with zstd using given compression level.
+#[cfg(feature = "client-zstd")]
+fn compress(data: &[u8], level: i32) -> Result<Vec<u8>> {
+    zstd::stream::encode_all(data, level).map_err(|_| Error::CompressionFailed)
+}
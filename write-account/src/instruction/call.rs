@@ -0,0 +1,90 @@
+//! Helpers for building the instruction which calls the target program.
+
+use alloc::vec::Vec;
+
+use solana_instruction::{AccountMeta, Instruction};
+use solana_pubkey::Pubkey;
+
+/// Helpers for passing the Write account to the target program.
+///
+/// Programs using the [`crate::entrypoint!`] macro read the instruction data
+/// from the last account if the instruction data is empty.
+pub struct Accounts;
+
+impl Accounts {
+    /// Returns account meta of the Write account.
+    ///
+    /// The account is neither a signer nor writable since the target program
+    /// only reads it.
+    pub fn meta(write_account: Pubkey) -> AccountMeta {
+        AccountMeta::new_readonly(write_account, false)
+    }
+
+    /// Appends the Write account to the accounts of the `instruction`.
+    ///
+    /// The instruction’s data is left untouched.  It should be empty unless
+    /// the target program uses the mixed mode (see [`CallViaAccount`]).
+    pub fn append(instruction: &mut Instruction, write_account: Pubkey) {
+        instruction.accounts.push(Self::meta(write_account));
+    }
+}
+
+/// Call to the target program with instruction data passed through the Write
+/// account.
+///
+/// # Example
+///
+/// ```ignore
+/// let (chunks, write_account, _) =
+///     WriteIter::new(&write_account_program_id, payer, b"", data)?;
+/// // Send Write instructions generated by `chunks`.
+/// let instruction =
+///     CallViaAccount::new(program_id, accounts).instruction(write_account);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallViaAccount {
+    /// Address of the target program.
+    pub program_id: Pubkey,
+
+    /// Accounts of the call not including the Write account.
+    pub accounts: Vec<AccountMeta>,
+
+    /// Inline instruction data.
+    ///
+    /// Empty unless the target program uses the mixed mode (see
+    /// [`super::WriteIter::new_mixed`]).
+    pub inline_data: Vec<u8>,
+}
+
+impl CallViaAccount {
+    /// Creates a call to `program_id` with given accounts.
+    pub fn new(program_id: Pubkey, accounts: Vec<AccountMeta>) -> Self {
+        Self { program_id, accounts, inline_data: Vec::new() }
+    }
+
+    /// Sets inline instruction data for target programs using the mixed mode.
+    ///
+    /// The inline data is as returned by [`super::WriteIter::new_mixed`].
+    pub fn with_inline_data(mut self, inline_data: Vec<u8>) -> Self {
+        self.inline_data = inline_data;
+        self
+    }
+
+    /// Returns the instruction calling the target program with given Write
+    /// account appended to the accounts.
+    pub fn instruction(&self, write_account: Pubkey) -> Instruction {
+        self.clone().into_instruction(write_account)
+    }
+
+    /// Converts the call into an instruction with given Write account
+    /// appended to the accounts.
+    pub fn into_instruction(self, write_account: Pubkey) -> Instruction {
+        let mut instruction = Instruction {
+            program_id: self.program_id,
+            accounts: self.accounts,
+            data: self.inline_data,
+        };
+        Accounts::append(&mut instruction, write_account);
+        instruction
+    }
+}
@@ -0,0 +1,50 @@
+//! Error returned when generating instructions fails.
+
+use solana_program_error::ProgramError;
+
+/// Error returned when generating instructions calling the write-account
+/// program fails.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum Error {
+    /// Data (together with its header) doesn’t fit in an account.
+    #[error("data too large: {len} bytes exceeds maximum of {max} bytes")]
+    DataTooLarge { len: usize, max: usize },
+
+    /// Seed is longer than the maximum of 31 bytes.
+    #[error("seed too long: {len} bytes exceeds maximum of {max} bytes")]
+    SeedTooLong { len: usize, max: usize },
+
+    /// Seed and bump don’t make a valid Write account address.
+    #[error("seed and bump don’t make a valid Write account address")]
+    InvalidBump,
+
+    /// Write account provided by the caller doesn’t match the one derived
+    /// from payer, seed and bump.
+    #[error("Write account doesn’t match address derived from seed and bump")]
+    DerivationMismatch,
+
+    /// Compression was requested for raw data which has no header to record
+    /// it in.
+    #[error("raw data cannot be compressed")]
+    RawCompressed,
+
+    /// Compressing the data failed.
+    #[error("compressing data failed")]
+    CompressionFailed,
+}
+
+/// Converts the error into the closest matching program error.
+impl From<Error> for ProgramError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::DataTooLarge { .. } => Self::ArithmeticOverflow,
+            Error::SeedTooLong { .. } => Self::MaxSeedLengthExceeded,
+            Error::InvalidBump | Error::DerivationMismatch => {
+                Self::InvalidSeeds
+            }
+            Error::RawCompressed | Error::CompressionFailed => {
+                Self::InvalidArgument
+            }
+        }
+    }
+}
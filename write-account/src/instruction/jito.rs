@@ -0,0 +1,206 @@
+//! Grouping of Write transactions into Jito bundles.
+//!
+//! Jito block engine executes transactions of a bundle sequentially and
+//! atomically — either all of them land in the same block or none do.  Bundles
+//! must include a tip transferred to one of Jito tip accounts (which can be
+//! fetched with `getTipAccounts` call of the block engine API).
+//!
+//! Uploading data in bundles lets relayers land uploads quickly during
+//! congestion and, if the data fits in a single bundle, atomically.
+
+use alloc::vec::Vec;
+
+use solana_instruction::Instruction;
+use solana_pubkey::{Pubkey, PUBKEY_BYTES};
+
+use super::WriteIter;
+
+/// Maximum number of transactions in a bundle.
+pub const MAX_BUNDLE_LEN: usize = 5;
+
+/// Number of bytes the tip takes in a transaction.
+///
+/// Tip account address and the transfer instruction: program id index,
+/// accounts length, two account indexes, data length and the data.
+const TIP_OVERHEAD: usize = PUBKEY_BYTES + 5 + 12;
+
+/// Bundle of Write transactions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bundle {
+    /// Instructions of each transaction of the bundle in execution order.
+    ///
+    /// The last transaction includes the tip.
+    pub transactions: Vec<Vec<Instruction>>,
+}
+
+/// Groups Write instructions generated by `chunks` into Jito bundles.
+///
+/// Each Write transaction starts with compute budget instructions (see
+/// [`WriteIter::compute_budget`]).  Each bundle consists of up to
+/// [`MAX_BUNDLE_LEN`] transactions with the last one transferring
+/// `tip_lamports` to `tip_account`.  The tip is paid by the fee payer (see
+/// [`WriteIter::fee_payer`]).  To make room for the tip instruction, the chunk
+/// written by the last transaction of a full bundle is shorter than others.
+/// The last bundle may instead include the tip in a separate transaction.
+///
+/// Bundles must be sent in order with each landing before the next one is
+/// sent.  Upload is atomic only if it fits in a single bundle.
+pub fn bundles(
+    chunks: &mut WriteIter,
+    tip_account: &Pubkey,
+    tip_lamports: u64,
+) -> Vec<Bundle> {
+    let budget = chunks.compute_budget_instructions();
+    let payer = chunks.fee_payer.unwrap_or(chunks.payer);
+    let tip = solana_system_interface::instruction::transfer(
+        &payer,
+        tip_account,
+        tip_lamports,
+    );
+    let tip_chunk_size = usize::from(chunks.chunk_size.get())
+        .saturating_sub(TIP_OVERHEAD)
+        .max(1);
+
+    let mut bundles = Vec::new();
+    let mut transactions = Vec::with_capacity(MAX_BUNDLE_LEN);
+    let mut fits_tip = false;
+    while let Some(mut range) = chunks.next_range() {
+        if transactions.len() == MAX_BUNDLE_LEN - 1 {
+            range.end = range.end.min(range.start + tip_chunk_size);
+        }
+        fits_tip = range.len() <= tip_chunk_size;
+        let write = chunks.advance(range);
+        transactions.push([&budget[..], &[write]].concat());
+        if transactions.len() == MAX_BUNDLE_LEN {
+            transactions.last_mut().unwrap().push(tip.clone());
+            let transactions = core::mem::take(&mut transactions);
+            bundles.push(Bundle { transactions });
+        }
+    }
+    if !transactions.is_empty() {
+        // There’s room for another transaction in the bundle so if the tip
+        // doesn’t fit in the last transaction, send it separately.
+        if fits_tip {
+            transactions.last_mut().unwrap().push(tip);
+        } else {
+            transactions.push(Vec::from([tip]));
+        }
+        bundles.push(Bundle { transactions });
+    }
+    bundles
+}
+
+#[cfg(all(test, feature = "client-rpc"))]
+mod tests {
+    use alloc::vec;
+
+    use pretty_assertions::assert_eq;
+    use solana_message::Message;
+    use solana_transaction::Transaction;
+
+    use super::*;
+
+    const PROGRAM: Pubkey = Pubkey::new_from_array([1; 32]);
+    const PAYER: Pubkey = Pubkey::new_from_array([2; 32]);
+    const TIP_ACCOUNT: Pubkey = Pubkey::new_from_array([3; 32]);
+
+    /// Lengths of chunks written by transactions of each bundle (if any) and
+    /// whether they include the tip.
+    type Shape = Vec<Vec<(Option<usize>, bool)>>;
+
+    /// Returns iterator writing `len` bytes and its chunk size.
+    fn write_iter(len: usize) -> (WriteIter<'static>, usize) {
+        let data = vec![42; len];
+        let (mut iter, ..) =
+            WriteIter::new_raw(&PROGRAM, PAYER, b"", data).unwrap();
+        iter.compute_budget(Some(100_000), Some(1_000));
+        let chunk_size = usize::from(iter.chunk_size.get());
+        (iter, chunk_size)
+    }
+
+    /// Describes transactions of the bundles.
+    ///
+    /// Checks that each transaction fits in a packet.
+    fn shape(bundles: &[Bundle]) -> Shape {
+        let tip = solana_system_interface::instruction::transfer(
+            &PAYER,
+            &TIP_ACCOUNT,
+            1000,
+        );
+        let describe = |instructions: &Vec<Instruction>| {
+            let message = Message::new(instructions, Some(&PAYER));
+            let tx = Transaction::new_unsigned(message);
+            assert!(bincode::serialize(&tx).unwrap().len() <= 1232);
+            let write = instructions
+                .iter()
+                .find(|ix| ix.program_id == PROGRAM)
+                .map(|ix| ix.data.len() - 7);
+            (write, instructions.last() == Some(&tip))
+        };
+        bundles
+            .iter()
+            .map(|bundle| {
+                assert!(bundle.transactions.len() <= MAX_BUNDLE_LEN);
+                bundle.transactions.iter().map(describe).collect()
+            })
+            .collect()
+    }
+
+    fn bundles_of(len: usize) -> (Shape, usize) {
+        let (mut iter, chunk_size) = write_iter(len);
+        let bundles = bundles(&mut iter, &TIP_ACCOUNT, 1000);
+        assert_eq!(None, iter.next());
+        (shape(&bundles), chunk_size)
+    }
+
+    #[test]
+    fn test_full_bundle() {
+        let (_, c) = write_iter(0);
+        let t = c - TIP_OVERHEAD;
+        let (got, _) = bundles_of(4 * c + t);
+        let want = vec![vec![
+            (Some(c), false),
+            (Some(c), false),
+            (Some(c), false),
+            (Some(c), false),
+            (Some(t), true),
+        ]];
+        assert_eq!(want, got);
+
+        // The fifth chunk is shortened even if more data follows.
+        let (got, _) = bundles_of(4 * c + t + 10);
+        let mut want = want;
+        want.push(vec![(Some(10), true)]);
+        assert_eq!(want, got);
+    }
+
+    #[test]
+    fn test_partial_bundle_with_tip() {
+        let (got, c) = bundles_of(30);
+        assert!(30 <= c - TIP_OVERHEAD);
+        assert_eq!(vec![vec![(Some(30), true)]], got);
+
+        let (_, c) = write_iter(0);
+        let (got, _) = bundles_of(2 * c + 10);
+        let want =
+            vec![vec![(Some(c), false), (Some(c), false), (Some(10), true)]];
+        assert_eq!(want, got);
+    }
+
+    #[test]
+    fn test_partial_bundle_with_separate_tip() {
+        let (_, c) = write_iter(0);
+        let (got, _) = bundles_of(c);
+        assert_eq!(vec![vec![(Some(c), false), (None, true)]], got);
+
+        let (got, _) = bundles_of(4 * c);
+        let want = vec![vec![
+            (Some(c), false),
+            (Some(c), false),
+            (Some(c), false),
+            (Some(c), false),
+            (None, true),
+        ]];
+        assert_eq!(want, got);
+    }
+}
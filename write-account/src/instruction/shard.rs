@@ -0,0 +1,390 @@
+//! Splitting of payloads too large for a single Write account.
+
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+
+use solana_instruction::{AccountMeta, Instruction};
+use solana_pubkey::{Pubkey, PUBKEY_BYTES};
+
+use super::{find_write_account, Accounts, Error, Result, WriteIter};
+use crate::header::V2Header;
+
+/// Maximum number of shards.
+///
+/// The instruction calling the target program must list Write accounts of all
+/// the shards and a transaction can reference at most 64 accounts including
+/// the fee payer and the called program.  Accounts of the target program
+/// lower the limit further.
+pub const MAX_SHARDS: usize = 64 - 2;
+
+/// Maximum total length of a sharded payload.
+///
+/// The entrypoint joins the shards by copying them into a single heap buffer.
+/// An SBF program’s heap is at most 256 KiB (requested with compute budget’s
+/// `RequestHeapFrame` instruction; the default is 32 KiB) so larger payloads
+/// could never be decoded.  The target program needs heap for its own
+/// allocations as well so in practice the limit is lower.
+pub const MAX_SHARDED_LEN: usize = 256 * 1024;
+
+/// Length of shards created by [`ShardedWrite::new`].
+///
+/// Account size can grow by at most 10 KiB in a single instruction.  With
+/// shards this small (including the header and the continuation address) no
+/// Write instruction grows the account by more than that so Write
+/// instructions of a shard may be executed in any order.
+const DEFAULT_SHARD_LEN: usize = 10 * 1024 - V2Header::LEN - PUBKEY_BYTES;
+
+/// Payload split across multiple chained Write accounts.
+///
+/// `ShardedWrite` splits a payload into shards each written into a separate
+/// Write account whose seed is the base seed followed by index of the shard.
+/// All but the last shard are written with [`WriteIter::new_v2_chained`]
+/// pointing at the next shard so that the entrypoint stitches the payload
+/// back together (see [`V2Header::FLAG_CHAINED`]).  Shards can be uploaded
+/// independently, e.g. in parallel or by multiple relayers.
+///
+/// The entrypoint copies the stitched payload into a heap buffer thus the
+/// payload can be at most [`MAX_SHARDED_LEN`] bytes long and the target
+/// program must request large enough heap.  It also can’t be split into more
+/// than [`MAX_SHARDS`] shards since the call to the target program must list
+/// all of them.
+///
+/// # Example
+///
+/// ```ignore
+/// let sharded = ShardedWrite::new(&write_account_program_id, payer, b"", data)?;
+/// let mut instruction = CallViaAccount::new(program_id, accounts)
+///     .into_instruction(sharded.account());
+/// sharded.append_accounts(&mut instruction);
+/// for shard in sharded.shards {
+///     // Send Write instructions generated by `shard.iter`.
+/// }
+/// // Send `instruction`.
+/// ```
+pub struct ShardedWrite<'a> {
+    /// Shards in order of the payload.
+    pub shards: Vec<Shard<'a>>,
+}
+
+/// Single shard of a [`ShardedWrite`].
+pub struct Shard<'a> {
+    /// Iterator generating Write instructions of the shard.
+    pub iter: WriteIter<'a>,
+
+    /// Address of the Write account holding the shard.
+    pub account: Pubkey,
+
+    /// Bump of the Write account holding the shard.
+    pub bump: u8,
+
+    /// Seed of the Write account holding the shard, i.e. base seed followed
+    /// by index of the shard.
+    pub seed: Vec<u8>,
+}
+
+impl<'a> ShardedWrite<'a> {
+    /// Splits `data` into shards of about 10 KiB.
+    ///
+    /// Write instructions of such shards may be executed in any order which
+    /// lets them be sent in parallel.  Fails if the seed is longer than 30
+    /// bytes (one byte is taken by the shard index) or if the data is longer
+    /// than [`MAX_SHARDED_LEN`].
+    pub fn new(
+        write_program: &'a Pubkey,
+        payer: Pubkey,
+        seed: &[u8],
+        data: Vec<u8>,
+    ) -> Result<Self> {
+        Self::with_shard_len(
+            write_program,
+            payer,
+            seed,
+            data,
+            DEFAULT_SHARD_LEN,
+        )
+    }
+
+    /// Splits `data` into shards of at most `shard_len` bytes.
+    ///
+    /// Fails under the same conditions as [`Self::new`], if the data needs
+    /// more than [`MAX_SHARDS`] shards or if shards are too large to fit in an
+    /// account.
+    pub fn with_shard_len(
+        write_program: &'a Pubkey,
+        payer: Pubkey,
+        seed: &[u8],
+        data: Vec<u8>,
+        shard_len: usize,
+    ) -> Result<Self> {
+        let shard_len = shard_len.max(1);
+        let count = data.len().div_ceil(shard_len).max(1);
+        if count > MAX_SHARDS || data.len() > MAX_SHARDED_LEN {
+            let max = shard_len.saturating_mul(MAX_SHARDS).min(MAX_SHARDED_LEN);
+            return Err(Error::DataTooLarge { len: data.len(), max });
+        }
+        let seeds = (0..count)
+            .map(|index| [seed, &[index as u8]].concat())
+            .collect::<Vec<_>>();
+        let accounts = seeds
+            .iter()
+            .map(|seed| find_write_account(write_program, &payer, seed))
+            .collect::<Result<Vec<_>>>()?;
+        let mut shards = Vec::with_capacity(count);
+        let mut chunks = data.chunks(shard_len);
+        for (index, seed) in seeds.into_iter().enumerate() {
+            let data = chunks.next().unwrap_or_default().to_vec();
+            let next = accounts.get(index + 1).map(|(account, _)| account);
+            let flags = if next.is_some() { V2Header::FLAG_CHAINED } else { 0 };
+            let header = V2Header { flags, ..Default::default() };
+            let (iter, account, bump) = WriteIter::new_v2_impl(
+                write_program,
+                payer,
+                Cow::Owned(seed.clone()),
+                header,
+                data,
+                next,
+            )?;
+            shards.push(Shard { iter, account, bump, seed });
+        }
+        Ok(Self { shards })
+    }
+
+    /// Returns address of the first shard’s Write account.
+    ///
+    /// This is the account which must be passed as the last account of the
+    /// instruction calling the target program (see [`Accounts::append`]).
+    pub fn account(&self) -> Pubkey { self.shards[0].account }
+
+    /// Returns account metas of the continuation accounts, i.e. all shards
+    /// but the first one.
+    pub fn continuation_accounts(&self) -> Vec<AccountMeta> {
+        self.shards[1..]
+            .iter()
+            .map(|shard| Accounts::meta(shard.account))
+            .collect()
+    }
+
+    /// Appends Write accounts of all the shards to the accounts of the
+    /// `instruction`.
+    ///
+    /// The first shard’s Write account (see [`Self::account`]) is appended
+    /// unless it’s already the last account of the instruction.  The
+    /// continuation accounts are inserted before it.
+    pub fn append_accounts(&self, instruction: &mut Instruction) {
+        let accounts = &mut instruction.accounts;
+        let first = self.account();
+        if accounts.last().is_none_or(|meta| meta.pubkey != first) {
+            accounts.push(Accounts::meta(first));
+        }
+        let at = accounts.len() - 1;
+        accounts.splice(at..at, self.continuation_accounts());
+    }
+
+    /// Returns instructions freeing Write accounts of all the shards.
+    pub fn free_instructions(&self) -> Vec<Instruction> {
+        self.shards.iter().map(|shard| shard.iter.free_instruction()).collect()
+    }
+}
+
+#[cfg(all(test, any(feature = "lib", feature = "pinocchio")))]
+mod tests {
+    use alloc::vec;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::codec::{
+        split_prefixed, Continuations, LengthPrefixed, PayloadCodec,
+        WriteAccountError,
+    };
+
+    const PROGRAM: Pubkey = Pubkey::new_from_array([1; 32]);
+    const PAYER: Pubkey = Pubkey::new_from_array([2; 32]);
+
+    /// Accounts passed to the target program along with their data.
+    struct TestAccounts(Vec<(Pubkey, Vec<u8>)>);
+
+    impl Continuations for TestAccounts {
+        type Error = WriteAccountError;
+
+        fn count(&self) -> usize { self.0.len() }
+
+        fn with_data<R>(
+            &self,
+            address: &[u8; 32],
+            f: impl FnOnce(&[u8]) -> Result<R, WriteAccountError>,
+        ) -> Result<R, WriteAccountError> {
+            let (_, data) = self
+                .0
+                .iter()
+                .find(|(key, _)| key.as_array() == address)
+                .ok_or(WriteAccountError::MissingContinuationAccount)?;
+            f(data)
+        }
+    }
+
+    /// Executes Write instructions generated by the shard’s iterator and
+    /// returns resulting content of its Write account.
+    fn account_data(shard: Shard) -> Vec<u8> {
+        let mut account = Vec::new();
+        for instruction in shard.iter {
+            let data = &instruction.data[3 + shard.seed.len()..];
+            let (offset, bytes) = data.split_first_chunk().unwrap();
+            let offset = u32::from_le_bytes(*offset) as usize;
+            let end = offset + bytes.len();
+            if account.len() < end {
+                account.resize(end, 0);
+            }
+            account[offset..end].copy_from_slice(bytes);
+        }
+        account
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = (0..35).collect::<Vec<u8>>();
+        let sharded = ShardedWrite::with_shard_len(
+            &PROGRAM,
+            PAYER,
+            b"seed",
+            data.clone(),
+            10,
+        )
+        .unwrap();
+        assert_eq!(4, sharded.shards.len());
+
+        let target = AccountMeta::new(Pubkey::new_from_array([3; 32]), false);
+        let mut instruction = Instruction {
+            program_id: Pubkey::new_from_array([4; 32]),
+            accounts: vec![target.clone()],
+            data: Vec::new(),
+        };
+        sharded.append_accounts(&mut instruction);
+        let addresses = sharded
+            .shards
+            .iter()
+            .map(|shard| shard.account)
+            .collect::<Vec<_>>();
+        let mut want = vec![target.pubkey];
+        want.extend_from_slice(&addresses[1..]);
+        want.push(addresses[0]);
+        let got = instruction
+            .accounts
+            .iter()
+            .map(|meta| meta.pubkey)
+            .collect::<Vec<_>>();
+        assert_eq!(want, got);
+
+        // Appending again doesn’t duplicate the first shard’s account.
+        let mut again = instruction.clone();
+        again.accounts.truncate(1);
+        again.accounts.push(Accounts::meta(addresses[0]));
+        sharded.append_accounts(&mut again);
+        assert_eq!(instruction.accounts, again.accounts);
+
+        let datas =
+            sharded.shards.into_iter().map(account_data).collect::<Vec<_>>();
+        for (index, account) in datas.iter().enumerate() {
+            let flags = LengthPrefixed::header_flags(account).unwrap();
+            let prefixed = split_prefixed(account).unwrap();
+            let next = addresses.get(index + 1).map(|key| key.to_bytes());
+            assert_eq!(next.is_some(), flags & V2Header::FLAG_CHAINED != 0);
+            assert_eq!(next, prefixed.next);
+            let start = index * 10;
+            assert_eq!(&data[start..(start + 10).min(35)], prefixed.payload);
+        }
+
+        // Accounts as seen by the entrypoint: the Write account is the last
+        // one and the continuations are looked up in the rest.
+        let data_of = |key: &Pubkey| {
+            let index = addresses.iter().position(|addr| addr == key);
+            index.map_or_else(Vec::new, |index| datas[index].clone())
+        };
+        let (last, rest) = instruction.accounts.split_last().unwrap();
+        let rest = TestAccounts(
+            rest.iter()
+                .map(|meta| (meta.pubkey, data_of(&meta.pubkey)))
+                .collect(),
+        );
+        let first = data_of(&last.pubkey);
+        let got = LengthPrefixed::decode_chained(&first, &rest).unwrap();
+        assert_eq!(&data[..], got);
+
+        // Missing continuation is detected.
+        let partial = TestAccounts(rest.0[..2].to_vec());
+        assert_eq!(
+            Err(WriteAccountError::MissingContinuationAccount),
+            LengthPrefixed::decode_chained(&datas[0], &partial)
+        );
+    }
+
+    #[test]
+    fn test_max_shards() {
+        let data = vec![0; MAX_SHARDS];
+        let sharded =
+            ShardedWrite::with_shard_len(&PROGRAM, PAYER, b"", data, 1)
+                .unwrap();
+        assert_eq!(MAX_SHARDS, sharded.shards.len());
+
+        // Together with the payer and the target program, the shards use up
+        // all accounts a transaction can reference.
+        let mut instruction = Instruction {
+            program_id: Pubkey::new_from_array([4; 32]),
+            accounts: Vec::new(),
+            data: Vec::new(),
+        };
+        sharded.append_accounts(&mut instruction);
+        let message =
+            solana_message::Message::new(&[instruction], Some(&PAYER));
+        assert_eq!(64, message.account_keys.len());
+
+        let data = vec![0; MAX_SHARDS + 1];
+        let err = ShardedWrite::with_shard_len(&PROGRAM, PAYER, b"", data, 1)
+            .err()
+            .unwrap();
+        assert_eq!(
+            Error::DataTooLarge { len: MAX_SHARDS + 1, max: MAX_SHARDS },
+            err
+        );
+    }
+
+    #[test]
+    fn test_max_len() {
+        let data = (0..MAX_SHARDED_LEN).map(|n| n as u8).collect::<Vec<_>>();
+        let sharded =
+            ShardedWrite::new(&PROGRAM, PAYER, b"", data.clone()).unwrap();
+        assert!(sharded.shards.len() <= MAX_SHARDS);
+
+        // Each shard fits in the 10 KiB an account can grow by in a single
+        // instruction.
+        let accounts = sharded
+            .shards
+            .into_iter()
+            .map(|shard| (shard.account, account_data(shard)))
+            .collect::<Vec<_>>();
+        for (_, data) in accounts.iter() {
+            assert!(data.len() <= 10 * 1024, "{}", data.len());
+        }
+        let first = accounts[0].1.clone();
+        let got =
+            LengthPrefixed::decode_chained(&first, &TestAccounts(accounts))
+                .unwrap();
+        assert_eq!(data, got);
+
+        let data = vec![0; MAX_SHARDED_LEN + 1];
+        let want = Error::DataTooLarge {
+            len: MAX_SHARDED_LEN + 1,
+            max: MAX_SHARDED_LEN,
+        };
+        let err = ShardedWrite::new(&PROGRAM, PAYER, b"", data.clone());
+        assert_eq!(want, err.err().unwrap());
+        let err = ShardedWrite::with_shard_len(
+            &PROGRAM,
+            PAYER,
+            b"",
+            data,
+            MAX_SHARDED_LEN,
+        );
+        assert_eq!(want, err.err().unwrap());
+    }
+}
@@ -0,0 +1,197 @@
+//! Write instructions generated from data read on demand.
+
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use std::io;
+
+use solana_instruction::Instruction;
+use solana_pubkey::Pubkey;
+
+use super::{check_len, Data, Result, WriteIter};
+
+/// Iterator generating Write instructions with data read from an
+/// [`io::Read`] source.
+///
+/// Unlike [`WriteIter`], which holds the entire payload in memory, this reads
+/// a chunk at a time as instructions are generated.  This allows uploading
+/// payloads larger than available memory (e.g. read from a file or
+/// a decompressor) without buffering them first.
+///
+/// The iterator yields an error if reading the data fails.  In that case the
+/// state of the reader is unknown and the iterator doesn’t generate any more
+/// instructions.
+///
+/// # Example
+///
+/// ```ignore
+/// let file = std::fs::File::open(path)?;
+/// let len = file.metadata()?.len() as usize;
+/// let (chunks, chunk_account, _) = StreamWriteIter::new(
+///     &write_account_program_id,
+///     authority.pubkey(),
+///     b"",
+///     std::io::BufReader::new(file),
+///     len,
+/// )?;
+/// for instruction in chunks {
+///     let instruction = instruction?;
+///     // Send transaction with `instruction`.
+/// }
+/// ```
+pub struct StreamWriteIter<'a, R> {
+    inner: WriteIter<'a>,
+    reader: R,
+    len: usize,
+}
+
+impl<'a, R: io::Read> StreamWriteIter<'a, R> {
+    /// Constructs a new iterator generating Write instructions writing
+    /// length-prefixed data read from `reader`.
+    ///
+    /// Behaves like [`WriteIter::new`] except that the data is read from
+    /// `reader` as instructions are generated.  Since the length prefix is
+    /// written first, `len` must be the exact number of bytes the reader is
+    /// going to provide.  If the reader ends prematurely, the iterator yields
+    /// an [`io::ErrorKind::UnexpectedEof`] error.  Any bytes past `len` are
+    /// not read.
+    pub fn new(
+        write_program: &'a Pubkey,
+        payer: Pubkey,
+        seed: &'a [u8],
+        reader: R,
+        len: usize,
+    ) -> Result<(Self, Pubkey, u8)> {
+        let prefix = check_len(len, 4)?.to_le_bytes();
+        let data = Data::new(prefix.to_vec(), Cow::Borrowed(&[]), Vec::new());
+        let (inner, account, bump) =
+            WriteIter::new_impl(write_program, payer, seed.into(), data)?;
+        Ok((Self { inner, reader, len: len + prefix.len() }, account, bump))
+    }
+
+    /// Sets maximum chunk size; see [`WriteIter::chunk_size`].
+    pub fn chunk_size(&mut self, chunk_size: usize) {
+        self.inner.chunk_size(chunk_size)
+    }
+
+    /// Sets compute unit limit and price of the Write transactions; see
+    /// [`WriteIter::compute_budget`].
+    pub fn compute_budget(
+        &mut self,
+        unit_limit: Option<u32>,
+        unit_price: Option<u64>,
+    ) {
+        self.inner.compute_budget(unit_limit, unit_price)
+    }
+
+    /// Returns compute budget instructions to include in each Write
+    /// transaction; see [`WriteIter::compute_budget_instructions`].
+    pub fn compute_budget_instructions(&self) -> Vec<Instruction> {
+        self.inner.compute_budget_instructions()
+    }
+
+    /// Consumes the iterator and returns Write account address and bump.
+    pub fn into_account(self) -> (Pubkey, u8) { self.inner.into_account() }
+
+    /// Generates Write instruction for the chunk at given range reading its
+    /// data from the reader.
+    fn read_chunk(
+        &mut self,
+        start: usize,
+        end: usize,
+    ) -> io::Result<Instruction> {
+        let mut data = self.inner.instruction_data(start, end - start);
+        // Length prefix is the only part of the data not read from the reader.
+        let prefix = self.inner.data.len();
+        self.inner
+            .data
+            .extend_into(start.min(prefix)..end.min(prefix), &mut data);
+        let offset = data.len();
+        data.resize(offset + end.max(prefix) - start.max(prefix), 0);
+        self.reader.read_exact(&mut data[offset..])?;
+        Ok(self.inner.write_instruction(data))
+    }
+}
+
+impl<R: io::Read> core::iter::Iterator for StreamWriteIter<'_, R> {
+    type Item = io::Result<Instruction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.inner.position;
+        if start >= self.len {
+            return None;
+        }
+        let chunk_size = usize::from(self.inner.chunk_size.get());
+        let end = start.saturating_add(chunk_size).min(self.len);
+        let result = self.read_chunk(start, end);
+        // Stop after an error since the reader may have been partially read.
+        self.inner.position = if result.is_ok() { end } else { self.len };
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    const PROGRAM: Pubkey = Pubkey::new_from_array([1; 32]);
+    const PAYER: Pubkey = Pubkey::new_from_array([2; 32]);
+
+    /// Returns instructions generated by [`WriteIter::new`] and
+    /// [`StreamWriteIter::new`] for given data and chunk size.
+    fn generate(
+        data: &[u8],
+        chunk_size: usize,
+    ) -> (Vec<Instruction>, Vec<Instruction>) {
+        let (mut want, ..) =
+            WriteIter::new(&PROGRAM, PAYER, b"seed", data.to_vec()).unwrap();
+        want.chunk_size(chunk_size);
+        let (mut got, ..) =
+            StreamWriteIter::new(&PROGRAM, PAYER, b"seed", data, data.len())
+                .unwrap();
+        got.chunk_size(chunk_size);
+        let got = got.collect::<io::Result<Vec<_>>>().unwrap();
+        (want.collect(), got)
+    }
+
+    #[test]
+    fn test_matches_write_iter() {
+        let data = (0..10).collect::<Vec<u8>>();
+        // Chunk sizes smaller than, equal to and straddling the four-byte
+        // length prefix as well as one covering the whole payload.
+        for chunk_size in [1, 2, 3, 4, 5, 6, 7, 13, 14, 100] {
+            let (want, got) = generate(&data, chunk_size);
+            assert_eq!(want, got, "chunk_size: {chunk_size}");
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        for chunk_size in [1, 3, 4, 100] {
+            let (want, got) = generate(&[], chunk_size);
+            assert_eq!(want, got, "chunk_size: {chunk_size}");
+        }
+        let (want, _) = generate(&[], 100);
+        assert_eq!(1, want.len());
+    }
+
+    #[test]
+    fn test_short_reader() {
+        let data = (0..10).collect::<Vec<u8>>();
+        // The reader provides only nine of the 14 bytes (including the
+        // length prefix) so the chunk covering the tenth byte fails.
+        for (chunk_size, ok) in [(3, 3), (4, 2), (6, 1), (9, 1), (100, 0)] {
+            let (mut iter, ..) =
+                StreamWriteIter::new(&PROGRAM, PAYER, b"seed", &data[..5], 10)
+                    .unwrap();
+            iter.chunk_size(chunk_size);
+            for _ in 0..ok {
+                iter.next().unwrap().unwrap();
+            }
+            let err = iter.next().unwrap().unwrap_err();
+            assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+            assert!(iter.next().is_none(), "chunk_size: {chunk_size}");
+        }
+    }
+}
@@ -0,0 +1,2532 @@
+//! High-level helpers sending the Write instructions to the cluster.
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+#[cfg(feature = "client-async")]
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::num::{NonZeroU32, NonZeroUsize};
+use core::time::Duration;
+
+use solana_account::Account;
+#[cfg(feature = "client-async")]
+use solana_account_info::MAX_PERMITTED_DATA_INCREASE;
+use solana_client::client_error::ClientError;
+#[cfg(feature = "client-async")]
+use solana_client::nonblocking::pubsub_client::{
+    PubsubClient, PubsubClientError,
+};
+#[cfg(feature = "client-async")]
+use solana_client::nonblocking::rpc_client::RpcClient as AsyncRpcClient;
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcSendTransactionConfig;
+#[cfg(feature = "client-async")]
+use solana_client::rpc_config::RpcSignatureSubscribeConfig;
+use solana_client::rpc_request::{
+    RpcError, MAX_GET_SIGNATURE_STATUSES_QUERY_ITEMS,
+};
+#[cfg(feature = "client-async")]
+use solana_client::rpc_response::RpcSignatureResult;
+use solana_client::rpc_response::RpcSimulateTransactionResult;
+use solana_commitment_config::CommitmentConfig;
+use solana_hash::Hash;
+use solana_instruction::Instruction;
+use solana_pubkey::{Pubkey, PUBKEY_BYTES};
+#[cfg(feature = "client-tpu")]
+use solana_quic_client::{QuicConfig, QuicConnectionManager, QuicPool};
+use solana_signature::Signature;
+use solana_signer::Signer;
+use solana_transaction::Transaction;
+use solana_transaction_error::TransactionError;
+
+use super::{Accounts, ChunkInfo, Error, ShardedWrite, WriteIter};
+
+/// Emits a `tracing` event at given level if `tracing` feature is enabled.
+///
+/// Arguments following the level are as in [`tracing::event!`] macro.
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(tracing::Level::$level, $($arg)*);
+    };
+}
+
+/// Error returned when uploading data into the Write account fails.
+#[derive(Debug)]
+pub enum UploadError {
+    /// Failed to generate the Write instructions (e.g. seed was too long).
+    Instruction(Error),
+
+    /// Failed to send or confirm a transaction.
+    Client(Box<ClientError>),
+
+    /// Simulation of the call to the target program failed; see
+    /// [`UploadConfig::preflight`].
+    Preflight(Box<Preflight>),
+
+    /// Data read from the Write account is malformed; see [`fetch_data`].
+    InvalidData,
+
+    /// Couldn’t find a seed of an account which doesn’t exist yet; see
+    /// [`unique_seed`].
+    SeedCollision,
+}
+
+impl From<Error> for UploadError {
+    fn from(err: Error) -> Self { Self::Instruction(err) }
+}
+
+impl From<ClientError> for UploadError {
+    fn from(err: ClientError) -> Self { Self::Client(Box::new(err)) }
+}
+
+impl core::fmt::Display for UploadError {
+    fn fmt(&self, fmtr: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Instruction(err) => err.fmt(fmtr),
+            Self::Client(err) => err.fmt(fmtr),
+            Self::Preflight(preflight) => match &preflight.err {
+                Some(err) => write!(fmtr, "preflight simulation failed: {err}"),
+                None => fmtr.write_str("preflight simulation failed"),
+            },
+            Self::InvalidData => fmtr.write_str("malformed Write account data"),
+            Self::SeedCollision => fmtr.write_str("no unused seed found"),
+        }
+    }
+}
+
+impl core::error::Error for UploadError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Instruction(err) => Some(err),
+            Self::Client(err) => Some(&**err),
+            Self::Preflight(preflight) => {
+                preflight.err.as_ref().map(|err| err as _)
+            }
+            Self::InvalidData | Self::SeedCollision => None,
+        }
+    }
+}
+
+
+/// Policy for retrying transactions which failed to be sent or confirmed.
+///
+/// A transaction is retried if sending it failed due to an RPC or network
+/// error, if the blockhash it was signed with expired or if its confirmation
+/// timed out.  Transactions rejected by the cluster for other reasons (e.g.
+/// insufficient funds) are not retried since they’re unlikely to succeed.
+/// Retrying a transaction which has in fact been executed is harmless since
+/// the Write instruction is idempotent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts to send a single transaction.
+    ///
+    /// Values below one are treated as one, i.e. no retries.
+    pub max_attempts: u32,
+
+    /// Delay before the first retry.
+    ///
+    /// The delay doubles with each subsequent retry.
+    pub initial_backoff: Duration,
+
+    /// Maximum delay between retries.
+    pub max_backoff: Duration,
+
+    /// Whether to fetch a new blockhash (and re-sign the transaction) before
+    /// each retry.
+    ///
+    /// If `false`, the transaction is resent with the original blockhash unless
+    /// the blockhash has expired.
+    pub refresh_blockhash: bool,
+}
+
+impl RetryPolicy {
+    /// The policy which doesn’t retry failed transactions.
+    pub const NONE: Self = Self {
+        max_attempts: 1,
+        initial_backoff: Duration::ZERO,
+        max_backoff: Duration::ZERO,
+        refresh_blockhash: false,
+    };
+
+    /// The default policy used by [`upload`] and [`upload_async`].
+    ///
+    /// Makes up to five attempts waiting half a second before the first retry
+    /// and refreshes blockhash before each retry.
+    pub const DEFAULT: Self = Self {
+        max_attempts: 5,
+        initial_backoff: Duration::from_millis(500),
+        max_backoff: Duration::from_secs(8),
+        refresh_blockhash: true,
+    };
+
+    /// Returns delay before retry following given failed attempt or `None` if
+    /// the transaction should not be retried.
+    ///
+    /// `attempt` counts from one.
+    fn backoff(&self, attempt: u32, err: &ClientError) -> Option<Duration> {
+        if attempt >= self.max_attempts || !is_transient(err) {
+            return None;
+        }
+        let shift = (attempt - 1).min(31);
+        let delay = self.initial_backoff.saturating_mul(1 << shift);
+        Some(delay.min(self.max_backoff))
+    }
+}
+
+/// Limit of the rate at which transactions are sent to the cluster.
+///
+/// Public RPC endpoints rate-limit aggressively and reject excess requests
+/// with HTTP 429 errors.  With the limit configured (see
+/// [`UploadConfig::rate_limit`]), the upload helpers pace submission of the
+/// transactions rather than fail mid-upload.  Requests made to confirm the
+/// transactions aren’t counted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Sustained number of transactions sent per second.
+    pub per_second: NonZeroU32,
+
+    /// Number of transactions which may be sent at once before pacing starts.
+    pub burst: NonZeroU32,
+}
+
+/// Paces sending of transactions according to a [`RateLimit`].
+///
+/// Implements the generic cell rate algorithm tracking theoretical time at
+/// which the next transaction may be sent.  Shared by concurrently sent
+/// transactions of an upload.
+struct Throttle {
+    /// Interval between transactions and tolerance of a burst of
+    /// transactions or `None` if rate isn’t limited.
+    limit: Option<(Duration, Duration)>,
+
+    /// Theoretical time at which the next transaction may be sent.
+    next: std::sync::Mutex<Option<std::time::Instant>>,
+}
+
+impl Throttle {
+    fn new(limit: Option<RateLimit>) -> Self {
+        let limit = limit.map(|limit| {
+            let interval = Duration::from_secs(1) / limit.per_second.get();
+            (interval, interval * (limit.burst.get() - 1))
+        });
+        Self { limit, next: Default::default() }
+    }
+
+    /// Reserves a slot for a transaction and returns how long to wait
+    /// before sending it.
+    fn delay(&self) -> Duration {
+        let Some((interval, tolerance)) = self.limit else {
+            return Duration::ZERO;
+        };
+        let now = std::time::Instant::now();
+        let mut next = self.next.lock().unwrap_or_else(|err| err.into_inner());
+        let tat = next.map_or(now, |next| next.max(now));
+        *next = Some(tat + interval);
+        tat.saturating_duration_since(now).saturating_sub(tolerance)
+    }
+}
+
+/// Recent blockhash shared by transactions of an upload.
+///
+/// The blockhash is cached along with its last valid block height.  See
+/// [`UploadConfig::blockhash_reuse_margin`].
+///
+/// To avoid querying block height for each transaction, the current height
+/// is estimated from the height read when the blockhash was fetched and the
+/// time elapsed since, assuming [`SLOT_DURATION`] per block.  Blocks aren’t
+/// produced faster than that (skipped slots produce no blocks) so the
+/// estimate errs on the side of refreshing the blockhash too early.
+struct BlockhashCache {
+    margin: Option<u64>,
+    cached: std::sync::Mutex<Option<CachedBlockhash>>,
+}
+
+/// Blockhash stored in [`BlockhashCache`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct CachedBlockhash {
+    blockhash: Hash,
+
+    /// Last block height at which the blockhash is valid.
+    last_valid: u64,
+
+    /// Block height when the blockhash was fetched.
+    height: u64,
+
+    /// Time when the blockhash was fetched.
+    fetched: std::time::Instant,
+}
+
+/// Target duration of a slot.
+const SLOT_DURATION: Duration = Duration::from_millis(400);
+
+impl BlockhashCache {
+    fn new(margin: Option<u64>) -> Self {
+        Self { margin, cached: Default::default() }
+    }
+
+    /// Returns the blockhash to sign a transaction with fetching a new one if
+    /// the cached one is about to expire.
+    fn get(
+        &self,
+        rpc: &RpcClient,
+        config: &UploadConfig,
+    ) -> Result<Hash, UploadError> {
+        let now = std::time::Instant::now();
+        if let Some(cached) = self.cached().filter(|c| self.is_fresh(c, now)) {
+            return Ok(cached.blockhash);
+        }
+        let (blockhash, last_valid) = latest_blockhash(rpc, config)?;
+        if self.margin.is_some() {
+            let commitment =
+                config.commitment.unwrap_or_else(|| rpc.commitment());
+            let height = rpc.get_block_height_with_commitment(commitment)?;
+            self.store(blockhash, last_valid, height, now);
+        }
+        Ok(blockhash)
+    }
+
+    /// Returns the blockhash to sign a transaction with fetching a new one if
+    /// the cached one is about to expire.
+    ///
+    /// Asynchronous version of [`Self::get`].
+    #[cfg(feature = "client-async")]
+    async fn get_async(
+        &self,
+        rpc: &AsyncRpcClient,
+        config: &UploadConfig,
+    ) -> Result<Hash, UploadError> {
+        let now = std::time::Instant::now();
+        if let Some(cached) = self.cached().filter(|c| self.is_fresh(c, now)) {
+            return Ok(cached.blockhash);
+        }
+        let (blockhash, last_valid) =
+            latest_blockhash_async(rpc, config).await?;
+        if self.margin.is_some() {
+            let commitment =
+                config.commitment.unwrap_or_else(|| rpc.commitment());
+            let height =
+                rpc.get_block_height_with_commitment(commitment).await?;
+            self.store(blockhash, last_valid, height, now);
+        }
+        Ok(blockhash)
+    }
+
+    /// Returns the cached blockhash without checking whether it’s about to
+    /// expire.
+    #[cfg(feature = "client-async")]
+    fn peek(&self) -> Option<Hash> {
+        self.cached().map(|cached| cached.blockhash)
+    }
+
+    /// Forgets `blockhash` if it’s cached so that the next transaction is
+    /// signed with a new one.
+    fn invalidate(&self, blockhash: &Hash) {
+        let mut cached = self.lock();
+        if cached.is_some_and(|cached| cached.blockhash == *blockhash) {
+            *cached = None;
+        }
+    }
+
+    fn cached(&self) -> Option<CachedBlockhash> {
+        self.margin?;
+        *self.lock()
+    }
+
+    /// Returns whether the cached blockhash may still be used at time `now`.
+    fn is_fresh(
+        &self,
+        cached: &CachedBlockhash,
+        now: std::time::Instant,
+    ) -> bool {
+        let elapsed = now.saturating_duration_since(cached.fetched);
+        let blocks = elapsed.as_millis() / SLOT_DURATION.as_millis();
+        let height = cached.height.saturating_add(blocks as u64);
+        self.margin.is_some_and(|margin| {
+            height.saturating_add(margin) < cached.last_valid
+        })
+    }
+
+    fn store(
+        &self,
+        blockhash: Hash,
+        last_valid: u64,
+        height: u64,
+        fetched: std::time::Instant,
+    ) {
+        if self.margin.is_some() {
+            let cached =
+                CachedBlockhash { blockhash, last_valid, height, fetched };
+            *self.lock() = Some(cached);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<CachedBlockhash>> {
+        self.cached.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self { Self::DEFAULT }
+}
+
+/// Returns whether the error may go away if the transaction is retried.
+fn is_transient(err: &ClientError) -> bool {
+    matches!(
+        err.get_transaction_error(),
+        None | Some(TransactionError::BlockhashNotFound)
+    )
+}
+
+
+/// Configuration of the upload helpers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UploadConfig {
+    /// Policy for retrying failed transactions.
+    pub retry: RetryPolicy,
+
+    /// Maximum number of transactions sent concurrently.
+    ///
+    /// Used by the asynchronous helpers only; see [`upload_async_with_config`].
+    pub parallelism: NonZeroUsize,
+
+    /// Compute unit limit set in each Write transaction.
+    ///
+    /// See [`WriteIter::compute_budget`].
+    pub compute_unit_limit: Option<u32>,
+
+    /// Compute unit price (in micro-lamports) set in each Write transaction.
+    ///
+    /// See [`WriteIter::compute_budget`].
+    pub compute_unit_price: Option<u64>,
+
+    /// Whether to fetch current content of the Write account and send Write
+    /// instructions only for ranges which differ from it.
+    ///
+    /// This lets an interrupted upload be resumed and makes uploading payload
+    /// similar to the one already in the account cheaper.  See
+    /// [`WriteIter::skip_unchanged`].
+    pub skip_unchanged: bool,
+
+    /// Whether to simulate the call to the target program before sending it.
+    ///
+    /// Used by [`call_with_account_data`] which, if the simulation fails,
+    /// returns [`UploadError::Preflight`] error without paying fees for the
+    /// failing call.  The Write account is not freed in that case.  See
+    /// [`preflight_call`].
+    pub preflight: bool,
+
+    /// Commitment level used when sending and confirming transactions and
+    /// fetching accounts.
+    ///
+    /// If `None`, commitment of the RPC client is used.  Relayers tuning for
+    /// latency may choose `processed` or `confirmed` while those valuing
+    /// safety may choose `finalized`.
+    pub commitment: Option<CommitmentConfig>,
+
+    /// Limit of the rate at which transactions are sent.
+    ///
+    /// If `None`, transactions are sent as fast as they are confirmed (or as
+    /// [`Self::parallelism`] allows).  See [`RateLimit`].
+    pub rate_limit: Option<RateLimit>,
+
+    /// How many blocks before its expiry a fetched blockhash stops being
+    /// reused for signing transactions.
+    ///
+    /// Fetching a fresh blockhash for each of hundreds of Write transactions
+    /// is wasteful.  Instead, the blockhash is cached along with its last
+    /// valid block height (as returned by `getLatestBlockhash`) and shared by
+    /// transactions sent until the block height gets within this many blocks
+    /// of it.  To avoid querying the cluster for each transaction, the block
+    /// height is estimated from the time elapsed since the blockhash was
+    /// fetched assuming 400 ms per block.  The margin leaves the transactions time to land.  A transaction
+    /// which fails is re-signed with a new blockhash (if
+    /// [`RetryPolicy::refresh_blockhash`] is set or its blockhash has expired)
+    /// without affecting transactions which have already been confirmed.
+    /// Since blockhashes are valid for 150 blocks, the margin should be well
+    /// below that.  `None` disables the caching.
+    pub blockhash_reuse_margin: Option<u64>,
+}
+
+impl UploadConfig {
+    /// The default configuration used by [`upload`] and [`upload_async`].
+    pub const DEFAULT: Self = Self {
+        retry: RetryPolicy::DEFAULT,
+        parallelism: NonZeroUsize::MIN,
+        compute_unit_limit: None,
+        compute_unit_price: None,
+        skip_unchanged: false,
+        preflight: false,
+        commitment: None,
+        rate_limit: None,
+        blockhash_reuse_margin: Some(75),
+    };
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self { Self::DEFAULT }
+}
+
+
+/// Uploads `data` into the Write account.
+///
+/// Generates Write instructions with [`WriteIter::new`] and sends each of them
+/// in a separate transaction paid for and signed by `signer`, waiting for each
+/// transaction to be confirmed before sending the next one.  Failed
+/// transactions are retried according to [`RetryPolicy::DEFAULT`].  Returns
+/// address and bump of the Write account which can be passed to the program
+/// reading the instruction data and later to [`super::free`].
+pub fn upload(
+    rpc: &RpcClient,
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    data: Vec<u8>,
+) -> Result<(Pubkey, u8), UploadError> {
+    let config = &UploadConfig::DEFAULT;
+    upload_with_config(rpc, write_program, signer, seed, data, config)
+}
+
+/// Uploads `data` into the Write account using given configuration.
+///
+/// Behaves like [`upload`] with behaviour adjusted according to `config`.
+/// [`UploadConfig::parallelism`] is ignored.
+pub fn upload_with_config(
+    rpc: &RpcClient,
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    data: Vec<u8>,
+    config: &UploadConfig,
+) -> Result<(Pubkey, u8), UploadError> {
+    upload_with_progress(rpc, write_program, signer, seed, data, config, |_| ())
+}
+
+/// Uploads `data` into the Write account reporting progress.
+///
+/// Behaves like [`upload_with_config`] and additionally calls `progress` with
+/// information about each chunk once the transaction writing it has been
+/// confirmed.  This lets callers render progress bars or log the upload.
+///
+/// # Example
+///
+/// ```ignore
+/// upload_with_progress(rpc, &program, &payer, b"", data, config, |chunk| {
+///     eprintln!("Wrote chunk {}/{}", chunk.index + 1, chunk.total);
+/// })?;
+/// ```
+pub fn upload_with_progress(
+    rpc: &RpcClient,
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    data: Vec<u8>,
+    config: &UploadConfig,
+    mut progress: impl FnMut(&ChunkInfo),
+) -> Result<(Pubkey, u8), UploadError> {
+    let progress = |chunk: ChunkInfo, _, _| progress(&chunk);
+    let sender = Sender::Rpc(rpc);
+    let iter = WriteIter::new(write_program, signer.pubkey(), seed, data)?.0;
+    upload_impl(sender, signer, iter, config, |_| Vec::new(), progress)
+}
+
+/// Uploads `data` into the Write account including extra instructions in each
+/// Write transaction.
+///
+/// Behaves like [`upload_with_config`] and additionally includes instructions
+/// returned by `extra` (called with index of the chunk) after the Write
+/// instruction of each transaction.  This lets callers attach memos, tips and
+/// similar instructions without assembling the transactions manually.  Chunk
+/// size is reduced so that the transaction fits the size limit.
+///
+/// The instructions may require signature of `signer` only.  They must not
+/// include compute budget instructions; use
+/// [`UploadConfig::compute_unit_limit`] and
+/// [`UploadConfig::compute_unit_price`] instead.  `extra` may be called more
+/// than once for the same chunk.
+///
+/// # Example
+///
+/// ```ignore
+/// upload_with_extra_instructions(rpc, &program, &payer, b"", data, config, |_| {
+///     vec![transfer(&payer.pubkey(), &tip_account, tip_lamports)]
+/// })?;
+/// ```
+pub fn upload_with_extra_instructions(
+    rpc: &RpcClient,
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    data: Vec<u8>,
+    config: &UploadConfig,
+    extra: impl FnMut(usize) -> Vec<Instruction>,
+) -> Result<(Pubkey, u8), UploadError> {
+    let sender = Sender::Rpc(rpc);
+    let iter = WriteIter::new(write_program, signer.pubkey(), seed, data)?.0;
+    upload_impl(sender, signer, iter, config, extra, |_, _, _| ())
+}
+
+/// Uploads `data` into the Write account returning guard which frees the
+/// account when dropped.
+///
+/// Behaves like [`upload_with_config`] except that the Write account is freed
+/// once the returned [`WriteGuard`] is dropped unless [`WriteGuard::commit`]
+/// is called.  If the upload fails, the account is freed before the error is
+/// returned.  This prevents leaking rent in tests and short-lived tools when
+/// an error path skips the cleanup.
+///
+/// # Example
+///
+/// ```ignore
+/// let guard = upload_with_guard(rpc, &program, &payer, b"", data, config)?;
+/// call_target_program(guard.account())?;
+/// // The Write account is freed when `guard` goes out of scope.
+/// ```
+pub fn upload_with_guard<'a, S: Signer + ?Sized>(
+    rpc: &'a RpcClient,
+    write_program: &Pubkey,
+    signer: &'a S,
+    seed: &[u8],
+    data: Vec<u8>,
+    config: &UploadConfig,
+) -> Result<WriteGuard<'a, S>, UploadError> {
+    let (iter, account, bump) =
+        WriteIter::new(write_program, signer.pubkey(), seed, data)?;
+    let guard = WriteGuard {
+        rpc,
+        signer,
+        account,
+        bump,
+        free: Some(iter.free_instruction()),
+        config: *config,
+    };
+    let sender = Sender::Rpc(rpc);
+    upload_impl(sender, signer, iter, config, |_| Vec::new(), |_, _, _| ())?;
+    Ok(guard)
+}
+
+/// Write account which is freed when dropped.
+///
+/// Returned by [`upload_with_guard`].  Errors encountered when freeing the
+/// account on drop are ignored; use [`Self::free`] to handle them.
+///
+/// Dropping the guard blocks until the Free transaction is sent and confirmed.
+/// The transaction is attempted only once regardless of configured
+/// [`UploadConfig::retry`] policy.  Nothing is sent if the guard is dropped
+/// while the thread is panicking.
+pub struct WriteGuard<'a, S: Signer + ?Sized> {
+    rpc: &'a RpcClient,
+    signer: &'a S,
+    account: Pubkey,
+    bump: u8,
+    free: Option<Instruction>,
+    config: UploadConfig,
+}
+
+impl<S: Signer + ?Sized> WriteGuard<'_, S> {
+    /// Returns address of the Write account.
+    pub fn account(&self) -> Pubkey { self.account }
+
+    /// Returns bump of the Write account.
+    pub fn bump(&self) -> u8 { self.bump }
+
+    /// Keeps the Write account rather than freeing it; returns its address
+    /// and bump.
+    pub fn commit(mut self) -> (Pubkey, u8) {
+        self.free = None;
+        (self.account, self.bump)
+    }
+
+    /// Frees the Write account returning signature of the Free transaction.
+    pub fn free(mut self) -> Result<Signature, UploadError> {
+        let free = self.free.take().unwrap();
+        self.send_free(free, &self.config)
+    }
+
+    fn send_free(
+        &self,
+        free: Instruction,
+        config: &UploadConfig,
+    ) -> Result<Signature, UploadError> {
+        let sender = Sender::Rpc(self.rpc);
+        let throttle = Throttle::new(None);
+        let blockhashes = BlockhashCache::new(None);
+        send(sender, &throttle, &blockhashes, self.signer, &[free], config)
+            .map(|(signature, _)| signature)
+    }
+}
+
+impl<S: Signer + ?Sized> Drop for WriteGuard<'_, S> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            return;
+        }
+        if let Some(free) = self.free.take() {
+            let config =
+                UploadConfig { retry: RetryPolicy::NONE, ..self.config };
+            let _ = self.send_free(free, &config);
+        }
+    }
+}
+
+/// Uploads `data` into a Write account paid for by an ephemeral `payer`.
+///
+/// Funds `payer` (typically a freshly generated keypair) from `wallet`, uses
+/// it to pay for and sign all Write transactions, calls `call` with address
+/// and bump of the Write account, frees the account and finally sweeps all
+/// lamports left on `payer` back to `wallet`.  Since each pipeline uses its
+/// own fee payer and Write account, parallel relayers don’t contend for the
+/// same account or run into duplicate transactions.  The Write account is
+/// created with an empty seed.
+///
+/// `payer` is funded with enough lamports to cover the rent of the Write
+/// account and fees of all the transactions assuming each of them may be
+/// attempted [`RetryPolicy::max_attempts`] times.  The excess is returned to
+/// `wallet` when sweeping.
+///
+/// The Write account is freed and `payer` swept even if uploading the data or
+/// `call` fails in which case the original error is returned.  If freeing or
+/// sweeping fails, the lamports remain on `payer`; callers may want to keep
+/// the keypair around until the function returns so that they can recover
+/// them.
+///
+/// # Example
+///
+/// ```ignore
+/// let payer = Keypair::new();
+/// upload_with_ephemeral_payer(
+///     rpc, &program, &wallet, &payer, data, config,
+///     |account, _bump| call_target_program(&payer, account),
+/// )?;
+/// ```
+pub fn upload_with_ephemeral_payer<T>(
+    rpc: &RpcClient,
+    write_program: &Pubkey,
+    wallet: &(impl Signer + ?Sized),
+    payer: &(impl Signer + ?Sized),
+    data: Vec<u8>,
+    config: &UploadConfig,
+    call: impl FnOnce(Pubkey, u8) -> Result<T, UploadError>,
+) -> Result<T, UploadError> {
+    let (iter, ..) = WriteIter::new(write_program, payer.pubkey(), b"", data)?;
+    upload_iter_with_ephemeral_payer(rpc, wallet, payer, iter, config, call)
+}
+
+/// Uploads data generated by given iterator using an ephemeral fee payer.
+///
+/// Behaves like [`upload_with_ephemeral_payer`] but uses Write instructions
+/// generated by `iter` which must have been constructed with `payer` as its
+/// payer.  This allows the iterator to be configured, e.g. with
+/// [`WriteIter::system_program`] and [`WriteIter::rent`] which are also used
+/// when funding and sweeping `payer`.
+pub fn upload_iter_with_ephemeral_payer<T>(
+    rpc: &RpcClient,
+    wallet: &(impl Signer + ?Sized),
+    payer: &(impl Signer + ?Sized),
+    mut iter: WriteIter<'_>,
+    config: &UploadConfig,
+    call: impl FnOnce(Pubkey, u8) -> Result<T, UploadError>,
+) -> Result<T, UploadError> {
+    let (account, bump) = (iter.write_account, iter.bump);
+    let system_program = iter.system_program;
+    let transfer = |from: &Pubkey, to: &Pubkey, lamports| {
+        let mut ix =
+            solana_system_interface::instruction::transfer(from, to, lamports);
+        super::remap_system_program(&mut ix, system_program);
+        ix
+    };
+    iter.compute_budget(config.compute_unit_limit, config.compute_unit_price);
+    let fee = signature_fee(rpc, &payer.pubkey())?;
+    let plan = iter.plan(fee, config.compute_unit_price);
+    let lamports = iter
+        .rent
+        .minimum_balance(0)
+        .saturating_add(plan.rent)
+        .saturating_add(
+            plan.fees.saturating_mul(config.retry.max_attempts.max(1).into()),
+        )
+        .saturating_add(fee);
+    let free = iter.free_instruction();
+
+    let sender = Sender::Rpc(rpc);
+    let throttle = Throttle::new(config.rate_limit);
+    let blockhashes = BlockhashCache::new(config.blockhash_reuse_margin);
+    let fund = transfer(&wallet.pubkey(), &payer.pubkey(), lamports);
+    let (_signature, _) =
+        send(sender, &throttle, &blockhashes, wallet, &[fund], config)?;
+    trace_event!(
+        INFO,
+        payer = %payer.pubkey(),
+        lamports,
+        signature = %_signature,
+        "funded ephemeral payer",
+    );
+
+    let result =
+        upload_impl(sender, payer, iter, config, |_| Vec::new(), |_, _, _| ())
+            .and_then(|_| call(account, bump));
+    let freed = send(sender, &throttle, &blockhashes, payer, &[free], config);
+    let swept =
+        sweep(sender, &throttle, &blockhashes, payer, fee, config, |n| {
+            transfer(&payer.pubkey(), &wallet.pubkey(), n)
+        });
+    let value = result?;
+    freed?;
+    swept?;
+    Ok(value)
+}
+
+/// Transfers all lamports held by `payer` to the wallet.
+///
+/// The transfer is paid for by `payer` whose balance drops to zero.  `fee` is
+/// the fee of a transaction with a single signature.  `transfer` builds the
+/// instruction transferring given amount of lamports from `payer` to the
+/// wallet.
+fn sweep(
+    sender: Sender<'_>,
+    throttle: &Throttle,
+    blockhashes: &BlockhashCache,
+    payer: &(impl Signer + ?Sized),
+    fee: u64,
+    config: &UploadConfig,
+    transfer: impl FnOnce(u64) -> Instruction,
+) -> Result<(), UploadError> {
+    let rpc = sender.rpc();
+    let commitment = config.commitment.unwrap_or_else(|| rpc.commitment());
+    let balance =
+        rpc.get_balance_with_commitment(&payer.pubkey(), commitment)?.value;
+    let Some(lamports) =
+        balance.checked_sub(fee).filter(|&lamports| lamports > 0)
+    else {
+        return Ok(());
+    };
+    let (_signature, _) = send(
+        sender,
+        throttle,
+        blockhashes,
+        payer,
+        &[transfer(lamports)],
+        config,
+    )?;
+    trace_event!(
+        INFO,
+        payer = %payer.pubkey(),
+        lamports,
+        signature = %_signature,
+        "swept ephemeral payer",
+    );
+    Ok(())
+}
+
+/// Returns fee of a transaction paid for by `payer` with a single signature.
+fn signature_fee(rpc: &RpcClient, payer: &Pubkey) -> Result<u64, UploadError> {
+    let message = solana_message::Message::new(&[], Some(payer));
+    Ok(rpc.get_fee_for_message(&message)?)
+}
+
+/// Uploads shards of a payload split with [`ShardedWrite`].
+///
+/// Uploads the shards one after another as [`upload_with_config`] does and
+/// returns addresses and bumps of their Write accounts in order of the
+/// payload.  [`UploadConfig::skip_unchanged`] applies to each shard.
+pub fn upload_sharded(
+    rpc: &RpcClient,
+    signer: &(impl Signer + ?Sized),
+    sharded: ShardedWrite,
+    config: &UploadConfig,
+) -> Result<Vec<(Pubkey, u8)>, UploadError> {
+    let sender = Sender::Rpc(rpc);
+    sharded
+        .shards
+        .into_iter()
+        .map(|shard| {
+            let extra = |_| Vec::new();
+            upload_impl(sender, signer, shard.iter, config, extra, |_, _, _| ())
+        })
+        .collect()
+}
+
+/// TPU client sending transactions over QUIC; see [`upload_with_tpu`].
+#[cfg(feature = "client-tpu")]
+pub type TpuClient = solana_client::tpu_client::TpuClient<
+    QuicPool,
+    QuicConnectionManager,
+    QuicConfig,
+>;
+
+/// Uploads `data` into the Write account sending the transactions directly to
+/// the leaders.
+///
+/// Behaves like [`upload_with_config`] except that the Write transactions are
+/// sent over QUIC to TPUs of the current and upcoming leaders rather than
+/// through RPC node’s `sendTransaction` method.  RPC client of the `tpu` is
+/// still used to fetch blockhashes and confirm the transactions.  During
+/// congestion this significantly improves landing rates of uploads consisting
+/// of many chunks.
+///
+/// # Example
+///
+/// ```ignore
+/// let tpu = TpuClient::new(rpc, &websocket_url, TpuClientConfig::default())?;
+/// upload_with_tpu(&tpu, &program, &payer, b"", data, config)?;
+/// ```
+#[cfg(feature = "client-tpu")]
+pub fn upload_with_tpu(
+    tpu: &TpuClient,
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    data: Vec<u8>,
+    config: &UploadConfig,
+) -> Result<(Pubkey, u8), UploadError> {
+    let sender = Sender::Tpu(tpu);
+    let progress = |_, _, _| ();
+    let iter = WriteIter::new(write_program, signer.pubkey(), seed, data)?.0;
+    upload_impl(sender, signer, iter, config, |_| Vec::new(), progress)
+}
+
+/// Way of submitting transactions to the cluster.
+#[derive(Clone, Copy)]
+enum Sender<'a> {
+    /// Transactions are sent through RPC node’s `sendTransaction` method.
+    Rpc(&'a RpcClient),
+
+    /// Transactions are sent directly to leaders’ TPUs.
+    #[cfg(feature = "client-tpu")]
+    Tpu(&'a TpuClient),
+}
+
+impl<'a> Sender<'a> {
+    /// Returns RPC client used to fetch blockhashes and accounts and to
+    /// confirm transactions.
+    fn rpc(self) -> &'a RpcClient {
+        match self {
+            Self::Rpc(rpc) => rpc,
+            #[cfg(feature = "client-tpu")]
+            Self::Tpu(tpu) => tpu.rpc_client(),
+        }
+    }
+}
+
+/// Uploads `data` into the Write account calling `progress` with each chunk
+/// and signature of the transaction which has written it.
+fn upload_impl(
+    sender: Sender<'_>,
+    signer: &(impl Signer + ?Sized),
+    mut chunks: WriteIter<'_>,
+    config: &UploadConfig,
+    mut extra: impl FnMut(usize) -> Vec<Instruction>,
+    mut progress: impl FnMut(ChunkInfo, Signature, u32),
+) -> Result<(Pubkey, u8), UploadError> {
+    let (account, bump) = (chunks.write_account, chunks.bump);
+    let budget = compute_budget(&mut chunks, config);
+    let throttle = Throttle::new(config.rate_limit);
+    let blockhashes = BlockhashCache::new(config.blockhash_reuse_margin);
+    trace_event!(INFO, %account, bump, "uploading data into Write account");
+    if config.skip_unchanged {
+        let rpc = sender.rpc();
+        let commitment = config.commitment.unwrap_or_else(|| rpc.commitment());
+        let account = rpc.get_account_with_commitment(&account, commitment)?;
+        chunks.skip_unchanged(
+            account.value.map_or_else(Vec::new, |acc| acc.data),
+        );
+    }
+    while chunks.next_range().is_some() {
+        let extra = extra(chunks.index);
+        reserve_extra(&mut chunks, &budget, &extra);
+        let Some(chunk) = chunks.next_chunk() else { break };
+        let write = core::slice::from_ref(&chunk.instruction);
+        let instructions = [&budget[..], write, &extra[..]].concat();
+        let (signature, attempts) = send(
+            sender,
+            &throttle,
+            &blockhashes,
+            signer,
+            &instructions,
+            config,
+        )?;
+        trace_chunk(&account, &chunk, &signature, attempts);
+        progress(chunk, signature, attempts);
+    }
+    trace_event!(INFO, %account, "upload complete");
+    Ok((account, bump))
+}
+
+/// Sends a transaction with given instructions retrying it if necessary.
+///
+/// Returns signature of the transaction which landed and number of attempts it
+/// took.
+fn send(
+    sender: Sender<'_>,
+    throttle: &Throttle,
+    blockhashes: &BlockhashCache,
+    signer: &(impl Signer + ?Sized),
+    instructions: &[Instruction],
+    config: &UploadConfig,
+) -> Result<(Signature, u32), UploadError> {
+    let rpc = sender.rpc();
+    let mut blockhash = blockhashes.get(rpc, config)?;
+    let mut attempt = 1;
+    loop {
+        let tx = transaction(signer, instructions, blockhash);
+        let delay = throttle.delay();
+        if !delay.is_zero() {
+            trace_event!(TRACE, ?delay, "throttling transaction");
+            std::thread::sleep(delay);
+        }
+        let err = match send_and_confirm(sender, &tx, config.commitment) {
+            Ok(signature) => return Ok((signature, attempt)),
+            Err(err) => err,
+        };
+        trace_event!(
+            WARN,
+            signature = %tx.signatures[0],
+            attempt,
+            error = %err,
+            "sending transaction failed",
+        );
+        let delay = config
+            .retry
+            .backoff(attempt, &err)
+            .ok_or(UploadError::Client(err))?;
+        std::thread::sleep(delay);
+        if config.retry.refresh_blockhash ||
+            !rpc.is_blockhash_valid(
+                &blockhash,
+                CommitmentConfig::processed(),
+            )?
+        {
+            blockhashes.invalidate(&blockhash);
+            blockhash = blockhashes.get(rpc, config)?;
+        }
+        attempt += 1;
+    }
+}
+
+/// Sends a transaction and waits for it to reach given commitment level.
+///
+/// If `commitment` is `None`, uses commitment of the RPC client.
+fn send_and_confirm(
+    sender: Sender<'_>,
+    tx: &Transaction,
+    commitment: Option<CommitmentConfig>,
+) -> Result<Signature, Box<ClientError>> {
+    let rpc = sender.rpc();
+    let (signature, commitment) = match (sender, commitment) {
+        (Sender::Rpc(_), None) => {
+            return Ok(rpc.send_and_confirm_transaction(tx)?)
+        }
+        (Sender::Rpc(_), Some(commitment)) => {
+            let config = send_transaction_config(commitment);
+            (rpc.send_transaction_with_config(tx, config)?, commitment)
+        }
+        #[cfg(feature = "client-tpu")]
+        (Sender::Tpu(tpu), commitment) => {
+            tpu.try_send_transaction(tx).map_err(ClientError::from)?;
+            (tx.signatures[0], commitment.unwrap_or_else(|| rpc.commitment()))
+        }
+    };
+    let blockhash = &tx.message.recent_blockhash;
+    loop {
+        match rpc
+            .get_signature_status_with_commitment(&signature, commitment)?
+        {
+            Some(result) => {
+                return result
+                    .map(|()| signature)
+                    .map_err(|err| Box::new(err.into()))
+            }
+            None if !rpc.is_blockhash_valid(
+                blockhash,
+                CommitmentConfig::processed(),
+            )? =>
+            {
+                return Err(Box::new(unconfirmed_error()))
+            }
+            None => std::thread::sleep(CONFIRM_POLL_INTERVAL),
+        }
+    }
+}
+
+/// Returns the latest blockhash and its last valid block height at commitment
+/// level chosen in `config`.
+fn latest_blockhash(
+    rpc: &RpcClient,
+    config: &UploadConfig,
+) -> Result<(Hash, u64), UploadError> {
+    let commitment = config.commitment.unwrap_or_else(|| rpc.commitment());
+    Ok(rpc.get_latest_blockhash_with_commitment(commitment)?)
+}
+
+/// Uploads `data` into the Write account using the nonblocking RPC client.
+///
+/// Asynchronous version of [`upload`].
+#[cfg(feature = "client-async")]
+pub async fn upload_async(
+    rpc: &AsyncRpcClient,
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    data: Vec<u8>,
+) -> Result<(Pubkey, u8), UploadError> {
+    let config = &UploadConfig::DEFAULT;
+    upload_async_with_config(rpc, write_program, signer, seed, data, config)
+        .await
+}
+
+/// Uploads `data` into the Write account sending multiple chunks at a time.
+///
+/// Like [`upload_async`] but sends up to `parallelism` transactions
+/// concurrently (see [`upload_async_with_config`]).
+#[cfg(feature = "client-async")]
+pub async fn upload_parallel_async(
+    rpc: &AsyncRpcClient,
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    data: Vec<u8>,
+    parallelism: NonZeroUsize,
+) -> Result<(Pubkey, u8), UploadError> {
+    let config = &UploadConfig { parallelism, ..UploadConfig::DEFAULT };
+    upload_async_with_config(rpc, write_program, signer, seed, data, config)
+        .await
+}
+
+/// Uploads `data` into the Write account using given configuration.
+///
+/// Asynchronous version of [`upload_with_config`].  Sends up to
+/// [`UploadConfig::parallelism`] transactions concurrently and waits for all
+/// of them to be confirmed before sending the next batch.  Since the
+/// write-account program can grow the account by at most
+/// [`MAX_PERMITTED_DATA_INCREASE`] bytes in a single instruction and the
+/// transactions in a batch may be executed in any order, a batch includes only
+/// chunks which end within that many bytes of the account size after the
+/// previous batch.  With the default chunk size this limits the effective
+/// parallelism to about ten transactions.
+#[cfg(feature = "client-async")]
+pub async fn upload_async_with_config(
+    rpc: &AsyncRpcClient,
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    data: Vec<u8>,
+    config: &UploadConfig,
+) -> Result<(Pubkey, u8), UploadError> {
+    upload_async_with_progress(
+        rpc,
+        write_program,
+        signer,
+        seed,
+        data,
+        config,
+        |_| (),
+    )
+    .await
+}
+
+/// Uploads `data` into the Write account reporting progress.
+///
+/// Asynchronous version of [`upload_with_progress`].  `progress` is called
+/// for each chunk of a batch once all transactions in the batch have been
+/// confirmed (see [`upload_async_with_config`]).
+#[cfg(feature = "client-async")]
+pub async fn upload_async_with_progress(
+    rpc: &AsyncRpcClient,
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    data: Vec<u8>,
+    config: &UploadConfig,
+    mut progress: impl FnMut(&ChunkInfo),
+) -> Result<(Pubkey, u8), UploadError> {
+    let progress = |chunk: ChunkInfo, _, _| progress(&chunk);
+    let sender = AsyncSender { rpc, pubsub: None };
+    let iter = WriteIter::new(write_program, signer.pubkey(), seed, data)?.0;
+    upload_async_impl(sender, signer, iter, config, |_| Vec::new(), progress)
+        .await
+}
+
+/// Uploads `data` into the Write account including extra instructions in each
+/// Write transaction.
+///
+/// Asynchronous version of [`upload_with_extra_instructions`].
+#[cfg(feature = "client-async")]
+pub async fn upload_async_with_extra_instructions(
+    rpc: &AsyncRpcClient,
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    data: Vec<u8>,
+    config: &UploadConfig,
+    extra: impl FnMut(usize) -> Vec<Instruction>,
+) -> Result<(Pubkey, u8), UploadError> {
+    let sender = AsyncSender { rpc, pubsub: None };
+    let iter = WriteIter::new(write_program, signer.pubkey(), seed, data)?.0;
+    upload_async_impl(sender, signer, iter, config, extra, |_, _, _| ()).await
+}
+
+/// Uploads `data` into the Write account confirming transactions through
+/// signature subscriptions.
+///
+/// Behaves like [`upload_async_with_config`] except that rather than polling
+/// the RPC node for status of each sent transaction, waits for notification
+/// of the `pubsub` client’s `signatureSubscribe` subscription.  This reduces
+/// load on the RPC node and latency of the confirmations when uploading data
+/// consisting of many chunks.  Validity of transaction’s blockhash is still
+/// checked periodically so that dropped transactions are retried.
+///
+/// # Example
+///
+/// ```ignore
+/// let pubsub = PubsubClient::new(&websocket_url).await?;
+/// upload_async_with_pubsub(&rpc, &pubsub, &program, &payer, b"", data, config)
+///     .await?;
+/// ```
+#[cfg(feature = "client-async")]
+pub async fn upload_async_with_pubsub(
+    rpc: &AsyncRpcClient,
+    pubsub: &PubsubClient,
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    data: Vec<u8>,
+    config: &UploadConfig,
+) -> Result<(Pubkey, u8), UploadError> {
+    let sender = AsyncSender { rpc, pubsub: Some(pubsub) };
+    let progress = |_, _, _| ();
+    let iter = WriteIter::new(write_program, signer.pubkey(), seed, data)?.0;
+    upload_async_impl(sender, signer, iter, config, |_| Vec::new(), progress)
+        .await
+}
+
+/// Clients used to send and confirm transactions by the asynchronous helpers.
+#[cfg(feature = "client-async")]
+#[derive(Clone, Copy)]
+struct AsyncSender<'a> {
+    /// RPC client used to send transactions and fetch blockhashes and
+    /// accounts.
+    rpc: &'a AsyncRpcClient,
+
+    /// PubSub client used to confirm transactions.  If `None`, transactions
+    /// are confirmed by polling the RPC node.
+    pubsub: Option<&'a PubsubClient>,
+}
+
+/// Uploads `data` into the Write account calling `progress` with each chunk
+/// and signature of the transaction which has written it.
+#[cfg(feature = "client-async")]
+async fn upload_async_impl(
+    sender: AsyncSender<'_>,
+    signer: &(impl Signer + ?Sized),
+    mut chunks: WriteIter<'_>,
+    config: &UploadConfig,
+    mut extra: impl FnMut(usize) -> Vec<Instruction>,
+    mut progress: impl FnMut(ChunkInfo, Signature, u32),
+) -> Result<(Pubkey, u8), UploadError> {
+    let rpc = sender.rpc;
+    let (account, bump) = (chunks.write_account, chunks.bump);
+    let budget = compute_budget(&mut chunks, config);
+    let throttle = Throttle::new(config.rate_limit);
+    let blockhashes = BlockhashCache::new(config.blockhash_reuse_margin);
+    trace_event!(INFO, %account, bump, "uploading data into Write account");
+    if config.skip_unchanged {
+        let commitment = config.commitment.unwrap_or_else(|| rpc.commitment());
+        let account =
+            rpc.get_account_with_commitment(&account, commitment).await?;
+        chunks.skip_unchanged(
+            account.value.map_or_else(Vec::new, |acc| acc.data),
+        );
+    }
+    let parallelism = config.parallelism.get();
+    let mut size = chunks.existing.len();
+    loop {
+        let batch = next_batch(
+            &mut chunks,
+            &mut size,
+            parallelism,
+            &budget,
+            &mut extra,
+        );
+        if batch.is_empty() {
+            trace_event!(INFO, %account, "upload complete");
+            break Ok((account, bump));
+        }
+
+        // Check expiry of the blockhash and fetch a new one (if necessary) once
+        // rather than for each transaction of the batch.
+        blockhashes.get_async(rpc, config).await?;
+        let signatures =
+            futures::future::try_join_all(batch.iter().map(|(_, ixs)| {
+                send_async(sender, &throttle, &blockhashes, signer, ixs, config)
+            }))
+            .await?;
+        for ((chunk, _), (signature, attempts)) in
+            batch.into_iter().zip(signatures)
+        {
+            trace_chunk(&account, &chunk, &signature, attempts);
+            progress(chunk, signature, attempts);
+        }
+    }
+}
+
+/// Uploads `data` into the Write account returning report of the transactions
+/// sent.
+///
+/// Behaves like [`upload_with_config`] and additionally records signature,
+/// slot and number of attempts of the transaction which wrote each chunk.
+/// Operators can archive the report as an audit trail and inspect specific
+/// chunk transactions later.  Slots are fetched once all chunks have been
+/// written.
+pub fn upload_with_report(
+    rpc: &RpcClient,
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    data: Vec<u8>,
+    config: &UploadConfig,
+) -> Result<UploadReport, UploadError> {
+    let mut chunks = Vec::new();
+    let progress = |chunk, signature, attempts| {
+        chunks.push(ChunkReport::new(chunk, signature, attempts))
+    };
+    let sender = Sender::Rpc(rpc);
+    let iter = WriteIter::new(write_program, signer.pubkey(), seed, data)?.0;
+    let (account, bump) =
+        upload_impl(sender, signer, iter, config, |_| Vec::new(), progress)?;
+    for batch in chunks.chunks_mut(MAX_GET_SIGNATURE_STATUSES_QUERY_ITEMS) {
+        let signatures = ChunkReport::signatures(batch);
+        let statuses = rpc.get_signature_statuses(&signatures)?.value;
+        let slots = statuses.into_iter().map(|status| Some(status?.slot));
+        ChunkReport::set_slots(batch, slots);
+    }
+    Ok(UploadReport { account, bump, chunks })
+}
+
+/// Uploads `data` into the Write account returning report of the transactions
+/// sent.
+///
+/// Asynchronous version of [`upload_with_report`].
+#[cfg(feature = "client-async")]
+pub async fn upload_async_with_report(
+    rpc: &AsyncRpcClient,
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    data: Vec<u8>,
+    config: &UploadConfig,
+) -> Result<UploadReport, UploadError> {
+    let mut chunks = Vec::new();
+    let progress = |chunk, signature, attempts| {
+        chunks.push(ChunkReport::new(chunk, signature, attempts))
+    };
+    let sender = AsyncSender { rpc, pubsub: None };
+    let iter = WriteIter::new(write_program, signer.pubkey(), seed, data)?.0;
+    let (account, bump) = upload_async_impl(
+        sender,
+        signer,
+        iter,
+        config,
+        |_| Vec::new(),
+        progress,
+    )
+    .await?;
+    for batch in chunks.chunks_mut(MAX_GET_SIGNATURE_STATUSES_QUERY_ITEMS) {
+        let signatures = ChunkReport::signatures(batch);
+        let statuses = rpc.get_signature_statuses(&signatures).await?.value;
+        let slots = statuses.into_iter().map(|status| Some(status?.slot));
+        ChunkReport::set_slots(batch, slots);
+    }
+    Ok(UploadReport { account, bump, chunks })
+}
+
+/// Report of an upload returned by [`upload_with_report`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UploadReport {
+    /// Address of the Write account.
+    pub account: Pubkey,
+
+    /// Bump of the Write account PDA.
+    pub bump: u8,
+
+    /// Transactions which wrote the chunks in the order of the chunks.
+    ///
+    /// Chunks skipped due to [`UploadConfig::skip_unchanged`] aren’t
+    /// included.
+    pub chunks: Vec<ChunkReport>,
+}
+
+/// Transaction which wrote a single chunk; see [`UploadReport`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkReport {
+    /// Index of the chunk counting from zero.
+    pub index: usize,
+
+    /// Offset within the Write account the chunk was written at.
+    pub offset: usize,
+
+    /// Length of the chunk.
+    pub len: usize,
+
+    /// Signature of the transaction which wrote the chunk.
+    pub signature: Signature,
+
+    /// Slot the transaction was processed in or `None` if the cluster
+    /// didn’t report it.
+    pub slot: Option<u64>,
+
+    /// Number of attempts it took to land the transaction.
+    pub attempts: u32,
+}
+
+impl ChunkReport {
+    fn new(chunk: ChunkInfo, signature: Signature, attempts: u32) -> Self {
+        Self {
+            index: chunk.index,
+            offset: chunk.offset,
+            len: chunk.len,
+            signature,
+            slot: None,
+            attempts,
+        }
+    }
+
+    /// Returns signatures of given chunks.
+    fn signatures(chunks: &[Self]) -> Vec<Signature> {
+        chunks.iter().map(|chunk| chunk.signature).collect()
+    }
+
+    /// Sets slots of given chunks.
+    fn set_slots(
+        chunks: &mut [Self],
+        slots: impl IntoIterator<Item = Option<u64>>,
+    ) {
+        for (chunk, slot) in chunks.iter_mut().zip(slots) {
+            chunk.slot = slot;
+        }
+    }
+}
+
+/// Calls `instruction` with its instruction data passed through the Write
+/// account.
+///
+/// Performs the full life cycle of the Write account: uploads `data` into it
+/// (see [`upload_with_config`]), appends the account to the accounts of
+/// `instruction`, sends the instruction and finally frees the account
+/// returning its rent to the `signer`.  The target program must read the
+/// instruction data from the last account (e.g. by using
+/// [`crate::entrypoint!`] macro) and `instruction` should have empty data.
+///
+/// If any step fails, the Write account is left in place.  It can be reused
+/// for another attempt or closed with [`super::free`].
+///
+/// # Example
+///
+/// ```ignore
+/// let instruction = Instruction::new_with_bytes(program_id, &[], accounts);
+/// let signatures = call_with_account_data(
+///     &rpc,
+///     &write_account_program_id,
+///     &payer,
+///     b"",
+///     instruction,
+///     instruction_data,
+///     &UploadConfig::DEFAULT,
+/// )?;
+/// println!("Called in {}", signatures.call);
+/// ```
+pub fn call_with_account_data(
+    rpc: &RpcClient,
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    mut instruction: Instruction,
+    data: Vec<u8>,
+    config: &UploadConfig,
+) -> Result<CallSignatures, UploadError> {
+    let mut writes = Vec::new();
+    let progress = |_, signature, _| writes.push(signature);
+    let sender = Sender::Rpc(rpc);
+    let iter = WriteIter::new(write_program, signer.pubkey(), seed, data)?.0;
+    let (account, bump) =
+        upload_impl(sender, signer, iter, config, |_| Vec::new(), progress)?;
+    if config.preflight {
+        preflight_call(rpc, signer, &instruction, account)?.into_result()?;
+    }
+    Accounts::append(&mut instruction, account);
+    let throttle = Throttle::new(config.rate_limit);
+    let blockhashes = BlockhashCache::new(config.blockhash_reuse_margin);
+    let (call, _) =
+        send(sender, &throttle, &blockhashes, signer, &[instruction], config)?;
+    let free = free(write_program, signer, seed, account, bump)?;
+    trace_event!(INFO, signature = %call, "called target program");
+    let (free, _) =
+        send(sender, &throttle, &blockhashes, signer, &[free], config)?;
+    trace_event!(INFO, %account, signature = %free, "freed Write account");
+    Ok(CallSignatures { writes, call, free })
+}
+
+/// Calls `instruction` with its instruction data passed through the Write
+/// account.
+///
+/// Asynchronous version of [`call_with_account_data`].  The data is uploaded
+/// with [`upload_async_with_config`].
+#[cfg(feature = "client-async")]
+pub async fn call_with_account_data_async(
+    rpc: &AsyncRpcClient,
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    mut instruction: Instruction,
+    data: Vec<u8>,
+    config: &UploadConfig,
+) -> Result<CallSignatures, UploadError> {
+    let mut writes = Vec::new();
+    let progress = |_, signature, _| writes.push(signature);
+    let sender = AsyncSender { rpc, pubsub: None };
+    let iter = WriteIter::new(write_program, signer.pubkey(), seed, data)?.0;
+    let (account, bump) = upload_async_impl(
+        sender,
+        signer,
+        iter,
+        config,
+        |_| Vec::new(),
+        progress,
+    )
+    .await?;
+    if config.preflight {
+        preflight_call_async(rpc, signer, &instruction, account)
+            .await?
+            .into_result()?;
+    }
+    Accounts::append(&mut instruction, account);
+    let throttle = Throttle::new(config.rate_limit);
+    let blockhashes = BlockhashCache::new(config.blockhash_reuse_margin);
+    let (call, _) = send_async(
+        sender,
+        &throttle,
+        &blockhashes,
+        signer,
+        &[instruction],
+        config,
+    )
+    .await?;
+    trace_event!(INFO, signature = %call, "called target program");
+    let free = free(write_program, signer, seed, account, bump)?;
+    let (free, _) =
+        send_async(sender, &throttle, &blockhashes, signer, &[free], config)
+            .await?;
+    trace_event!(INFO, %account, signature = %free, "freed Write account");
+    Ok(CallSignatures { writes, call, free })
+}
+
+/// Signatures of transactions sent by [`call_with_account_data`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallSignatures {
+    /// Signatures of the transactions writing chunks into the Write account
+    /// in the order of the chunks.
+    pub writes: Vec<Signature>,
+
+    /// Signature of the transaction calling the target program.
+    pub call: Signature,
+
+    /// Signature of the transaction freeing the Write account.
+    pub free: Signature,
+}
+
+/// Returns transactions uploading `data` (and optionally calling
+/// `instruction`) without sending them.
+///
+/// Performs the same flow as [`upload_with_config`] or, if `instruction` is
+/// given, [`call_with_account_data`] except that rather than sending the
+/// transactions returns them signed and encoded with [`encode_transactions`].
+/// This lets the transactions be piped into external submission
+/// infrastructure, explorers or review tooling.  The transactions must be
+/// executed in order.
+///
+/// The RPC client is used only to fetch the blockhash and, if
+/// [`UploadConfig::skip_unchanged`] is set, the Write account.  Since the
+/// blockhash expires after about a minute, the transactions should be
+/// submitted promptly.
+pub fn dry_run(
+    rpc: &RpcClient,
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    data: Vec<u8>,
+    instruction: Option<Instruction>,
+    config: &UploadConfig,
+) -> Result<Vec<String>, UploadError> {
+    let (mut chunks, account, bump) =
+        WriteIter::new(write_program, signer.pubkey(), seed, data)?;
+    let budget = compute_budget(&mut chunks, config);
+    if config.skip_unchanged {
+        let commitment = config.commitment.unwrap_or_else(|| rpc.commitment());
+        let account = rpc.get_account_with_commitment(&account, commitment)?;
+        chunks.skip_unchanged(
+            account.value.map_or_else(Vec::new, |acc| acc.data),
+        );
+    }
+    let mut batches = chunks
+        .map(|instruction| [&budget[..], &[instruction]].concat())
+        .collect::<Vec<_>>();
+    if let Some(mut instruction) = instruction {
+        Accounts::append(&mut instruction, account);
+        batches.push(Vec::from([instruction]));
+        batches.push(Vec::from([free(
+            write_program,
+            signer,
+            seed,
+            account,
+            bump,
+        )?]));
+    }
+    let (blockhash, _) = latest_blockhash(rpc, config)?;
+    Ok(encode_transactions(signer, &batches, blockhash))
+}
+
+/// Signs transactions with given instructions and encodes them in base64.
+///
+/// Each element of `batches` holds instructions of a single transaction paid
+/// for and signed by `signer` (e.g. as returned by
+/// [`WriteIter::into_instruction_batches`]).  The transactions are
+/// serialised in the wire format and encoded in base64 as accepted by
+/// `sendTransaction` RPC method.
+pub fn encode_transactions(
+    signer: &(impl Signer + ?Sized),
+    batches: &[Vec<Instruction>],
+    blockhash: Hash,
+) -> Vec<String> {
+    use base64::Engine;
+
+    batches
+        .iter()
+        .map(|instructions| {
+            let tx = transaction(signer, instructions, blockhash);
+            // Serialising a transaction into a vector cannot fail.
+            let tx = bincode::serialize(&tx).unwrap();
+            base64::engine::general_purpose::STANDARD.encode(tx)
+        })
+        .collect()
+}
+
+/// Result of simulating the call to the target program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Preflight {
+    /// Error the transaction failed with or `None` if it succeeded.
+    pub err: Option<TransactionError>,
+
+    /// Log messages emitted during the simulation.
+    pub logs: Vec<String>,
+
+    /// Number of compute units the transaction consumed.
+    pub units_consumed: Option<u64>,
+}
+
+impl Preflight {
+    /// Returns [`UploadError::Preflight`] error if the simulation failed.
+    pub fn into_result(self) -> Result<Self, UploadError> {
+        match self.err {
+            None => Ok(self),
+            Some(_) => Err(UploadError::Preflight(Box::new(self))),
+        }
+    }
+
+    fn from_result(result: RpcSimulateTransactionResult) -> Self {
+        Self {
+            err: result.err,
+            logs: result.logs.unwrap_or_default(),
+            units_consumed: result.units_consumed,
+        }
+    }
+}
+
+/// Simulates calling `instruction` with the Write account appended to its
+/// accounts.
+///
+/// This is meant to be used once the data has been written to the Write
+/// account and before the actual call is sent.  Malformed payloads are caught
+/// early with the logs and compute units usage helping to diagnose the issue
+/// without paying fees for a failing transaction.  Note that the simulation
+/// succeeding doesn’t guarantee the actual call will since state of the
+/// accounts may change in the meantime.
+///
+/// The simulated transaction is paid for and signed by `signer`.  Returns an
+/// error only if the simulation couldn’t be performed; failure of the
+/// transaction is reported in [`Preflight::err`].
+pub fn preflight_call(
+    rpc: &RpcClient,
+    signer: &(impl Signer + ?Sized),
+    instruction: &Instruction,
+    write_account: Pubkey,
+) -> Result<Preflight, UploadError> {
+    let mut instruction = instruction.clone();
+    Accounts::append(&mut instruction, write_account);
+    let blockhash = rpc.get_latest_blockhash()?;
+    let tx = transaction(signer, &[instruction], blockhash);
+    Ok(Preflight::from_result(rpc.simulate_transaction(&tx)?.value))
+}
+
+/// Simulates calling `instruction` with the Write account appended to its
+/// accounts.
+///
+/// Asynchronous version of [`preflight_call`].
+#[cfg(feature = "client-async")]
+pub async fn preflight_call_async(
+    rpc: &AsyncRpcClient,
+    signer: &(impl Signer + ?Sized),
+    instruction: &Instruction,
+    write_account: Pubkey,
+) -> Result<Preflight, UploadError> {
+    let mut instruction = instruction.clone();
+    Accounts::append(&mut instruction, write_account);
+    let blockhash = rpc.get_latest_blockhash().await?;
+    let tx = transaction(signer, &[instruction], blockhash);
+    Ok(Preflight::from_result(rpc.simulate_transaction(&tx).await?.value))
+}
+
+/// Length of seeds generated by [`unique_seed`].
+pub const UNIQUE_SEED_LEN: usize = 8;
+
+/// Picks a random seed of a Write account which doesn’t exist yet.
+///
+/// Services running many concurrent uploads under one payer need a distinct
+/// Write account for each of them.  This generates a random
+/// [`UNIQUE_SEED_LEN`]-byte seed and checks whether the Write account derived
+/// from it already exists.  On collision, tries again with another seed.
+/// Returns the seed and address and bump of the Write account.
+///
+/// The seed is random rather than checked to be unused by concurrent callers
+/// so two uploads picking the same seed before either of them creates the
+/// account is possible in principle, though, with 64 bits of randomness,
+/// exceedingly unlikely.  Returns [`UploadError::SeedCollision`] if no unused
+/// seed is found in a few attempts.
+pub fn unique_seed(
+    rpc: &RpcClient,
+    write_program: &Pubkey,
+    payer: &Pubkey,
+) -> Result<([u8; UNIQUE_SEED_LEN], Pubkey, u8), UploadError> {
+    for _ in 0..UNIQUE_SEED_ATTEMPTS {
+        let seed = random_seed();
+        let (account, bump) =
+            super::find_write_account(write_program, payer, &seed)?;
+        let commitment = rpc.commitment();
+        if rpc
+            .get_account_with_commitment(&account, commitment)?
+            .value
+            .is_none()
+        {
+            return Ok((seed, account, bump));
+        }
+    }
+    Err(UploadError::SeedCollision)
+}
+
+/// Picks a random seed of a Write account which doesn’t exist yet.
+///
+/// Asynchronous version of [`unique_seed`].
+#[cfg(feature = "client-async")]
+pub async fn unique_seed_async(
+    rpc: &AsyncRpcClient,
+    write_program: &Pubkey,
+    payer: &Pubkey,
+) -> Result<([u8; UNIQUE_SEED_LEN], Pubkey, u8), UploadError> {
+    for _ in 0..UNIQUE_SEED_ATTEMPTS {
+        let seed = random_seed();
+        let (account, bump) =
+            super::find_write_account(write_program, payer, &seed)?;
+        let commitment = rpc.commitment();
+        let account_info =
+            rpc.get_account_with_commitment(&account, commitment).await?;
+        if account_info.value.is_none() {
+            return Ok((seed, account, bump));
+        }
+    }
+    Err(UploadError::SeedCollision)
+}
+
+/// Number of seeds [`unique_seed`] tries before giving up.
+const UNIQUE_SEED_ATTEMPTS: usize = 8;
+
+/// Generates a random seed.
+///
+/// Uses randomly-keyed [`std::hash::RandomState`] hasher rather than
+/// a dedicated random number generator to avoid additional dependencies.
+/// Each `RandomState` is keyed differently and the current time is mixed in
+/// for good measure.
+fn random_seed() -> [u8; UNIQUE_SEED_LEN] {
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut hasher = std::hash::RandomState::new().build_hasher();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    hasher.write_u128(now.as_nanos());
+    hasher.finish().to_le_bytes()
+}
+
+/// Downloads length-prefixed data from the Write account.
+///
+/// Reads data written by [`upload`] (or iterator constructed with
+/// [`WriteIter::new`]), validates the 4-byte length prefix and returns the
+/// payload without the prefix.  Any bytes past the payload (e.g. left over
+/// from a longer payload written into the account earlier) are discarded.
+///
+/// Returns [`UploadError::InvalidData`] error if the account is too short for
+/// the length it declares.  This is also the case for data using other
+/// layouts (such as compressed data or data with a versioned header) since the
+/// length they decode to exceeds maximum account size.
+pub fn fetch_data(
+    rpc: &RpcClient,
+    write_account: &Pubkey,
+) -> Result<Vec<u8>, UploadError> {
+    strip_length_prefix(rpc.get_account_data(write_account)?)
+}
+
+/// Downloads length-prefixed data from the Write account.
+///
+/// Asynchronous version of [`fetch_data`].
+#[cfg(feature = "client-async")]
+pub async fn fetch_data_async(
+    rpc: &AsyncRpcClient,
+    write_account: &Pubkey,
+) -> Result<Vec<u8>, UploadError> {
+    strip_length_prefix(rpc.get_account_data(write_account).await?)
+}
+
+/// Validates and strips the length prefix discarding any trailing bytes.
+fn strip_length_prefix(mut data: Vec<u8>) -> Result<Vec<u8>, UploadError> {
+    let (prefix, payload) =
+        data.split_first_chunk::<4>().ok_or(UploadError::InvalidData)?;
+    let len = usize::try_from(u32::from_le_bytes(*prefix))
+        .ok()
+        .filter(|len| *len <= payload.len())
+        .ok_or(UploadError::InvalidData)?;
+    data.truncate(4 + len);
+    data.drain(..4);
+    Ok(data)
+}
+
+/// Write account found by [`list_write_accounts`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteAccountInfo {
+    /// Address of the Write account.
+    pub address: Pubkey,
+
+    /// Seed the account was created with.
+    pub seed: Vec<u8>,
+
+    /// Bump of the Write account PDA.
+    pub bump: u8,
+
+    /// Size of the account’s data.
+    pub len: usize,
+
+    /// Balance of the account, i.e. rent returned once the account is freed.
+    pub lamports: u64,
+}
+
+/// Lists Write accounts owned by `payer`.
+///
+/// The Write account doesn’t record its payer and the payer is mixed into the
+/// PDA with a hash so there’s no way to filter program’s accounts by payer.
+/// Instead, this fetches all accounts of the `write_program` (with
+/// `getProgramAccounts` call) and re-derives addresses from given candidate
+/// `seeds`.  Accounts which match one of the addresses are returned in the
+/// order of the seeds.  Accounts created with seeds not on the list (as well
+/// as keypair accounts) are not found.
+///
+/// This lets operators find forgotten Write accounts which are still holding
+/// rent.  The accounts can be closed with [`super::free`].
+///
+/// Note that many RPC providers restrict `getProgramAccounts` calls.
+pub fn list_write_accounts<'a>(
+    rpc: &RpcClient,
+    write_program: &Pubkey,
+    payer: &Pubkey,
+    seeds: impl IntoIterator<Item = &'a [u8]>,
+) -> Result<Vec<WriteAccountInfo>, UploadError> {
+    let candidates = candidate_accounts(write_program, payer, seeds)?;
+    let accounts = rpc.get_program_accounts(write_program)?;
+    Ok(match_accounts(candidates, accounts))
+}
+
+/// Lists Write accounts owned by `payer`.
+///
+/// Asynchronous version of [`list_write_accounts`].
+#[cfg(feature = "client-async")]
+pub async fn list_write_accounts_async<'a>(
+    rpc: &AsyncRpcClient,
+    write_program: &Pubkey,
+    payer: &Pubkey,
+    seeds: impl IntoIterator<Item = &'a [u8]>,
+) -> Result<Vec<WriteAccountInfo>, UploadError> {
+    let candidates = candidate_accounts(write_program, payer, seeds)?;
+    let accounts = rpc.get_program_accounts(write_program).await?;
+    Ok(match_accounts(candidates, accounts))
+}
+
+/// Candidate Write accounts mapping the address to position of the seed, the
+/// seed and the bump.
+type Candidates<'a> = BTreeMap<Pubkey, (usize, &'a [u8], u8)>;
+
+/// Derives Write account addresses for candidate seeds.
+fn candidate_accounts<'a>(
+    write_program: &Pubkey,
+    payer: &Pubkey,
+    seeds: impl IntoIterator<Item = &'a [u8]>,
+) -> Result<Candidates<'a>, Error> {
+    seeds
+        .into_iter()
+        .enumerate()
+        .map(|(index, seed)| {
+            let (address, bump) =
+                super::find_write_account(write_program, payer, seed)?;
+            Ok((address, (index, seed, bump)))
+        })
+        .collect()
+}
+
+/// Picks accounts whose addresses are among the candidates.
+fn match_accounts(
+    candidates: Candidates,
+    accounts: Vec<(Pubkey, Account)>,
+) -> Vec<WriteAccountInfo> {
+    let mut found = accounts
+        .into_iter()
+        .filter_map(|(address, account)| {
+            let (index, seed, bump) = *candidates.get(&address)?;
+            Some((index, WriteAccountInfo {
+                address,
+                seed: seed.to_vec(),
+                bump,
+                len: account.data.len(),
+                lamports: account.lamports,
+            }))
+        })
+        .collect::<Vec<_>>();
+    found.sort_unstable_by_key(|(index, _)| *index);
+    found.into_iter().map(|(_, info)| info).collect()
+}
+
+/// Returns the Free instruction for the Write account.
+fn free(
+    write_program: &Pubkey,
+    signer: &(impl Signer + ?Sized),
+    seed: &[u8],
+    account: Pubkey,
+    bump: u8,
+) -> Result<Instruction, Error> {
+    let payer = signer.pubkey();
+    super::free(*write_program, payer, Some(account), seed, bump)
+}
+
+/// Reserves space for `extra` instructions in the next Write transaction.
+///
+/// Accounts already referenced by the Write transaction aren’t counted.  See
+/// [`WriteIter::reserve_bytes`].
+fn reserve_extra(
+    chunks: &mut WriteIter,
+    budget: &[Instruction],
+    extra: &[Instruction],
+) {
+    let mut keys = BTreeSet::from([
+        chunks.payer,
+        chunks.write_account,
+        *chunks.write_program,
+        chunks.system_program,
+    ]);
+    keys.extend(chunks.fee_payer);
+    keys.extend(budget.iter().map(|instruction| instruction.program_id));
+    let bytes = extra
+        .iter()
+        .map(|instruction| {
+            let accounts = &instruction.accounts;
+            let new_keys = core::iter::once(&instruction.program_id)
+                .chain(accounts.iter().map(|meta| &meta.pubkey))
+                .filter(|key| keys.insert(**key))
+                .count();
+            let data = instruction.data.len();
+            new_keys * PUBKEY_BYTES +
+                1 +
+                short_vec_len(accounts.len()) +
+                accounts.len() +
+                short_vec_len(data) +
+                data
+        })
+        .sum();
+    if bytes != chunks.reserved {
+        chunks.reserve_bytes(bytes);
+    }
+}
+
+/// Returns length of encoding of a short vector length prefix.
+fn short_vec_len(len: usize) -> usize {
+    match len {
+        0..0x80 => 1,
+        0x80..0x4000 => 2,
+        _ => 3,
+    }
+}
+
+/// Takes chunks written by the next batch of concurrently sent transactions.
+///
+/// Returns up to `parallelism` chunks along with instructions of transactions
+/// writing them.  Since the account can grow by at most
+/// [`MAX_PERMITTED_DATA_INCREASE`] bytes in a single instruction and the
+/// transactions of a batch may execute in any order, each chunk of the batch
+/// must end within that many bytes of `size`.  `size` is the size the Write
+/// account is known to have (initially the length of the existing data; see
+/// [`WriteIter::skip_unchanged`]) and is updated to the size the account has
+/// once the batch lands.  The first chunk is always taken so that the upload
+/// makes progress (if the account is too small, the transaction fails rather
+/// than the upload silently stopping).
+#[cfg(feature = "client-async")]
+fn next_batch(
+    chunks: &mut WriteIter,
+    size: &mut usize,
+    parallelism: usize,
+    budget: &[Instruction],
+    extra: &mut impl FnMut(usize) -> Vec<Instruction>,
+) -> Vec<(ChunkInfo, Vec<Instruction>)> {
+    let limit = size.saturating_add(MAX_PERMITTED_DATA_INCREASE);
+    let mut batch = Vec::with_capacity(parallelism);
+    while batch.len() < parallelism && chunks.next_range().is_some() {
+        let extra = extra(chunks.index);
+        reserve_extra(chunks, budget, &extra);
+        let Some(range) = chunks.next_range() else { break };
+        if !batch.is_empty() && chunks.base_offset + range.end > limit {
+            break;
+        }
+        let Some(chunk) = chunks.next_chunk() else { break };
+        let write = core::slice::from_ref(&chunk.instruction);
+        let instructions = [budget, write, &extra[..]].concat();
+        batch.push((chunk, instructions));
+    }
+    *size = (*size).max(chunks.base_offset + chunks.position);
+    batch
+}
+
+/// Sends a transaction with given instructions retrying it if necessary.
+///
+/// Asynchronous version of [`send`].
+#[cfg(feature = "client-async")]
+async fn send_async(
+    sender: AsyncSender<'_>,
+    throttle: &Throttle,
+    blockhashes: &BlockhashCache,
+    signer: &(impl Signer + ?Sized),
+    instructions: &[Instruction],
+    config: &UploadConfig,
+) -> Result<(Signature, u32), UploadError> {
+    let rpc = sender.rpc;
+    // Expiry of the cached blockhash has been checked when preparing the
+    // batch.
+    let mut blockhash = match blockhashes.peek() {
+        Some(blockhash) => blockhash,
+        None => blockhashes.get_async(rpc, config).await?,
+    };
+    let mut attempt = 1;
+    loop {
+        let tx = transaction(signer, instructions, blockhash);
+        let delay = throttle.delay();
+        if !delay.is_zero() {
+            trace_event!(TRACE, ?delay, "throttling transaction");
+            tokio::time::sleep(delay).await;
+        }
+        let confirmed = match sender.pubsub {
+            Some(pubsub) => {
+                let commitment =
+                    config.commitment.unwrap_or_else(|| rpc.commitment());
+                send_and_subscribe(rpc, pubsub, &tx, commitment).await
+            }
+            None => send_and_confirm_async(rpc, &tx, config.commitment).await,
+        };
+        let err = match confirmed {
+            Ok(signature) => return Ok((signature, attempt)),
+            Err(err) => err,
+        };
+        trace_event!(
+            WARN,
+            signature = %tx.signatures[0],
+            attempt,
+            error = %err,
+            "sending transaction failed",
+        );
+        let delay = config
+            .retry
+            .backoff(attempt, &err)
+            .ok_or(UploadError::Client(err))?;
+        tokio::time::sleep(delay).await;
+        if config.retry.refresh_blockhash ||
+            !rpc.is_blockhash_valid(
+                &blockhash,
+                CommitmentConfig::processed(),
+            )
+            .await?
+        {
+            blockhashes.invalidate(&blockhash);
+            blockhash = blockhashes.get_async(rpc, config).await?;
+        }
+        attempt += 1;
+    }
+}
+
+/// Sends a transaction and waits for it to reach given commitment level.
+///
+/// Asynchronous version of [`send_and_confirm`].
+#[cfg(feature = "client-async")]
+async fn send_and_confirm_async(
+    rpc: &AsyncRpcClient,
+    tx: &Transaction,
+    commitment: Option<CommitmentConfig>,
+) -> Result<Signature, Box<ClientError>> {
+    let Some(commitment) = commitment else {
+        return Ok(rpc.send_and_confirm_transaction(tx).await?);
+    };
+    let config = send_transaction_config(commitment);
+    let signature = rpc.send_transaction_with_config(tx, config).await?;
+    let blockhash = &tx.message.recent_blockhash;
+    loop {
+        let status = rpc
+            .get_signature_status_with_commitment(&signature, commitment)
+            .await?;
+        match status {
+            Some(result) => {
+                return result
+                    .map(|()| signature)
+                    .map_err(|err| Box::new(err.into()))
+            }
+            None if !rpc
+                .is_blockhash_valid(blockhash, CommitmentConfig::processed())
+                .await? =>
+            {
+                return Err(Box::new(unconfirmed_error()))
+            }
+            None => tokio::time::sleep(CONFIRM_POLL_INTERVAL).await,
+        }
+    }
+}
+
+/// Sends a transaction and waits for signature notification reporting it
+/// reached given commitment level.
+///
+/// Subscribes to the signature before sending the transaction so that the
+/// notification isn’t missed.  Checks validity of transaction’s blockhash
+/// every [`BLOCKHASH_CHECK_INTERVAL`] and gives up once it expires.
+#[cfg(feature = "client-async")]
+async fn send_and_subscribe(
+    rpc: &AsyncRpcClient,
+    pubsub: &PubsubClient,
+    tx: &Transaction,
+    commitment: CommitmentConfig,
+) -> Result<Signature, Box<ClientError>> {
+    use futures::StreamExt;
+
+    let signature = tx.signatures[0];
+    let config = RpcSignatureSubscribeConfig {
+        commitment: Some(commitment),
+        enable_received_notification: Some(false),
+    };
+    let (mut stream, unsubscribe) = pubsub
+        .signature_subscribe(&signature, Some(config))
+        .await
+        .map_err(pubsub_error)?;
+    let result = async {
+        let config = send_transaction_config(commitment);
+        rpc.send_transaction_with_config(tx, config).await?;
+        let blockhash = &tx.message.recent_blockhash;
+        loop {
+            let next = stream.next();
+            match tokio::time::timeout(BLOCKHASH_CHECK_INTERVAL, next).await {
+                Ok(Some(response)) => match response.value {
+                    RpcSignatureResult::ProcessedSignature(result) => {
+                        break match result.err {
+                            None => Ok(signature),
+                            Some(err) => Err(Box::new(err.into())),
+                        }
+                    }
+                    RpcSignatureResult::ReceivedSignature(_) => (),
+                },
+                Ok(None) => {
+                    let msg = "signature subscription closed".into();
+                    break Err(Box::new(RpcError::ForUser(msg).into()));
+                }
+                Err(_)
+                    if !rpc
+                        .is_blockhash_valid(
+                            blockhash,
+                            CommitmentConfig::processed(),
+                        )
+                        .await? =>
+                {
+                    break Err(Box::new(unconfirmed_error()))
+                }
+                Err(_) => (),
+            }
+        }
+    }
+    .await;
+    drop(stream);
+    unsubscribe().await;
+    result
+}
+
+/// Converts PubSub client error into an RPC client error.
+///
+/// The error isn’t a transaction error so [`RetryPolicy`] treats it as
+/// transient.
+#[cfg(feature = "client-async")]
+fn pubsub_error(err: PubsubClientError) -> ClientError {
+    RpcError::ForUser(err.to_string()).into()
+}
+
+/// Returns the latest blockhash and its last valid block height at commitment
+/// level chosen in `config`.
+#[cfg(feature = "client-async")]
+async fn latest_blockhash_async(
+    rpc: &AsyncRpcClient,
+    config: &UploadConfig,
+) -> Result<(Hash, u64), UploadError> {
+    let commitment = config.commitment.unwrap_or_else(|| rpc.commitment());
+    Ok(rpc.get_latest_blockhash_with_commitment(commitment).await?)
+}
+
+/// Interval between checks of the status of a sent transaction.
+const CONFIRM_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Interval between checks of validity of the blockhash of a transaction
+/// awaiting signature notification; see [`upload_async_with_pubsub`].
+#[cfg(feature = "client-async")]
+const BLOCKHASH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Returns configuration for sending transaction with preflight checks at
+/// given commitment level.
+fn send_transaction_config(
+    commitment: CommitmentConfig,
+) -> RpcSendTransactionConfig {
+    RpcSendTransactionConfig {
+        preflight_commitment: Some(commitment.commitment),
+        ..RpcSendTransactionConfig::default()
+    }
+}
+
+/// Returns error reported when transaction’s blockhash expires before it’s
+/// confirmed.
+///
+/// The error isn’t a transaction error so [`RetryPolicy`] treats it as
+/// transient.
+fn unconfirmed_error() -> ClientError {
+    RpcError::ForUser(
+        "unable to confirm transaction before its blockhash expired".into(),
+    )
+    .into()
+}
+
+/// Emits `tracing` event reporting that a chunk has been written.
+fn trace_chunk(
+    account: &Pubkey,
+    chunk: &ChunkInfo,
+    signature: &Signature,
+    attempts: u32,
+) {
+    trace_event!(
+        DEBUG,
+        %account,
+        index = chunk.index,
+        total = chunk.total,
+        offset = chunk.offset,
+        len = chunk.len,
+        %signature,
+        attempts,
+        "chunk written",
+    );
+    #[cfg(not(feature = "tracing"))]
+    let _ = (account, chunk, signature, attempts);
+}
+
+/// Configures compute budget of the Write transactions according to `config`.
+///
+/// Returns compute budget instructions to include in each Write transaction.
+fn compute_budget(
+    chunks: &mut WriteIter,
+    config: &UploadConfig,
+) -> Vec<Instruction> {
+    chunks.compute_budget(config.compute_unit_limit, config.compute_unit_price);
+    chunks.compute_budget_instructions()
+}
+
+/// Creates a transaction with given instructions paid for by the `signer`.
+fn transaction(
+    signer: &(impl Signer + ?Sized),
+    instructions: &[Instruction],
+    blockhash: Hash,
+) -> Transaction {
+    Transaction::new_signed_with_payer(
+        instructions,
+        Some(&signer.pubkey()),
+        &[signer],
+        blockhash,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+    #[cfg(feature = "client-async")]
+    use core::ops::Range;
+    use std::sync::{Arc, Mutex};
+
+    use solana_client::rpc_client::RpcClientConfig;
+    use solana_client::rpc_request::RpcRequest;
+    use solana_client::rpc_sender::{RpcSender, RpcTransportStats};
+
+    use super::*;
+
+    #[test]
+    fn test_blockhash_cache() {
+        let blockhash = Hash::new_from_array([1; 32]);
+        let now = std::time::Instant::now();
+        let cached = |height, secs| CachedBlockhash {
+            blockhash,
+            last_valid: 200,
+            height,
+            fetched: now - Duration::from_secs(secs),
+        };
+
+        let cache = BlockhashCache::new(Some(10));
+        assert_eq!(None, cache.cached());
+        cache.store(blockhash, 200, 100, now);
+        assert_eq!(Some(cached(100, 0)), cache.cached());
+        assert!(cache.is_fresh(&cached(100, 0), now));
+        assert!(cache.is_fresh(&cached(189, 0), now));
+        assert!(!cache.is_fresh(&cached(190, 0), now));
+        assert!(!cache.is_fresh(&cached(250, 0), now));
+        // Height is estimated assuming 400 ms per block.
+        assert!(cache.is_fresh(&cached(100, 35), now));
+        assert!(!cache.is_fresh(&cached(100, 36), now));
+
+        cache.invalidate(&Hash::new_from_array([2; 32]));
+        assert_eq!(Some(cached(100, 0)), cache.cached());
+        cache.invalidate(&blockhash);
+        assert_eq!(None, cache.cached());
+
+        // Without margin, blockhashes are never cached.
+        let cache = BlockhashCache::new(None);
+        cache.store(blockhash, 200, 100, now);
+        assert_eq!(None, cache.cached());
+        assert!(!cache.is_fresh(&cached(0, 0), now));
+    }
+
+    /// RPC sender answering `getLatestBlockhash` and `getBlockHeight`
+    /// requests and recording all requests it receives.
+    #[derive(Clone, Default)]
+    struct CountingSender(Arc<Mutex<Vec<RpcRequest>>>);
+
+    #[async_trait::async_trait]
+    impl RpcSender for CountingSender {
+        async fn send(
+            &self,
+            request: RpcRequest,
+            _params: serde_json::Value,
+        ) -> solana_client::client_error::Result<serde_json::Value> {
+            self.0.lock().unwrap().push(request);
+            Ok(match request.to_string().as_str() {
+                "getLatestBlockhash" => serde_json::json!({
+                    "context": { "slot": 1 },
+                    "value": {
+                        "blockhash": Hash::new_from_array([1; 32]).to_string(),
+                        "lastValidBlockHeight": 1150,
+                    },
+                }),
+                "getBlockHeight" => serde_json::json!(1000),
+                _ => unimplemented!("{request}"),
+            })
+        }
+
+        fn get_transport_stats(&self) -> RpcTransportStats {
+            Default::default()
+        }
+
+        fn url(&self) -> String { String::from("counting") }
+    }
+
+    #[test]
+    fn test_blockhash_cache_rpc_calls() {
+        let count = |margin| {
+            let sender = CountingSender::default();
+            let rpc = RpcClient::new_sender(
+                sender.clone(),
+                RpcClientConfig::default(),
+            );
+            let cache = BlockhashCache::new(margin);
+            for _ in 0..100 {
+                let blockhash =
+                    cache.get(&rpc, &UploadConfig::DEFAULT).unwrap();
+                assert_eq!(Hash::new_from_array([1; 32]), blockhash);
+            }
+            let requests = sender.0.lock().unwrap();
+            requests.iter().map(ToString::to_string).collect::<Vec<_>>()
+        };
+
+        // Block height is fetched once along with the blockhash rather than
+        // for each transaction.
+        assert_eq!(
+            ["getLatestBlockhash", "getBlockHeight"][..],
+            count(Some(75))
+        );
+        // Without caching, each transaction fetches a blockhash.
+        assert_eq!(vec!["getLatestBlockhash"; 100], count(None));
+    }
+
+    /// Splits chunks generated by `chunks` into batches and returns ranges of
+    /// the account each batch writes.
+    #[cfg(feature = "client-async")]
+    fn batches(
+        mut chunks: WriteIter,
+        parallelism: usize,
+    ) -> Vec<Vec<Range<usize>>> {
+        let mut size = chunks.existing.len();
+        let mut batches = Vec::new();
+        loop {
+            let limit = size + MAX_PERMITTED_DATA_INCREASE;
+            let batch = next_batch(
+                &mut chunks,
+                &mut size,
+                parallelism,
+                &[],
+                &mut |_| Vec::new(),
+            );
+            if batch.is_empty() {
+                break batches;
+            }
+            assert!(batch.len() <= parallelism);
+            let ranges = batch
+                .into_iter()
+                .map(|(chunk, instructions)| {
+                    assert_eq!(vec![chunk.instruction], instructions);
+                    chunk.offset..chunk.offset + chunk.len
+                })
+                .collect::<Vec<_>>();
+            assert!(ranges.iter().all(|range| range.end <= limit));
+            batches.push(ranges);
+        }
+    }
+
+    /// Returns iterator writing 30 000 bytes in 500-byte chunks.
+    #[cfg(feature = "client-async")]
+    fn write_iter(existing: Option<Vec<u8>>) -> (WriteIter<'static>, Vec<u8>) {
+        const PROGRAM: Pubkey = Pubkey::new_from_array([1; 32]);
+        let payer = Pubkey::new_from_array([2; 32]);
+        let data = (0..30_000).map(|idx| idx as u8).collect::<Vec<_>>();
+        let (mut iter, ..) =
+            WriteIter::new_raw(&PROGRAM, payer, b"", data.clone()).unwrap();
+        iter.chunk_size(500);
+        if let Some(existing) = existing {
+            iter.skip_unchanged(existing);
+        }
+        (iter, data)
+    }
+
+    /// Returns 500-byte chunks in given range.
+    #[cfg(feature = "client-async")]
+    fn chunk_ranges(range: Range<usize>) -> Vec<Range<usize>> {
+        range.step_by(500).map(|start| start..start + 500).collect()
+    }
+
+    #[test]
+    #[cfg(feature = "client-async")]
+    fn test_next_batch() {
+        let want = vec![
+            chunk_ranges(0..10_000),
+            chunk_ranges(10_000..20_000),
+            chunk_ranges(20_000..30_000),
+        ];
+        assert_eq!(want, batches(write_iter(None).0, 100));
+
+        let want = chunk_ranges(0..30_000)
+            .chunks(4)
+            .map(|batch| batch.to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(want, batches(write_iter(None).0, 4));
+    }
+
+    #[test]
+    #[cfg(feature = "client-async")]
+    fn test_next_batch_skip_unchanged() {
+        let (_, data) = write_iter(None);
+        let mut existing = data[..15_000].to_vec();
+        existing[100] = 0xff;
+        let (iter, _) = write_iter(Some(existing));
+        let mut first = chunk_ranges(15_000..25_000);
+        first.insert(0, 100..101);
+        let want = vec![first, chunk_ranges(25_000..30_000)];
+        assert_eq!(want, batches(iter, 100));
+
+        // Identical data needs no batches.
+        let (mut iter, data) = write_iter(None);
+        iter.skip_unchanged(data);
+        assert_eq!(Vec::<Vec<Range<usize>>>::new(), batches(iter, 100));
+    }
+
+    #[test]
+    fn test_match_accounts() {
+        let program = Pubkey::new_from_array([1; 32]);
+        let payer = Pubkey::new_from_array([2; 32]);
+        let seeds = [&b"c"[..], b"a", b"missing", b"b"];
+        let candidates = candidate_accounts(&program, &payer, seeds).unwrap();
+        let address = |seed: &[u8]| {
+            crate::instruction::find_write_account(&program, &payer, seed)
+                .unwrap()
+        };
+        let account = |len, lamports| Account {
+            lamports,
+            data: vec![0; len],
+            owner: program,
+            ..Account::default()
+        };
+
+        // Accounts come in arbitrary order and include accounts which
+        // aren’t among the candidates.
+        let accounts = Vec::from([
+            (address(b"a").0, account(1, 10)),
+            (Pubkey::new_from_array([3; 32]), account(2, 20)),
+            (address(b"b").0, account(3, 30)),
+            (address(b"c").0, account(4, 40)),
+            (address(b"other").0, account(5, 50)),
+        ]);
+        let info = |seed: &[u8], len, lamports| {
+            let (address, bump) = address(seed);
+            WriteAccountInfo {
+                address,
+                seed: seed.to_vec(),
+                bump,
+                len,
+                lamports,
+            }
+        };
+        // Order of the seeds is preserved.
+        let want = [info(b"c", 4, 40), info(b"a", 1, 10), info(b"b", 3, 30)];
+        assert_eq!(&want[..], match_accounts(candidates, accounts));
+    }
+
+    #[test]
+    fn test_strip_length_prefix() {
+        let strip = |data: &[u8]| strip_length_prefix(data.to_vec()).ok();
+        assert_eq!(Some(b"data".to_vec()), strip(b"\x04\0\0\0data"));
+        assert_eq!(Some(Vec::new()), strip(b"\0\0\0\0"));
+        // Trailing bytes are discarded.
+        assert_eq!(Some(b"da".to_vec()), strip(b"\x02\0\0\0data"));
+        assert_eq!(Some(Vec::new()), strip(b"\0\0\0\0data"));
+        // Truncated prefix.
+        assert_eq!(None, strip(b""));
+        assert_eq!(None, strip(b"\x04\0\0"));
+        // Declared length past the end of the data.
+        assert_eq!(None, strip(b"\x05\0\0\0data"));
+        assert_eq!(None, strip(b"\0\0\0\x80data"));
+
+        let err = strip_length_prefix(b"\x05\0\0\0data".to_vec());
+        assert!(matches!(err, Err(UploadError::InvalidData)));
+    }
+}
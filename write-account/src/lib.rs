@@ -30,14 +30,49 @@
 //! functions for invoking program with instruction stored in an account (when
 //! built with `client` feature) and helper [`mod@entrypoint`] module for Solana
 //! programs which want to support reading instruction data from an account
-//! (when built with `lib` feature).
+//! (when built with `lib` feature).  Programs written with pinocchio can use
+//! [`mod@pinocchio`] module instead (when built with `pinocchio` feature)
+//! which doesn’t depend on `solana-program`.
 //!
 //! The account data must be a length-prefixed slice of bytes.  In other words,
 //! borsh-serialised `Vec<u8>`.  The account may contain trailing bytes which
-//! are ignored.
+//! are ignored.  If the most significant bit of the length is set, the payload
+//! is a zstd frame (see [`codec::ZSTD_FLAG`]; requires `zstd` feature).
+//! Alternatively, the data may start with a versioned header described in
+//! [`header`] module.
+//!
+//! The crate is `no_std` (but requires `alloc`) unless built with features
+//! which need the standard library.
+//!
+//! The `client` feature depends on neither the standard library nor the RPC
+//! client.  It can therefore be built for `wasm32-unknown-unknown` target
+//! which lets web wallets and dApp frontends construct the Write and Free
+//! instructions directly.  `client-rpc`, `client-async` and `client-zstd`
+//! features on the other hand pull native-only dependencies.
 //!
 //! ## Feature flags
 #![doc = document_features::document_features!()]
+#![no_std]
+
+extern crate alloc;
+// With no features selected, the crate is empty.  Link the standard library
+// then so that the cdylib has a panic handler and allocator which otherwise
+// come from the program.  Likewise, pinocchio doesn’t link it off-chain unless
+// its entrypoint macros are used.
+#[cfg(any(
+    test,
+    feature = "client-rpc",
+    feature = "std",
+    not(any(feature = "client", feature = "lib", feature = "solana-program")),
+    all(feature = "pinocchio", not(target_os = "solana"))
+))]
+extern crate std;
+
+#[cfg(any(feature = "client", feature = "lib", feature = "pinocchio"))]
+pub mod header;
+
+#[cfg(any(feature = "lib", feature = "pinocchio"))]
+pub mod codec;
 
 #[cfg(feature = "client")]
 pub mod instruction;
@@ -45,5 +80,42 @@ pub mod instruction;
 #[cfg(feature = "lib")]
 pub mod entrypoint;
 
-#[cfg(not(any(feature = "client", feature = "lib")))]
+#[cfg(feature = "pinocchio")]
+pub mod pinocchio;
+
+// Tests of the write-account program use the entrypoint’s test fixtures.
+#[cfg(any(
+    feature = "lib",
+    all(
+        test,
+        feature = "solana-program",
+        not(any(feature = "client", feature = "pinocchio"))
+    )
+))]
+mod sdk;
+
+#[cfg(all(
+    feature = "lib",
+    not(any(feature = "solana-program", feature = "split-sdk"))
+))]
+compile_error!(
+    "`lib` feature requires `solana-program` or `split-sdk` feature"
+);
+
+#[cfg(all(
+    feature = "solana-program",
+    not(any(feature = "client", feature = "lib", feature = "pinocchio"))
+))]
 mod program;
+
+#[cfg(all(
+    test,
+    any(
+        feature = "lib",
+        all(
+            feature = "solana-program",
+            not(any(feature = "client", feature = "pinocchio"))
+        )
+    )
+))]
+mod test_utils;
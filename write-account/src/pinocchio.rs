@@ -0,0 +1,216 @@
+//! Entrypoint for programs built with pinocchio rather than solana-program.
+//!
+//! Provides the same handling of empty instruction data as the
+//! [`mod@crate::entrypoint`] module but operates on pinocchio’s
+//! [`AccountView`] type.  Doesn’t depend on `solana-program` or any other
+//! crate of the SDK; payloads are decoded with codecs from the
+//! [`mod@crate::codec`] module.
+
+use core::mem::MaybeUninit;
+
+use pinocchio::error::ProgramError;
+use pinocchio::{AccountView, Address};
+
+use crate::codec::{PayloadCodec, WriteAccountError};
+
+
+/// Deserialize the input arguments.
+///
+/// Behaves like [`pinocchio::entrypoint::deserialize`] except for special
+/// handling of empty instruction data as described in
+/// [`crate::entrypoint::deserialize`].  Data of the account is decoded with
+/// codec `C`.  Chained payloads aren’t supported.
+///
+/// The account holding the instruction data is kept immutably borrowed for the
+/// rest of the program’s execution so that the instruction data cannot be
+/// modified through a duplicate of that account.
+///
+/// Pinocchio ignores accounts above `MAX_ACCOUNTS`.  If the instruction data is
+/// empty and there are more accounts than that, returns `TooManyAccounts` error
+/// since the account holding the instruction data would not be parsed.
+///
+/// # Safety
+///
+/// Must be called with pointer to properly serialised instruction such as done
+/// by the Solana runtime.  See [`pinocchio::entrypoint::deserialize`].
+pub unsafe fn deserialize<C: PayloadCodec, const MAX_ACCOUNTS: usize>(
+    input: *mut u8,
+    accounts: &mut [MaybeUninit<AccountView>; MAX_ACCOUNTS],
+) -> Result<(&'static Address, usize, &'static [u8]), ProgramError> {
+    // SAFETY: Caller promises this is safe.
+    let total = unsafe { *(input as *const u64) };
+    // SAFETY: Caller promises this is safe.
+    let (program_id, mut count, mut instruction_data) =
+        unsafe { pinocchio::entrypoint::deserialize(input, accounts) };
+
+    // If instruction data is empty, the actual instruction data comes from the
+    // last account passed in the call.
+    if instruction_data.is_empty() {
+        if total != count as u64 {
+            return Err(error(WriteAccountError::TooManyAccounts));
+        }
+        count = count
+            .checked_sub(1)
+            .ok_or(error(WriteAccountError::MissingDataAccount))?;
+        // SAFETY: `deserialize` initialised the element.
+        let ix_acc = unsafe { accounts[count].assume_init_ref() };
+        instruction_data = get_ix_data::<C>(ix_acc)?;
+    }
+
+    Ok((program_id, count, instruction_data))
+}
+
+
+/// Interprets data in the account as instruction data.
+fn get_ix_data<C: PayloadCodec>(
+    account: &AccountView,
+) -> Result<&'static [u8], ProgramError> {
+    let data = account.try_borrow()?;
+    // SAFETY: The data lives in the input buffer which is valid for the entire
+    // execution of the program.  We never release the borrow (see below) so
+    // nothing can modify the data.
+    let slice: &'static [u8] =
+        unsafe { core::slice::from_raw_parts(data.as_ptr(), data.len()) };
+    core::mem::forget(data);
+    C::decode(slice).map_err(error)
+}
+
+/// Converts the error into pinocchio’s error type.
+fn error(err: WriteAccountError) -> ProgramError {
+    ProgramError::Custom(err as u32)
+}
+
+
+/// Declare the program entrypoint for a pinocchio program and set up global
+/// handlers.
+///
+/// Analogous to [`pinocchio::entrypoint!`] macro with additional handling of
+/// empty instruction data as described in [`deserialize`].
+///
+/// Just like with [`crate::entrypoint!`], codec can be specified with `codec`
+/// argument.
+#[macro_export]
+#[rustfmt::skip]
+macro_rules! pinocchio_entrypoint {
+    ($process_instruction:expr) => {
+        $crate::pinocchio_entrypoint!(
+            $process_instruction,
+            codec = $crate::codec::LengthPrefixed
+        );
+    };
+    ($process_instruction:expr, codec = $codec:ty) => {
+        $crate::pinocchio_entrypoint!(
+            @emit $process_instruction, [$codec],
+            [$crate::pinocchio::__private::pinocchio::MAX_TX_ACCOUNTS]
+        );
+        $crate::pinocchio::__private::pinocchio::default_allocator!();
+        $crate::pinocchio::__private::pinocchio::default_panic_handler!();
+    };
+    (@emit $process_instruction:expr, [$codec:ty], [$max:expr]) => {
+        /// Solana program entry point.
+        ///
+        /// # Safety
+        ///
+        /// Must be called with pointer to properly serialised instruction such
+        /// as done by the Solana runtime.
+        #[no_mangle]
+        pub unsafe extern "C" fn entrypoint(input: *mut u8) -> u64 {
+            // SAFETY: Caller guarantees it’s safe.
+            unsafe {
+                $crate::pinocchio::__private::entrypoint_impl::<
+                    $codec,
+                    _,
+                    { $max },
+                >(input, $process_instruction)
+            }
+        }
+    };
+}
+
+/// Declare the program entrypoint for a pinocchio program which doesn’t use
+/// heap and set up global handlers.
+///
+/// Analogous to [`crate::entrypoint_no_alloc!`] macro.  Like
+/// [`crate::pinocchio_entrypoint!`] it handles empty instruction data as
+/// described in [`deserialize`] but sets up pinocchio’s
+/// [`no_allocator!`](pinocchio::no_allocator) rather than the default bump
+/// allocator.  The program (and the codec) must therefore not allocate.  In
+/// particular, [`LengthPrefixed`](crate::codec::LengthPrefixed) aborts the
+/// program when given a compressed or chained payload.
+///
+/// Accepts optional `codec = C` and `max_accounts = N` arguments (in that
+/// order).  The latter specifies the maximum number of accounts the program
+/// accepts.  The accounts are stored in an array on stack so smaller values
+/// reduce the stack usage.  By default [`pinocchio::MAX_TX_ACCOUNTS`] accounts
+/// are supported.  If the runtime passes more accounts and the instruction
+/// data is read from an account, the entrypoint fails with
+/// [`WriteAccountError::TooManyAccounts`] error.
+///
+/// For example: `pinocchio_entrypoint_no_alloc!(process, max_accounts = 8)`.
+#[macro_export]
+#[rustfmt::skip]
+macro_rules! pinocchio_entrypoint_no_alloc {
+    ($process_instruction:expr $(, codec = $codec:ty)?) => {
+        $crate::pinocchio_entrypoint_no_alloc!(
+            $process_instruction $(, codec = $codec)?,
+            max_accounts = $crate::pinocchio::__private::pinocchio::MAX_TX_ACCOUNTS
+        );
+    };
+    ($process_instruction:expr, max_accounts = $max:expr) => {
+        $crate::pinocchio_entrypoint_no_alloc!(
+            $process_instruction,
+            codec = $crate::codec::LengthPrefixed,
+            max_accounts = $max
+        );
+    };
+    ($process_instruction:expr, codec = $codec:ty, max_accounts = $max:expr) => {
+        $crate::pinocchio_entrypoint!(
+            @emit $process_instruction, [$codec], [$max]
+        );
+        $crate::pinocchio::__private::pinocchio::no_allocator!();
+        $crate::pinocchio::__private::pinocchio::default_panic_handler!();
+    };
+}
+
+
+#[doc(hidden)]
+pub mod __private {
+    use core::mem::MaybeUninit;
+
+    pub use pinocchio;
+    use pinocchio::error::ProgramError;
+    use pinocchio::{AccountView, Address};
+
+    #[inline(always)]
+    pub unsafe fn entrypoint_impl<
+        C: super::PayloadCodec,
+        E: Into<ProgramError>,
+        const MAX_ACCOUNTS: usize,
+    >(
+        input: *mut u8,
+        process: impl FnOnce(&Address, &mut [AccountView], &[u8]) -> Result<(), E>,
+    ) -> u64 {
+        let mut accounts =
+            [const { MaybeUninit::<AccountView>::uninit() }; MAX_ACCOUNTS];
+        // SAFETY: Caller promises this is safe.
+        let parsed = unsafe {
+            super::deserialize::<C, MAX_ACCOUNTS>(input, &mut accounts)
+        };
+        let (program_id, num_accounts, instruction_data) = match parsed {
+            Ok(it) => it,
+            Err(error) => return error.into(),
+        };
+        // SAFETY: deserialize initialised the first num_accounts entries of the
+        // array and `MU<X>` has the same layout as `X`.
+        let accounts = unsafe {
+            core::slice::from_raw_parts_mut(
+                accounts.as_mut_ptr().cast::<AccountView>(),
+                num_accounts,
+            )
+        };
+        match process(program_id, accounts, instruction_data) {
+            Ok(()) => pinocchio::SUCCESS,
+            Err(error) => error.into().into(),
+        }
+    }
+}
@@ -77,6 +77,11 @@ solana_program::entrypoint::custom_panic_default!();
 /// the account and from this program’s point of view, payer is considered an
 /// owner of the write account.
 ///
+/// Alternatively, the Write account may be a keypair account (e.g. created
+/// with the System program’s `CreateAccount` instruction and assigned to this
+/// program).  In that case the Write account must sign the transaction, the
+/// seed must be empty and the bump must be zero.
+///
 /// If the Write account doesn’t exist, creates the account.  Similarly, if it’s
 /// too small, increases its size.  Note that due to Solana’s limitations,
 /// account’s size can increase by at most 10 KiB (that includes creation of the
@@ -158,10 +163,14 @@ fn setup_write_account(
             size as u64,
             program_id,
         );
+        // Keypair account signs the transaction and has no seeds.
+        let seeds = accounts.write_seeds();
+        let signers: &[&[&[u8]]] =
+            if accounts.write.is_signer { &[] } else { &[&seeds] };
         solana_program::program::invoke_signed(
             &instruction,
             &[accounts.payer.clone(), accounts.write.clone()],
-            &[&accounts.write_seeds()],
+            signers,
         )
     } else if accounts.write.data_len() < size {
         // If size is less than required, resize.  We may need to transfer more
@@ -210,7 +219,7 @@ struct Accounts<'a, 'info> {
     payer: &'a AccountInfo<'info>,
 
     /// The Write account.  It’s address is a PDA using `[payer.key,
-    /// seed_and_bump]` seeds.
+    /// seed_and_bump]` seeds unless it’s a signer (i.e. a keypair account).
     write: &'a AccountInfo<'info>,
 
     /// Seed and bump used in PDA of the Write account.
@@ -222,8 +231,9 @@ impl<'a, 'info> Accounts<'a, 'info> {
     ///
     /// Expects the following accounts in the `accounts` slice:
     /// 1. Payer account which is signer and writable,
-    /// 2. Write account which is writable and a PDA using `[payer.key, seed,
-    ///    bump]` seeds.
+    /// 2. Write account which is writable and either a PDA using `[payer.key,
+    ///    seed, bump]` seeds or a signer.  In the latter case, the seed must be
+    ///    empty and bump must be zero.
     ///
     /// Reads seed and bump from `instruction` advancing it.  Specifically,
     /// reads the following dynamically-sized structure:
@@ -251,7 +261,7 @@ impl<'a, 'info> Accounts<'a, 'info> {
             return Err(ProgramError::InvalidAccountData);
         }
 
-        // Write account.  Must be writable and PDA.
+        // Write account.  Must be writable and PDA or signer.
         let write = next_account_info(accounts)?;
         if !write.is_writable {
             return Err(ProgramError::InvalidAccountData);
@@ -259,6 +269,15 @@ impl<'a, 'info> Accounts<'a, 'info> {
         let seed_len = read(instruction, u8::from_le_bytes)?;
         let seed_and_bump = read_slice(instruction, seed_len as usize + 1)?;
         let this = Self { payer, write, seed_and_bump };
+        if write.is_signer {
+            // Keypair account has no seeds.  Require them to be empty so there
+            // is only one encoding of the instruction.
+            return if seed_and_bump == [0] {
+                Ok(this)
+            } else {
+                Err(ProgramError::InvalidInstructionData)
+            };
+        }
 
         match Pubkey::create_program_address(&this.write_seeds(), program_id) {
             Ok(pda) if &pda == this.write.key => Ok(this),
@@ -317,3 +336,204 @@ fn read_slice<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
     *bytes = tail;
     Ok(head)
 }
+
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    use solana_program::entrypoint::ProgramResult;
+    use solana_program::instruction::Instruction;
+    use solana_program::program_stubs::{set_syscall_stubs, SyscallStubs};
+
+    use super::*;
+    use crate::test_utils::{serialise_input_with_id, TestAccount};
+
+    const PROGRAM_ID: Pubkey = Pubkey::new_from_array([1; 32]);
+    const PAYER: Pubkey = Pubkey::new_from_array([2; 32]);
+    const WRITE: Pubkey = Pubkey::new_from_array([3; 32]);
+    const SYSTEM: Pubkey = solana_program::system_program::ID;
+
+    /// Syscall stubs providing the Rent sysvar and executing System
+    /// program’s `Transfer` and `CreateAccount` instructions.
+    struct Stubs;
+
+    impl SyscallStubs for Stubs {
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            // SAFETY: Rent::get passes pointer to a Rent object.
+            unsafe { var_addr.cast::<Rent>().write(Rent::default()) };
+            solana_program::entrypoint::SUCCESS
+        }
+
+        fn sol_invoke_signed(
+            &self,
+            instruction: &Instruction,
+            account_infos: &[AccountInfo],
+            signers_seeds: &[&[&[u8]]],
+        ) -> ProgramResult {
+            // Keypair Write account signs the transaction itself.
+            assert!(signers_seeds.is_empty());
+            assert_eq!(SYSTEM, instruction.program_id);
+            let (tag, rest) = instruction.data.split_at(4);
+            let lamports = u64::from_le_bytes(rest[..8].try_into().unwrap());
+            let [from, to] = account_infos else { panic!() };
+            **from.try_borrow_mut_lamports()? -= lamports;
+            **to.try_borrow_mut_lamports()? += lamports;
+            match tag {
+                // CreateAccount
+                [0, 0, 0, 0] => {
+                    let space =
+                        u64::from_le_bytes(rest[8..16].try_into().unwrap());
+                    to.resize(space as usize)?;
+                    to.assign(&Pubkey::try_from(&rest[16..48]).unwrap());
+                }
+                // Transfer
+                [2, 0, 0, 0] => (),
+                _ => panic!("unexpected instruction: {instruction:?}"),
+            }
+            Ok(())
+        }
+    }
+
+    fn payer() -> TestAccount {
+        TestAccount {
+            key: PAYER,
+            owner: SYSTEM,
+            is_signer: true,
+            is_writable: true,
+            executable: false,
+            rent_epoch: u64::MAX,
+            lamports: 1_000_000_000,
+            data: Vec::new(),
+        }
+    }
+
+    /// Returns keypair Write account with given data.
+    fn write(data: &[u8]) -> TestAccount {
+        let lamports = Rent::default().minimum_balance(data.len());
+        TestAccount {
+            key: WRITE,
+            owner: PROGRAM_ID,
+            is_signer: true,
+            is_writable: true,
+            executable: false,
+            rent_epoch: u64::MAX,
+            lamports: if data.is_empty() { 0 } else { lamports },
+            data: data.to_vec(),
+        }
+    }
+
+    /// Runs the program with given accounts and instruction data and returns
+    /// the result along with the payer and the Write accounts’ lamports, owner
+    /// and data.
+    fn process(
+        accounts: &[TestAccount],
+        instruction: &[u8],
+    ) -> (Result, Vec<(u64, Pubkey, Vec<u8>)>) {
+        set_syscall_stubs(Box::new(Stubs));
+        let (mut input, offset) =
+            serialise_input_with_id(&PROGRAM_ID, accounts, instruction);
+        // SAFETY: input is a correctly aligned serialised instruction.
+        let (program_id, accounts, instruction) = unsafe {
+            solana_program::entrypoint::deserialize(
+                input[offset..].as_mut_ptr(),
+            )
+        };
+        let result = process_instruction(program_id, &accounts, instruction);
+        let state = accounts
+            .iter()
+            .take(2)
+            .map(|account| {
+                let data = account.try_borrow_data().unwrap().to_vec();
+                (account.lamports(), *account.owner, data)
+            })
+            .collect();
+        (result, state)
+    }
+
+    fn rent(len: usize) -> u64 { Rent::default().minimum_balance(len) }
+
+    #[test]
+    fn test_keypair_create() {
+        let accounts = [payer(), write(b"")];
+        let (result, state) = process(&accounts, b"\0\0\0\0\0\0\0hello");
+        assert_eq!(Ok(()), result);
+        assert_eq!(
+            [
+                (1_000_000_000 - rent(5), SYSTEM, Vec::new()),
+                (rent(5), PROGRAM_ID, b"hello".to_vec()),
+            ][..],
+            state,
+        );
+    }
+
+    #[test]
+    fn test_keypair_write() {
+        let accounts = [payer(), write(b"hello")];
+        let (result, state) = process(&accounts, b"\0\0\0\x01\0\0\0ip");
+        assert_eq!(Ok(()), result);
+        assert_eq!(
+            [
+                (1_000_000_000, SYSTEM, Vec::new()),
+                (rent(5), PROGRAM_ID, b"hiplo".to_vec()),
+            ][..],
+            state,
+        );
+    }
+
+    #[test]
+    fn test_keypair_resize() {
+        let accounts = [payer(), write(b"hello")];
+        let (result, state) = process(&accounts, b"\0\0\0\x03\0\0\0p, world");
+        assert_eq!(Ok(()), result);
+        assert_eq!(
+            [
+                (1_000_000_000 - (rent(11) - rent(5)), SYSTEM, Vec::new()),
+                (rent(11), PROGRAM_ID, b"help, world".to_vec()),
+            ][..],
+            state,
+        );
+    }
+
+    #[test]
+    fn test_keypair_free() {
+        let accounts = [payer(), write(b"hello")];
+        // Same data as generated by instruction::free_keypair.
+        let (result, state) = process(&accounts, &[0, 0, 0]);
+        assert_eq!(Ok(()), result);
+        assert_eq!(
+            [
+                (1_000_000_000 + rent(5), SYSTEM, Vec::new()),
+                (0, SYSTEM, Vec::new()),
+            ][..],
+            state,
+        );
+    }
+
+    #[test]
+    fn test_non_signer_write_account_must_be_pda() {
+        let mut write = write(b"hello");
+        write.is_signer = false;
+        let accounts = [payer(), write];
+        let (result, _) = process(&accounts, b"\0\0\0\0\0\0\0ip");
+        assert_eq!(Err(ProgramError::InvalidSeeds), result);
+        let (result, _) = process(&accounts, &[0, 0, 0]);
+        assert_eq!(Err(ProgramError::InvalidSeeds), result);
+    }
+
+    #[test]
+    fn test_keypair_rejects_seed_and_bump() {
+        let accounts = [payer(), write(b"hello")];
+        for instruction in [
+            &b"\0\x01x\0\x01\0\0\0ip"[..],
+            b"\0\0\xff\x01\0\0\0ip",
+            b"\0\x01x\xff",
+            b"\0\0\x01",
+        ] {
+            let (result, state) = process(&accounts, instruction);
+            assert_eq!(Err(ProgramError::InvalidInstructionData), result);
+            assert_eq!(b"hello", &state[1].2[..]);
+        }
+    }
+}
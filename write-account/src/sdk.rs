@@ -0,0 +1,37 @@
+//! Solana SDK items used by the [`crate::entrypoint`] module.
+//!
+//! The items come from the monolithic `solana-program` crate unless `split-sdk`
+//! feature is enabled in which case they come from the fine-grained crates
+//! `solana-program` has been split into.  Types in both are the same so the
+//! choice doesn’t affect users of the library.
+//!
+//! The entrypoint refers to SDK items only through this module.  In particular
+//! it relies only on items (`AccountInfo` fields, `Pubkey`, `ProgramError`
+//! etc.) whose shape is the same in 1.x and 2.x releases of the SDK.  Serving
+//! programs built against `solana-program` 1.x therefore only requires another
+//! set of re-exports here (behind an `sdk1` feature) and a renamed 1.x
+//! dependency.  That isn’t supported: `solana-program` 1.x pins `zeroize`
+//! below 1.4 (through `curve25519-dalek` 3) which conflicts with `zeroize`
+//! required by the 2.x client dependencies so both major versions cannot be
+//! resolved in a single lock file of this workspace.
+
+#[cfg(not(feature = "split-sdk"))]
+#[allow(unused_imports)]
+pub use solana_program::{
+    account_info, entrypoint, hash, msg, program_error, pubkey,
+};
+#[cfg(feature = "split-sdk")]
+#[allow(unused_imports)]
+pub use {
+    solana_account_info as account_info, solana_msg::msg,
+    solana_program_entrypoint as entrypoint,
+    solana_program_error as program_error, solana_pubkey as pubkey,
+};
+
+/// SHA-256 hashing.
+#[cfg(feature = "split-sdk")]
+#[allow(unused_imports)]
+pub mod hash {
+    pub use solana_hash::HASH_BYTES;
+    pub use solana_sha256_hasher::hash;
+}
@@ -0,0 +1,158 @@
+//! Test fixtures shared by tests of the [`crate::entrypoint`] module and the
+//! write-account program.
+
+use alloc::vec::Vec;
+
+use pretty_assertions::assert_eq;
+
+use crate::sdk::account_info::AccountInfo;
+use crate::sdk::entrypoint::{
+    BPF_ALIGN_OF_U128, MAX_PERMITTED_DATA_INCREASE, NON_DUP_MARKER,
+};
+use crate::sdk::pubkey::Pubkey;
+
+/// Account passed to the program.
+#[derive(Clone, Debug)]
+pub(crate) struct TestAccount {
+    pub key: Pubkey,
+    pub owner: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+    pub executable: bool,
+    pub rent_epoch: u64,
+    pub lamports: u64,
+    pub data: Vec<u8>,
+}
+
+impl TestAccount {
+    /// Returns an account with given data and unique key, owner and
+    /// (pseudo-random) remaining fields.
+    pub fn new(data: impl Into<Vec<u8>>) -> Self {
+        use core::sync::atomic::{AtomicU64, Ordering};
+        static CNT: AtomicU64 = AtomicU64::new(1);
+
+        let bits = CNT.fetch_add(1, Ordering::SeqCst);
+        Self {
+            key: Pubkey::new_unique(),
+            lamports: CNT.fetch_add(1, Ordering::SeqCst),
+            data: data.into(),
+            owner: Pubkey::new_unique(),
+            rent_epoch: CNT.fetch_add(1, Ordering::SeqCst),
+            is_signer: bits & 1 != 0,
+            is_writable: bits & 2 != 0,
+            executable: bits & 4 != 0,
+        }
+    }
+}
+
+impl PartialEq<AccountInfo<'_>> for TestAccount {
+    fn eq(&self, rhs: &AccountInfo<'_>) -> bool {
+        &self.key == rhs.key &&
+            self.lamports == rhs.lamports() &&
+            self.data.as_slice() == &rhs.try_borrow_data().unwrap()[..] &&
+            &self.owner == rhs.owner &&
+            self.rent_epoch == rhs.rent_epoch &&
+            self.is_signer == rhs.is_signer &&
+            self.is_writable == rhs.is_writable &&
+            self.executable == rhs.executable
+    }
+}
+
+/// Serialises accounts and instruction data for a program with a unique id
+/// the way the runtime does.
+///
+/// Returns the program id, the serialised input and offset within it at which
+/// the correctly aligned input starts.
+pub(crate) fn serialise_input(
+    accounts: &[TestAccount],
+    instruction_data: &[u8],
+) -> (Pubkey, Vec<u8>, usize) {
+    let program_id = Pubkey::new_unique();
+    let (data, pad) =
+        serialise_input_with_id(&program_id, accounts, instruction_data);
+    (program_id, data, pad)
+}
+
+/// Serialises accounts and instruction data for given program the way the
+/// runtime does.
+///
+/// Returns the serialised input and offset within it at which the correctly
+/// aligned input starts.
+pub(crate) fn serialise_input_with_id(
+    program_id: &Pubkey,
+    accounts: &[TestAccount],
+    instruction_data: &[u8],
+) -> (Vec<u8>, usize) {
+    let mut vec = Vec::<u8>::new();
+    vec.extend_from_slice(&(accounts.len() as u64).to_le_bytes());
+
+    fn align(addr: usize) -> usize {
+        match addr % BPF_ALIGN_OF_U128 {
+            0 => 0,
+            n => BPF_ALIGN_OF_U128 - n,
+        }
+    }
+
+    for account in accounts {
+        vec.extend_from_slice(&[
+            NON_DUP_MARKER,
+            account.is_signer as u8,
+            account.is_writable as u8,
+            account.executable as u8,
+        ]);
+        // Original data length used to limit resizing.
+        vec.extend_from_slice(&(account.data.len() as u32).to_le_bytes());
+        vec.extend_from_slice(account.key.as_ref());
+        vec.extend_from_slice(account.owner.as_ref());
+        vec.extend_from_slice(&account.lamports.to_le_bytes());
+        vec.extend_from_slice(&(account.data.len() as u64).to_le_bytes());
+        vec.extend_from_slice(account.data.as_slice());
+        let align_offset = align(account.data.len());
+        let padding = MAX_PERMITTED_DATA_INCREASE + align_offset;
+        vec.resize(vec.len() + padding, 0);
+        vec.extend_from_slice(&account.rent_epoch.to_le_bytes());
+    }
+
+    vec.extend_from_slice(&(instruction_data.len() as u64).to_le_bytes());
+    vec.extend_from_slice(instruction_data);
+    vec.extend_from_slice(program_id.as_ref());
+    vec.reserve(BPF_ALIGN_OF_U128 - 1);
+
+    // Make sure the data is serialised.  We do it by inserting appropriate
+    // number of bytes at the start of the vector.
+    let pad = match vec.as_ptr().addr() % BPF_ALIGN_OF_U128 {
+        0 => 0,
+        n => {
+            vec.splice(0..0, core::iter::repeat_n(0, 8 - n));
+            8 - n
+        }
+    };
+    assert_eq!(0, (vec.as_ptr().wrapping_add(pad)).addr() % BPF_ALIGN_OF_U128);
+
+    (vec, pad)
+}
+
+/// Tests whether `serialise_input` is implemented correctly.  If this test
+/// fails, other tests are likely to fail as well.
+#[test]
+fn test_serialise() {
+    let accounts = [
+        TestAccount::new(b"raz"),
+        TestAccount::new(b"dwa"),
+        TestAccount::new(b"trzy"),
+        TestAccount::new(b"cztery"),
+    ];
+    let (program, mut data, offset) = serialise_input(&accounts[..], b"data");
+
+    // SAFETY: Data is correctly aligned and serialised.  (We assume).
+    let (got_program, got_accounts, got_data) = unsafe {
+        crate::sdk::entrypoint::deserialize(data[offset..].as_mut_ptr())
+    };
+
+    assert_eq!(&program, got_program);
+    assert_eq!(&b"data"[..], got_data);
+    assert_eq!(accounts.len(), got_accounts.len());
+    for (acc, got) in accounts.iter().zip(got_accounts.iter()) {
+        assert_eq!(acc, got);
+    }
+}